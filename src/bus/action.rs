@@ -0,0 +1,474 @@
+use std::any::type_name;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use super::{endpoint, Bus, BusError, CallError, Endpoint, Spawned, SPAWNED_POLL};
+use crate::clock::{Deadline, SharedClock};
+use crate::links::{CarbonData, CarbonTaskConfiguration};
+
+/// Feedback kept per goal for the client to collect; older feedback is
+/// dropped.
+pub const FEEDBACK_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GoalStatus {
+    /// Queued behind other goals.
+    Pending,
+    Active,
+    Succeeded,
+    Aborted,
+    Cancelled,
+    TimedOut,
+}
+
+impl GoalStatus {
+    pub fn is_done(self) -> bool {
+        !matches!(self, GoalStatus::Pending | GoalStatus::Active)
+    }
+}
+
+/// How a goal ended.
+#[derive(Clone, Debug)]
+pub enum GoalOutcome<R> {
+    Succeeded(CarbonData<R>),
+    /// The task gave up, saying why.
+    Aborted(String),
+    Cancelled,
+    /// The goal's time ran out, queued or running.
+    TimedOut,
+}
+
+impl<R> GoalOutcome<R> {
+    pub fn status(&self) -> GoalStatus {
+        match self {
+            GoalOutcome::Succeeded(_) => GoalStatus::Succeeded,
+            GoalOutcome::Aborted(_) => GoalStatus::Aborted,
+            GoalOutcome::Cancelled => GoalStatus::Cancelled,
+            GoalOutcome::TimedOut => GoalStatus::TimedOut,
+        }
+    }
+}
+
+/// A long-running task working towards a goal, e.g. "navigate to pose" or
+/// "execute trajectory". Where [`crate::links::Task::process`] maps one input
+/// to one output, `execute` runs until the goal is reached, reporting
+/// feedback on the way and stopping early once
+/// [`GoalContext::is_cancelled`].
+pub trait ActionTask {
+    type Goal;
+    type Feedback;
+    type Result;
+    fn setup(&self, configuration: Option<&CarbonTaskConfiguration>);
+    fn execute(
+        &self,
+        goal: CarbonData<Self::Goal>,
+        context: &GoalContext<'_, Self::Feedback>,
+    ) -> GoalOutcome<Self::Result>;
+}
+
+// Shared by a goal's handle and the server running it.
+struct GoalState<F, R> {
+    progress: Mutex<(GoalStatus, Option<GoalOutcome<R>>)>,
+    // Signalled when the goal finishes.
+    finished: Condvar,
+    cancel: AtomicBool,
+    feedback: Mutex<VecDeque<CarbonData<F>>>,
+}
+
+impl<F, R> GoalState<F, R> {
+    fn set_status(&self, status: GoalStatus) {
+        self.progress.lock().expect("Goal lock poisoned").0 = status;
+    }
+
+    fn finish(&self, outcome: GoalOutcome<R>) {
+        *self.progress.lock().expect("Goal lock poisoned") = (outcome.status(), Some(outcome));
+        self.finished.notify_all();
+    }
+}
+
+struct GoalRequest<G, F, R> {
+    goal: CarbonData<G>,
+    state: Arc<GoalState<F, R>>,
+    deadline: Deadline,
+}
+
+type QueuedGoal<T> =
+    GoalRequest<<T as ActionTask>::Goal, <T as ActionTask>::Feedback, <T as ActionTask>::Result>;
+
+impl Bus {
+    /// Serves the action `name` with `task`. Only one server may take goals
+    /// for a name at a time.
+    pub fn advertise_action<T>(&self, name: &str, task: T) -> Result<ActionServer<T>, BusError>
+    where
+        T: ActionTask,
+        T::Goal: Send + 'static,
+        T::Feedback: Send + 'static,
+        T::Result: Send + 'static,
+    {
+        let endpoint = endpoint::<Endpoint<QueuedGoal<T>>>(
+            &self.actions,
+            name,
+            type_name::<(T::Goal, T::Feedback, T::Result)>(),
+        )?;
+        let (goals, alive) = endpoint.advertise(name)?;
+        Ok(ActionServer {
+            name: name.to_string(),
+            task,
+            goals,
+            _alive: alive,
+        })
+    }
+
+    /// A client of the action `name`, which need not be served yet.
+    pub fn action_client<G, F, R>(&self, name: &str) -> Result<ActionClient<G, F, R>, BusError>
+    where
+        G: Send + 'static,
+        F: Send + 'static,
+        R: Send + 'static,
+    {
+        Ok(ActionClient {
+            name: name.to_string(),
+            endpoint: endpoint(&self.actions, name, type_name::<(G, F, R)>())?,
+            clock: self.clock.clone(),
+        })
+    }
+}
+
+/// What a running goal sees of its client.
+pub struct GoalContext<'a, F> {
+    cancel: &'a AtomicBool,
+    // Set when a spawned server is being dropped.
+    stop: Option<&'a AtomicBool>,
+    feedback: &'a Mutex<VecDeque<CarbonData<F>>>,
+    deadline: &'a Deadline,
+}
+
+impl<F> GoalContext<'_, F> {
+    /// Whether the goal should stop: the client cancelled it, its time ran
+    /// out or its server is shutting down. The task then returns
+    /// [`GoalOutcome::Cancelled`].
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Acquire)
+            || self.stop.is_some_and(|stop| stop.load(Ordering::Acquire))
+            || self.deadline.expired()
+    }
+
+    pub fn feedback(&self, feedback: CarbonData<F>) {
+        let mut queue = self.feedback.lock().expect("Feedback lock poisoned");
+        if queue.len() >= FEEDBACK_DEPTH {
+            queue.pop_front();
+        }
+        queue.push_back(feedback);
+    }
+}
+
+/// Runs goals sent to an action with a task, one at a time in the order they
+/// arrive, on whichever thread calls [`ActionServer::serve_once`]. Dropping
+/// the server withdraws the action; goals still queued are dropped.
+pub struct ActionServer<T: ActionTask> {
+    name: String,
+    task: T,
+    goals: Receiver<QueuedGoal<T>>,
+    _alive: Arc<()>,
+}
+
+impl<T: ActionTask> ActionServer<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn task(&self) -> &T {
+        &self.task
+    }
+
+    /// Runs the next goal to the end, waiting up to `timeout` for one.
+    /// Returns whether a goal was run.
+    pub fn serve_once(&self, timeout: Duration) -> bool {
+        self.serve(timeout, None)
+    }
+
+    fn serve(&self, timeout: Duration, stop: Option<&AtomicBool>) -> bool {
+        let request = match self.goals.recv_timeout(timeout) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return false,
+        };
+        let state = &request.state;
+        if state.cancel.load(Ordering::Acquire) {
+            state.finish(GoalOutcome::Cancelled);
+            return true;
+        }
+        if request.deadline.expired() {
+            state.finish(GoalOutcome::TimedOut);
+            return true;
+        }
+        state.set_status(GoalStatus::Active);
+        let context = GoalContext {
+            cancel: &state.cancel,
+            stop,
+            feedback: &state.feedback,
+            deadline: &request.deadline,
+        };
+        let outcome = match self.task.execute(request.goal, &context) {
+            GoalOutcome::Cancelled
+                if !state.cancel.load(Ordering::Acquire) && request.deadline.expired() =>
+            {
+                GoalOutcome::TimedOut
+            }
+            outcome => outcome,
+        };
+        state.finish(outcome);
+        true
+    }
+
+    /// Serves on a thread of its own until the returned handle is dropped.
+    pub fn spawn(self) -> Spawned
+    where
+        T: Send + 'static,
+        T::Goal: Send + 'static,
+        T::Feedback: Send + 'static,
+        T::Result: Send + 'static,
+    {
+        Spawned::new(move |stop| {
+            self.serve(SPAWNED_POLL, Some(stop));
+        })
+    }
+}
+
+/// Sends goals to an action.
+pub struct ActionClient<G, F, R> {
+    name: String,
+    endpoint: Arc<Endpoint<GoalRequest<G, F, R>>>,
+    clock: SharedClock,
+}
+
+impl<G, F, R> ActionClient<G, F, R> {
+    /// Clock goal time limits and waits are measured on.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.endpoint.sender().is_some()
+    }
+
+    /// Queues `goal`, which times out after `limit`, counting time spent
+    /// queued; `None` lets it run as long as it takes.
+    pub fn send_goal(
+        &self,
+        goal: CarbonData<G>,
+        limit: Option<Duration>,
+    ) -> Result<GoalHandle<F, R>, CallError> {
+        let sender = self
+            .endpoint
+            .sender()
+            .ok_or_else(|| CallError::Unavailable(self.name.clone()))?;
+        let state = Arc::new(GoalState {
+            progress: Mutex::new((GoalStatus::Pending, None)),
+            finished: Condvar::new(),
+            cancel: AtomicBool::new(false),
+            feedback: Mutex::new(VecDeque::new()),
+        });
+        sender
+            .send(GoalRequest {
+                goal,
+                state: state.clone(),
+                deadline: Deadline::after(&self.clock, limit),
+            })
+            .map_err(|_| CallError::Unavailable(self.name.clone()))?;
+        Ok(GoalHandle {
+            name: self.name.clone(),
+            state,
+            clock: self.clock.clone(),
+        })
+    }
+}
+
+/// Follows a goal sent to an action.
+pub struct GoalHandle<F, R> {
+    name: String,
+    state: Arc<GoalState<F, R>>,
+    clock: SharedClock,
+}
+
+impl<F, R> GoalHandle<F, R> {
+    pub fn status(&self) -> GoalStatus {
+        self.state.progress.lock().expect("Goal lock poisoned").0
+    }
+
+    /// Asks the task to stop. Goals still queued are cancelled without
+    /// running.
+    pub fn cancel(&self) {
+        self.state.cancel.store(true, Ordering::Release);
+    }
+
+    /// Feedback reported since the last call, oldest first.
+    pub fn take_feedback(&self) -> Vec<CarbonData<F>> {
+        std::mem::take(&mut *self.state.feedback.lock().expect("Feedback lock poisoned")).into()
+    }
+
+    /// Waits up to `timeout` on the client's clock for the goal to finish and
+    /// returns how it ended; collect the result with
+    /// [`GoalHandle::take_outcome`].
+    pub fn wait(&self, timeout: Duration) -> Result<GoalStatus, CallError> {
+        let deadline = Deadline::after(&self.clock, Some(timeout));
+        let mut progress = self.state.progress.lock().expect("Goal lock poisoned");
+        while !progress.0.is_done() {
+            // Only this handle holds the goal once the server has dropped it.
+            if Arc::strong_count(&self.state) == 1 {
+                return Err(CallError::Dropped(self.name.clone()));
+            }
+            if deadline.expired() {
+                return Err(CallError::Timeout(self.name.clone()));
+            }
+            progress = self
+                .state
+                .finished
+                .wait_timeout(progress, SPAWNED_POLL.min(timeout))
+                .expect("Goal lock poisoned")
+                .0;
+        }
+        Ok(progress.0)
+    }
+
+    /// How the goal ended, once it has; later calls return `None`.
+    pub fn take_outcome(&self) -> Option<GoalOutcome<R>> {
+        self.state
+            .progress
+            .lock()
+            .expect("Goal lock poisoned")
+            .1
+            .take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::links::CarbonMetadata;
+
+    // Runs until told to stop.
+    struct Patrol;
+
+    impl ActionTask for Patrol {
+        type Goal = ();
+        type Feedback = u32;
+        type Result = ();
+
+        fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+        fn execute(
+            &self,
+            _goal: CarbonData<()>,
+            context: &GoalContext<'_, u32>,
+        ) -> GoalOutcome<()> {
+            let mut laps = 0;
+            while !context.is_cancelled() {
+                laps += 1;
+                context.feedback(CarbonData::new(laps, CarbonMetadata::default()));
+                thread::sleep(Duration::from_millis(1));
+            }
+            GoalOutcome::Cancelled
+        }
+    }
+
+    fn goal() -> CarbonData<()> {
+        CarbonData::new((), CarbonMetadata::default())
+    }
+
+    #[test]
+    fn dropping_a_spawned_server_stops_its_goal() {
+        let bus = Bus::default();
+        let server = bus.advertise_action("patrol", Patrol).unwrap().spawn();
+        let client = bus.action_client::<(), u32, ()>("patrol").unwrap();
+        let handle = client.send_goal(goal(), None).unwrap();
+        let start = Instant::now();
+        while handle.status() != GoalStatus::Active {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "goal never started"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(server);
+        assert_eq!(handle.status(), GoalStatus::Cancelled);
+        assert!(!handle.take_feedback().is_empty());
+    }
+
+    #[test]
+    fn waits_are_measured_on_the_client_clock() {
+        let bus = Bus::default();
+        let _server = bus.advertise_action("patrol", Patrol).unwrap();
+        let clock = Arc::new(SimulatedClock::new());
+        let client = bus
+            .action_client::<(), u32, ()>("patrol")
+            .unwrap()
+            .with_clock(clock.clone());
+        let handle = client.send_goal(goal(), None).unwrap();
+
+        let stepped = Arc::new(AtomicBool::new(false));
+        let stepper = {
+            let (clock, stepped) = (clock.clone(), stepped.clone());
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                stepped.store(true, Ordering::Release);
+                clock.step(Duration::from_secs(1));
+            })
+        };
+        assert_eq!(
+            handle.wait(Duration::from_secs(1)),
+            Err(CallError::Timeout("patrol".to_string()))
+        );
+        assert!(stepped.load(Ordering::Acquire));
+        stepper.join().unwrap();
+        assert_eq!(handle.status(), GoalStatus::Pending);
+    }
+
+    #[test]
+    fn goal_limits_count_time_spent_queued() {
+        let bus = Bus::default();
+        let server = bus.advertise_action("patrol", Patrol).unwrap();
+        let clock = Arc::new(SimulatedClock::new());
+        let client = bus
+            .action_client::<(), u32, ()>("patrol")
+            .unwrap()
+            .with_clock(clock.clone());
+        let handle = client
+            .send_goal(goal(), Some(Duration::from_millis(500)))
+            .unwrap();
+        clock.step(Duration::from_millis(500));
+        assert!(server.serve_once(Duration::ZERO));
+        assert_eq!(handle.wait(Duration::ZERO), Ok(GoalStatus::TimedOut));
+        assert!(matches!(handle.take_outcome(), Some(GoalOutcome::TimedOut)));
+    }
+
+    #[test]
+    fn cancelled_goals_stop() {
+        let bus = Bus::default();
+        let server = bus.advertise_action("patrol", Patrol).unwrap().spawn();
+        let client = bus.action_client::<(), u32, ()>("patrol").unwrap();
+        let handle = client.send_goal(goal(), None).unwrap();
+        handle.cancel();
+        assert_eq!(
+            handle.wait(Duration::from_secs(5)),
+            Ok(GoalStatus::Cancelled)
+        );
+        drop(server);
+        assert!(matches!(
+            client.send_goal(goal(), None),
+            Err(CallError::Unavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,789 @@
+pub mod action;
+pub mod service;
+
+use std::any::{type_name, Any};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::clock::{default_clock, Deadline, SharedClock};
+use crate::links::{CarbonData, Controller};
+
+pub use action::{
+    ActionClient, ActionServer, ActionTask, GoalContext, GoalHandle, GoalOutcome, GoalStatus,
+};
+pub use service::{CallError, ServiceClient, ServiceServer};
+
+/// How many messages a queue holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum History {
+    KeepLast(usize),
+    KeepAll,
+}
+
+/// What happens when a subscriber's queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reliability {
+    /// A reliable publisher waits for room, up to its timeout.
+    Reliable,
+    /// The oldest message is dropped.
+    BestEffort,
+}
+
+/// Whether messages outlive their publication.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    Volatile,
+    /// Publishers keep their history and replay it to subscribers joining
+    /// later that ask for it, e.g. for maps and descriptions.
+    TransientLocal,
+}
+
+/// Quality of service of a publisher or subscriber. The default keeps the
+/// last 10 messages, best effort, so one stalled subscriber never holds up a
+/// publisher; ask for [`QoS::reliable`] where every message matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QoS {
+    pub history: History,
+    pub reliability: Reliability,
+    pub durability: Durability,
+}
+
+impl Default for QoS {
+    fn default() -> Self {
+        Self {
+            history: History::KeepLast(10),
+            reliability: Reliability::BestEffort,
+            durability: Durability::Volatile,
+        }
+    }
+}
+
+impl QoS {
+    /// Only the freshest readings matter: a short queue, never waiting.
+    pub fn sensor_data() -> Self {
+        Self {
+            history: History::KeepLast(5),
+            reliability: Reliability::BestEffort,
+            durability: Durability::Volatile,
+        }
+    }
+
+    /// The last message is kept for subscribers joining later.
+    pub fn latched() -> Self {
+        Self {
+            history: History::KeepLast(1),
+            reliability: Reliability::Reliable,
+            durability: Durability::TransientLocal,
+        }
+    }
+
+    pub fn keep_last(mut self, depth: usize) -> Self {
+        self.history = History::KeepLast(depth);
+        self
+    }
+
+    /// Publishing waits, up to the publisher's timeout, for a full
+    /// subscriber that is also reliable to make room.
+    pub fn reliable(mut self) -> Self {
+        self.reliability = Reliability::Reliable;
+        self
+    }
+
+    fn depth(&self) -> usize {
+        match self.history {
+            History::KeepLast(depth) => depth.max(1),
+            History::KeepAll => usize::MAX,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusError {
+    /// The topic, service or action exists with other message types.
+    TypeMismatch {
+        name: String,
+        existing: &'static str,
+        requested: &'static str,
+    },
+    /// Another server already answers this service or action.
+    AlreadyAdvertised(String),
+    /// Reliable subscribers whose queues stayed full; the message was not
+    /// delivered to them.
+    Timeout { topic: String, undelivered: usize },
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusError::TypeMismatch {
+                name,
+                existing,
+                requested,
+            } => write!(f, "{name:?} carries {existing}, not {requested}"),
+            BusError::AlreadyAdvertised(name) => write!(f, "{name:?} already has a server"),
+            BusError::Timeout { topic, undelivered } => write!(
+                f,
+                "{undelivered} subscribers of {topic:?} did not make room in time"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+type Message<T> = Arc<CarbonData<T>>;
+
+struct QueueState<T> {
+    messages: VecDeque<Message<T>>,
+    dropped: u64,
+}
+
+struct Queue<T> {
+    qos: QoS,
+    state: Mutex<QueueState<T>>,
+    // Signalled when a message arrives or leaves.
+    changed: Condvar,
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state.lock().expect("Queue lock poisoned")
+    }
+
+    fn push_dropping_oldest(&self, state: &mut QueueState<T>, message: Message<T>) {
+        if state.messages.len() >= self.qos.depth() {
+            state.messages.pop_front();
+            state.dropped += 1;
+        }
+        state.messages.push_back(message);
+        self.changed.notify_all();
+    }
+}
+
+struct Topic<T> {
+    name: String,
+    subscribers: Mutex<Vec<Weak<Queue<T>>>>,
+    // History kept for transient-local subscribers, as deep as the deepest
+    // transient-local publisher asked for.
+    latched: Mutex<(usize, VecDeque<Message<T>>)>,
+    publishers: Mutex<usize>,
+}
+
+// Type-erased view of a topic for the registry.
+trait AnyTopic: Send + Sync {
+    fn as_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+    fn type_name(&self) -> &'static str;
+    fn info(&self) -> TopicInfo;
+}
+
+impl<T: Send + Sync + 'static> AnyTopic for Topic<T> {
+    fn as_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    fn info(&self) -> TopicInfo {
+        let subscribers = self
+            .subscribers
+            .lock()
+            .expect("Subscribers lock poisoned")
+            .iter()
+            .filter(|queue| queue.strong_count() > 0)
+            .count();
+        TopicInfo {
+            name: self.name.clone(),
+            type_name: type_name::<T>(),
+            publishers: *self.publishers.lock().expect("Publishers lock poisoned"),
+            subscribers,
+        }
+    }
+}
+
+/// A topic and who uses it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopicInfo {
+    pub name: String,
+    pub type_name: &'static str,
+    pub publishers: usize,
+    pub subscribers: usize,
+}
+
+/// In-process publish/subscribe of [`CarbonData`] on named, typed topics,
+/// so several tasks can listen to the same stream. Messages are shared, not
+/// copied, between subscribers. The bus also carries request/response
+/// services and goal-based actions; see [`service`] and [`action`]. Cloning
+/// the bus shares its topics and endpoints.
+#[derive(Clone)]
+pub struct Bus {
+    topics: Arc<Mutex<HashMap<String, Arc<dyn AnyTopic>>>>,
+    services: Endpoints,
+    actions: Endpoints,
+    clock: SharedClock,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self {
+            topics: Arc::default(),
+            services: Endpoints::default(),
+            actions: Endpoints::default(),
+            clock: default_clock(),
+        }
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clock publish and receive timeouts are measured on, handed to the
+    /// publishers, subscribers and clients created afterwards.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn publisher<T: Send + Sync + 'static>(
+        &self,
+        topic: &str,
+        qos: QoS,
+    ) -> Result<Publisher<T>, BusError> {
+        let topic = self.topic::<T>(topic)?;
+        *topic.publishers.lock().expect("Publishers lock poisoned") += 1;
+        if qos.durability == Durability::TransientLocal {
+            let mut latched = topic.latched.lock().expect("Latched lock poisoned");
+            latched.0 = latched.0.max(qos.depth());
+        }
+        Ok(Publisher {
+            topic,
+            qos,
+            timeout: Duration::from_millis(100),
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// A transient-local subscriber first receives what transient-local
+    /// publishers kept, oldest first.
+    pub fn subscribe<T: Send + Sync + 'static>(
+        &self,
+        topic: &str,
+        qos: QoS,
+    ) -> Result<Subscriber<T>, BusError> {
+        let topic = self.topic::<T>(topic)?;
+        let queue = Arc::new(Queue {
+            qos,
+            state: Mutex::new(QueueState {
+                messages: VecDeque::new(),
+                dropped: 0,
+            }),
+            changed: Condvar::new(),
+        });
+        if qos.durability == Durability::TransientLocal {
+            let latched = topic.latched.lock().expect("Latched lock poisoned");
+            let mut state = queue.lock();
+            for message in &latched.1 {
+                queue.push_dropping_oldest(&mut state, message.clone());
+            }
+        }
+        topic
+            .subscribers
+            .lock()
+            .expect("Subscribers lock poisoned")
+            .push(Arc::downgrade(&queue));
+        Ok(Subscriber {
+            topic,
+            queue,
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// Every topic by name.
+    pub fn topics(&self) -> Vec<TopicInfo> {
+        let mut topics: Vec<TopicInfo> = self
+            .topics
+            .lock()
+            .expect("Topics lock poisoned")
+            .values()
+            .map(|topic| topic.info())
+            .collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+        topics
+    }
+
+    fn topic<T: Send + Sync + 'static>(&self, name: &str) -> Result<Arc<Topic<T>>, BusError> {
+        let mut topics = self.topics.lock().expect("Topics lock poisoned");
+        let topic = topics.entry(name.to_string()).or_insert_with(|| {
+            Arc::new(Topic::<T> {
+                name: name.to_string(),
+                subscribers: Mutex::new(Vec::new()),
+                latched: Mutex::new((0, VecDeque::new())),
+                publishers: Mutex::new(0),
+            })
+        });
+        let existing = topic.type_name();
+        topic
+            .clone()
+            .as_any()
+            .downcast::<Topic<T>>()
+            .map_err(|_| BusError::TypeMismatch {
+                name: name.to_string(),
+                existing,
+                requested: type_name::<T>(),
+            })
+    }
+}
+
+// Services or actions by name, each with the type name of its messages.
+type Endpoints = Arc<Mutex<HashMap<String, (&'static str, Arc<dyn Any + Send + Sync>)>>>;
+
+// The endpoint `name`, created if missing, checked to carry `requested`.
+fn endpoint<E: Default + Send + Sync + 'static>(
+    endpoints: &Endpoints,
+    name: &str,
+    requested: &'static str,
+) -> Result<Arc<E>, BusError> {
+    let mut endpoints = endpoints.lock().expect("Endpoints lock poisoned");
+    let (existing, endpoint) = endpoints
+        .entry(name.to_string())
+        .or_insert_with(|| (requested, Arc::new(E::default())));
+    endpoint
+        .clone()
+        .downcast::<E>()
+        .map_err(|_| BusError::TypeMismatch {
+            name: name.to_string(),
+            existing,
+            requested,
+        })
+}
+
+// A server's queue and a token that lives as long as the server does.
+type Registration<M> = Option<(Sender<M>, Weak<()>)>;
+
+// Where clients of a service or action find its server, if one is up.
+struct Endpoint<M> {
+    server: Mutex<Registration<M>>,
+}
+
+impl<M> Default for Endpoint<M> {
+    fn default() -> Self {
+        Self {
+            server: Mutex::new(None),
+        }
+    }
+}
+
+impl<M> Endpoint<M> {
+    fn sender(&self) -> Option<Sender<M>> {
+        let server = self.server.lock().expect("Endpoint lock poisoned");
+        let (sender, alive) = server.as_ref()?;
+        (alive.strong_count() > 0).then(|| sender.clone())
+    }
+
+    // Opens the queue for a new server, whose token is returned with it.
+    fn advertise(&self, name: &str) -> Result<(Receiver<M>, Arc<()>), BusError> {
+        let mut server = self.server.lock().expect("Endpoint lock poisoned");
+        if server
+            .as_ref()
+            .is_some_and(|(_, alive)| alive.strong_count() > 0)
+        {
+            return Err(BusError::AlreadyAdvertised(name.to_string()));
+        }
+        let (sender, receiver) = mpsc::channel();
+        let alive = Arc::new(());
+        *server = Some((sender, Arc::downgrade(&alive)));
+        Ok((receiver, alive))
+    }
+}
+
+// How long spawned servers wait for work before checking whether to stop.
+const SPAWNED_POLL: Duration = Duration::from_millis(10);
+
+/// A service or action server answering on its own thread until dropped.
+pub struct Spawned {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spawned {
+    // Calls `serve` with the stop flag until it is set; `serve` should return
+    // within `SPAWNED_POLL` when idle, and soon after the flag is set.
+    fn new(mut serve: impl FnMut(&AtomicBool) + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            while !stopped.load(Ordering::Acquire) {
+                serve(&stopped);
+            }
+        });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Spawned {
+    /// Waits for the call in progress to finish. A goal in progress is
+    /// cancelled, and waited for as it stops.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Publisher<T> {
+    topic: Arc<Topic<T>>,
+    qos: QoS,
+    /// Longest wait for a full reliable subscriber, on the bus's clock.
+    pub timeout: Duration,
+    clock: SharedClock,
+}
+
+impl<T> Publisher<T> {
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic.name
+    }
+
+    /// Delivers `data` to every subscriber and returns how many received it.
+    /// When both sides are reliable it waits for room in the subscriber's
+    /// queue, failing with [`BusError::Timeout`] for those that stay full.
+    pub fn publish(&self, data: CarbonData<T>) -> Result<usize, BusError> {
+        let message = Arc::new(data);
+        if self.qos.durability == Durability::TransientLocal {
+            let mut latched = self.topic.latched.lock().expect("Latched lock poisoned");
+            if latched.1.len() >= latched.0.max(1) {
+                latched.1.pop_front();
+            }
+            latched.1.push_back(message.clone());
+        }
+        let queues: Vec<Arc<Queue<T>>> = {
+            let mut subscribers = self
+                .topic
+                .subscribers
+                .lock()
+                .expect("Subscribers lock poisoned");
+            subscribers.retain(|queue| queue.strong_count() > 0);
+            subscribers.iter().filter_map(Weak::upgrade).collect()
+        };
+        let deadline = Deadline::after(&self.clock, Some(self.timeout));
+        let (mut delivered, mut undelivered) = (0, 0);
+        for queue in queues {
+            let mut state = queue.lock();
+            let wait = self.qos.reliability == Reliability::Reliable
+                && queue.qos.reliability == Reliability::Reliable;
+            if wait {
+                while state.messages.len() >= queue.qos.depth() && !deadline.expired() {
+                    state = queue
+                        .changed
+                        .wait_timeout(state, SPAWNED_POLL.min(self.timeout))
+                        .expect("Queue lock poisoned")
+                        .0;
+                }
+                if state.messages.len() >= queue.qos.depth() {
+                    state.dropped += 1;
+                    undelivered += 1;
+                    continue;
+                }
+            }
+            queue.push_dropping_oldest(&mut state, message.clone());
+            delivered += 1;
+        }
+        if undelivered > 0 {
+            return Err(BusError::Timeout {
+                topic: self.topic.name.clone(),
+                undelivered,
+            });
+        }
+        Ok(delivered)
+    }
+}
+
+impl<T> Drop for Publisher<T> {
+    fn drop(&mut self) {
+        *self
+            .topic
+            .publishers
+            .lock()
+            .expect("Publishers lock poisoned") -= 1;
+    }
+}
+
+/// Receives a topic's messages in publication order. Dropping it
+/// unsubscribes.
+pub struct Subscriber<T> {
+    topic: Arc<Topic<T>>,
+    queue: Arc<Queue<T>>,
+    clock: SharedClock,
+}
+
+impl<T> Subscriber<T> {
+    pub fn topic(&self) -> &str {
+        &self.topic.name
+    }
+
+    pub fn try_recv(&self) -> Option<Message<T>> {
+        let message = self.queue.lock().messages.pop_front();
+        if message.is_some() {
+            self.queue.changed.notify_all();
+        }
+        message
+    }
+
+    /// Waits up to `timeout` on the bus's clock for a message.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Message<T>> {
+        let deadline = Deadline::after(&self.clock, Some(timeout));
+        let mut state = self.queue.lock();
+        while state.messages.is_empty() {
+            if deadline.expired() {
+                return None;
+            }
+            state = self
+                .queue
+                .changed
+                .wait_timeout(state, SPAWNED_POLL.min(timeout))
+                .expect("Queue lock poisoned")
+                .0;
+        }
+        let message = state.messages.pop_front();
+        self.queue.changed.notify_all();
+        message
+    }
+
+    /// Every queued message, oldest first.
+    pub fn drain(&self) -> Vec<Message<T>> {
+        let messages: Vec<_> = self.queue.lock().messages.drain(..).collect();
+        self.queue.changed.notify_all();
+        messages
+    }
+
+    /// The newest queued message, discarding the rest.
+    pub fn latest(&self) -> Option<Message<T>> {
+        self.drain().pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Messages lost to a full queue.
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+/// Runs `task` on every message waiting on `input` and publishes the results
+/// to `output`. Returns how many were processed. A failed publish stops the
+/// pump, leaving the messages after it queued for the next call. The result
+/// that failed is not retried, since subscribers with room already have it;
+/// those without count it in [`Subscriber::dropped`].
+pub fn pump<I: Clone, O, C: Controller<I, O>>(
+    task: &C,
+    input: &Subscriber<I>,
+    output: &Publisher<O>,
+) -> Result<usize, BusError> {
+    let mut count = 0;
+    // Only what is waiting now, so a busy publisher cannot keep this going.
+    for _ in 0..input.len() {
+        let Some(message) = input.try_recv() else {
+            break;
+        };
+        output.publish(task.process(Arc::unwrap_or_clone(message)))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::links::{CarbonMetadata, CarbonTaskConfiguration, Task};
+
+    fn message(value: i32) -> CarbonData<i32> {
+        CarbonData::new(value, CarbonMetadata::default())
+    }
+
+    fn values(messages: Vec<Message<i32>>) -> Vec<i32> {
+        messages.iter().map(|message| *message.data()).collect()
+    }
+
+    struct Double;
+
+    impl Task for Double {
+        type Input = CarbonData<i32>;
+        type Output = CarbonData<i32>;
+
+        fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+        fn process(&self, input: Self::Input) -> Self::Output {
+            input.map(|value| value * 2)
+        }
+    }
+
+    impl Controller<i32, i32> for Double {}
+
+    #[test]
+    fn keep_last_drops_the_oldest() {
+        let bus = Bus::new();
+        let publisher = bus.publisher::<i32>("count", QoS::default()).unwrap();
+        let subscriber = bus
+            .subscribe::<i32>("count", QoS::default().keep_last(3))
+            .unwrap();
+        for value in 0..5 {
+            assert_eq!(publisher.publish(message(value)).unwrap(), 1);
+        }
+        assert_eq!(values(subscriber.drain()), [2, 3, 4]);
+        assert_eq!(subscriber.dropped(), 2);
+    }
+
+    #[test]
+    fn the_default_never_waits_for_a_stalled_subscriber() {
+        let bus = Bus::new();
+        let publisher = bus
+            .publisher::<i32>("count", QoS::default())
+            .unwrap()
+            .with_timeout(Duration::from_secs(10));
+        let _stalled = bus
+            .subscribe::<i32>("count", QoS::default().keep_last(1))
+            .unwrap();
+        let start = Instant::now();
+        for value in 0..3 {
+            publisher.publish(message(value)).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reliable_publishers_time_out_on_full_queues() {
+        let bus = Bus::new();
+        let publisher = bus
+            .publisher::<i32>("count", QoS::default().reliable())
+            .unwrap()
+            .with_timeout(Duration::from_millis(20));
+        let subscriber = bus
+            .subscribe::<i32>("count", QoS::default().keep_last(1).reliable())
+            .unwrap();
+        publisher.publish(message(1)).unwrap();
+        assert_eq!(
+            publisher.publish(message(2)),
+            Err(BusError::Timeout {
+                topic: "count".to_string(),
+                undelivered: 1,
+            })
+        );
+        assert_eq!(values(subscriber.drain()), [1]);
+    }
+
+    #[test]
+    fn timeouts_are_measured_on_the_bus_clock() {
+        let clock = Arc::new(SimulatedClock::new());
+        let bus = Bus::new().with_clock(clock.clone());
+        let publisher = bus
+            .publisher::<i32>("count", QoS::default().reliable())
+            .unwrap()
+            .with_timeout(Duration::from_secs(1));
+        let subscriber = bus
+            .subscribe::<i32>("count", QoS::default().keep_last(1).reliable())
+            .unwrap();
+        publisher.publish(message(1)).unwrap();
+
+        // A second of simulated time passes only once the stepper runs.
+        let stepper = thread::spawn({
+            let clock = clock.clone();
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                clock.step(Duration::from_secs(1));
+            }
+        });
+        let start = Instant::now();
+        assert!(matches!(
+            publisher.publish(message(2)),
+            Err(BusError::Timeout { undelivered: 1, .. })
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        stepper.join().unwrap();
+
+        assert_eq!(values(subscriber.drain()), [1]);
+        let stepper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            clock.step(Duration::from_secs(1));
+        });
+        let start = Instant::now();
+        assert!(subscriber.recv_timeout(Duration::from_secs(1)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        stepper.join().unwrap();
+    }
+
+    #[test]
+    fn latched_messages_reach_late_subscribers() {
+        let bus = Bus::new();
+        let publisher = bus.publisher::<i32>("map", QoS::latched()).unwrap();
+        publisher.publish(message(1)).unwrap();
+        publisher.publish(message(2)).unwrap();
+        let late = bus.subscribe::<i32>("map", QoS::latched()).unwrap();
+        assert_eq!(values(late.drain()), [2]);
+    }
+
+    #[test]
+    fn topics_keep_their_type() {
+        let bus = Bus::new();
+        let _publisher = bus.publisher::<i32>("count", QoS::default()).unwrap();
+        assert!(matches!(
+            bus.subscribe::<f32>("count", QoS::default()),
+            Err(BusError::TypeMismatch { .. })
+        ));
+        assert_eq!(bus.topics()[0].publishers, 1);
+    }
+
+    #[test]
+    fn pump_stops_at_a_failed_publish() {
+        let bus = Bus::new();
+        let input = bus.publisher::<i32>("in", QoS::default()).unwrap();
+        let inbox = bus.subscribe::<i32>("in", QoS::default()).unwrap();
+        let output = bus
+            .publisher::<i32>("out", QoS::default().reliable())
+            .unwrap()
+            .with_timeout(Duration::ZERO);
+        let outbox = bus
+            .subscribe::<i32>("out", QoS::default().keep_last(2).reliable())
+            .unwrap();
+        for value in 1..=4 {
+            input.publish(message(value)).unwrap();
+        }
+        assert!(matches!(
+            pump(&Double, &inbox, &output),
+            Err(BusError::Timeout { .. })
+        ));
+        // The third message failed and is lost; the fourth is still waiting.
+        assert_eq!(values(outbox.drain()), [2, 4]);
+        assert_eq!(outbox.dropped(), 1);
+        assert_eq!(values(inbox.drain()), [4]);
+
+        input.publish(message(5)).unwrap();
+        input.publish(message(6)).unwrap();
+        assert_eq!(pump(&Double, &inbox, &output), Ok(2));
+        assert_eq!(values(outbox.drain()), [10, 12]);
+    }
+}
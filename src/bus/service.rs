@@ -0,0 +1,273 @@
+use std::any::type_name;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{endpoint, Bus, BusError, Endpoint, Spawned, SPAWNED_POLL};
+use crate::clock::{Deadline, SharedClock};
+use crate::links::{CarbonData, Task};
+
+/// Why a service call or action goal got no answer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// Nothing is serving the name.
+    Unavailable(String),
+    /// No answer in time. The server may still act on the request.
+    Timeout(String),
+    /// The server went away without answering.
+    Dropped(String),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Unavailable(name) => write!(f, "nothing serves {name:?}"),
+            CallError::Timeout(name) => write!(f, "{name:?} did not answer in time"),
+            CallError::Dropped(name) => write!(f, "{name:?} went away without answering"),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+struct Call<Req, Resp> {
+    request: CarbonData<Req>,
+    reply: Sender<CarbonData<Resp>>,
+}
+
+impl Bus {
+    /// Serves `name` by running `task` on each request, e.g. "save map" or
+    /// "set parameter". Only one server may answer a name at a time.
+    pub fn advertise_service<Req, Resp, T>(
+        &self,
+        name: &str,
+        task: T,
+    ) -> Result<ServiceServer<Req, Resp, T>, BusError>
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        T: Task<Input = CarbonData<Req>, Output = CarbonData<Resp>>,
+    {
+        let endpoint = endpoint::<Endpoint<Call<Req, Resp>>>(
+            &self.services,
+            name,
+            type_name::<(Req, Resp)>(),
+        )?;
+        let (calls, alive) = endpoint.advertise(name)?;
+        Ok(ServiceServer {
+            name: name.to_string(),
+            task,
+            calls,
+            _alive: alive,
+        })
+    }
+
+    /// A client of `name`, which need not be served yet.
+    pub fn service_client<Req, Resp>(
+        &self,
+        name: &str,
+    ) -> Result<ServiceClient<Req, Resp>, BusError>
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+    {
+        Ok(ServiceClient {
+            name: name.to_string(),
+            endpoint: endpoint(&self.services, name, type_name::<(Req, Resp)>())?,
+            clock: self.clock.clone(),
+        })
+    }
+}
+
+/// Answers calls to a service with a task. Calls queue until served, one at
+/// a time, on whichever thread calls [`ServiceServer::serve_once`]. Dropping
+/// the server withdraws the service.
+pub struct ServiceServer<Req, Resp, T> {
+    name: String,
+    task: T,
+    calls: Receiver<Call<Req, Resp>>,
+    _alive: Arc<()>,
+}
+
+impl<Req, Resp, T> ServiceServer<Req, Resp, T>
+where
+    T: Task<Input = CarbonData<Req>, Output = CarbonData<Resp>>,
+{
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn task(&self) -> &T {
+        &self.task
+    }
+
+    /// Answers the next call, waiting up to `timeout` for one. Returns
+    /// whether a call was answered.
+    pub fn serve_once(&self, timeout: Duration) -> bool {
+        match self.calls.recv_timeout(timeout) {
+            Ok(call) => {
+                // The caller may have given up already.
+                let _ = call.reply.send(self.task.process(call.request));
+                true
+            }
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => false,
+        }
+    }
+
+    /// Answers every call already waiting and returns how many there were.
+    pub fn serve_pending(&self) -> usize {
+        let mut count = 0;
+        while self.serve_once(Duration::ZERO) {
+            count += 1;
+        }
+        count
+    }
+
+    /// Serves on a thread of its own until the returned handle is dropped.
+    pub fn spawn(self) -> Spawned
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        T: Send + 'static,
+    {
+        Spawned::new(move |_| {
+            self.serve_once(SPAWNED_POLL);
+        })
+    }
+}
+
+/// Calls a service and waits for the answer.
+pub struct ServiceClient<Req, Resp> {
+    name: String,
+    endpoint: Arc<Endpoint<Call<Req, Resp>>>,
+    clock: SharedClock,
+}
+
+impl<Req, Resp> ServiceClient<Req, Resp> {
+    /// Clock call timeouts are measured on.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.endpoint.sender().is_some()
+    }
+
+    /// Sends `request` and waits up to `timeout` on the client's clock for the
+    /// response. Calling from the thread that serves the service times out.
+    pub fn call(
+        &self,
+        request: CarbonData<Req>,
+        timeout: Duration,
+    ) -> Result<CarbonData<Resp>, CallError> {
+        let sender = self
+            .endpoint
+            .sender()
+            .ok_or_else(|| CallError::Unavailable(self.name.clone()))?;
+        let deadline = Deadline::after(&self.clock, Some(timeout));
+        let (reply, response) = mpsc::channel();
+        sender
+            .send(Call { request, reply })
+            .map_err(|_| CallError::Unavailable(self.name.clone()))?;
+        loop {
+            match response.recv_timeout(SPAWNED_POLL.min(timeout)) {
+                Ok(response) => return Ok(response),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(CallError::Dropped(self.name.clone()))
+                }
+                Err(RecvTimeoutError::Timeout) if deadline.expired() => {
+                    return Err(CallError::Timeout(self.name.clone()))
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::links::{CarbonMetadata, CarbonTaskConfiguration};
+
+    struct Double;
+
+    impl Task for Double {
+        type Input = CarbonData<i32>;
+        type Output = CarbonData<i32>;
+
+        fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+        fn process(&self, input: Self::Input) -> Self::Output {
+            input.map(|value| value * 2)
+        }
+    }
+
+    fn request(value: i32) -> CarbonData<i32> {
+        CarbonData::new(value, CarbonMetadata::default())
+    }
+
+    #[test]
+    fn spawned_servers_answer_calls() {
+        let bus = Bus::default();
+        let client = bus.service_client::<i32, i32>("double").unwrap();
+        assert_eq!(
+            client.call(request(1), Duration::ZERO).unwrap_err(),
+            CallError::Unavailable("double".to_string())
+        );
+        let server = bus.advertise_service("double", Double).unwrap().spawn();
+        let response = client.call(request(21), Duration::from_secs(5)).unwrap();
+        assert_eq!(*response.data(), 42);
+        drop(server);
+        assert!(!client.is_available());
+    }
+
+    #[test]
+    fn calls_time_out_on_the_client_clock() {
+        let bus = Bus::default();
+        let _server = bus.advertise_service("double", Double).unwrap();
+        let clock = Arc::new(SimulatedClock::new());
+        let client = bus
+            .service_client::<i32, i32>("double")
+            .unwrap()
+            .with_clock(clock.clone());
+        let stepped = Arc::new(AtomicBool::new(false));
+        let stepper = {
+            let (clock, stepped) = (clock.clone(), stepped.clone());
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                stepped.store(true, Ordering::Release);
+                clock.step(Duration::from_secs(1));
+            })
+        };
+        assert_eq!(
+            client.call(request(1), Duration::from_secs(1)).unwrap_err(),
+            CallError::Timeout("double".to_string())
+        );
+        assert!(stepped.load(Ordering::Acquire));
+        stepper.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_servers_fail_pending_calls() {
+        let bus = Bus::default();
+        let server = bus.advertise_service("double", Double).unwrap();
+        let client = bus.service_client::<i32, i32>("double").unwrap();
+        let caller = thread::spawn(move || client.call(request(1), Duration::from_secs(5)));
+        thread::sleep(Duration::from_millis(50));
+        drop(server);
+        assert_eq!(
+            caller.join().unwrap().unwrap_err(),
+            CallError::Dropped("double".to_string())
+        );
+    }
+}
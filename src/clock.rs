@@ -0,0 +1,301 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A source of time, in nanoseconds since an arbitrary epoch, as carried by
+/// [`crate::links::CarbonMetadata`]. Tasks, filters and timers read time only
+/// through a clock, so simulations and replays run deterministically.
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> u64;
+
+    /// Blocks until the clock reads `deadline`. Clocks that do not follow
+    /// real time are polled, so another thread must advance them.
+    fn sleep_until(&self, deadline: u64) {
+        while self.now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real monotonic clock; what components use unless given another.
+pub fn default_clock() -> SharedClock {
+    Arc::new(MonotonicClock)
+}
+
+/// Seconds between two clock readings, zero if `later` is not later.
+pub fn seconds_between(earlier: u64, later: u64) -> f32 {
+    later.saturating_sub(earlier) as f32 * 1e-9
+}
+
+/// Time between the timestamps of successive inputs, for tasks that step
+/// with each one.
+#[derive(Debug, Default)]
+pub struct TimestampDelta {
+    last: Mutex<Option<u64>>,
+}
+
+impl TimestampDelta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seconds since the latest timestamp seen. The first, and any not later
+    /// than the latest, count as `fallback` seconds; a late input does not
+    /// move time backwards for the next one.
+    pub fn step(&self, timestamp: u64, fallback: f32) -> f32 {
+        let mut last = self.last.lock().expect("Timestamp lock poisoned");
+        match *last {
+            Some(latest) if timestamp > latest => {
+                *last = Some(timestamp);
+                seconds_between(latest, timestamp)
+            }
+            Some(_) => fallback,
+            None => {
+                *last = Some(timestamp);
+                fallback
+            }
+        }
+    }
+
+    /// Forgets the previous timestamp.
+    pub fn reset(&self) {
+        *self.last.lock().expect("Timestamp lock poisoned") = None;
+    }
+}
+
+/// `duration` in clock units, saturating.
+pub fn nanoseconds(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128) as u64
+}
+
+#[cfg(unix)]
+fn monotonic_now() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Cannot fail for CLOCK_MONOTONIC with a valid pointer.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    (time.tv_sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(time.tv_nsec as u64)
+}
+
+#[cfg(not(unix))]
+fn monotonic_now() -> u64 {
+    static EPOCH: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+    nanoseconds(EPOCH.get_or_init(Instant::now).elapsed())
+}
+
+/// Real time that never goes backwards. On Unix this is `CLOCK_MONOTONIC`,
+/// so readings, and the timestamps taken from them, compare between
+/// processes on one host. Elsewhere it counts from the first reading in the
+/// process, and only compares within it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> u64 {
+        monotonic_now()
+    }
+
+    fn sleep_until(&self, deadline: u64) {
+        let now = self.now();
+        if deadline > now {
+            thread::sleep(Duration::from_nanos(deadline - now));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SimulatedState {
+    // Simulated time when `anchor` was taken.
+    time: u64,
+    anchor: Instant,
+    scale: f64,
+}
+
+impl SimulatedState {
+    fn now(&self) -> u64 {
+        let running = self.anchor.elapsed().as_secs_f64() * self.scale;
+        self.time + (running * 1e9) as u64
+    }
+}
+
+/// Simulated time, advanced by explicit steps and, once given a scale, by
+/// real time multiplied by it. It starts paused at zero.
+#[derive(Debug)]
+pub struct SimulatedClock {
+    state: Mutex<SimulatedState>,
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(SimulatedState {
+                time: 0,
+                anchor: Instant::now(),
+                scale: 0.0,
+            }),
+        }
+    }
+
+    /// Runs `scale` times faster than real time; zero pauses.
+    pub fn set_scale(&self, scale: f64) {
+        let mut state = self.state.lock().expect("Clock lock poisoned");
+        state.time = state.now();
+        state.anchor = Instant::now();
+        state.scale = scale.max(0.0);
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.state.lock().expect("Clock lock poisoned").scale
+    }
+
+    pub fn step(&self, duration: Duration) {
+        self.state.lock().expect("Clock lock poisoned").time += nanoseconds(duration);
+    }
+
+    /// Jumps to `time`, unless that would go backwards.
+    pub fn advance_to(&self, time: u64) {
+        let mut state = self.state.lock().expect("Clock lock poisoned");
+        state.time = state.now().max(time);
+        state.anchor = Instant::now();
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.state.lock().expect("Clock lock poisoned").now()
+    }
+}
+
+/// Time driven by the timestamps of replayed messages. Timestamps older
+/// than the latest are ignored, so the clock never runs backwards.
+#[derive(Debug, Default)]
+pub struct ReplayClock {
+    time: AtomicU64,
+}
+
+impl ReplayClock {
+    pub fn new(start: u64) -> Self {
+        Self {
+            time: AtomicU64::new(start),
+        }
+    }
+
+    /// Advances to the timestamp of the message being replayed.
+    pub fn advance_to(&self, timestamp: u64) {
+        self.time.fetch_max(timestamp, Ordering::AcqRel);
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> u64 {
+        self.time.load(Ordering::Acquire)
+    }
+}
+
+/// A point in time after which work such as a planner's search is abandoned.
+#[derive(Clone, Debug)]
+pub struct Deadline {
+    clock: SharedClock,
+    at: Option<u64>,
+}
+
+impl Deadline {
+    /// `limit` from now on `clock`; `None` never expires.
+    pub fn after(clock: &SharedClock, limit: Option<Duration>) -> Self {
+        Self {
+            clock: clock.clone(),
+            at: limit.map(|limit| clock.now().saturating_add(nanoseconds(limit))),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        self.at.is_some_and(|at| self.clock.now() >= at)
+    }
+}
+
+/// Paces a loop at a fixed period on a clock.
+#[derive(Clone, Debug)]
+pub struct Rate {
+    clock: SharedClock,
+    period: u64,
+    next: u64,
+}
+
+impl Rate {
+    pub fn new(clock: &SharedClock, period: Duration) -> Self {
+        let period = nanoseconds(period);
+        Self {
+            clock: clock.clone(),
+            period,
+            next: clock.now().saturating_add(period),
+        }
+    }
+
+    /// Waits for the next tick and returns the time. Ticks missed by a slow
+    /// loop are skipped rather than run back to back.
+    pub fn sleep(&mut self) -> u64 {
+        self.clock.sleep_until(self.next);
+        let now = self.clock.now();
+        let ticks = now.saturating_sub(self.next) / self.period.max(1) + 1;
+        self.next = self.next.saturating_add(ticks.saturating_mul(self.period));
+        now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_deltas_fall_back_without_a_step() {
+        let delta = TimestampDelta::new();
+        assert_eq!(delta.step(1_000_000_000, 0.1), 0.1);
+        assert_eq!(delta.step(1_500_000_000, 0.1), 0.5);
+        // Repeated or reordered timestamps give no time to step by.
+        assert_eq!(delta.step(1_500_000_000, 0.1), 0.1);
+        assert_eq!(delta.step(1_200_000_000, 0.1), 0.1);
+        // Measured from the latest timestamp, not the late one.
+        assert!((delta.step(1_600_000_000, 0.5) - 0.1).abs() < 1e-6);
+        delta.reset();
+        assert_eq!(delta.step(2_000_000_000, 0.1), 0.1);
+    }
+
+    #[test]
+    fn simulated_time_moves_only_when_stepped() {
+        let simulated = Arc::new(SimulatedClock::new());
+        let clock: SharedClock = simulated.clone();
+        let deadline = Deadline::after(&clock, Some(Duration::from_millis(10)));
+        assert_eq!(clock.now(), 0);
+        assert!(!deadline.expired());
+        thread::sleep(Duration::from_millis(20));
+        assert!(!deadline.expired());
+        simulated.step(Duration::from_millis(5));
+        assert_eq!(clock.now(), 5_000_000);
+        assert!(!deadline.expired());
+        simulated.step(Duration::from_millis(5));
+        assert!(deadline.expired());
+    }
+
+    #[test]
+    fn monotonic_time_follows_real_time() {
+        let clock = MonotonicClock;
+        let start = clock.now();
+        clock.sleep_until(start + 20_000_000);
+        let elapsed = clock.now() - start;
+        assert!(elapsed >= 20_000_000, "only {elapsed} ns passed");
+    }
+}
@@ -0,0 +1,268 @@
+pub mod pid;
+pub mod trajectory;
+
+use std::sync::Mutex;
+
+use crate::clock::TimestampDelta;
+use crate::drive::EncoderFeedback;
+use crate::joints::JointLimits;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::JointCommand;
+use pid::Pid;
+
+/// What a joint's actuator is told to do, in the units of [`JointCommand`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActuatorCommand {
+    Position(f32),
+    Velocity(f32),
+    Effort(f32),
+}
+
+/// Cascaded control of one joint: an optional position loop producing a
+/// velocity setpoint, then an optional velocity loop producing an effort.
+/// Command fields without a loop to close pass straight to the actuator, and
+/// the rest serve as feedforward for the loop they enter:
+///
+/// - position and velocity loops: position → velocity → effort,
+/// - position loop only: position → velocity, for velocity-driven motors,
+/// - velocity loop only: velocity → effort, with positions passed through,
+/// - no loops: the command is forwarded as is.
+///
+/// Setpoints and outputs are kept within the joint limits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JointController {
+    pub limits: JointLimits,
+    pub position_loop: Option<Pid>,
+    pub velocity_loop: Option<Pid>,
+    // For estimating velocity when the encoder reports only position.
+    last_position: Option<f32>,
+}
+
+impl JointController {
+    pub fn new(limits: JointLimits) -> Self {
+        Self {
+            limits,
+            position_loop: None,
+            velocity_loop: None,
+            last_position: None,
+        }
+    }
+
+    /// Its output, a velocity, is further bounded by the velocity limit.
+    pub fn with_position_loop(mut self, pid: Pid) -> Self {
+        let limit = self.limits.velocity;
+        self.position_loop = Some(narrowed(pid, limit));
+        self
+    }
+
+    /// Its output, an effort, is further bounded by the effort limit.
+    pub fn with_velocity_loop(mut self, pid: Pid) -> Self {
+        let limit = self.limits.effort;
+        self.velocity_loop = Some(narrowed(pid, limit));
+        self
+    }
+
+    pub fn reset(&mut self) {
+        for pid in [&mut self.position_loop, &mut self.velocity_loop]
+            .into_iter()
+            .flatten()
+        {
+            pid.reset();
+        }
+        self.last_position = None;
+    }
+
+    /// The actuator command for `command` after `dt` seconds, or `None` if
+    /// the command is empty or a loop it needs has no feedback.
+    pub fn update(
+        &mut self,
+        command: &JointCommand,
+        feedback: &EncoderFeedback,
+        dt: f32,
+    ) -> Option<ActuatorCommand> {
+        let measured_velocity = feedback.velocity.or_else(|| {
+            let (position, last) = (feedback.position?, self.last_position?);
+            (dt > 0.0).then(|| (position - last) / dt)
+        });
+        if feedback.position.is_some() {
+            self.last_position = feedback.position;
+        }
+
+        let velocity = match (command.position, &mut self.position_loop) {
+            (Some(setpoint), Some(pid)) => {
+                let measured = feedback.position?;
+                let feedforward = command.velocity.unwrap_or(0.0);
+                Some(pid.update(self.limits.clamp(setpoint), measured, feedforward, dt))
+            }
+            (Some(setpoint), None)
+                if self.velocity_loop.is_none() || command.velocity.is_none() =>
+            {
+                return Some(ActuatorCommand::Position(self.limits.clamp(setpoint)));
+            }
+            _ => command.velocity,
+        };
+        let velocity =
+            velocity.map(|velocity| velocity.clamp(-self.limits.velocity, self.limits.velocity));
+
+        let effort = match (velocity, &mut self.velocity_loop) {
+            (Some(setpoint), Some(pid)) => {
+                let feedforward = command.effort.unwrap_or(0.0);
+                pid.update(setpoint, measured_velocity?, feedforward, dt)
+            }
+            (Some(velocity), None) => return Some(ActuatorCommand::Velocity(velocity)),
+            (None, _) => command.effort?,
+        };
+        Some(ActuatorCommand::Effort(
+            effort.clamp(-self.limits.effort, self.limits.effort),
+        ))
+    }
+}
+
+// Keeps the output bounds of `pid` within `±limit`.
+fn narrowed(mut pid: Pid, limit: f32) -> Pid {
+    pid.min_output = pid.min_output.max(-limit);
+    pid.max_output = pid.max_output.min(limit);
+    pid
+}
+
+/// Runs a [`JointController`] on commands paired with encoder readings,
+/// stepping it by the time between their timestamps.
+pub struct JointControlLoop {
+    /// Seconds assumed for the first input, before timestamps give a step.
+    pub period: f32,
+    controller: Mutex<JointController>,
+    last_timestamp: TimestampDelta,
+}
+
+impl JointControlLoop {
+    pub fn new(controller: JointController, period: f32) -> Self {
+        Self {
+            period,
+            controller: Mutex::new(controller),
+            last_timestamp: TimestampDelta::new(),
+        }
+    }
+
+    /// Clears the loop state, e.g. after the joint was disabled.
+    pub fn reset(&self) {
+        self.controller
+            .lock()
+            .expect("Controller lock poisoned")
+            .reset();
+        self.last_timestamp.reset();
+    }
+}
+
+impl Task for JointControlLoop {
+    type Input = CarbonData<(JointCommand, EncoderFeedback)>;
+    type Output = CarbonData<Option<ActuatorCommand>>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let dt = self
+            .last_timestamp
+            .step(input.metadata.timestamp, self.period);
+        let mut controller = self.controller.lock().expect("Controller lock poisoned");
+        input.map(|(command, feedback)| controller.update(&command, &feedback, dt))
+    }
+}
+
+impl Controller<(JointCommand, EncoderFeedback), Option<ActuatorCommand>> for JointControlLoop {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pid::PidGains;
+
+    fn limits() -> JointLimits {
+        JointLimits {
+            lower: -1.0,
+            upper: 1.0,
+            velocity: 0.5,
+            effort: 2.0,
+            ..Default::default()
+        }
+    }
+
+    fn at(position: f32, velocity: f32) -> EncoderFeedback {
+        EncoderFeedback {
+            position: Some(position),
+            velocity: Some(velocity),
+        }
+    }
+
+    #[test]
+    fn cascaded_loops_drive_a_joint_to_its_setpoint_within_limits() {
+        let mut controller = JointController::new(limits())
+            .with_position_loop(Pid::new(PidGains::new(4.0, 0.0, 0.0)))
+            .with_velocity_loop(Pid::new(PidGains::new(5.0, 2.0, 0.0)));
+        let command = JointCommand {
+            position: Some(2.0),
+            velocity: None,
+            effort: None,
+        };
+        // A unit mass with some friction.
+        let (mut position, mut velocity, dt) = (0.0f32, 0.0f32, 0.01);
+        for _ in 0..1000 {
+            let Some(ActuatorCommand::Effort(effort)) =
+                controller.update(&command, &at(position, velocity), dt)
+            else {
+                panic!("expected an effort");
+            };
+            assert!(effort.abs() <= 2.0);
+            velocity += (effort - 0.5 * velocity) * dt;
+            position += velocity * dt;
+            assert!(velocity.abs() < 0.5 + 0.05, "{velocity}");
+        }
+        // The setpoint was clamped to the upper limit.
+        assert!((position - 1.0).abs() < 0.01, "{position}");
+    }
+
+    #[test]
+    fn commands_without_loops_pass_through_clamped() {
+        let mut controller = JointController::new(limits());
+        let position = JointCommand {
+            position: Some(3.0),
+            velocity: None,
+            effort: None,
+        };
+        assert_eq!(
+            controller.update(&position, &EncoderFeedback::default(), 0.01),
+            Some(ActuatorCommand::Position(1.0))
+        );
+        let velocity = JointCommand {
+            position: None,
+            velocity: Some(-2.0),
+            effort: None,
+        };
+        assert_eq!(
+            controller.update(&velocity, &EncoderFeedback::default(), 0.01),
+            Some(ActuatorCommand::Velocity(-0.5))
+        );
+    }
+
+    #[test]
+    fn velocity_is_estimated_from_positions_when_not_reported() {
+        let mut controller = JointController::new(limits())
+            .with_velocity_loop(Pid::new(PidGains::new(1.0, 0.0, 0.0)));
+        let command = JointCommand {
+            position: None,
+            velocity: Some(0.5),
+            effort: None,
+        };
+        let reading = |position| EncoderFeedback {
+            position: Some(position),
+            velocity: None,
+        };
+        // No velocity until there are two positions.
+        assert_eq!(controller.update(&command, &reading(0.0), 0.1), None);
+        // Moving at 0.2 against 0.5 wanted.
+        let Some(ActuatorCommand::Effort(effort)) =
+            controller.update(&command, &reading(0.02), 0.1)
+        else {
+            panic!("expected an effort");
+        };
+        assert!((effort - 0.3).abs() < 1e-5, "{effort}");
+    }
+}
@@ -0,0 +1,172 @@
+/// Proportional, integral and derivative gains.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PidGains {
+    pub proportional: f32,
+    pub integral: f32,
+    pub derivative: f32,
+}
+
+impl PidGains {
+    pub fn new(proportional: f32, integral: f32, derivative: f32) -> Self {
+        Self {
+            proportional,
+            integral,
+            derivative,
+        }
+    }
+}
+
+/// PID controller with feedforward, a low-pass filtered derivative and a
+/// clamped output. The integral stops growing while the output is held at a
+/// limit in the direction of the error, so it does not wind up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pid {
+    pub gains: PidGains,
+    pub min_output: f32,
+    pub max_output: f32,
+    /// Time constant, in seconds, of the filter on the derivative term; zero
+    /// leaves it unfiltered.
+    pub derivative_filter: f32,
+    /// Differentiates the measurement rather than the error, so setpoint
+    /// steps do not kick the output.
+    pub derivative_on_measurement: bool,
+    integral: f32,
+    derivative: f32,
+    previous: Option<f32>,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains) -> Self {
+        Self {
+            gains,
+            min_output: f32::NEG_INFINITY,
+            max_output: f32::INFINITY,
+            derivative_filter: 0.0,
+            derivative_on_measurement: true,
+            integral: 0.0,
+            derivative: 0.0,
+            previous: None,
+        }
+    }
+
+    pub fn with_output_limits(mut self, min_output: f32, max_output: f32) -> Self {
+        self.min_output = min_output;
+        self.max_output = max_output;
+        self
+    }
+
+    pub fn with_derivative_filter(mut self, time_constant: f32) -> Self {
+        self.derivative_filter = time_constant;
+        self
+    }
+
+    pub fn with_derivative_on_error(mut self) -> Self {
+        self.derivative_on_measurement = false;
+        self
+    }
+
+    /// The accumulated integral term, already scaled by its gain.
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.previous = None;
+    }
+
+    /// Output after `dt` seconds, adding `feedforward` before clamping.
+    pub fn update(&mut self, setpoint: f32, measurement: f32, feedforward: f32, dt: f32) -> f32 {
+        let error = setpoint - measurement;
+        let signal = if self.derivative_on_measurement {
+            -measurement
+        } else {
+            error
+        };
+        if let Some(previous) = self.previous.filter(|_| dt > 0.0) {
+            let raw = (signal - previous) / dt;
+            let smoothing = dt / (self.derivative_filter.max(0.0) + dt);
+            self.derivative += smoothing * (raw - self.derivative);
+        }
+        self.previous = Some(signal);
+
+        let fixed =
+            self.gains.proportional * error + self.gains.derivative * self.derivative + feedforward;
+        let integral = (self.integral + self.gains.integral * error * dt.max(0.0))
+            .clamp(self.min_output.min(0.0), self.max_output.max(0.0));
+        let unclamped = fixed + integral;
+        let winding_up = (unclamped > self.max_output && error > 0.0)
+            || (unclamped < self.min_output && error < 0.0);
+        if !winding_up {
+            self.integral = integral;
+        }
+        (fixed + self.integral).clamp(self.min_output, self.max_output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_integral_holds_while_the_output_is_saturated() {
+        let mut pid = Pid::new(PidGains::new(0.1, 1.0, 0.0)).with_output_limits(-1.0, 1.0);
+        // Far from the setpoint, so the output soon saturates.
+        let mut outputs = Vec::new();
+        for _ in 0..500 {
+            outputs.push(pid.update(5.0, 0.0, 0.0, 0.01));
+        }
+        assert_eq!(outputs.last(), Some(&1.0));
+        let held = pid.integral();
+        assert!(held <= 0.5 + 0.05 + 1e-6, "wound up to {held}");
+        for _ in 0..500 {
+            assert_eq!(pid.update(5.0, 0.0, 0.0, 0.01), 1.0);
+            assert_eq!(pid.integral(), held);
+        }
+        // Overshooting the setpoint pulls the output off the limit at once.
+        assert!(pid.update(5.0, 5.5, 0.0, 0.01) < 1.0);
+        assert!(pid.integral() < held);
+    }
+
+    #[test]
+    fn the_integral_stops_at_the_output_limits() {
+        // Pure integral action cannot hold more than the output can use.
+        let mut pid = Pid::new(PidGains::new(0.0, 10.0, 0.0)).with_output_limits(-2.0, 3.0);
+        for _ in 0..100 {
+            pid.update(1.0, 0.0, 0.0, 0.1);
+        }
+        assert!(pid.integral() <= 3.0);
+        for _ in 0..100 {
+            pid.update(-1.0, 0.0, 0.0, 0.1);
+        }
+        assert!(pid.integral() >= -2.0);
+    }
+
+    #[test]
+    fn derivative_on_measurement_does_not_kick_on_a_setpoint_step() {
+        let gains = PidGains::new(1.0, 0.0, 1.0);
+        let mut on_measurement = Pid::new(gains);
+        let mut on_error = Pid::new(gains).with_derivative_on_error();
+        for pid in [&mut on_measurement, &mut on_error] {
+            assert_eq!(pid.update(0.0, 0.0, 0.0, 0.01), 0.0);
+        }
+        // The setpoint jumps by one while the measurement stays put.
+        assert!((on_measurement.update(1.0, 0.0, 0.0, 0.01) - 1.0).abs() < 1e-6);
+        assert!(on_error.update(1.0, 0.0, 0.0, 0.01) > 50.0);
+        // Yet both damp a moving measurement alike.
+        let (a, b) = (
+            on_measurement.update(1.0, 0.1, 0.0, 0.01),
+            on_error.update(1.0, 0.1, 0.0, 0.01),
+        );
+        assert!((a - b).abs() < 1e-3, "{a} against {b}");
+        assert!(a < 0.0, "{a}");
+    }
+
+    #[test]
+    fn feedforward_adds_before_clamping() {
+        let mut pid = Pid::new(PidGains::new(1.0, 0.0, 0.0)).with_output_limits(-1.0, 1.0);
+        assert_eq!(pid.update(0.5, 0.0, 0.25, 0.01), 0.75);
+        assert_eq!(pid.update(0.5, 0.0, 2.0, 0.01), 1.0);
+    }
+}
@@ -0,0 +1,554 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::clock::TimestampDelta;
+use crate::drive::EncoderFeedback;
+use crate::joints::Joint;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::JointCommand;
+use crate::trajectory::{JointTrajectory, TrajectoryPoint};
+
+/// Position errors, in radians or metres, allowed while following a
+/// trajectory and at its end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerances {
+    /// Largest error of any joint while the trajectory runs.
+    pub path: f32,
+    /// Largest error of any joint to count as arrived.
+    pub goal: f32,
+    /// Largest speed of any joint to count as arrived, where the encoders
+    /// report one.
+    pub goal_velocity: f32,
+    /// Seconds after the trajectory ends allowed for settling within the goal
+    /// tolerance.
+    pub goal_time: f32,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            path: 0.1,
+            goal: 0.01,
+            goal_velocity: 0.05,
+            goal_time: 1.0,
+        }
+    }
+}
+
+/// Why a trajectory was rejected or abandoned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrajectoryError {
+    Empty,
+    UnknownJoint(String),
+    /// A joint fell further behind than the path tolerance.
+    PathTolerance {
+        joint: String,
+        error: f32,
+    },
+    /// A joint was still outside the goal tolerance when the goal time ran
+    /// out.
+    GoalTolerance {
+        joint: String,
+        error: f32,
+    },
+}
+
+impl fmt::Display for TrajectoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrajectoryError::Empty => f.write_str("trajectory has no points"),
+            TrajectoryError::UnknownJoint(joint) => write!(f, "unknown joint {joint:?}"),
+            TrajectoryError::PathTolerance { joint, error } => {
+                write!(f, "joint {joint:?} is {error} off the path")
+            }
+            TrajectoryError::GoalTolerance { joint, error } => {
+                write!(f, "joint {joint:?} is {error} off the goal")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TrajectoryError {}
+
+/// Identifies a submitted trajectory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GoalId(pub u64);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GoalStatus {
+    Active,
+    Succeeded,
+    Aborted(TrajectoryError),
+    /// Replaced by a newer goal.
+    Preempted,
+    Canceled,
+}
+
+impl GoalStatus {
+    pub fn is_done(&self) -> bool {
+        *self != GoalStatus::Active
+    }
+}
+
+/// Progress of the current goal after a control tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectoryFeedback {
+    pub goal: GoalId,
+    pub status: GoalStatus,
+    /// Seconds since the goal started.
+    pub time: f32,
+    /// Fraction of the trajectory duration elapsed, from 0 to 1.
+    pub progress: f32,
+    /// The setpoint sent to the trajectory's joints.
+    pub desired: TrajectoryPoint,
+    /// Measured minus desired position of each trajectory joint, where known.
+    pub errors: Vec<Option<f32>>,
+}
+
+struct Goal {
+    id: GoalId,
+    trajectory: JointTrajectory,
+    tolerances: Tolerances,
+    // Index into the controller's joints of each trajectory joint.
+    joints: Vec<usize>,
+    time: f32,
+}
+
+/// Executes joint trajectories one goal at a time: every tick it samples the
+/// trajectory, sends the setpoints through [`Joint::update_joint`] and checks
+/// the measured positions against the tolerances. A new goal preempts the
+/// current one. Finished goals are collected by
+/// [`TrajectoryController::take_results`].
+pub struct TrajectoryController<J: Joint> {
+    joint_names: Vec<String>,
+    joints: Vec<J>,
+    /// Mass or inertia per joint, for feedforward efforts.
+    pub inertias: Vec<f32>,
+    goal: Option<Goal>,
+    next_id: u64,
+    results: Vec<(GoalId, GoalStatus)>,
+}
+
+impl<J: Joint> TrajectoryController<J> {
+    /// Pairs each joint with its name.
+    pub fn new(joints: Vec<(String, J)>) -> Self {
+        let (joint_names, joints) = joints.into_iter().unzip();
+        Self {
+            joint_names,
+            joints,
+            inertias: Vec::new(),
+            goal: None,
+            next_id: 0,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn with_inertias(mut self, inertias: Vec<f32>) -> Self {
+        self.inertias = inertias;
+        self
+    }
+
+    pub fn joint_names(&self) -> &[String] {
+        &self.joint_names
+    }
+
+    pub fn joints(&self) -> &[J] {
+        &self.joints
+    }
+
+    pub fn joints_mut(&mut self) -> &mut [J] {
+        &mut self.joints
+    }
+
+    /// The goal being executed.
+    pub fn active_goal(&self) -> Option<GoalId> {
+        self.goal.as_ref().map(|goal| goal.id)
+    }
+
+    /// Starts `trajectory`, preempting any active goal. Joints it does not
+    /// name keep their last command. An invalid trajectory is rejected and
+    /// the active goal continues.
+    pub fn submit(
+        &mut self,
+        trajectory: JointTrajectory,
+        tolerances: Tolerances,
+    ) -> Result<GoalId, TrajectoryError> {
+        if trajectory.is_empty() {
+            return Err(TrajectoryError::Empty);
+        }
+        let joints = trajectory
+            .joint_names
+            .iter()
+            .map(|name| {
+                self.joint_names
+                    .iter()
+                    .position(|known| known == name)
+                    .ok_or_else(|| TrajectoryError::UnknownJoint(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(previous) = self.goal.take() {
+            self.results.push((previous.id, GoalStatus::Preempted));
+        }
+        let id = GoalId(self.next_id);
+        self.next_id += 1;
+        self.goal = Some(Goal {
+            id,
+            trajectory,
+            tolerances,
+            joints,
+            time: 0.0,
+        });
+        Ok(id)
+    }
+
+    /// Stops the active goal, holding the joints where `feedback` says they
+    /// are.
+    pub fn cancel(&mut self, feedback: &[EncoderFeedback]) {
+        if let Some(goal) = self.goal.take() {
+            self.hold(&goal, feedback);
+            self.results.push((goal.id, GoalStatus::Canceled));
+        }
+    }
+
+    /// Outcomes of goals finished since the last call, oldest first.
+    pub fn take_results(&mut self) -> Vec<(GoalId, GoalStatus)> {
+        std::mem::take(&mut self.results)
+    }
+
+    /// Advances the active goal by `dt` seconds given one reading per joint,
+    /// in the controller's joint order. `None` when idle.
+    pub fn update(&mut self, feedback: &[EncoderFeedback], dt: f32) -> Option<TrajectoryFeedback> {
+        let mut goal = self.goal.take()?;
+        goal.time += dt.max(0.0);
+        let duration = goal.trajectory.duration();
+        let start = goal.trajectory.points[0].time;
+        let desired = goal.trajectory.sample(start + goal.time)?;
+
+        let measured = |index: usize| {
+            feedback
+                .get(goal.joints[index])
+                .copied()
+                .unwrap_or_default()
+        };
+        let errors: Vec<Option<f32>> = (0..goal.joints.len())
+            .map(|index| {
+                measured(index)
+                    .position
+                    .map(|position| position - desired.positions[index])
+            })
+            .collect();
+        let name = |index: usize| goal.trajectory.joint_names[index].clone();
+        let worst = errors
+            .iter()
+            .enumerate()
+            .filter_map(|(index, error)| error.map(|error| (index, error.abs())))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        let status = if goal.time <= duration {
+            match worst {
+                Some((index, error)) if error > goal.tolerances.path => {
+                    GoalStatus::Aborted(TrajectoryError::PathTolerance {
+                        joint: name(index),
+                        error,
+                    })
+                }
+                _ => GoalStatus::Active,
+            }
+        } else {
+            let settled = (0..goal.joints.len()).all(|index| {
+                let reading = measured(index);
+                errors[index].is_some_and(|error| error.abs() <= goal.tolerances.goal)
+                    && reading
+                        .velocity
+                        .is_none_or(|velocity| velocity.abs() <= goal.tolerances.goal_velocity)
+            });
+            if settled {
+                GoalStatus::Succeeded
+            } else if goal.time > duration + goal.tolerances.goal_time {
+                let (index, error) = worst.unwrap_or((0, f32::NAN));
+                GoalStatus::Aborted(TrajectoryError::GoalTolerance {
+                    joint: name(index),
+                    error,
+                })
+            } else {
+                GoalStatus::Active
+            }
+        };
+
+        match status {
+            GoalStatus::Aborted(_) => self.hold(&goal, feedback),
+            _ => {
+                let commands = desired.commands(&self.goal_inertias(&goal));
+                for (&joint, command) in goal.joints.iter().zip(commands) {
+                    self.joints[joint].update_joint(command);
+                }
+            }
+        }
+        let report = TrajectoryFeedback {
+            goal: goal.id,
+            status: status.clone(),
+            time: goal.time,
+            progress: if duration > 0.0 {
+                (goal.time / duration).min(1.0)
+            } else {
+                1.0
+            },
+            desired,
+            errors,
+        };
+        if status.is_done() {
+            self.results.push((goal.id, status));
+        } else {
+            self.goal = Some(goal);
+        }
+        Some(report)
+    }
+
+    // The inertias of the trajectory's joints, in its order.
+    fn goal_inertias(&self, goal: &Goal) -> Vec<f32> {
+        goal.joints
+            .iter()
+            .map(|&joint| self.inertias.get(joint).copied().unwrap_or(0.0))
+            .collect()
+    }
+
+    // Commands the goal's joints to stay at their measured positions.
+    fn hold(&mut self, goal: &Goal, feedback: &[EncoderFeedback]) {
+        for &joint in &goal.joints {
+            let position = feedback.get(joint).and_then(|reading| reading.position);
+            self.joints[joint].update_joint(JointCommand {
+                position,
+                velocity: Some(0.0),
+                effort: None,
+            });
+        }
+    }
+}
+
+/// Runs a [`TrajectoryController`] on joint readings, in the controller's
+/// joint order. Goals are submitted and canceled through the task.
+pub struct TrajectoryExecutor<J: Joint> {
+    /// Step in seconds until two input timestamps give the real one.
+    pub period: f32,
+    controller: Mutex<TrajectoryController<J>>,
+    last_timestamp: TimestampDelta,
+}
+
+impl<J: Joint> TrajectoryExecutor<J> {
+    pub fn new(controller: TrajectoryController<J>, period: f32) -> Self {
+        Self {
+            period,
+            controller: Mutex::new(controller),
+            last_timestamp: TimestampDelta::new(),
+        }
+    }
+
+    pub fn submit(
+        &self,
+        trajectory: JointTrajectory,
+        tolerances: Tolerances,
+    ) -> Result<GoalId, TrajectoryError> {
+        self.controller
+            .lock()
+            .expect("TrajectoryController lock poisoned")
+            .submit(trajectory, tolerances)
+    }
+
+    pub fn cancel(&self, feedback: &[EncoderFeedback]) {
+        self.controller
+            .lock()
+            .expect("TrajectoryController lock poisoned")
+            .cancel(feedback);
+    }
+
+    pub fn take_results(&self) -> Vec<(GoalId, GoalStatus)> {
+        self.controller
+            .lock()
+            .expect("TrajectoryController lock poisoned")
+            .take_results()
+    }
+}
+
+impl<J: Joint> Task for TrajectoryExecutor<J> {
+    type Input = CarbonData<Vec<EncoderFeedback>>;
+    type Output = CarbonData<Option<TrajectoryFeedback>>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let dt = self
+            .last_timestamp
+            .step(input.metadata.timestamp, self.period);
+        let mut controller = self
+            .controller
+            .lock()
+            .expect("TrajectoryController lock poisoned");
+        input.map(|feedback| controller.update(&feedback, dt))
+    }
+}
+
+impl<J: Joint> Controller<Vec<EncoderFeedback>, Option<TrajectoryFeedback>>
+    for TrajectoryExecutor<J>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::pid::{Pid, PidGains};
+    use crate::control::{ActuatorCommand, JointController};
+    use crate::hardware::JointSetpoint;
+    use crate::joints::JointLimits;
+    use crate::trajectory::{Profile, TrajectoryGenerator};
+
+    // Two velocity-driven joints, each closing a position loop on the
+    // controller's setpoints with the trajectory velocity as feedforward.
+    struct Arm {
+        controller: TrajectoryController<JointSetpoint>,
+        loops: Vec<JointController>,
+        positions: Vec<f32>,
+        /// Fraction of the commanded velocity each joint achieves.
+        strength: Vec<f32>,
+    }
+
+    impl Arm {
+        fn new() -> Self {
+            let joints = ["shoulder", "elbow"]
+                .map(|name| (name.to_string(), JointSetpoint::default()))
+                .to_vec();
+            let joint_loop = JointController::new(JointLimits::default())
+                .with_position_loop(Pid::new(PidGains::new(10.0, 0.0, 0.0)));
+            Self {
+                controller: TrajectoryController::new(joints),
+                loops: vec![joint_loop; 2],
+                positions: vec![0.0; 2],
+                strength: vec![1.0; 2],
+            }
+        }
+
+        fn feedback(&self) -> Vec<EncoderFeedback> {
+            self.positions
+                .iter()
+                .map(|&position| EncoderFeedback {
+                    position: Some(position),
+                    velocity: None,
+                })
+                .collect()
+        }
+
+        // One control tick of `dt` seconds, then the joints move.
+        fn tick(&mut self, dt: f32) -> Option<TrajectoryFeedback> {
+            let feedback = self.feedback();
+            let report = self.controller.update(&feedback, dt);
+            let joints = self.controller.joints().iter().zip(&mut self.loops);
+            for (((setpoint, controller), reading), (position, strength)) in joints
+                .zip(&feedback)
+                .zip(self.positions.iter_mut().zip(&self.strength))
+            {
+                match controller.update(&setpoint.get(), reading, dt) {
+                    Some(ActuatorCommand::Velocity(velocity)) => {
+                        *position += strength * velocity * dt
+                    }
+                    Some(ActuatorCommand::Position(target)) => *position = target,
+                    _ => {}
+                }
+            }
+            report
+        }
+    }
+
+    fn trajectory(goal: [f32; 2]) -> JointTrajectory {
+        TrajectoryGenerator::new(Profile::Trapezoidal, 0.01).generate(
+            vec!["shoulder".to_string(), "elbow".to_string()],
+            &[vec![0.0, 0.0], goal.to_vec()],
+            &[JointLimits::default(); 2],
+        )
+    }
+
+    #[test]
+    fn the_controller_tracks_a_sampled_profile() {
+        let mut arm = Arm::new();
+        let trajectory = trajectory([1.0, -0.5]);
+        let duration = trajectory.duration();
+        let id = arm
+            .controller
+            .submit(trajectory, Tolerances::default())
+            .unwrap();
+
+        let mut ticks = 0;
+        let status = loop {
+            let report = arm.tick(0.01).expect("the goal is active");
+            ticks += 1;
+            for error in report.errors.iter().flatten() {
+                assert!(error.abs() < 0.05, "{error} off at {}", report.time);
+            }
+            if report.status.is_done() {
+                break report.status;
+            }
+            assert!(ticks < 1000, "never finished");
+        };
+        assert_eq!(status, GoalStatus::Succeeded);
+        assert!(ticks as f32 * 0.01 <= duration + 0.5);
+        assert!((arm.positions[0] - 1.0).abs() <= 0.01);
+        assert!((arm.positions[1] + 0.5).abs() <= 0.01);
+        assert_eq!(arm.controller.take_results(), [(id, GoalStatus::Succeeded)]);
+        assert_eq!(arm.tick(0.01), None);
+    }
+
+    #[test]
+    fn a_joint_falling_behind_aborts_and_holds() {
+        let mut arm = Arm::new();
+        arm.strength[1] = 0.2;
+        arm.controller
+            .submit(trajectory([1.0, -1.0]), Tolerances::default())
+            .unwrap();
+        let status = loop {
+            let report = arm.tick(0.01).expect("the goal is active");
+            if report.status.is_done() {
+                break report.status;
+            }
+        };
+        let GoalStatus::Aborted(TrajectoryError::PathTolerance { joint, error }) = status else {
+            panic!("{status:?}");
+        };
+        assert_eq!(joint, "elbow");
+        assert!(error > 0.1);
+        // Held where they were.
+        let held = arm.controller.joints()[1].get();
+        assert_eq!(held.velocity, Some(0.0));
+        assert!(held.position.is_some_and(|position| position > -1.0));
+    }
+
+    #[test]
+    fn new_goals_preempt_and_bad_ones_are_rejected() {
+        let mut arm = Arm::new();
+        let first = arm
+            .controller
+            .submit(trajectory([1.0, 0.0]), Tolerances::default())
+            .unwrap();
+        arm.tick(0.01);
+        let mut unknown = trajectory([0.5, 0.5]);
+        unknown.joint_names[1] = "wrist".to_string();
+        assert_eq!(
+            arm.controller.submit(unknown, Tolerances::default()),
+            Err(TrajectoryError::UnknownJoint("wrist".to_string()))
+        );
+        assert_eq!(arm.controller.active_goal(), Some(first));
+
+        let second = arm
+            .controller
+            .submit(trajectory([0.5, 0.5]), Tolerances::default())
+            .unwrap();
+        assert_eq!(
+            arm.controller.take_results(),
+            [(first, GoalStatus::Preempted)]
+        );
+        let feedback = arm.feedback();
+        arm.controller.cancel(&feedback);
+        assert_eq!(
+            arm.controller.take_results(),
+            [(second, GoalStatus::Canceled)]
+        );
+        assert_eq!(arm.controller.active_goal(), None);
+    }
+}
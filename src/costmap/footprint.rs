@@ -0,0 +1,178 @@
+use std::f32::consts::TAU;
+
+use crate::description::{Geometry, PlacedGeometry};
+use crate::primitives::{Pose2D, Vec2, Vec3};
+
+/// The robot's outline in the base frame as a convex polygon.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Footprint {
+    polygon: Vec<Vec2>,
+    inscribed_radius: f32,
+    circumscribed_radius: f32,
+}
+
+// Segments used to approximate round shapes.
+const CIRCLE_SEGMENTS: usize = 16;
+
+impl Footprint {
+    /// Convex outline of `points`, which may be in any order.
+    pub fn from_points(points: &[Vec2]) -> Self {
+        let polygon = convex_hull(points);
+        let inscribed_radius = if polygon.len() >= 3 && polygon_contains(&polygon, Vec2::ZERO) {
+            polygon
+                .iter()
+                .zip(polygon.iter().cycle().skip(1))
+                .map(|(a, b)| distance_to_segment(Vec2::ZERO, *a, *b))
+                .fold(f32::INFINITY, f32::min)
+        } else {
+            0.0
+        };
+        let circumscribed_radius = polygon.iter().map(|p| p.length()).fold(0.0, f32::max);
+        Self {
+            polygon,
+            inscribed_radius,
+            circumscribed_radius,
+        }
+    }
+
+    pub fn circle(radius: f32) -> Self {
+        Self::from_points(&circle_points(Vec2::ZERO, radius))
+    }
+
+    /// Projects a link's collision geometry, placed relative to the base frame,
+    /// onto the ground plane.
+    pub fn from_geometry(geometry: &[PlacedGeometry]) -> Self {
+        let mut points = Vec::new();
+        for placed in geometry {
+            let outline: Vec<Vec3> = match &placed.geometry {
+                Geometry::Cylinder { radius, .. } | Geometry::Sphere { radius } => {
+                    circle_points(Vec2::ZERO, *radius)
+                        .into_iter()
+                        .map(|p| p.extend(0.0))
+                        .collect()
+                }
+                Geometry::Mesh { vertices, .. } => vertices
+                    .iter()
+                    .map(|&(x, y, z)| Vec3::new(x, y, z))
+                    .collect(),
+                other => match other.bounds() {
+                    Some((min, max)) => [
+                        Vec3::new(min.x, min.y, min.z),
+                        Vec3::new(max.x, min.y, min.z),
+                        Vec3::new(max.x, max.y, min.z),
+                        Vec3::new(min.x, max.y, min.z),
+                        Vec3::new(min.x, min.y, max.z),
+                        Vec3::new(max.x, min.y, max.z),
+                        Vec3::new(max.x, max.y, max.z),
+                        Vec3::new(min.x, max.y, max.z),
+                    ]
+                    .to_vec(),
+                    None => Vec::new(),
+                },
+            };
+            points.extend(
+                outline
+                    .into_iter()
+                    .map(|p| placed.transform.transform_point(p).truncate()),
+            );
+        }
+        Self::from_points(&points)
+    }
+
+    /// Vertices in counter-clockwise order.
+    pub fn polygon(&self) -> &[Vec2] {
+        &self.polygon
+    }
+
+    /// Radius of the largest circle about the base origin inside the outline.
+    pub fn inscribed_radius(&self) -> f32 {
+        self.inscribed_radius
+    }
+
+    /// Radius of the smallest circle about the base origin enclosing it.
+    pub fn circumscribed_radius(&self) -> f32 {
+        self.circumscribed_radius
+    }
+
+    /// The outline with the base at `pose`.
+    pub fn transformed(&self, pose: &Pose2D) -> Vec<Vec2> {
+        self.polygon
+            .iter()
+            .map(|p| pose.transform_point(*p))
+            .collect()
+    }
+
+    /// Whether `point`, in the base frame, lies inside the outline.
+    pub fn contains(&self, point: Vec2) -> bool {
+        polygon_contains(&self.polygon, point)
+    }
+
+    /// Distance from `point`, in the base frame, to the outline; zero inside.
+    pub fn distance(&self, point: Vec2) -> f32 {
+        if self.polygon.len() >= 3 && self.contains(point) {
+            return 0.0;
+        }
+        self.polygon
+            .iter()
+            .zip(self.polygon.iter().cycle().skip(1))
+            .map(|(a, b)| distance_to_segment(point, *a, *b))
+            .fold(f32::INFINITY, f32::min)
+    }
+}
+
+fn circle_points(center: Vec2, radius: f32) -> Vec<Vec2> {
+    (0..CIRCLE_SEGMENTS)
+        .map(|i| center + Vec2::from_angle(i as f32 * TAU / CIRCLE_SEGMENTS as f32) * radius)
+        .collect()
+}
+
+// Andrew's monotone chain; counter-clockwise without repeated end point.
+fn convex_hull(points: &[Vec2]) -> Vec<Vec2> {
+    let mut sorted: Vec<Vec2> = points.iter().copied().filter(|p| p.is_finite()).collect();
+    sorted.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+    let mut hull: Vec<Vec2> = Vec::with_capacity(2 * sorted.len());
+    for pass in 0..2 {
+        let start = hull.len();
+        let points: Box<dyn Iterator<Item = &Vec2>> = if pass == 0 {
+            Box::new(sorted.iter())
+        } else {
+            Box::new(sorted.iter().rev())
+        };
+        for &point in points {
+            while hull.len() >= start + 2 {
+                let (a, b) = (hull[hull.len() - 2], hull[hull.len() - 1]);
+                if (b - a).perp_dot(point - a) > 0.0 {
+                    break;
+                }
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// Even-odd point in polygon test.
+pub fn polygon_contains(polygon: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for (a, b) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y) {
+            let crossing = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < crossing {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let edge = b - a;
+    let t = ((point - a).dot(edge) / edge.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+    point.distance(a + edge * t)
+}
@@ -0,0 +1,423 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use super::footprint::{polygon_contains, Footprint};
+use super::{Costmap, CostmapLayer, FREE, INSCRIBED, LETHAL, NO_INFORMATION};
+use crate::lidar::PointCloud;
+use crate::mapping::occupancy_grid::{raytrace, CellState, OccupancyGrid};
+use crate::primitives::{IVec2, Pose2D, Vec2};
+
+/// Copies a prebuilt occupancy map: occupied cells are lethal, free cells free
+/// and unknown cells left without information.
+pub struct StaticLayer {
+    map: OccupancyGrid,
+}
+
+impl StaticLayer {
+    pub fn new(map: OccupancyGrid) -> Self {
+        Self { map }
+    }
+
+    pub fn map(&self) -> &OccupancyGrid {
+        &self.map
+    }
+
+    pub fn set_map(&mut self, map: OccupancyGrid) {
+        self.map = map;
+    }
+}
+
+impl CostmapLayer for StaticLayer {
+    fn name(&self) -> &str {
+        "static"
+    }
+
+    fn update_costs(&mut self, costmap: &mut Costmap, _robot_pose: &Pose2D) {
+        for y in 0..costmap.height() as i32 {
+            for x in 0..costmap.width() as i32 {
+                let cell = IVec2::new(x, y);
+                match self.map.state_at(costmap.cell_to_world(cell)) {
+                    CellState::Occupied => costmap.set_cost(cell, LETHAL),
+                    CellState::Free => costmap.set_cost(cell, FREE),
+                    CellState::Unknown => {}
+                }
+            }
+        }
+    }
+}
+
+/// Obstacles seen by range sensors. Returns within `obstacle_range` mark their
+/// cell; every beam clears the cells it passes through up to `raytrace_range`.
+/// Obstacles are kept in world cells so they survive a rolling window moving,
+/// and dropped once they leave the window or `obstacle_range` of the robot.
+pub struct ObstacleLayer {
+    resolution: f32,
+    obstacles: HashSet<IVec2>,
+    pub obstacle_range: f32,
+    pub raytrace_range: f32,
+}
+
+impl ObstacleLayer {
+    pub fn new(resolution: f32, obstacle_range: f32, raytrace_range: f32) -> Self {
+        Self {
+            resolution,
+            obstacles: HashSet::new(),
+            obstacle_range,
+            raytrace_range,
+        }
+    }
+
+    pub fn obstacles(&self) -> impl Iterator<Item = Vec2> + '_ {
+        self.obstacles
+            .iter()
+            .map(|cell| (cell.as_vec2() + 0.5) * self.resolution)
+    }
+
+    pub fn clear(&mut self) {
+        self.obstacles.clear();
+    }
+
+    fn cell(&self, position: Vec2) -> IVec2 {
+        (position / self.resolution).floor().as_ivec2()
+    }
+}
+
+impl CostmapLayer for ObstacleLayer {
+    fn name(&self) -> &str {
+        "obstacles"
+    }
+
+    // Clears every beam before marking so a return isn't erased by its
+    // neighbours' beams grazing the same cell.
+    fn observe(&mut self, scan: &PointCloud, sensor_pose: &Pose2D) {
+        let start = self.cell(sensor_pose.position());
+        let mut marks = Vec::new();
+        for point in &scan.points {
+            let local = point.position.truncate();
+            let range = local.length();
+            if !range.is_finite() || range <= f32::EPSILON {
+                continue;
+            }
+            let reach = range.min(self.raytrace_range);
+            let end = self.cell(sensor_pose.transform_point(local * (reach / range)));
+            raytrace(start, end, |cell| {
+                if cell != end || reach < range {
+                    self.obstacles.remove(&cell);
+                }
+                true
+            });
+            if range <= self.obstacle_range {
+                marks.push(self.cell(sensor_pose.transform_point(local)));
+            }
+        }
+        self.obstacles.extend(marks);
+    }
+
+    fn update_costs(&mut self, costmap: &mut Costmap, robot_pose: &Pose2D) {
+        let resolution = self.resolution;
+        let range_squared = self.obstacle_range * self.obstacle_range;
+        self.obstacles.retain(|cell| {
+            let position = (cell.as_vec2() + 0.5) * resolution;
+            costmap.contains_cell(costmap.world_to_cell(position))
+                && position.distance_squared(robot_pose.position()) <= range_squared
+        });
+        for cell in &self.obstacles {
+            let position = (cell.as_vec2() + 0.5) * self.resolution;
+            costmap.set_cost(costmap.world_to_cell(position), LETHAL);
+        }
+    }
+}
+
+/// Spreads lethal cells out by the robot's size so planners can treat the
+/// robot as a point: cells within the inscribed radius of an obstacle become
+/// [`INSCRIBED`], and costs decay exponentially beyond it up to
+/// `inflation_radius`.
+pub struct InflationLayer {
+    pub inscribed_radius: f32,
+    pub inflation_radius: f32,
+    /// Rate of the exponential decay, per metre.
+    pub cost_scaling_factor: f32,
+    /// Whether to raise unknown cells too.
+    pub inflate_unknown: bool,
+    kernel: Vec<(IVec2, u8)>,
+    kernel_resolution: f32,
+}
+
+impl InflationLayer {
+    pub fn new(inscribed_radius: f32, inflation_radius: f32, cost_scaling_factor: f32) -> Self {
+        Self {
+            inscribed_radius,
+            inflation_radius: inflation_radius.max(inscribed_radius),
+            cost_scaling_factor,
+            inflate_unknown: false,
+            kernel: Vec::new(),
+            kernel_resolution: 0.0,
+        }
+    }
+
+    pub fn from_footprint(
+        footprint: &Footprint,
+        inflation_radius: f32,
+        cost_scaling_factor: f32,
+    ) -> Self {
+        Self::new(
+            footprint.inscribed_radius(),
+            inflation_radius,
+            cost_scaling_factor,
+        )
+    }
+
+    /// Cost of a cell `distance` metres from the nearest obstacle.
+    pub fn cost(&self, distance: f32) -> u8 {
+        if distance <= 0.0 {
+            LETHAL
+        } else if distance <= self.inscribed_radius {
+            INSCRIBED
+        } else if distance > self.inflation_radius {
+            FREE
+        } else {
+            let decay = (-self.cost_scaling_factor * (distance - self.inscribed_radius)).exp();
+            (f32::from(INSCRIBED - 1) * decay) as u8
+        }
+    }
+
+    fn rebuild_kernel(&mut self, resolution: f32) {
+        let reach = (self.inflation_radius / resolution).ceil() as i32;
+        self.kernel.clear();
+        for y in -reach..=reach {
+            for x in -reach..=reach {
+                let offset = IVec2::new(x, y);
+                let cost = self.cost(offset.as_vec2().length() * resolution);
+                if cost > FREE {
+                    self.kernel.push((offset, cost));
+                }
+            }
+        }
+        self.kernel_resolution = resolution;
+    }
+}
+
+impl CostmapLayer for InflationLayer {
+    fn name(&self) -> &str {
+        "inflation"
+    }
+
+    fn update_costs(&mut self, costmap: &mut Costmap, _robot_pose: &Pose2D) {
+        if self.kernel_resolution != costmap.resolution() {
+            self.rebuild_kernel(costmap.resolution());
+        }
+        let is_lethal = |cell: IVec2| costmap.cost(cell) == Some(LETHAL);
+        // Cells surrounded by other obstacles add nothing their neighbours don't.
+        let sources: Vec<IVec2> = (0..costmap.height() as i32)
+            .flat_map(|y| (0..costmap.width() as i32).map(move |x| IVec2::new(x, y)))
+            .filter(|&cell| {
+                is_lethal(cell)
+                    && [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                        .iter()
+                        .any(|&step| !is_lethal(cell + step))
+            })
+            .collect();
+        for source in sources {
+            for &(offset, cost) in &self.kernel {
+                let cell = source + offset;
+                match costmap.cost(cell) {
+                    Some(NO_INFORMATION) if self.inflate_unknown => costmap.set_cost(cell, cost),
+                    Some(current) if current != NO_INFORMATION && cost > current => {
+                        costmap.set_cost(cell, cost)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Forbidden areas, given as polygons in the costmap frame, painted lethal. The
+/// zone list is shared so it can be edited while the costmap is in use.
+#[derive(Default)]
+pub struct KeepOutLayer {
+    zones: Arc<RwLock<Vec<Vec<Vec2>>>>,
+}
+
+impl KeepOutLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_zone(self, polygon: Vec<Vec2>) -> Self {
+        self.zones
+            .write()
+            .expect("Keep-out zones lock poisoned")
+            .push(polygon);
+        self
+    }
+
+    pub fn zones(&self) -> Arc<RwLock<Vec<Vec<Vec2>>>> {
+        self.zones.clone()
+    }
+}
+
+impl CostmapLayer for KeepOutLayer {
+    fn name(&self) -> &str {
+        "keep_out"
+    }
+
+    fn update_costs(&mut self, costmap: &mut Costmap, _robot_pose: &Pose2D) {
+        let zones = self.zones.read().expect("Keep-out zones lock poisoned");
+        for zone in zones.iter().filter(|zone| zone.len() >= 3) {
+            let (min, max) = zone.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), p| (min.min(*p), max.max(*p)),
+            );
+            let (low, high) = (costmap.world_to_cell(min), costmap.world_to_cell(max));
+            for y in low.y.max(0)..=high.y.min(costmap.height() as i32 - 1) {
+                for x in low.x.max(0)..=high.x.min(costmap.width() as i32 - 1) {
+                    let cell = IVec2::new(x, y);
+                    if polygon_contains(zone, costmap.cell_to_world(cell)) {
+                        costmap.set_cost(cell, LETHAL);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::costmap::LayeredCostmap;
+    use crate::primitives::{Point, Vec3};
+
+    // A 4 m square costmap at 10 cm.
+    fn costmap() -> Costmap {
+        Costmap::new(0.1, 40, 40, Vec2::ZERO)
+    }
+
+    fn scan(points: &[[f32; 2]]) -> PointCloud {
+        PointCloud::new(
+            points
+                .iter()
+                .map(|&[x, y]| Point::new(Vec3::new(x, y, 0.0), 1.0))
+                .collect(),
+        )
+    }
+
+    fn lethal_cells(costmap: &Costmap) -> Vec<IVec2> {
+        (0..costmap.height() as i32)
+            .flat_map(|y| (0..costmap.width() as i32).map(move |x| IVec2::new(x, y)))
+            .filter(|&cell| costmap.cost(cell) == Some(LETHAL))
+            .collect()
+    }
+
+    #[test]
+    fn inflation_decays_away_from_obstacles() {
+        let mut layer = InflationLayer::new(0.2, 0.6, 5.0);
+        let mut costmap = costmap();
+        costmap.set_cost(IVec2::new(20, 20), LETHAL);
+        costmap.set_cost(IVec2::new(20, 28), NO_INFORMATION);
+        layer.update_costs(&mut costmap, &Pose2D::identity());
+
+        let at = |x: i32| costmap.cost(IVec2::new(x, 20)).unwrap();
+        assert_eq!(at(20), LETHAL);
+        assert_eq!(at(21), INSCRIBED);
+        assert_eq!(at(22), INSCRIBED);
+        assert_eq!(at(24), (252.0 * (-1f32).exp()) as u8);
+        assert_eq!(at(27), FREE);
+        assert!((22..=27).all(|x| at(x) >= at(x + 1)));
+        // Symmetric, and unknown cells are left alone by default.
+        assert_eq!(costmap.cost(IVec2::new(16, 20)), Some(at(24)));
+        assert_eq!(costmap.cost(IVec2::new(20, 28)), Some(NO_INFORMATION));
+
+        let decayed = at(24);
+        layer.inflate_unknown = true;
+        costmap.set_cost(IVec2::new(20, 24), NO_INFORMATION);
+        layer.update_costs(&mut costmap, &Pose2D::identity());
+        assert_eq!(costmap.cost(IVec2::new(20, 24)), Some(decayed));
+    }
+
+    #[test]
+    fn returns_mark_obstacles_and_beams_clear_them() {
+        let mut layer = ObstacleLayer::new(0.1, 2.5, 3.0);
+        let sensor = Pose2D::new(0.55, 2.05, 0.0);
+        let robot = Pose2D::new(0.55, 2.05, 0.0);
+
+        layer.observe(&scan(&[[1.5, 0.0]]), &sensor);
+        let mut map = costmap();
+        layer.update_costs(&mut map, &robot);
+        assert_eq!(lethal_cells(&map), [IVec2::new(20, 20)]);
+
+        // The obstacle moved away: the new beam passes through the old cell.
+        layer.observe(&scan(&[[2.4, 0.0]]), &sensor);
+        let mut map = costmap();
+        layer.update_costs(&mut map, &robot);
+        assert_eq!(lethal_cells(&map), [IVec2::new(29, 20)]);
+
+        // Returns beyond the obstacle range only clear.
+        layer.observe(&scan(&[[2.8, 0.0]]), &sensor);
+        let mut map = costmap();
+        layer.update_costs(&mut map, &robot);
+        assert!(lethal_cells(&map).is_empty());
+    }
+
+    #[test]
+    fn obstacles_out_of_the_window_or_range_are_forgotten() {
+        let mut layer = ObstacleLayer::new(0.1, 2.5, 3.0);
+        layer.observe(
+            &scan(&[[1.0, 0.0], [0.0, 1.0]]),
+            &Pose2D::new(1.05, 1.05, 0.0),
+        );
+        assert_eq!(layer.obstacles().count(), 2);
+
+        // The window scrolls past one of them.
+        let mut map = costmap();
+        map.set_origin(Vec2::new(0.0, 1.5));
+        layer.update_costs(&mut map, &Pose2D::new(1.05, 1.05, 0.0));
+        let remaining: Vec<Vec2> = layer.obstacles().collect();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].distance(Vec2::new(1.05, 2.05)) < 1e-5);
+
+        // The robot drives out of marking range of the other.
+        let mut map = costmap();
+        layer.update_costs(&mut map, &Pose2D::new(2.5, 3.0, 0.0));
+        assert_eq!(layer.obstacles().count(), 1);
+        layer.update_costs(&mut map, &Pose2D::new(3.9, 0.1, 0.0));
+        assert_eq!(layer.obstacles().count(), 0);
+    }
+
+    #[test]
+    fn keep_out_zones_are_lethal_and_editable() {
+        let zone = vec![
+            Vec2::new(1.0, 1.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(1.0, 2.0),
+        ];
+        let keep_out = KeepOutLayer::new().with_zone(zone);
+        let zones = keep_out.zones();
+        let mut layered = LayeredCostmap::new(costmap(), Footprint::circle(0.2))
+            .with_layer(keep_out)
+            .with_layer(InflationLayer::new(0.2, 0.5, 5.0));
+        layered.track_unknown = false;
+        layered.update(&Pose2D::identity());
+
+        let costmap = layered.costmap();
+        assert_eq!(lethal_cells(costmap).len(), 100);
+        assert_eq!(costmap.cost_at(Vec2::new(1.5, 1.5)), LETHAL);
+        assert_eq!(costmap.cost_at(Vec2::new(2.15, 1.5)), INSCRIBED);
+        assert_eq!(costmap.cost_at(Vec2::new(3.5, 3.5)), FREE);
+        assert_eq!(costmap.cost_at(Vec2::new(5.0, 1.5)), NO_INFORMATION);
+        assert_eq!(
+            layered.footprint_cost(&Pose2D::new(1.5, 2.15, 0.0)),
+            Some(LETHAL)
+        );
+
+        assert_eq!(
+            layered.footprint_cost(&Pose2D::new(3.0, 3.0, 0.0)),
+            Some(FREE)
+        );
+
+        zones.write().unwrap().clear();
+        layered.update(&Pose2D::identity());
+        assert!(lethal_cells(layered.costmap()).is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+pub mod footprint;
+pub mod layers;
+
+use std::sync::{Arc, RwLock};
+
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Task};
+use crate::mapping::occupancy_grid::raytrace;
+use crate::mapping::PosedScan;
+use crate::primitives::{IVec2, Pose2D, Vec2};
+use footprint::Footprint;
+
+/// Cost of a cell the robot can occupy freely.
+pub const FREE: u8 = 0;
+/// Cost of a cell within the footprint's inscribed radius of an obstacle, so
+/// the robot's centre there means a certain collision.
+pub const INSCRIBED: u8 = 253;
+/// Cost of a cell containing an obstacle.
+pub const LETHAL: u8 = 254;
+/// Cost of a cell nothing is known about.
+pub const NO_INFORMATION: u8 = 255;
+
+/// Axis-aligned 2D grid of traversal costs, laid out like an
+/// [`OccupancyGrid`](crate::mapping::occupancy_grid::OccupancyGrid).
+#[derive(Clone, Debug)]
+pub struct Costmap {
+    resolution: f32,
+    width: usize,
+    height: usize,
+    origin: Vec2,
+    costs: Vec<u8>,
+}
+
+impl Costmap {
+    pub fn new(resolution: f32, width: usize, height: usize, origin: Vec2) -> Self {
+        Self {
+            resolution,
+            width,
+            height,
+            origin,
+            costs: vec![FREE; width * height],
+        }
+    }
+
+    pub fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn origin(&self) -> Vec2 {
+        self.origin
+    }
+
+    /// World position of the upper-right corner.
+    pub fn extent(&self) -> Vec2 {
+        self.origin + Vec2::new(self.width as f32, self.height as f32) * self.resolution
+    }
+
+    /// Moves the window without keeping any costs; layers repaint it on the
+    /// next update.
+    pub fn set_origin(&mut self, origin: Vec2) {
+        self.origin = origin;
+    }
+
+    pub fn world_to_cell(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / self.resolution)
+            .floor()
+            .as_ivec2()
+    }
+
+    /// World position of the cell centre.
+    pub fn cell_to_world(&self, cell: IVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + 0.5) * self.resolution
+    }
+
+    pub fn contains_cell(&self, cell: IVec2) -> bool {
+        cell.x >= 0
+            && cell.y >= 0
+            && (cell.x as usize) < self.width
+            && (cell.y as usize) < self.height
+    }
+
+    pub fn index(&self, cell: IVec2) -> Option<usize> {
+        self.contains_cell(cell)
+            .then(|| cell.y as usize * self.width + cell.x as usize)
+    }
+
+    pub fn cost(&self, cell: IVec2) -> Option<u8> {
+        self.index(cell).map(|index| self.costs[index])
+    }
+
+    /// Cost at a world position; cells outside the map have no information.
+    pub fn cost_at(&self, position: Vec2) -> u8 {
+        self.cost(self.world_to_cell(position))
+            .unwrap_or(NO_INFORMATION)
+    }
+
+    pub fn set_cost(&mut self, cell: IVec2, cost: u8) {
+        if let Some(index) = self.index(cell) {
+            self.costs[index] = cost;
+        }
+    }
+
+    /// Sets the cell to `cost` if that is higher, treating unknown cells as
+    /// the lowest cost.
+    pub fn raise(&mut self, cell: IVec2, cost: u8) {
+        if let Some(index) = self.index(cell) {
+            let current = self.costs[index];
+            if current == NO_INFORMATION || cost > current {
+                self.costs[index] = cost;
+            }
+        }
+    }
+
+    pub fn fill(&mut self, cost: u8) {
+        self.costs.fill(cost);
+    }
+
+    /// Row-major costs, starting at the lower-left cell.
+    pub fn costs(&self) -> &[u8] {
+        &self.costs
+    }
+
+    /// Highest cost of the cells under `polygon`, given in world coordinates,
+    /// or `None` if any of them lies outside the map.
+    pub fn footprint_cost(&self, polygon: &[Vec2]) -> Option<u8> {
+        let cells: Vec<IVec2> = polygon.iter().map(|p| self.world_to_cell(*p)).collect();
+        let (&first, rest) = cells.split_first()?;
+        let (low, high) = rest.iter().fold((first, first), |(low, high), cell| {
+            (low.min(*cell), high.max(*cell))
+        });
+        let mut highest = Some(FREE);
+        for (start, end) in cells.iter().zip(cells.iter().cycle().skip(1)) {
+            raytrace(*start, *end, |cell| {
+                highest = highest.zip(self.cost(cell)).map(|(a, b)| a.max(b));
+                highest.is_some()
+            });
+        }
+        for y in low.y..=high.y {
+            for x in low.x..=high.x {
+                let cell = IVec2::new(x, y);
+                if footprint::polygon_contains(polygon, self.cell_to_world(cell)) {
+                    highest = highest.zip(self.cost(cell)).map(|(a, b)| a.max(b));
+                }
+            }
+        }
+        highest
+    }
+}
+
+/// A source of costs composed into a [`LayeredCostmap`], e.g. a static map,
+/// live obstacles or keep-out zones.
+pub trait CostmapLayer: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Takes in a scan given in the sensor frame, with the sensor at
+    /// `sensor_pose` in the costmap frame.
+    fn observe(&mut self, _scan: &PointCloud, _sensor_pose: &Pose2D) {}
+
+    /// Writes this layer's costs into `costmap`, on top of the layers before it.
+    fn update_costs(&mut self, costmap: &mut Costmap, robot_pose: &Pose2D);
+}
+
+/// A master costmap rebuilt from an ordered stack of layers. Later layers see,
+/// and may overwrite or raise, the costs of earlier ones, so inflation usually
+/// goes last.
+pub struct LayeredCostmap {
+    master: Costmap,
+    layers: Vec<Box<dyn CostmapLayer>>,
+    footprint: Footprint,
+    /// Unknown cells stay at [`NO_INFORMATION`] instead of [`FREE`].
+    pub track_unknown: bool,
+    /// Keeps the window centred on the robot instead of fixed in the world.
+    pub rolling_window: bool,
+}
+
+impl LayeredCostmap {
+    pub fn new(costmap: Costmap, footprint: Footprint) -> Self {
+        Self {
+            master: costmap,
+            layers: Vec::new(),
+            footprint,
+            track_unknown: true,
+            rolling_window: false,
+        }
+    }
+
+    pub fn with_layer(mut self, layer: impl CostmapLayer + 'static) -> Self {
+        self.add_layer(Box::new(layer));
+        self
+    }
+
+    pub fn with_rolling_window(mut self, rolling_window: bool) -> Self {
+        self.rolling_window = rolling_window;
+        self
+    }
+
+    pub fn add_layer(&mut self, layer: Box<dyn CostmapLayer>) {
+        self.layers.push(layer);
+    }
+
+    pub fn layers(&self) -> &[Box<dyn CostmapLayer>] {
+        &self.layers
+    }
+
+    pub fn footprint(&self) -> &Footprint {
+        &self.footprint
+    }
+
+    pub fn costmap(&self) -> &Costmap {
+        &self.master
+    }
+
+    /// Passes a scan to every layer; see [`CostmapLayer::observe`].
+    pub fn observe(&mut self, scan: &PointCloud, sensor_pose: &Pose2D) {
+        for layer in &mut self.layers {
+            layer.observe(scan, sensor_pose);
+        }
+    }
+
+    /// Repaints the master costmap from all layers.
+    pub fn update(&mut self, robot_pose: &Pose2D) {
+        if self.rolling_window {
+            let half_extent = (self.master.extent() - self.master.origin()) / 2.0;
+            let origin = ((robot_pose.position() - half_extent) / self.master.resolution()).round()
+                * self.master.resolution();
+            self.master.set_origin(origin);
+        }
+        self.master.fill(if self.track_unknown {
+            NO_INFORMATION
+        } else {
+            FREE
+        });
+        for layer in &mut self.layers {
+            layer.update_costs(&mut self.master, robot_pose);
+        }
+    }
+
+    /// Highest cost under the footprint with the robot at `pose`.
+    pub fn footprint_cost(&self, pose: &Pose2D) -> Option<u8> {
+        self.master
+            .footprint_cost(&self.footprint.transformed(pose))
+    }
+}
+
+/// Feeds posed scans into a shared layered costmap and repaints it.
+pub struct CostmapUpdater {
+    costmap: Arc<RwLock<LayeredCostmap>>,
+    /// Pose of the sensor in the base frame.
+    sensor_mount: Pose2D,
+}
+
+impl CostmapUpdater {
+    pub fn new(costmap: Arc<RwLock<LayeredCostmap>>, sensor_mount: Pose2D) -> Self {
+        Self {
+            costmap,
+            sensor_mount,
+        }
+    }
+
+    pub fn costmap(&self) -> Arc<RwLock<LayeredCostmap>> {
+        self.costmap.clone()
+    }
+}
+
+impl Task for CostmapUpdater {
+    type Input = CarbonData<PosedScan>;
+    type Output = ();
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let PosedScan { scan, base_pose } = input.into_data();
+        let sensor_pose = base_pose.compose(&self.sensor_mount);
+        let mut costmap = self.costmap.write().expect("LayeredCostmap lock poisoned");
+        costmap.observe(&scan, &sensor_pose);
+        costmap.update(&base_pose);
+    }
+}
@@ -0,0 +1,267 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::primitives::{Transform, Vec3};
+
+// ENUM for now, might replace with individual geometry types implementing a trait later
+//
+// Shapes are centred on the origin of their link frame. Cylinders are aligned with
+// the z axis, boxes span `depth` along x, `width` along y and `height` along z.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Cylinder {
+        radius: f32,
+        height: f32,
+    },
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        height: f32,
+        width: f32,
+        depth: f32,
+    },
+    Plane {
+        width: f32,
+        depth: f32,
+    },
+    Mesh {
+        vertices: Vec<(f32, f32, f32)>,
+        indices: Vec<u32>,
+    },
+}
+
+impl Geometry {
+    /// Whether `point`, given in the geometry's own frame, lies inside the shape
+    /// grown by `padding` in every direction. Meshes are approximated by their
+    /// axis-aligned bounding box.
+    pub fn contains(&self, point: Vec3, padding: f32) -> bool {
+        match self {
+            Self::Cylinder { radius, height } => {
+                point.truncate().length() <= radius + padding
+                    && point.z.abs() <= height / 2.0 + padding
+            }
+            Self::Sphere { radius } => point.length() <= radius + padding,
+            Self::Box {
+                height,
+                width,
+                depth,
+            } => {
+                point.x.abs() <= depth / 2.0 + padding
+                    && point.y.abs() <= width / 2.0 + padding
+                    && point.z.abs() <= height / 2.0 + padding
+            }
+            Self::Plane { width, depth } => {
+                point.x.abs() <= depth / 2.0 + padding
+                    && point.y.abs() <= width / 2.0 + padding
+                    && point.z.abs() <= padding
+            }
+            Self::Mesh { .. } => match self.bounds() {
+                Some((min, max)) => {
+                    point.cmpge(min - padding).all() && point.cmple(max + padding).all()
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Axis-aligned bounding box as `(min, max)` corners.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let half_extents = match self {
+            Self::Cylinder { radius, height } => Vec3::new(*radius, *radius, height / 2.0),
+            Self::Sphere { radius } => Vec3::splat(*radius),
+            Self::Box {
+                height,
+                width,
+                depth,
+            } => Vec3::new(depth / 2.0, width / 2.0, height / 2.0),
+            Self::Plane { width, depth } => Vec3::new(depth / 2.0, width / 2.0, 0.0),
+            Self::Mesh { vertices, .. } => {
+                let mut vertices = vertices.iter().map(|&(x, y, z)| Vec3::new(x, y, z));
+                let first = vertices.next()?;
+                return Some(vertices.fold((first, first), |(min, max), vertex| {
+                    (min.min(vertex), max.max(vertex))
+                }));
+            }
+        };
+        Some((-half_extents, half_extents))
+    }
+
+    /// Radius of the smallest origin-centred sphere enclosing the shape.
+    pub fn bounding_radius(&self) -> f32 {
+        match self {
+            Self::Sphere { radius } => *radius,
+            Self::Mesh { vertices, .. } => vertices
+                .iter()
+                .map(|&(x, y, z)| Vec3::new(x, y, z).length())
+                .fold(0.0, f32::max),
+            _ => self
+                .bounds()
+                .map(|(min, max)| min.abs().max(max.abs()).length())
+                .unwrap_or(0.0),
+        }
+    }
+
+    /// Points on the surface, no further than about `spacing` apart, for
+    /// testing against other shapes with [`Geometry::contains`].
+    ///
+    /// Panics if `spacing` is not positive.
+    pub fn surface_points(&self, spacing: f32) -> Vec<Vec3> {
+        assert!(
+            spacing > 0.0,
+            "surface point spacing must be positive, got {spacing}"
+        );
+        let count = |length: f32| ((length / spacing).ceil() as usize).max(1);
+        // Points on a `width` by `depth` rectangle in the z = 0 plane.
+        let grid = |depth: f32, width: f32| {
+            let (columns, rows) = (count(depth), count(width));
+            (0..=columns).flat_map(move |i| {
+                (0..=rows).map(move |j| {
+                    Vec3::new(
+                        depth * (i as f32 / columns as f32 - 0.5),
+                        width * (j as f32 / rows as f32 - 0.5),
+                        0.0,
+                    )
+                })
+            })
+        };
+        match self {
+            Self::Cylinder { radius, height } => {
+                let around = count(TAU * radius).max(8);
+                let rings = count(*height);
+                let mut points = Vec::new();
+                for ring in 0..=rings {
+                    let z = height * (ring as f32 / rings as f32 - 0.5);
+                    points.extend((0..around).map(|k| {
+                        let angle = TAU * k as f32 / around as f32;
+                        Vec3::new(radius * angle.cos(), radius * angle.sin(), z)
+                    }));
+                }
+                for cap in [-height / 2.0, height / 2.0] {
+                    points.extend(
+                        grid(2.0 * radius, 2.0 * radius)
+                            .filter(|p| p.length() <= *radius)
+                            .map(|p| p + Vec3::Z * cap),
+                    );
+                }
+                points
+            }
+            Self::Sphere { radius } => {
+                // Fibonacci lattice.
+                let total = count(4.0 * PI * radius * radius / spacing).max(12);
+                let golden = PI * (3.0 - 5f32.sqrt());
+                (0..total)
+                    .map(|k| {
+                        let z = 1.0 - 2.0 * (k as f32 + 0.5) / total as f32;
+                        let ring = (1.0 - z * z).sqrt();
+                        let angle = golden * k as f32;
+                        *radius * Vec3::new(ring * angle.cos(), ring * angle.sin(), z)
+                    })
+                    .collect()
+            }
+            Self::Box {
+                height,
+                width,
+                depth,
+            } => {
+                let half = Vec3::new(depth / 2.0, width / 2.0, height / 2.0);
+                let mut points = Vec::new();
+                for sign in [-1.0, 1.0] {
+                    points.extend(grid(*depth, *width).map(|p| p + Vec3::Z * half.z * sign));
+                    points
+                        .extend(grid(*depth, *height).map(|p| Vec3::new(p.x, half.y * sign, p.y)));
+                    points
+                        .extend(grid(*width, *height).map(|p| Vec3::new(half.x * sign, p.x, p.y)));
+                }
+                points
+            }
+            Self::Plane { width, depth } => grid(*depth, *width).collect(),
+            Self::Mesh { vertices, indices } => {
+                let vertex = |index: &u32| {
+                    let &(x, y, z) = vertices.get(*index as usize)?;
+                    Some(Vec3::new(x, y, z))
+                };
+                let mut points: Vec<Vec3> = vertices
+                    .iter()
+                    .map(|&(x, y, z)| Vec3::new(x, y, z))
+                    .collect();
+                for triangle in indices.chunks_exact(3) {
+                    let (Some(a), Some(b), Some(c)) = (
+                        vertex(&triangle[0]),
+                        vertex(&triangle[1]),
+                        vertex(&triangle[2]),
+                    ) else {
+                        continue;
+                    };
+                    let steps = count((b - a).length().max((c - a).length()));
+                    for i in 0..=steps {
+                        for j in 0..=steps - i {
+                            let (u, v) = (i as f32 / steps as f32, j as f32 / steps as f32);
+                            points.push(a + (b - a) * u + (c - a) * v);
+                        }
+                    }
+                }
+                points
+            }
+        }
+    }
+}
+
+/// A link's collision shape and where it sits relative to some frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlacedGeometry {
+    pub geometry: Geometry,
+    pub transform: Transform,
+}
+
+impl PlacedGeometry {
+    pub fn new(geometry: Geometry, transform: Transform) -> Self {
+        Self {
+            geometry,
+            transform,
+        }
+    }
+
+    /// Whether `point`, given in the frame `transform` is relative to, lies
+    /// inside the padded geometry.
+    pub fn contains(&self, point: Vec3, padding: f32) -> bool {
+        self.geometry
+            .contains(self.transform.inverse().transform_point(point), padding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surface_points_cover_a_box_at_the_spacing() {
+        let geometry = Geometry::Box {
+            height: 0.2,
+            width: 0.4,
+            depth: 0.6,
+        };
+        let points = geometry.surface_points(0.1);
+        assert!(!points.is_empty());
+        for point in &points {
+            let on_face = (point.x.abs() - 0.3).abs() < 1e-5
+                || (point.y.abs() - 0.2).abs() < 1e-5
+                || (point.z.abs() - 0.1).abs() < 1e-5;
+            assert!(on_face, "{point:?}");
+        }
+    }
+
+    #[test]
+    fn surface_points_lie_on_a_sphere_and_cover_it() {
+        let points = Geometry::Sphere { radius: 0.5 }.surface_points(0.05);
+        assert!(points.len() > 100);
+        for point in &points {
+            assert!((point.length() - 0.5).abs() < 1e-5, "{point:?}");
+        }
+        // Every octant is sampled.
+        let octants: std::collections::HashSet<_> = points
+            .iter()
+            .map(|p| (p.x > 0.0, p.y > 0.0, p.z > 0.0))
+            .collect();
+        assert_eq!(octants.len(), 8);
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::mem;
+
+use super::{DiagnosticStatus, Level};
+
+/// A node of the health tree: a reported status, a group of them, or both.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HealthNode {
+    /// Last segment of the path; empty for the root.
+    pub name: String,
+    pub path: String,
+    /// The worst of its own status and its children's.
+    pub level: Level,
+    /// Its own message, or the message of its worst child.
+    pub message: String,
+    /// Its own key-values, empty for groups.
+    pub values: Vec<(String, String)>,
+    pub children: Vec<HealthNode>,
+}
+
+impl HealthNode {
+    /// The node at a `/`-separated path below this one.
+    pub fn find(&self, path: &str) -> Option<&HealthNode> {
+        path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .try_fold(self, |node, segment| {
+                node.children.iter().find(|child| child.name == segment)
+            })
+    }
+
+    /// Paths of the deepest nodes at `level` or worse, e.g. to alert on: the
+    /// leaves, and reported nodes worse than all their children.
+    pub fn failing(&self, level: Level) -> Vec<&str> {
+        if self.level < level {
+            return Vec::new();
+        }
+        let mut paths: Vec<&str> = self
+            .children
+            .iter()
+            .flat_map(|child| child.failing(level))
+            .collect();
+        let worst_child = self.children.iter().map(|child| child.level).max();
+        if worst_child.is_none_or(|worst| worst < self.level) {
+            paths.insert(0, &self.path);
+        }
+        paths
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let name = if self.path.is_empty() {
+            "robot"
+        } else {
+            &self.name
+        };
+        write!(
+            f,
+            "{:indent$}[{}] {}",
+            "",
+            self.level,
+            name,
+            indent = 2 * depth
+        )?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        for (key, value) in &self.values {
+            write!(f, ", {key}={value}")?;
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HealthNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// A change of level of a node of the health tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub path: String,
+    /// `None` when the node first appeared.
+    pub previous: Option<Level>,
+    pub level: Level,
+    pub message: String,
+    /// Nanoseconds.
+    pub timestamp: u64,
+}
+
+struct Entry {
+    status: DiagnosticStatus,
+    received: u64,
+}
+
+/// Collects the latest status of every device and task and rolls them up
+/// into a health tree by their `/`-separated names. Statuses not refreshed
+/// within the stale timeout count as [`Level::Stale`].
+pub struct Aggregator {
+    /// Seconds.
+    pub stale_timeout: f32,
+    entries: BTreeMap<String, Entry>,
+    levels: HashMap<String, Level>,
+    alerts: Vec<Alert>,
+}
+
+impl Aggregator {
+    pub fn new(stale_timeout: f32) -> Self {
+        Self {
+            stale_timeout,
+            entries: BTreeMap::new(),
+            levels: HashMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+
+    /// Replaces the status of `status.name`, received at `timestamp`.
+    pub fn report(&mut self, status: DiagnosticStatus, timestamp: u64) {
+        let name = status.name.trim_matches('/').to_string();
+        self.entries.insert(
+            name.clone(),
+            Entry {
+                status: DiagnosticStatus { name, ..status },
+                received: timestamp,
+            },
+        );
+    }
+
+    /// Forgets a status. Returns `false` if none was reported.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name.trim_matches('/')).is_some()
+    }
+
+    /// The latest status reported under `name`, marked stale if too old.
+    pub fn status(&self, name: &str, now: u64) -> Option<DiagnosticStatus> {
+        self.entries
+            .get(name.trim_matches('/'))
+            .map(|entry| self.aged(entry, now))
+    }
+
+    /// The level of any node, reported or group.
+    pub fn level(&self, path: &str, now: u64) -> Option<Level> {
+        self.tree(now).find(path).map(|node| node.level)
+    }
+
+    pub fn tree(&self, now: u64) -> HealthNode {
+        let mut root = HealthNode::default();
+        for entry in self.entries.values() {
+            let status = self.aged(entry, now);
+            let mut node = &mut root;
+            for segment in status.name.split('/').filter(|s| !s.is_empty()) {
+                let index = match node.children.iter().position(|c| c.name == segment) {
+                    Some(index) => index,
+                    None => {
+                        let path = if node.path.is_empty() {
+                            segment.to_string()
+                        } else {
+                            format!("{}/{segment}", node.path)
+                        };
+                        node.children.push(HealthNode {
+                            name: segment.to_string(),
+                            path,
+                            ..HealthNode::default()
+                        });
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut node.children[index];
+            }
+            node.level = status.level;
+            node.message = status.message;
+            node.values = status.values;
+        }
+        roll_up(&mut root);
+        root
+    }
+
+    /// Compares the tree at `now` with the previous call and records an
+    /// alert for every node whose level changed.
+    pub fn update(&mut self, now: u64) {
+        let tree = self.tree(now);
+        let mut levels = HashMap::new();
+        let mut stack = vec![&tree];
+        while let Some(node) = stack.pop() {
+            let previous = self.levels.get(&node.path).copied();
+            if previous != Some(node.level) {
+                self.alerts.push(Alert {
+                    path: node.path.clone(),
+                    previous,
+                    level: node.level,
+                    message: node.message.clone(),
+                    timestamp: now,
+                });
+            }
+            levels.insert(node.path.clone(), node.level);
+            stack.extend(node.children.iter().rev());
+        }
+        self.levels = levels;
+    }
+
+    /// Alerts recorded since the last call, in tree order per update.
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        mem::take(&mut self.alerts)
+    }
+
+    fn aged(&self, entry: &Entry, now: u64) -> DiagnosticStatus {
+        let age = now.saturating_sub(entry.received) as f32 * 1e-9;
+        let mut status = entry.status.clone();
+        if age > self.stale_timeout {
+            status.level = Level::Stale;
+        }
+        status
+    }
+}
+
+// Gives groups the level and message of their worst child.
+fn roll_up(node: &mut HealthNode) {
+    for child in &mut node.children {
+        roll_up(child);
+    }
+    if let Some(worst) = node.children.iter().max_by_key(|child| child.level) {
+        if worst.level > node.level || (node.message.is_empty() && node.values.is_empty()) {
+            node.level = node.level.max(worst.level);
+            node.message = format!("{}: {}", worst.name, worst.message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn status(name: &str, level: Level, message: &str) -> DiagnosticStatus {
+        DiagnosticStatus::new(name, level, message)
+    }
+
+    #[test]
+    fn statuses_go_stale_without_refreshing() {
+        let mut aggregator = Aggregator::new(2.0);
+        aggregator.report(status("sensors/lidar", Level::Ok, "reading"), 0);
+        assert_eq!(
+            aggregator.level("sensors/lidar", 2 * SECOND),
+            Some(Level::Ok)
+        );
+        let stale = aggregator
+            .status("/sensors/lidar/", 2 * SECOND + SECOND / 10)
+            .unwrap();
+        assert_eq!(stale.level, Level::Stale);
+        assert_eq!(stale.message, "reading");
+        assert_eq!(aggregator.level("sensors", 3 * SECOND), Some(Level::Stale));
+
+        // A fresh report brings it back.
+        aggregator.report(status("sensors/lidar", Level::Ok, "reading"), 3 * SECOND);
+        assert_eq!(aggregator.level("sensors", 3 * SECOND), Some(Level::Ok));
+        assert!(aggregator.remove("sensors/lidar"));
+        assert_eq!(aggregator.level("sensors", 3 * SECOND), None);
+    }
+
+    #[test]
+    fn groups_take_the_worst_level_below_them() {
+        let mut aggregator = Aggregator::new(10.0);
+        aggregator.report(status("sensors/lidar", Level::Ok, "reading"), 0);
+        aggregator.report(status("sensors/imu", Level::Warn, "missing data"), 0);
+        aggregator.report(status("drive/left", Level::Ok, "connected"), 0);
+        aggregator.report(status("drive/right", Level::Error, "disconnected"), 0);
+        aggregator.report(status("drive/right/encoder", Level::Ok, "reading"), 0);
+
+        let tree = aggregator.tree(SECOND);
+        assert_eq!(tree.level, Level::Error);
+        assert_eq!(tree.message, "drive: right: disconnected");
+        let sensors = tree.find("sensors").unwrap();
+        assert_eq!(sensors.level, Level::Warn);
+        assert_eq!(sensors.message, "imu: missing data");
+        // A reported node keeps its own status when its children are better.
+        let right = tree.find("drive/right").unwrap();
+        assert_eq!(
+            (right.level, right.message.as_str()),
+            (Level::Error, "disconnected")
+        );
+        assert_eq!(tree.failing(Level::Warn), ["drive/right", "sensors/imu"]);
+        assert_eq!(tree.failing(Level::Error), ["drive/right"]);
+    }
+
+    #[test]
+    fn alerts_follow_a_status_as_it_escalates() {
+        let mut aggregator = Aggregator::new(5.0);
+        aggregator.report(status("sensors/lidar", Level::Ok, "reading"), 0);
+        aggregator.update(0);
+        let appeared: Vec<_> = aggregator
+            .take_alerts()
+            .into_iter()
+            .map(|alert| (alert.path, alert.previous))
+            .collect();
+        assert_eq!(
+            appeared,
+            [
+                (String::new(), None),
+                ("sensors".to_string(), None),
+                ("sensors/lidar".to_string(), None)
+            ]
+        );
+
+        // Nothing changed, nothing to say.
+        aggregator.update(SECOND);
+        assert!(aggregator.take_alerts().is_empty());
+
+        let mut escalate = |level: Level, time: u64| {
+            if level != Level::Stale {
+                aggregator.report(status("sensors/lidar", level, "lidar"), time);
+            }
+            aggregator.update(time);
+            aggregator.take_alerts()
+        };
+        let mut previous = Level::Ok;
+        for (level, time) in [(Level::Warn, 2), (Level::Error, 3), (Level::Stale, 9)] {
+            let alerts = escalate(level, time * SECOND);
+            // The status and every group above it.
+            let paths: Vec<&str> = alerts.iter().map(|alert| alert.path.as_str()).collect();
+            assert_eq!(paths, ["", "sensors", "sensors/lidar"], "{level}");
+            for alert in &alerts {
+                assert_eq!((alert.previous, alert.level), (Some(previous), level));
+                assert_eq!(alert.timestamp, time * SECOND);
+            }
+            previous = level;
+        }
+
+        // Recovering is an alert too.
+        let alerts = escalate(Level::Ok, 10 * SECOND);
+        assert_eq!(alerts.len(), 3);
+        assert!(alerts
+            .iter()
+            .all(|alert| alert.previous == Some(Level::Stale) && alert.level == Level::Ok));
+    }
+}
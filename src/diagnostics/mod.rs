@@ -0,0 +1,357 @@
+pub mod aggregator;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::imu::IMU;
+use crate::lidar::LIDAR;
+use crate::links::{Actuator, CarbonDataPacket, CarbonTaskConfiguration, Controller, Sensor, Task};
+use crate::ports::{ConnectionState, Port, PortReader};
+
+pub use aggregator::{Aggregator, Alert, HealthNode};
+
+/// Health of a device or task, from best to worst.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Level {
+    #[default]
+    Ok,
+    Warn,
+    Error,
+    /// Nothing was reported for longer than the aggregator's stale timeout.
+    Stale,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Ok => "OK",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Stale => "STALE",
+        })
+    }
+}
+
+/// A report from one device or task. Names are `/`-separated paths placing
+/// it in the health tree, e.g. `sensors/lidar`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiagnosticStatus {
+    pub name: String,
+    pub level: Level,
+    pub message: String,
+    pub values: Vec<(String, String)>,
+}
+
+impl DiagnosticStatus {
+    pub fn new(name: &str, level: Level, message: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            level,
+            message: message.to_string(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn with_value(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.values.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for DiagnosticStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.level, self.name, self.message)?;
+        for (key, value) in &self.values {
+            write!(f, ", {key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Event rate over a sliding window, checked against an expected range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrequencyMonitor {
+    /// Hz.
+    pub min_frequency: f32,
+    /// Hz.
+    pub max_frequency: f32,
+    /// Fraction by which the rate may leave the range before warning.
+    pub tolerance: f32,
+    /// Seconds of events the rate is averaged over.
+    pub window: f32,
+    events: VecDeque<u64>,
+    first: Option<u64>,
+    total: u64,
+}
+
+impl FrequencyMonitor {
+    pub fn new(min_frequency: f32, max_frequency: f32) -> Self {
+        Self {
+            min_frequency,
+            max_frequency,
+            tolerance: 0.1,
+            window: 5.0,
+            events: VecDeque::new(),
+            first: None,
+            total: 0,
+        }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_window(mut self, window: f32) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Records an event at `timestamp`, in nanoseconds.
+    pub fn tick(&mut self, timestamp: u64) {
+        self.first.get_or_insert(timestamp);
+        self.events.push_back(timestamp);
+        self.total += 1;
+        self.trim(timestamp);
+    }
+
+    /// Events since the monitor was created.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Events per second over the window ending at `now`, or over the time
+    /// since the first event if shorter. `None` before the first event.
+    pub fn frequency(&mut self, now: u64) -> Option<f32> {
+        self.trim(now);
+        let since_first = now.saturating_sub(self.first?) as f32 * 1e-9;
+        if since_first >= self.window {
+            return Some(self.events.len() as f32 / self.window);
+        }
+        // Until the window fills, count the intervals since the first event.
+        (since_first > 0.0).then(|| (self.total - 1) as f32 / since_first)
+    }
+
+    pub fn status(&mut self, name: &str, now: u64) -> DiagnosticStatus {
+        let frequency = self.frequency(now);
+        let (level, message) = match frequency {
+            None if self.total == 0 => (Level::Error, "no events"),
+            None => (Level::Ok, "first event"),
+            Some(rate) if rate < self.min_frequency * (1.0 - self.tolerance) => {
+                (Level::Warn, "frequency too low")
+            }
+            Some(rate) if rate > self.max_frequency * (1.0 + self.tolerance) => {
+                (Level::Warn, "frequency too high")
+            }
+            Some(_) => (Level::Ok, "frequency ok"),
+        };
+        DiagnosticStatus::new(name, level, message)
+            .with_value("frequency", frequency.unwrap_or(0.0))
+            .with_value("min frequency", self.min_frequency)
+            .with_value("max frequency", self.max_frequency)
+            .with_value("events", self.total)
+    }
+
+    fn trim(&mut self, now: u64) {
+        let horizon = now.saturating_sub((self.window.max(0.0) * 1e9) as u64);
+        while self.events.front().is_some_and(|&event| event < horizon) {
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Status of a [`Port`]'s connection.
+pub fn port_status(name: &str, port: &Port) -> DiagnosticStatus {
+    let (level, message) = match port.state() {
+        ConnectionState::Connected => (Level::Ok, "connected".to_string()),
+        ConnectionState::Disconnected => (Level::Error, "disconnected".to_string()),
+        ConnectionState::Reconnecting { attempt } => {
+            (Level::Warn, format!("reconnecting, attempt {attempt}"))
+        }
+        ConnectionState::Failed => (Level::Error, "reconnecting gave up".to_string()),
+    };
+    DiagnosticStatus::new(name, level, &message).with_value("address", port.address())
+}
+
+/// Wraps a device driver to count its reads, so a driver whose
+/// [`PortReader::read_data`] keeps returning `None` is reported instead of
+/// failing silently.
+pub struct DiagnosedReader<R: PortReader> {
+    pub reader: R,
+    /// Misses in a row before warning.
+    pub warn_misses: u32,
+    /// Misses in a row before reporting an error.
+    pub error_misses: u32,
+    reads: u64,
+    misses: u64,
+    consecutive_misses: u32,
+}
+
+impl<R: PortReader> DiagnosedReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            warn_misses: 5,
+            error_misses: 50,
+            reads: 0,
+            misses: 0,
+            consecutive_misses: 0,
+        }
+    }
+
+    pub fn with_miss_thresholds(mut self, warn_misses: u32, error_misses: u32) -> Self {
+        self.warn_misses = warn_misses;
+        self.error_misses = error_misses;
+        self
+    }
+
+    pub fn status(&self, name: &str) -> DiagnosticStatus {
+        let (level, message) = if self.consecutive_misses >= self.error_misses {
+            (Level::Error, "no data")
+        } else if self.consecutive_misses >= self.warn_misses {
+            (Level::Warn, "missing data")
+        } else if self.reads == 0 {
+            (Level::Warn, "not read yet")
+        } else {
+            (Level::Ok, "reading")
+        };
+        DiagnosticStatus::new(name, level, message)
+            .with_value("reads", self.reads)
+            .with_value("misses", self.misses)
+            .with_value("consecutive misses", self.consecutive_misses)
+    }
+}
+
+impl<R: PortReader> PortReader for DiagnosedReader<R> {
+    type Output = R::Output;
+
+    fn read_data(&mut self) -> Option<Self::Output> {
+        self.reads += 1;
+        let data = self.reader.read_data();
+        if data.is_some() {
+            self.consecutive_misses = 0;
+        } else {
+            self.misses += 1;
+            self.consecutive_misses = self.consecutive_misses.saturating_add(1);
+        }
+        data
+    }
+}
+
+impl<R: LIDAR> LIDAR for DiagnosedReader<R> {}
+
+impl<R: IMU> IMU for DiagnosedReader<R> {}
+
+/// Wraps a task to measure how often it runs, from the timestamps of its
+/// inputs, or of its outputs for sensors.
+pub struct Monitored<T: Task> {
+    pub task: T,
+    monitor: Mutex<FrequencyMonitor>,
+}
+
+impl<T: Task> Monitored<T> {
+    pub fn new(task: T, monitor: FrequencyMonitor) -> Self {
+        Self {
+            task,
+            monitor: Mutex::new(monitor),
+        }
+    }
+
+    pub fn status(&self, name: &str, now: u64) -> DiagnosticStatus {
+        self.monitor
+            .lock()
+            .expect("FrequencyMonitor lock poisoned")
+            .status(name, now)
+    }
+}
+
+impl<T: Task> Task for Monitored<T> {
+    type Input = T::Input;
+    type Output = T::Output;
+
+    fn setup(&self, configuration: Option<&CarbonTaskConfiguration>) {
+        self.task.setup(configuration);
+    }
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let input_timestamp = input.timestamp();
+        let output = self.task.process(input);
+        if let Some(timestamp) = input_timestamp.or_else(|| output.timestamp()) {
+            self.monitor
+                .lock()
+                .expect("FrequencyMonitor lock poisoned")
+                .tick(timestamp);
+        }
+        output
+    }
+}
+
+impl<I, T: Actuator<I>> Actuator<I> for Monitored<T> {}
+
+impl<O, T: Sensor<O>> Sensor<O> for Monitored<T> {}
+
+impl<I, O, T: Controller<I, O>> Controller<I, O> for Monitored<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn frequency_monitors_warn_outside_their_range() {
+        let mut monitor = FrequencyMonitor::new(9.0, 11.0).with_window(1.0);
+        assert_eq!(monitor.status("lidar", 0).level, Level::Error);
+        monitor.tick(0);
+        assert_eq!(monitor.status("lidar", 0).level, Level::Ok);
+        for tick in 1..=20 {
+            monitor.tick(tick * SECOND / 10);
+        }
+        let now = 2 * SECOND + SECOND / 20;
+        let status = monitor.status("lidar", now);
+        assert_eq!(status.level, Level::Ok, "{status}");
+        assert_eq!(monitor.frequency(now), Some(10.0));
+
+        // Events stop: the window empties and the rate falls.
+        let status = monitor.status("lidar", 2 * SECOND + SECOND / 2);
+        assert_eq!(
+            (status.level, status.message.as_str()),
+            (Level::Warn, "frequency too low")
+        );
+        assert_eq!(status.value("events"), Some("21"));
+    }
+
+    struct Flaky(Vec<Option<u8>>);
+
+    impl PortReader for Flaky {
+        type Output = u8;
+
+        fn read_data(&mut self) -> Option<u8> {
+            self.0.pop().flatten()
+        }
+    }
+
+    #[test]
+    fn diagnosed_readers_escalate_with_misses_in_a_row() {
+        let mut reader =
+            DiagnosedReader::new(Flaky(vec![None, None, None, Some(1)])).with_miss_thresholds(1, 3);
+        assert_eq!(reader.status("imu").message, "not read yet");
+        assert_eq!(reader.read_data(), Some(1));
+        assert_eq!(reader.status("imu").level, Level::Ok);
+        reader.read_data();
+        assert_eq!(reader.status("imu").level, Level::Warn);
+        reader.read_data();
+        reader.read_data();
+        let status = reader.status("imu");
+        assert_eq!(status.level, Level::Error);
+        assert_eq!(status.value("misses"), Some("3"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::sync::Mutex;
+
+use crate::clock::TimestampDelta;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::{Pose2D, Twist2D};
+
+/// Wheel or joint encoder reading: angle in radians and angular velocity in
+/// rad/s, or metres and m/s for prismatic joints.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct EncoderFeedback {
+    pub position: Option<f32>,
+    pub velocity: Option<f32>,
+}
+
+/// Commanded wheel angular velocity in rad/s.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct CommandVelocity(pub f32);
+
+/// Kinematics of a two-wheeled differential drive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DifferentialDrive {
+    pub wheel_radius: f32,
+    /// Distance between the two wheel contact points.
+    pub wheel_separation: f32,
+}
+
+impl DifferentialDrive {
+    pub fn new(wheel_radius: f32, wheel_separation: f32) -> Self {
+        Self {
+            wheel_radius,
+            wheel_separation,
+        }
+    }
+
+    /// Body twist from left and right wheel angular velocities.
+    pub fn twist(&self, left: f32, right: f32) -> Twist2D {
+        let left = left * self.wheel_radius;
+        let right = right * self.wheel_radius;
+        Twist2D::new((left + right) / 2.0, (right - left) / self.wheel_separation)
+    }
+
+    /// Left and right wheel commands realising a body twist.
+    pub fn wheel_commands(&self, twist: &Twist2D) -> (CommandVelocity, CommandVelocity) {
+        let half_turn = twist.angular * self.wheel_separation / 2.0;
+        (
+            CommandVelocity((twist.linear - half_turn) / self.wheel_radius),
+            CommandVelocity((twist.linear + half_turn) / self.wheel_radius),
+        )
+    }
+}
+
+/// Speed and acceleration bounds for a planar base.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TwistLimits {
+    /// Fastest forward speed, m/s.
+    pub max_linear: f32,
+    /// Lowest forward speed, m/s; negative to allow reversing.
+    pub min_linear: f32,
+    /// Fastest yaw rate either way, rad/s.
+    pub max_angular: f32,
+    /// m/s².
+    pub max_linear_acceleration: f32,
+    /// rad/s².
+    pub max_angular_acceleration: f32,
+}
+
+impl Default for TwistLimits {
+    fn default() -> Self {
+        Self {
+            max_linear: 0.5,
+            min_linear: 0.0,
+            max_angular: 1.5,
+            max_linear_acceleration: 1.0,
+            max_angular_acceleration: 3.0,
+        }
+    }
+}
+
+impl TwistLimits {
+    /// Clamps `twist` to the speed limits.
+    pub fn clamp_velocity(&self, twist: &Twist2D) -> Twist2D {
+        Twist2D::new(
+            twist.linear.clamp(self.min_linear, self.max_linear),
+            twist.angular.clamp(-self.max_angular, self.max_angular),
+        )
+    }
+
+    /// Clamps `twist` to the speed limits and to what is reachable from
+    /// `current` within `dt` seconds.
+    pub fn apply(&self, twist: &Twist2D, current: &Twist2D, dt: f32) -> Twist2D {
+        let twist = self.clamp_velocity(twist);
+        let linear_step = self.max_linear_acceleration * dt;
+        let angular_step = self.max_angular_acceleration * dt;
+        Twist2D::new(
+            twist
+                .linear
+                .clamp(current.linear - linear_step, current.linear + linear_step),
+            twist.angular.clamp(
+                current.angular - angular_step,
+                current.angular + angular_step,
+            ),
+        )
+    }
+}
+
+/// Dead-reckons the base pose in the odometry frame from wheel encoders.
+#[derive(Clone, Debug)]
+pub struct DifferentialDriveOdometry {
+    pub drive: DifferentialDrive,
+    pose: Pose2D,
+    twist: Twist2D,
+    last_positions: Option<(f32, f32)>,
+}
+
+impl DifferentialDriveOdometry {
+    pub fn new(drive: DifferentialDrive) -> Self {
+        Self {
+            drive,
+            pose: Pose2D::identity(),
+            twist: Twist2D::default(),
+            last_positions: None,
+        }
+    }
+
+    pub fn pose(&self) -> Pose2D {
+        self.pose
+    }
+
+    pub fn twist(&self) -> Twist2D {
+        self.twist
+    }
+
+    pub fn reset(&mut self, pose: Pose2D) {
+        self.pose = pose;
+        self.twist = Twist2D::default();
+        self.last_positions = None;
+    }
+
+    /// Advances the pose by `dt` seconds of encoder feedback and returns the
+    /// motion relative to the previous pose. Wheel positions are preferred, and
+    /// their travel is applied even if `dt` is not positive; velocities are
+    /// integrated when positions are unavailable.
+    pub fn update(&mut self, left: &EncoderFeedback, right: &EncoderFeedback, dt: f32) -> Pose2D {
+        let positions = left.position.zip(right.position);
+        let delta = match (positions, self.last_positions) {
+            (Some((left, right)), Some((last_left, last_right))) => {
+                let travel = self.drive.twist(left - last_left, right - last_right);
+                if dt > 0.0 {
+                    self.twist = Twist2D::new(travel.linear / dt, travel.angular / dt);
+                }
+                travel.integrate(1.0)
+            }
+            (Some(_), None) => Pose2D::identity(),
+            _ => match left.velocity.zip(right.velocity) {
+                Some((left, right)) => {
+                    self.twist = self.drive.twist(left, right);
+                    self.twist.integrate(dt)
+                }
+                None => Pose2D::identity(),
+            },
+        };
+        if positions.is_some() {
+            self.last_positions = positions;
+        }
+        self.pose = self.pose.compose(&delta);
+        delta
+    }
+}
+
+/// Turns body twist commands into left and right wheel commands within the
+/// base's limits. Acceleration is limited over the time between command
+/// timestamps; `period` stands in for it on the first command.
+pub struct DriveController {
+    pub drive: DifferentialDrive,
+    pub limits: TwistLimits,
+    /// Fastest wheel angular velocity, rad/s. Commands beyond it are scaled
+    /// down, keeping the turning radius.
+    pub max_wheel_velocity: Option<f32>,
+    pub period: f32,
+    twist: Mutex<Twist2D>,
+    last_timestamp: TimestampDelta,
+}
+
+impl DriveController {
+    pub fn new(drive: DifferentialDrive, limits: TwistLimits, period: f32) -> Self {
+        Self {
+            drive,
+            limits,
+            max_wheel_velocity: None,
+            period,
+            twist: Mutex::new(Twist2D::default()),
+            last_timestamp: TimestampDelta::new(),
+        }
+    }
+
+    pub fn with_max_wheel_velocity(mut self, max_wheel_velocity: f32) -> Self {
+        self.max_wheel_velocity = Some(max_wheel_velocity);
+        self
+    }
+
+    /// The last twist actually commanded.
+    pub fn twist(&self) -> Twist2D {
+        *self.twist.lock().expect("Twist lock poisoned")
+    }
+
+    /// Limits `twist` given `dt` seconds since the previous command and
+    /// returns the wheel commands.
+    pub fn command(&self, twist: &Twist2D, dt: f32) -> (CommandVelocity, CommandVelocity) {
+        let mut last = self.twist.lock().expect("Twist lock poisoned");
+        let mut twist = self.limits.apply(twist, &last, dt);
+        let (left, right) = self.drive.wheel_commands(&twist);
+        let fastest = left.0.abs().max(right.0.abs());
+        let commands = match self.max_wheel_velocity {
+            Some(limit) if fastest > limit => {
+                let scale = limit / fastest;
+                twist = Twist2D::new(twist.linear * scale, twist.angular * scale);
+                (
+                    CommandVelocity(left.0 * scale),
+                    CommandVelocity(right.0 * scale),
+                )
+            }
+            _ => (left, right),
+        };
+        *last = twist;
+        commands
+    }
+}
+
+impl Task for DriveController {
+    type Input = CarbonData<Twist2D>;
+    type Output = CarbonData<(CommandVelocity, CommandVelocity)>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let dt = self
+            .last_timestamp
+            .step(input.metadata.timestamp, self.period);
+        input.map(|twist| self.command(&twist, dt))
+    }
+}
+
+impl Controller<Twist2D, (CommandVelocity, CommandVelocity)> for DriveController {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(left: f32, right: f32) -> (EncoderFeedback, EncoderFeedback) {
+        let feedback = |position| EncoderFeedback {
+            position: Some(position),
+            velocity: None,
+        };
+        (feedback(left), feedback(right))
+    }
+
+    #[test]
+    fn wheel_travel_moves_the_base() {
+        let mut odometry = DifferentialDriveOdometry::new(DifferentialDrive::new(0.1, 0.5));
+        let (left, right) = positions(0.0, 0.0);
+        assert_eq!(odometry.update(&left, &right, 0.1), Pose2D::identity());
+
+        let (left, right) = positions(2.0, 2.0);
+        let delta = odometry.update(&left, &right, 0.5);
+        assert!(
+            (delta.x - 0.2).abs() < 1e-6 && delta.y.abs() < 1e-6,
+            "{delta:?}"
+        );
+        assert!((odometry.twist().linear - 0.4).abs() < 1e-6);
+
+        // Turning in place: each wheel rolls a quarter of the turning circle.
+        let quarter = std::f32::consts::FRAC_PI_2 * 0.25 / 0.1;
+        let (left, right) = positions(2.0 - quarter, 2.0 + quarter);
+        odometry.update(&left, &right, 1.0);
+        let pose = odometry.pose();
+        assert!(
+            (pose.x - 0.2).abs() < 1e-5 && pose.y.abs() < 1e-5,
+            "{pose:?}"
+        );
+        assert!((pose.theta - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn travel_without_elapsed_time_is_not_lost() {
+        let mut odometry = DifferentialDriveOdometry::new(DifferentialDrive::new(0.1, 0.5));
+        let (left, right) = positions(0.0, 0.0);
+        odometry.update(&left, &right, 0.1);
+        let (left, right) = positions(1.0, 1.0);
+        odometry.update(&left, &right, 0.1);
+
+        // A repeated timestamp, with stale velocities that must be ignored.
+        let (mut left, mut right) = positions(3.0, 3.0);
+        left.velocity = Some(-5.0);
+        right.velocity = Some(-5.0);
+        let delta = odometry.update(&left, &right, 0.0);
+
+        assert!((delta.x - 0.2).abs() < 1e-6, "{delta:?}");
+        assert!((odometry.pose().x - 0.3).abs() < 1e-6);
+        assert!((odometry.twist().linear - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn velocities_are_integrated_without_positions() {
+        let mut odometry = DifferentialDriveOdometry::new(DifferentialDrive::new(0.1, 0.5));
+        let feedback = EncoderFeedback {
+            position: None,
+            velocity: Some(3.0),
+        };
+        let delta = odometry.update(&feedback, &feedback, 0.5);
+        assert!((delta.x - 0.15).abs() < 1e-6, "{delta:?}");
+    }
+}
@@ -0,0 +1,558 @@
+use super::matrix::Matrix;
+use crate::primitives::wrap_angle;
+
+/// How the state evolves between measurements.
+pub trait ProcessModel {
+    fn state_size(&self) -> usize;
+
+    /// State after `dt` seconds.
+    fn predict(&self, state: &[f64], dt: f64) -> Vec<f64>;
+
+    /// Additive process noise accumulated over `dt` seconds.
+    fn noise(&self, state: &[f64], dt: f64) -> Matrix;
+
+    /// Jacobian of [`ProcessModel::predict`]; numeric unless overridden.
+    fn jacobian(&self, state: &[f64], dt: f64) -> Matrix {
+        numeric_jacobian(state, |x| self.predict(x, dt), |a, b| self.difference(a, b))
+    }
+
+    /// `a - b`, overridden to wrap angular components.
+    fn difference(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        a.iter().zip(b).map(|(a, b)| a - b).collect()
+    }
+
+    /// Brings a state back into canonical form, e.g. wrapping angles.
+    fn normalize(&self, _state: &mut [f64]) {}
+}
+
+/// How a sensor observes the state.
+pub trait MeasurementModel {
+    /// Expected measurement at `state`.
+    fn predict(&self, state: &[f64]) -> Vec<f64>;
+
+    /// Jacobian of [`MeasurementModel::predict`]; numeric unless overridden.
+    fn jacobian(&self, state: &[f64]) -> Matrix {
+        numeric_jacobian(state, |x| self.predict(x), |a, b| self.residual(a, b))
+    }
+
+    /// `measured - predicted`, overridden to wrap angular components.
+    fn residual(&self, measured: &[f64], predicted: &[f64]) -> Vec<f64> {
+        measured.iter().zip(predicted).map(|(a, b)| a - b).collect()
+    }
+}
+
+/// Central-difference Jacobian of `f` at `state`.
+pub fn numeric_jacobian(
+    state: &[f64],
+    f: impl Fn(&[f64]) -> Vec<f64>,
+    difference: impl Fn(&[f64], &[f64]) -> Vec<f64>,
+) -> Matrix {
+    const STEP: f64 = 1e-6;
+    let outputs = f(state).len();
+    let mut jacobian = Matrix::zeros(outputs, state.len());
+    let mut perturbed = state.to_vec();
+    for column in 0..state.len() {
+        perturbed[column] = state[column] + STEP;
+        let plus = f(&perturbed);
+        perturbed[column] = state[column] - STEP;
+        let minus = f(&perturbed);
+        perturbed[column] = state[column];
+        for (row, value) in difference(&plus, &minus).into_iter().enumerate() {
+            jacobian[(row, column)] = value / (2.0 * STEP);
+        }
+    }
+    jacobian
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpdateOutcome {
+    Accepted {
+        /// Squared Mahalanobis distance of the innovation.
+        distance_squared: f64,
+    },
+    /// The innovation fell outside the gate and the measurement was ignored.
+    Rejected { distance_squared: f64 },
+}
+
+impl UpdateOutcome {
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, UpdateOutcome::Accepted { .. })
+    }
+}
+
+/// A recursive Bayesian filter with Gaussian state.
+pub trait KalmanFilter {
+    fn state(&self) -> &[f64];
+
+    fn covariance(&self) -> &Matrix;
+
+    fn reset(&mut self, state: Vec<f64>, covariance: Matrix);
+
+    fn predict(&mut self, dt: f64);
+
+    /// Fuses `measured` with noise `covariance`. With a `gate`, measurements
+    /// whose squared Mahalanobis distance exceeds it are rejected.
+    fn update(
+        &mut self,
+        model: &dyn MeasurementModel,
+        measured: &[f64],
+        covariance: &Matrix,
+        gate: Option<f64>,
+    ) -> UpdateOutcome;
+}
+
+// Applies the gate, returning the innovation covariance inverse on success.
+fn apply_gate(
+    innovation: &[f64],
+    innovation_covariance: &Matrix,
+    gate: Option<f64>,
+) -> Result<(Matrix, f64), UpdateOutcome> {
+    let Some(inverse) = innovation_covariance.inverse() else {
+        return Err(UpdateOutcome::Rejected {
+            distance_squared: f64::INFINITY,
+        });
+    };
+    let distance_squared: f64 = innovation
+        .iter()
+        .zip(inverse.mul_vector(innovation))
+        .map(|(a, b)| a * b)
+        .sum();
+    match gate {
+        Some(threshold) if distance_squared.is_nan() || distance_squared > threshold => {
+            Err(UpdateOutcome::Rejected { distance_squared })
+        }
+        _ => Ok((inverse, distance_squared)),
+    }
+}
+
+pub struct ExtendedKalmanFilter<P> {
+    pub model: P,
+    state: Vec<f64>,
+    covariance: Matrix,
+}
+
+impl<P: ProcessModel> ExtendedKalmanFilter<P> {
+    pub fn new(model: P, state: Vec<f64>, covariance: Matrix) -> Self {
+        Self {
+            model,
+            state,
+            covariance,
+        }
+    }
+}
+
+impl<P: ProcessModel> KalmanFilter for ExtendedKalmanFilter<P> {
+    fn state(&self) -> &[f64] {
+        &self.state
+    }
+
+    fn covariance(&self) -> &Matrix {
+        &self.covariance
+    }
+
+    fn reset(&mut self, state: Vec<f64>, covariance: Matrix) {
+        self.state = state;
+        self.covariance = covariance;
+    }
+
+    fn predict(&mut self, dt: f64) {
+        let jacobian = self.model.jacobian(&self.state, dt);
+        let noise = self.model.noise(&self.state, dt);
+        self.state = self.model.predict(&self.state, dt);
+        self.model.normalize(&mut self.state);
+        self.covariance = &(&(&jacobian * &self.covariance) * &jacobian.transpose()) + &noise;
+        self.covariance.symmetrize();
+    }
+
+    fn update(
+        &mut self,
+        model: &dyn MeasurementModel,
+        measured: &[f64],
+        covariance: &Matrix,
+        gate: Option<f64>,
+    ) -> UpdateOutcome {
+        let jacobian = model.jacobian(&self.state);
+        let innovation = model.residual(measured, &model.predict(&self.state));
+        let cross = &self.covariance * &jacobian.transpose();
+        let innovation_covariance = &(&jacobian * &cross) + covariance;
+        let (inverse, distance_squared) =
+            match apply_gate(&innovation, &innovation_covariance, gate) {
+                Ok(gated) => gated,
+                Err(rejected) => return rejected,
+            };
+        let gain = &cross * &inverse;
+        for (value, correction) in self.state.iter_mut().zip(gain.mul_vector(&innovation)) {
+            *value += correction;
+        }
+        self.model.normalize(&mut self.state);
+        // Joseph form keeps the covariance positive definite.
+        let size = self.state.len();
+        let factor = &Matrix::identity(size) - &(&gain * &jacobian);
+        self.covariance = &(&(&factor * &self.covariance) * &factor.transpose())
+            + &(&(&gain * covariance) * &gain.transpose());
+        self.covariance.symmetrize();
+        UpdateOutcome::Accepted { distance_squared }
+    }
+}
+
+/// Sigma point spread; see Wan & van der Merwe, "The Unscented Kalman Filter
+/// for Nonlinear Estimation".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnscentedParameters {
+    pub alpha: f64,
+    pub beta: f64,
+    pub kappa: f64,
+}
+
+impl Default for UnscentedParameters {
+    fn default() -> Self {
+        Self {
+            alpha: 0.1,
+            beta: 2.0,
+            kappa: 0.0,
+        }
+    }
+}
+
+pub struct UnscentedKalmanFilter<P> {
+    pub model: P,
+    pub parameters: UnscentedParameters,
+    state: Vec<f64>,
+    covariance: Matrix,
+}
+
+impl<P: ProcessModel> UnscentedKalmanFilter<P> {
+    pub fn new(
+        model: P,
+        parameters: UnscentedParameters,
+        state: Vec<f64>,
+        covariance: Matrix,
+    ) -> Self {
+        Self {
+            model,
+            parameters,
+            state,
+            covariance,
+        }
+    }
+
+    fn lambda(&self) -> f64 {
+        let size = self.state.len() as f64;
+        self.parameters.alpha.powi(2) * (size + self.parameters.kappa) - size
+    }
+
+    // Mean and covariance weights.
+    fn weights(&self) -> (Vec<f64>, Vec<f64>) {
+        let size = self.state.len() as f64;
+        let lambda = self.lambda();
+        let other = 1.0 / (2.0 * (size + lambda));
+        let count = 2 * self.state.len() + 1;
+        let mut mean = vec![other; count];
+        let mut covariance = vec![other; count];
+        mean[0] = lambda / (size + lambda);
+        covariance[0] = mean[0] + 1.0 - self.parameters.alpha.powi(2) + self.parameters.beta;
+        (mean, covariance)
+    }
+
+    fn sigma_points(&self) -> Vec<Vec<f64>> {
+        let size = self.state.len();
+        let scaled = self.covariance.scale(size as f64 + self.lambda());
+        // Fall back to the diagonal if round-off broke positive definiteness.
+        let root = scaled.cholesky().unwrap_or_else(|| {
+            Matrix::from_diagonal(
+                &scaled
+                    .diagonal()
+                    .iter()
+                    .map(|v| v.max(0.0).sqrt())
+                    .collect::<Vec<_>>(),
+            )
+        });
+        let mut points = Vec::with_capacity(2 * size + 1);
+        points.push(self.state.clone());
+        for sign in [1.0, -1.0] {
+            for column in 0..size {
+                let mut point = self.state.clone();
+                for (row, value) in point.iter_mut().enumerate() {
+                    *value += sign * root[(row, column)];
+                }
+                self.model.normalize(&mut point);
+                points.push(point);
+            }
+        }
+        points
+    }
+}
+
+// Weighted mean of `points`, accumulating differences from the first point so
+// angles average correctly.
+fn weighted_mean(
+    points: &[Vec<f64>],
+    weights: &[f64],
+    difference: impl Fn(&[f64], &[f64]) -> Vec<f64>,
+) -> Vec<f64> {
+    let mut mean = points[0].clone();
+    for (point, weight) in points.iter().zip(weights).skip(1) {
+        for (value, delta) in mean.iter_mut().zip(difference(point, &points[0])) {
+            *value += weight * delta;
+        }
+    }
+    mean
+}
+
+fn weighted_covariance(deviations: &[Vec<f64>], other: &[Vec<f64>], weights: &[f64]) -> Matrix {
+    let mut covariance = Matrix::zeros(deviations[0].len(), other[0].len());
+    for ((a, b), weight) in deviations.iter().zip(other).zip(weights) {
+        for (row, a) in a.iter().enumerate() {
+            for (column, b) in b.iter().enumerate() {
+                covariance[(row, column)] += weight * a * b;
+            }
+        }
+    }
+    covariance
+}
+
+impl<P: ProcessModel> KalmanFilter for UnscentedKalmanFilter<P> {
+    fn state(&self) -> &[f64] {
+        &self.state
+    }
+
+    fn covariance(&self) -> &Matrix {
+        &self.covariance
+    }
+
+    fn reset(&mut self, state: Vec<f64>, covariance: Matrix) {
+        self.state = state;
+        self.covariance = covariance;
+    }
+
+    fn predict(&mut self, dt: f64) {
+        let (mean_weights, covariance_weights) = self.weights();
+        let points: Vec<Vec<f64>> = self
+            .sigma_points()
+            .iter()
+            .map(|point| {
+                let mut predicted = self.model.predict(point, dt);
+                self.model.normalize(&mut predicted);
+                predicted
+            })
+            .collect();
+        let mut mean = weighted_mean(&points, &mean_weights, |a, b| self.model.difference(a, b));
+        self.model.normalize(&mut mean);
+        let deviations: Vec<Vec<f64>> = points
+            .iter()
+            .map(|point| self.model.difference(point, &mean))
+            .collect();
+        let noise = self.model.noise(&self.state, dt);
+        self.covariance =
+            &weighted_covariance(&deviations, &deviations, &covariance_weights) + &noise;
+        self.covariance.symmetrize();
+        self.state = mean;
+    }
+
+    fn update(
+        &mut self,
+        model: &dyn MeasurementModel,
+        measured: &[f64],
+        covariance: &Matrix,
+        gate: Option<f64>,
+    ) -> UpdateOutcome {
+        let (mean_weights, covariance_weights) = self.weights();
+        let points = self.sigma_points();
+        let observations: Vec<Vec<f64>> = points.iter().map(|point| model.predict(point)).collect();
+        let expected = weighted_mean(&observations, &mean_weights, |a, b| model.residual(a, b));
+        let observation_deviations: Vec<Vec<f64>> = observations
+            .iter()
+            .map(|observation| model.residual(observation, &expected))
+            .collect();
+        let state_deviations: Vec<Vec<f64>> = points
+            .iter()
+            .map(|point| self.model.difference(point, &self.state))
+            .collect();
+        let innovation_covariance = &weighted_covariance(
+            &observation_deviations,
+            &observation_deviations,
+            &covariance_weights,
+        ) + covariance;
+        let cross = weighted_covariance(
+            &state_deviations,
+            &observation_deviations,
+            &covariance_weights,
+        );
+
+        let innovation = model.residual(measured, &expected);
+        let (inverse, distance_squared) =
+            match apply_gate(&innovation, &innovation_covariance, gate) {
+                Ok(gated) => gated,
+                Err(rejected) => return rejected,
+            };
+        let gain = &cross * &inverse;
+        for (value, correction) in self.state.iter_mut().zip(gain.mul_vector(&innovation)) {
+            *value += correction;
+        }
+        self.model.normalize(&mut self.state);
+        self.covariance =
+            &self.covariance - &(&(&gain * &innovation_covariance) * &gain.transpose());
+        self.covariance.symmetrize();
+        UpdateOutcome::Accepted { distance_squared }
+    }
+}
+
+/// A sensor that reads a subset of the state directly, such as a velocity from
+/// wheel odometry or a pose fix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectObservation {
+    indices: Vec<usize>,
+    angular: Vec<bool>,
+}
+
+impl DirectObservation {
+    pub fn new(indices: &[usize]) -> Self {
+        Self {
+            indices: indices.to_vec(),
+            angular: vec![false; indices.len()],
+        }
+    }
+
+    /// Marks state components in `angular` as angles whose residuals wrap.
+    pub fn with_angles(mut self, angular: &[usize]) -> Self {
+        for (index, is_angle) in self.indices.iter().zip(&mut self.angular) {
+            *is_angle = angular.contains(index);
+        }
+        self
+    }
+}
+
+impl MeasurementModel for DirectObservation {
+    fn predict(&self, state: &[f64]) -> Vec<f64> {
+        self.indices.iter().map(|&index| state[index]).collect()
+    }
+
+    fn jacobian(&self, state: &[f64]) -> Matrix {
+        let mut jacobian = Matrix::zeros(self.indices.len(), state.len());
+        for (row, &index) in self.indices.iter().enumerate() {
+            jacobian[(row, index)] = 1.0;
+        }
+        jacobian
+    }
+
+    fn residual(&self, measured: &[f64], predicted: &[f64]) -> Vec<f64> {
+        measured
+            .iter()
+            .zip(predicted)
+            .zip(&self.angular)
+            .map(
+                |((a, b), &is_angle)| {
+                    if is_angle {
+                        wrap_angle(a - b)
+                    } else {
+                        a - b
+                    }
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rand_distr::StandardNormal;
+
+    use super::*;
+
+    // Position and velocity along a line.
+    struct ConstantVelocity;
+
+    impl ProcessModel for ConstantVelocity {
+        fn state_size(&self) -> usize {
+            2
+        }
+
+        fn predict(&self, state: &[f64], dt: f64) -> Vec<f64> {
+            vec![state[0] + state[1] * dt, state[1]]
+        }
+
+        fn noise(&self, _state: &[f64], dt: f64) -> Matrix {
+            Matrix::from_diagonal(&[1e-4 * dt, 1e-4 * dt])
+        }
+    }
+
+    // Runs `filter` on noisy position fixes of a body moving at 2 m/s from
+    // 1 m, and returns the final state.
+    fn track(filter: &mut dyn KalmanFilter) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(21);
+        let noise = Matrix::from_diagonal(&[0.01]);
+        let position = DirectObservation::new(&[0]);
+        for step in 1..=100 {
+            filter.predict(0.1);
+            let truth = 1.0 + 2.0 * 0.1 * f64::from(step);
+            let measured = truth + 0.1 * rng.sample::<f64, _>(StandardNormal);
+            assert!(filter
+                .update(&position, &[measured], &noise, Some(10.8))
+                .is_accepted());
+        }
+        filter.state().to_vec()
+    }
+
+    fn initial_covariance() -> Matrix {
+        Matrix::from_diagonal(&[10.0, 10.0])
+    }
+
+    #[test]
+    fn extended_filter_converges_on_constant_velocity() {
+        let mut filter =
+            ExtendedKalmanFilter::new(ConstantVelocity, vec![0.0, 0.0], initial_covariance());
+        let state = track(&mut filter);
+        assert!((state[0] - 21.0).abs() < 0.1, "{state:?}");
+        assert!((state[1] - 2.0).abs() < 0.05, "{state:?}");
+        assert!(filter.covariance()[(1, 1)] < 1e-2);
+    }
+
+    #[test]
+    fn unscented_filter_converges_on_constant_velocity() {
+        let mut filter = UnscentedKalmanFilter::new(
+            ConstantVelocity,
+            UnscentedParameters::default(),
+            vec![0.0, 0.0],
+            initial_covariance(),
+        );
+        let state = track(&mut filter);
+        assert!((state[0] - 21.0).abs() < 0.1, "{state:?}");
+        assert!((state[1] - 2.0).abs() < 0.05, "{state:?}");
+        assert!(filter.covariance().cholesky().is_some());
+    }
+
+    #[test]
+    fn the_gate_rejects_an_outlier_and_leaves_the_state() {
+        let mut filter =
+            ExtendedKalmanFilter::new(ConstantVelocity, vec![0.0, 0.0], initial_covariance());
+        track(&mut filter);
+        let (state, covariance) = (filter.state().to_vec(), filter.covariance().clone());
+
+        let position = DirectObservation::new(&[0]);
+        let noise = Matrix::from_diagonal(&[0.01]);
+        let outcome = filter.update(&position, &[state[0] + 5.0], &noise, Some(10.8));
+        assert!(
+            matches!(outcome, UpdateOutcome::Rejected { distance_squared } if distance_squared > 10.8)
+        );
+        assert_eq!(filter.state(), state.as_slice());
+        assert_eq!(filter.covariance(), &covariance);
+
+        // Without a gate the same fix is taken.
+        let outcome = filter.update(&position, &[state[0] + 5.0], &noise, None);
+        assert!(outcome.is_accepted());
+        assert!(filter.state()[0] > state[0]);
+    }
+
+    #[test]
+    fn angles_wrap_into_a_half_open_range() {
+        use std::f64::consts::PI;
+        assert!((wrap_angle(3.0 * PI / 2.0) + PI / 2.0).abs() < 1e-12);
+        assert_eq!(wrap_angle(-PI), PI);
+        assert_eq!(wrap_angle(PI), PI);
+        let residual = DirectObservation::new(&[0]).with_angles(&[0]);
+        let wrapped = residual.residual(&[PI - 0.1], &[-PI + 0.1]);
+        assert!((wrapped[0] + 0.2).abs() < 1e-12);
+    }
+}
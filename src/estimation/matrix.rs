@@ -0,0 +1,308 @@
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+
+/// A small dense row-major matrix for filters whose state size is only known
+/// at runtime.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    columns: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn zeros(rows: usize, columns: usize) -> Self {
+        Self {
+            rows,
+            columns,
+            data: vec![0.0; rows * columns],
+        }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        Self::from_diagonal(&vec![1.0; size])
+    }
+
+    pub fn from_diagonal(diagonal: &[f64]) -> Self {
+        let mut matrix = Self::zeros(diagonal.len(), diagonal.len());
+        for (index, value) in diagonal.iter().enumerate() {
+            matrix[(index, index)] = *value;
+        }
+        matrix
+    }
+
+    /// Panics if the rows differ in length.
+    pub fn from_rows<R: AsRef<[f64]>>(rows: &[R]) -> Self {
+        let columns = rows.first().map_or(0, |row| row.as_ref().len());
+        let mut data = Vec::with_capacity(rows.len() * columns);
+        for row in rows {
+            assert_eq!(row.as_ref().len(), columns, "ragged matrix rows");
+            data.extend_from_slice(row.as_ref());
+        }
+        Self {
+            rows: rows.len(),
+            columns,
+            data,
+        }
+    }
+
+    /// A single-column matrix.
+    pub fn column(values: &[f64]) -> Self {
+        Self {
+            rows: values.len(),
+            columns: 1,
+            data: values.to_vec(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn as_slice(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut transposed = Self::zeros(self.columns, self.rows);
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                transposed[(column, row)] = self[(row, column)];
+            }
+        }
+        transposed
+    }
+
+    pub fn scale(&self, factor: f64) -> Self {
+        Self {
+            data: self.data.iter().map(|value| value * factor).collect(),
+            ..self.clone()
+        }
+    }
+
+    pub fn diagonal(&self) -> Vec<f64> {
+        (0..self.rows.min(self.columns))
+            .map(|index| self[(index, index)])
+            .collect()
+    }
+
+    /// `self * vector` for a vector given as a slice.
+    pub fn mul_vector(&self, vector: &[f64]) -> Vec<f64> {
+        assert_eq!(self.columns, vector.len(), "dimension mismatch");
+        (0..self.rows)
+            .map(|row| {
+                let start = row * self.columns;
+                self.data[start..start + self.columns]
+                    .iter()
+                    .zip(vector)
+                    .map(|(a, b)| a * b)
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Averages with the transpose to remove round-off asymmetry.
+    pub fn symmetrize(&mut self) {
+        for row in 0..self.rows {
+            for column in row + 1..self.columns {
+                let mean = 0.5 * (self[(row, column)] + self[(column, row)]);
+                self[(row, column)] = mean;
+                self[(column, row)] = mean;
+            }
+        }
+    }
+
+    /// Inverse by Gauss-Jordan elimination with partial pivoting; `None` if
+    /// singular.
+    pub fn inverse(&self) -> Option<Self> {
+        assert_eq!(self.rows, self.columns, "inverse of a non-square matrix");
+        let size = self.rows;
+        let mut work = self.clone();
+        let mut inverse = Self::identity(size);
+        for pivot in 0..size {
+            let best = (pivot..size)
+                .max_by(|&a, &b| work[(a, pivot)].abs().total_cmp(&work[(b, pivot)].abs()))?;
+            if work[(best, pivot)].abs() < 1e-300 {
+                return None;
+            }
+            work.swap_rows(pivot, best);
+            inverse.swap_rows(pivot, best);
+            let scale = 1.0 / work[(pivot, pivot)];
+            for column in 0..size {
+                work[(pivot, column)] *= scale;
+                inverse[(pivot, column)] *= scale;
+            }
+            for row in 0..size {
+                let factor = work[(row, pivot)];
+                if row == pivot || factor == 0.0 {
+                    continue;
+                }
+                for column in 0..size {
+                    work[(row, column)] -= factor * work[(pivot, column)];
+                    inverse[(row, column)] -= factor * inverse[(pivot, column)];
+                }
+            }
+        }
+        Some(inverse)
+    }
+
+    /// Lower-triangular `L` with `L Lᵀ = self`; `None` unless positive definite.
+    pub fn cholesky(&self) -> Option<Self> {
+        assert_eq!(self.rows, self.columns, "Cholesky of a non-square matrix");
+        let size = self.rows;
+        let mut lower = Self::zeros(size, size);
+        for row in 0..size {
+            for column in 0..=row {
+                let sum: f64 = (0..column)
+                    .map(|k| lower[(row, k)] * lower[(column, k)])
+                    .sum();
+                if row == column {
+                    let value = self[(row, row)] - sum;
+                    if value <= 0.0 {
+                        return None;
+                    }
+                    lower[(row, column)] = value.sqrt();
+                } else {
+                    lower[(row, column)] = (self[(row, column)] - sum) / lower[(column, column)];
+                }
+            }
+        }
+        Some(lower)
+    }
+
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        for column in 0..self.columns {
+            self.data
+                .swap(a * self.columns + column, b * self.columns + column);
+        }
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (row, column): (usize, usize)) -> &f64 {
+        &self.data[row * self.columns + column]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (row, column): (usize, usize)) -> &mut f64 {
+        &mut self.data[row * self.columns + column]
+    }
+}
+
+impl Mul for &Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: &Matrix) -> Matrix {
+        assert_eq!(self.columns, other.rows, "dimension mismatch");
+        let mut product = Matrix::zeros(self.rows, other.columns);
+        for row in 0..self.rows {
+            for k in 0..self.columns {
+                let value = self[(row, k)];
+                if value == 0.0 {
+                    continue;
+                }
+                for column in 0..other.columns {
+                    product[(row, column)] += value * other[(k, column)];
+                }
+            }
+        }
+        product
+    }
+}
+
+impl Add for &Matrix {
+    type Output = Matrix;
+
+    fn add(self, other: &Matrix) -> Matrix {
+        assert_eq!((self.rows, self.columns), (other.rows, other.columns));
+        Matrix {
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| a + b)
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Sub for &Matrix {
+    type Output = Matrix;
+
+    fn sub(self, other: &Matrix) -> Matrix {
+        assert_eq!((self.rows, self.columns), (other.rows, other.columns));
+        Matrix {
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| a - b)
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Matrix, b: &Matrix) {
+        assert_eq!((a.rows(), a.columns()), (b.rows(), b.columns()));
+        for (x, y) in a.as_slice().iter().zip(b.as_slice()) {
+            assert!((x - y).abs() < 1e-9, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn inverse_of_a_known_matrix() {
+        let matrix = Matrix::from_rows(&[[4.0, 7.0], [2.0, 6.0]]);
+        let expected = Matrix::from_rows(&[[0.6, -0.7], [-0.2, 0.4]]);
+        assert_close(&matrix.inverse().unwrap(), &expected);
+    }
+
+    #[test]
+    fn inverse_needs_pivoting_and_undoes_the_product() {
+        // A zero in the first pivot position.
+        let matrix = Matrix::from_rows(&[[0.0, 2.0, 1.0], [3.0, 6.0, 1.0], [2.0, 5.0, 3.0]]);
+        let inverse = matrix.inverse().unwrap();
+        assert_close(&(&matrix * &inverse), &Matrix::identity(3));
+        assert_close(&(&inverse * &matrix), &Matrix::identity(3));
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let matrix = Matrix::from_rows(&[[1.0, 2.0], [2.0, 4.0]]);
+        assert_eq!(matrix.inverse(), None);
+    }
+
+    #[test]
+    fn cholesky_of_a_known_matrix() {
+        let matrix = Matrix::from_rows(&[
+            [4.0, 12.0, -16.0],
+            [12.0, 37.0, -43.0],
+            [-16.0, -43.0, 98.0],
+        ]);
+        let lower = matrix.cholesky().unwrap();
+        let expected = Matrix::from_rows(&[[2.0, 0.0, 0.0], [6.0, 1.0, 0.0], [-8.0, 5.0, 3.0]]);
+        assert_close(&lower, &expected);
+        assert_close(&(&lower * &lower.transpose()), &matrix);
+    }
+
+    #[test]
+    fn cholesky_rejects_matrices_that_are_not_positive_definite() {
+        let indefinite = Matrix::from_rows(&[[1.0, 2.0], [2.0, 1.0]]);
+        assert_eq!(indefinite.cholesky(), None);
+        assert_eq!(Matrix::zeros(2, 2).cholesky(), None);
+    }
+}
@@ -0,0 +1,232 @@
+pub mod filter;
+pub mod matrix;
+pub mod planar;
+pub mod spatial;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::drive::EncoderFeedback;
+use crate::joints::{FrameId, TransformTree};
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::{PoseWithCovariance2D, PoseWithCovariance3D, Transform, Vec3};
+use filter::{
+    ExtendedKalmanFilter, KalmanFilter, MeasurementModel, ProcessModel, UnscentedKalmanFilter,
+    UnscentedParameters, UpdateOutcome,
+};
+use matrix::Matrix;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    Extended,
+    Unscented(UnscentedParameters),
+}
+
+/// Noise and outlier gating for one input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputConfig {
+    /// Variance of each measured component.
+    pub variance: Vec<f64>,
+    /// Squared Mahalanobis distance beyond which a measurement is rejected,
+    /// typically a chi-squared quantile for the measurement's dimension.
+    pub gate: Option<f64>,
+}
+
+impl InputConfig {
+    pub fn new(variance: &[f64], gate: Option<f64>) -> Self {
+        Self {
+            variance: variance.to_vec(),
+            gate,
+        }
+    }
+
+    pub fn covariance(&self) -> Matrix {
+        Matrix::from_diagonal(&self.variance)
+    }
+}
+
+/// A measurement for a [`StateEstimator`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EstimatorInput {
+    /// Wheel speeds, or wheel positions differenced against the previous
+    /// reading when the encoders report no speed.
+    WheelEncoders {
+        left: EncoderFeedback,
+        right: EncoderFeedback,
+    },
+    /// Gyroscope reading in the base frame, rad/s.
+    AngularVelocity(Vec3),
+    /// Accelerometer reading in the base frame, m/s², including gravity.
+    LinearAcceleration(Vec3),
+    PlanarPose(PoseWithCovariance2D),
+    Pose(PoseWithCovariance3D),
+}
+
+pub trait StateEstimator {
+    type Estimate;
+
+    /// Predicts forward to `time` (seconds) and fuses `input`. Returns `None`
+    /// if the input carried nothing usable.
+    fn fuse(&mut self, time: f64, input: &EstimatorInput) -> Option<UpdateOutcome>;
+
+    fn estimate(&self) -> Self::Estimate;
+
+    /// Filtered base pose as a transform from the base into its parent frame.
+    fn transform(&self) -> Transform;
+}
+
+/// A filter together with the time of its state.
+pub struct FilterCore {
+    filter: Box<dyn KalmanFilter + Send>,
+    time: Option<f64>,
+}
+
+impl FilterCore {
+    pub fn new<P: ProcessModel + Send + 'static>(
+        kind: FilterKind,
+        model: P,
+        state: Vec<f64>,
+        covariance: Matrix,
+    ) -> Self {
+        let filter: Box<dyn KalmanFilter + Send> = match kind {
+            FilterKind::Extended => Box::new(ExtendedKalmanFilter::new(model, state, covariance)),
+            FilterKind::Unscented(parameters) => Box::new(UnscentedKalmanFilter::new(
+                model, parameters, state, covariance,
+            )),
+        };
+        Self { filter, time: None }
+    }
+
+    pub fn state(&self) -> &[f64] {
+        self.filter.state()
+    }
+
+    pub fn covariance(&self) -> &Matrix {
+        self.filter.covariance()
+    }
+
+    pub fn time(&self) -> Option<f64> {
+        self.time
+    }
+
+    pub fn reset(&mut self, state: Vec<f64>, covariance: Matrix) {
+        self.filter.reset(state, covariance);
+        self.time = None;
+    }
+
+    /// Advances the state to `time`. Measurements older than the state are
+    /// fused without rewinding.
+    pub fn predict_to(&mut self, time: f64) {
+        if let Some(previous) = self.time {
+            let dt = time - previous;
+            if dt <= 0.0 {
+                return;
+            }
+            self.filter.predict(dt);
+        }
+        self.time = Some(time);
+    }
+
+    pub fn fuse(
+        &mut self,
+        time: f64,
+        model: &dyn MeasurementModel,
+        measured: &[f64],
+        covariance: &Matrix,
+        gate: Option<f64>,
+    ) -> UpdateOutcome {
+        self.predict_to(time);
+        self.filter.update(model, measured, covariance, gate)
+    }
+}
+
+// Wheel angular velocities from encoder feedback, differencing positions
+// between readings when the encoders report none.
+#[derive(Clone, Debug, Default)]
+struct WheelSpeeds {
+    last_positions: Option<(f64, f32, f32)>,
+}
+
+impl WheelSpeeds {
+    fn update(
+        &mut self,
+        time: f64,
+        left: &EncoderFeedback,
+        right: &EncoderFeedback,
+    ) -> Option<(f32, f32)> {
+        let velocities = left.velocity.zip(right.velocity);
+        let Some((left, right)) = left.position.zip(right.position) else {
+            return velocities;
+        };
+        let previous = self.last_positions;
+        if previous.is_none_or(|(last_time, _, _)| time > last_time) {
+            self.last_positions = Some((time, left, right));
+        }
+        velocities.or_else(|| {
+            let (last_time, last_left, last_right) = previous?;
+            let dt = (time - last_time) as f32;
+            (dt > 0.0).then(|| ((left - last_left) / dt, (right - last_right) / dt))
+        })
+    }
+}
+
+// Adds an `f32` covariance block, picking `indices` from `source`.
+fn add_covariance<const N: usize>(
+    covariance: &mut Matrix,
+    source: &[[f32; N]; N],
+    indices: &[usize],
+) {
+    for (row, &source_row) in indices.iter().enumerate() {
+        for (column, &source_column) in indices.iter().enumerate() {
+            covariance[(row, column)] += f64::from(source[source_row][source_column]);
+        }
+    }
+}
+
+/// Runs a [`StateEstimator`] as a task, writing the filtered base pose into
+/// the transform tree. Input timestamps are read from the metadata, in
+/// nanoseconds.
+pub struct OdometryFusion<E> {
+    estimator: Mutex<E>,
+    tree: Arc<RwLock<TransformTree>>,
+    base_frame: FrameId,
+}
+
+impl<E: StateEstimator> OdometryFusion<E> {
+    pub fn new(estimator: E, tree: Arc<RwLock<TransformTree>>, base_frame: FrameId) -> Self {
+        Self {
+            estimator: Mutex::new(estimator),
+            tree,
+            base_frame,
+        }
+    }
+
+    pub fn estimate(&self) -> E::Estimate {
+        self.estimator
+            .lock()
+            .expect("StateEstimator lock poisoned")
+            .estimate()
+    }
+}
+
+impl<E: StateEstimator> Task for OdometryFusion<E> {
+    type Input = CarbonData<EstimatorInput>;
+    type Output = CarbonData<E::Estimate>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let mut estimator = self.estimator.lock().expect("StateEstimator lock poisoned");
+        let time = input.metadata.timestamp as f64 * 1e-9;
+        input.map(|input| {
+            if estimator.fuse(time, &input).is_some() {
+                self.tree
+                    .write()
+                    .expect("TransformTree lock poisoned")
+                    .set_transform(self.base_frame, estimator.transform());
+            }
+            estimator.estimate()
+        })
+    }
+}
+
+impl<E: StateEstimator> Controller<EstimatorInput, E::Estimate> for OdometryFusion<E> {}
@@ -0,0 +1,286 @@
+use super::filter::{DirectObservation, MeasurementModel, ProcessModel, UpdateOutcome};
+use super::matrix::Matrix;
+use super::{
+    add_covariance, EstimatorInput, FilterCore, FilterKind, InputConfig, StateEstimator,
+    WheelSpeeds,
+};
+use crate::drive::DifferentialDrive;
+use crate::primitives::{wrap_angle, Pose2D, PoseWithCovariance2D, Transform, Twist2D};
+
+pub const X: usize = 0;
+pub const Y: usize = 1;
+pub const YAW: usize = 2;
+/// Forward speed.
+pub const VELOCITY: usize = 3;
+pub const YAW_RATE: usize = 4;
+/// Forward acceleration.
+pub const ACCELERATION: usize = 5;
+pub const STATE_SIZE: usize = 6;
+
+/// Unicycle with constant yaw rate and forward acceleration.
+#[derive(Clone, Debug)]
+pub struct PlanarMotionModel {
+    /// Variance added to each state component per second.
+    pub process_noise: [f64; STATE_SIZE],
+}
+
+impl ProcessModel for PlanarMotionModel {
+    fn state_size(&self) -> usize {
+        STATE_SIZE
+    }
+
+    fn predict(&self, state: &[f64], dt: f64) -> Vec<f64> {
+        let distance = state[VELOCITY] * dt + 0.5 * state[ACCELERATION] * dt * dt;
+        let heading = state[YAW] + 0.5 * state[YAW_RATE] * dt;
+        let mut predicted = state.to_vec();
+        predicted[X] += distance * heading.cos();
+        predicted[Y] += distance * heading.sin();
+        predicted[YAW] += state[YAW_RATE] * dt;
+        predicted[VELOCITY] += state[ACCELERATION] * dt;
+        predicted
+    }
+
+    fn noise(&self, _state: &[f64], dt: f64) -> Matrix {
+        Matrix::from_diagonal(&self.process_noise.map(|q| q * dt))
+    }
+
+    fn difference(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        let mut difference: Vec<f64> = a.iter().zip(b).map(|(a, b)| a - b).collect();
+        difference[YAW] = wrap_angle(difference[YAW]);
+        difference
+    }
+
+    fn normalize(&self, state: &mut [f64]) {
+        state[YAW] = wrap_angle(state[YAW]);
+    }
+}
+
+/// Planar accelerometer: forward acceleration and the centripetal term.
+pub struct PlanarAcceleration;
+
+impl MeasurementModel for PlanarAcceleration {
+    fn predict(&self, state: &[f64]) -> Vec<f64> {
+        vec![state[ACCELERATION], state[VELOCITY] * state[YAW_RATE]]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlanarEstimatorConfig {
+    pub filter: FilterKind,
+    pub process_noise: [f64; STATE_SIZE],
+    pub initial_variance: [f64; STATE_SIZE],
+    pub drive: DifferentialDrive,
+    /// Forward speed and yaw rate from the wheels.
+    pub wheel_odometry: InputConfig,
+    /// Yaw rate.
+    pub gyroscope: InputConfig,
+    /// Forward and lateral acceleration.
+    pub accelerometer: InputConfig,
+    /// Added to the covariance each pose fix carries.
+    pub pose: InputConfig,
+}
+
+impl PlanarEstimatorConfig {
+    pub fn new(drive: DifferentialDrive) -> Self {
+        Self {
+            filter: FilterKind::Extended,
+            process_noise: [0.01, 0.01, 0.01, 0.5, 0.5, 1.0],
+            initial_variance: [1e-6, 1e-6, 1e-6, 1.0, 1.0, 1.0],
+            drive,
+            wheel_odometry: InputConfig::new(&[1e-3, 1e-2], Some(13.8)),
+            gyroscope: InputConfig::new(&[1e-4], Some(10.8)),
+            accelerometer: InputConfig::new(&[0.1, 0.1], Some(13.8)),
+            pose: InputConfig::new(&[0.0, 0.0, 0.0], Some(16.3)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlanarEstimate {
+    pub pose: PoseWithCovariance2D,
+    pub twist: Twist2D,
+}
+
+/// Fuses wheel odometry, IMU and pose fixes for a robot moving in the plane.
+pub struct PlanarEstimator {
+    pub config: PlanarEstimatorConfig,
+    core: FilterCore,
+    wheels: WheelSpeeds,
+}
+
+impl PlanarEstimator {
+    pub fn new(config: PlanarEstimatorConfig) -> Self {
+        let core = FilterCore::new(
+            config.filter,
+            PlanarMotionModel {
+                process_noise: config.process_noise,
+            },
+            vec![0.0; STATE_SIZE],
+            Matrix::from_diagonal(&config.initial_variance),
+        );
+        Self {
+            config,
+            core,
+            wheels: WheelSpeeds::default(),
+        }
+    }
+
+    /// Restarts from `pose` at rest.
+    pub fn initialize(&mut self, pose: &Pose2D) {
+        let mut state = vec![0.0; STATE_SIZE];
+        state[X] = pose.x.into();
+        state[Y] = pose.y.into();
+        state[YAW] = pose.theta.into();
+        self.core
+            .reset(state, Matrix::from_diagonal(&self.config.initial_variance));
+        self.wheels = WheelSpeeds::default();
+    }
+
+    pub fn core(&self) -> &FilterCore {
+        &self.core
+    }
+
+    fn fuse_pose(&mut self, time: f64, measured: [f64; 3], covariance: Matrix) -> UpdateOutcome {
+        let model = DirectObservation::new(&[X, Y, YAW]).with_angles(&[YAW]);
+        self.core
+            .fuse(time, &model, &measured, &covariance, self.config.pose.gate)
+    }
+}
+
+impl StateEstimator for PlanarEstimator {
+    type Estimate = PlanarEstimate;
+
+    fn fuse(&mut self, time: f64, input: &EstimatorInput) -> Option<UpdateOutcome> {
+        let config = &self.config;
+        match input {
+            EstimatorInput::WheelEncoders { left, right } => {
+                let (left, right) = self.wheels.update(time, left, right)?;
+                let twist = config.drive.twist(left, right);
+                Some(self.core.fuse(
+                    time,
+                    &DirectObservation::new(&[VELOCITY, YAW_RATE]),
+                    &[twist.linear.into(), twist.angular.into()],
+                    &config.wheel_odometry.covariance(),
+                    config.wheel_odometry.gate,
+                ))
+            }
+            EstimatorInput::AngularVelocity(rate) => Some(self.core.fuse(
+                time,
+                &DirectObservation::new(&[YAW_RATE]),
+                &[rate.z.into()],
+                &config.gyroscope.covariance(),
+                config.gyroscope.gate,
+            )),
+            EstimatorInput::LinearAcceleration(acceleration) => Some(self.core.fuse(
+                time,
+                &PlanarAcceleration,
+                &[acceleration.x.into(), acceleration.y.into()],
+                &config.accelerometer.covariance(),
+                config.accelerometer.gate,
+            )),
+            EstimatorInput::PlanarPose(fix) => {
+                let mut covariance = config.pose.covariance();
+                add_covariance(&mut covariance, &fix.covariance, &[0, 1, 2]);
+                let pose = fix.pose;
+                Some(self.fuse_pose(
+                    time,
+                    [pose.x.into(), pose.y.into(), pose.theta.into()],
+                    covariance,
+                ))
+            }
+            EstimatorInput::Pose(fix) => {
+                let mut covariance = config.pose.covariance();
+                add_covariance(&mut covariance, &fix.covariance, &[0, 1, 5]);
+                let position = fix.transform.translation();
+                let (_, _, yaw) = fix.transform.euler();
+                Some(self.fuse_pose(
+                    time,
+                    [position.x.into(), position.y.into(), yaw.into()],
+                    covariance,
+                ))
+            }
+        }
+    }
+
+    fn estimate(&self) -> PlanarEstimate {
+        let state = self.core.state();
+        let covariance = self.core.covariance();
+        let mut pose_covariance = [[0.0; 3]; 3];
+        for (row, values) in pose_covariance.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = covariance[(row, column)] as f32;
+            }
+        }
+        PlanarEstimate {
+            pose: PoseWithCovariance2D {
+                pose: Pose2D::new(state[X] as f32, state[Y] as f32, state[YAW] as f32),
+                covariance: pose_covariance,
+            },
+            twist: Twist2D::new(state[VELOCITY] as f32, state[YAW_RATE] as f32),
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        self.estimate().pose.pose.to_transform()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drive::EncoderFeedback;
+
+    fn positions(left: f32, right: f32) -> EstimatorInput {
+        let encoder = |position| EncoderFeedback {
+            position: Some(position),
+            velocity: None,
+        };
+        EstimatorInput::WheelEncoders {
+            left: encoder(left),
+            right: encoder(right),
+        }
+    }
+
+    fn fix(x: f32, y: f32, theta: f32) -> EstimatorInput {
+        EstimatorInput::PlanarPose(PoseWithCovariance2D {
+            pose: Pose2D::new(x, y, theta),
+            covariance: [[0.01, 0.0, 0.0], [0.0, 0.01, 0.0], [0.0, 0.0, 0.01]],
+        })
+    }
+
+    #[test]
+    fn encoders_with_only_positions_are_differenced() {
+        let mut estimator =
+            PlanarEstimator::new(PlanarEstimatorConfig::new(DifferentialDrive::new(0.1, 0.4)));
+
+        // Nothing to difference against yet.
+        assert_eq!(estimator.fuse(0.0, &positions(0.0, 0.0)), None);
+        // Both wheels at 5 rad/s: 0.5 m/s forward.
+        for step in 1..=50 {
+            let angle = 5.0 * 0.02 * step as f32;
+            let outcome = estimator.fuse(0.02 * f64::from(step), &positions(angle, angle));
+            assert!(outcome.is_some_and(|outcome| outcome.is_accepted()));
+        }
+
+        let estimate = estimator.estimate();
+        assert!((estimate.twist.linear - 0.5).abs() < 0.01, "{estimate:?}");
+        assert!(estimate.twist.angular.abs() < 0.01);
+        assert!((estimate.pose.pose.x - 0.5).abs() < 0.05, "{estimate:?}");
+    }
+
+    #[test]
+    fn an_outlier_pose_fix_is_gated_out() {
+        let mut estimator =
+            PlanarEstimator::new(PlanarEstimatorConfig::new(DifferentialDrive::new(0.1, 0.4)));
+        estimator.initialize(&Pose2D::new(1.0, 2.0, 0.5));
+
+        let outcome = estimator.fuse(0.1, &fix(1.02, 1.98, 0.51)).unwrap();
+        assert!(outcome.is_accepted());
+        let before = estimator.estimate().pose.pose;
+
+        let outcome = estimator.fuse(0.2, &fix(6.0, -3.0, 2.5)).unwrap();
+        assert!(!outcome.is_accepted(), "{outcome:?}");
+        let after = estimator.estimate().pose.pose;
+        assert!(after.position().distance(before.position()) < 0.01);
+    }
+}
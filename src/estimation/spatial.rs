@@ -0,0 +1,335 @@
+use glam::{DMat3, DQuat, DVec3, EulerRot};
+
+use super::filter::{DirectObservation, MeasurementModel, ProcessModel, UpdateOutcome};
+use super::matrix::Matrix;
+use super::{
+    add_covariance, EstimatorInput, FilterCore, FilterKind, InputConfig, StateEstimator,
+    WheelSpeeds,
+};
+use crate::drive::DifferentialDrive;
+use crate::primitives::{wrap_angle, PoseWithCovariance3D, Transform, Twist3D};
+
+/// Position in the parent frame.
+pub const POSITION: usize = 0;
+/// Roll, pitch and yaw.
+pub const ORIENTATION: usize = 3;
+/// Linear velocity in the body frame.
+pub const VELOCITY: usize = 6;
+/// Angular velocity in the body frame.
+pub const ANGULAR_VELOCITY: usize = 9;
+/// Linear acceleration in the body frame, excluding gravity.
+pub const ACCELERATION: usize = 12;
+pub const STATE_SIZE: usize = 15;
+
+pub const STANDARD_GRAVITY: f64 = 9.80665;
+
+fn vector(state: &[f64], start: usize) -> DVec3 {
+    DVec3::new(state[start], state[start + 1], state[start + 2])
+}
+
+fn rotation(state: &[f64]) -> DMat3 {
+    let [roll, pitch, yaw] = [
+        state[ORIENTATION],
+        state[ORIENTATION + 1],
+        state[ORIENTATION + 2],
+    ];
+    DMat3::from_quat(DQuat::from_euler(EulerRot::ZYX, yaw, pitch, roll))
+}
+
+/// Rigid body with constant body-frame angular velocity and acceleration.
+/// Orientation is integrated in roll, pitch and yaw, so pitch must stay away
+/// from ±90°.
+#[derive(Clone, Debug)]
+pub struct SpatialMotionModel {
+    /// Variance added to each state component per second.
+    pub process_noise: [f64; STATE_SIZE],
+}
+
+impl ProcessModel for SpatialMotionModel {
+    fn state_size(&self) -> usize {
+        STATE_SIZE
+    }
+
+    fn predict(&self, state: &[f64], dt: f64) -> Vec<f64> {
+        let velocity = vector(state, VELOCITY);
+        let acceleration = vector(state, ACCELERATION);
+        let rates = vector(state, ANGULAR_VELOCITY);
+        let displacement = rotation(state) * (velocity * dt + 0.5 * acceleration * dt * dt);
+
+        let (roll, pitch) = (state[ORIENTATION], state[ORIENTATION + 1]);
+        let (sin_roll, cos_roll) = roll.sin_cos();
+        let (tan_pitch, cos_pitch) = (pitch.tan(), pitch.cos());
+        let euler_rates = DVec3::new(
+            rates.x + (sin_roll * rates.y + cos_roll * rates.z) * tan_pitch,
+            cos_roll * rates.y - sin_roll * rates.z,
+            (sin_roll * rates.y + cos_roll * rates.z) / cos_pitch,
+        );
+
+        let mut predicted = state.to_vec();
+        for axis in 0..3 {
+            predicted[POSITION + axis] += displacement[axis];
+            predicted[ORIENTATION + axis] += euler_rates[axis] * dt;
+            predicted[VELOCITY + axis] += acceleration[axis] * dt;
+        }
+        predicted
+    }
+
+    fn noise(&self, _state: &[f64], dt: f64) -> Matrix {
+        Matrix::from_diagonal(&self.process_noise.map(|q| q * dt))
+    }
+
+    fn difference(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        let mut difference: Vec<f64> = a.iter().zip(b).map(|(a, b)| a - b).collect();
+        for value in &mut difference[ORIENTATION..ORIENTATION + 3] {
+            *value = wrap_angle(*value);
+        }
+        difference
+    }
+
+    fn normalize(&self, state: &mut [f64]) {
+        for value in &mut state[ORIENTATION..ORIENTATION + 3] {
+            *value = wrap_angle(*value);
+        }
+    }
+}
+
+/// Accelerometer reading: body acceleration plus the reaction to gravity.
+pub struct SpecificForce;
+
+impl MeasurementModel for SpecificForce {
+    fn predict(&self, state: &[f64]) -> Vec<f64> {
+        let gravity = rotation(state).transpose() * DVec3::new(0.0, 0.0, STANDARD_GRAVITY);
+        (vector(state, ACCELERATION) + gravity).to_array().to_vec()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpatialEstimatorConfig {
+    pub filter: FilterKind,
+    pub process_noise: [f64; STATE_SIZE],
+    pub initial_variance: [f64; STATE_SIZE],
+    pub drive: DifferentialDrive,
+    /// Forward, lateral and vertical speed and yaw rate from the wheels; the
+    /// lateral and vertical speeds are measured as zero.
+    pub wheel_odometry: InputConfig,
+    /// Angular velocity about x, y and z.
+    pub gyroscope: InputConfig,
+    /// Specific force along x, y and z.
+    pub accelerometer: InputConfig,
+    /// Added to the covariance each pose fix carries.
+    pub pose: InputConfig,
+}
+
+impl SpatialEstimatorConfig {
+    pub fn new(drive: DifferentialDrive) -> Self {
+        let mut process_noise = [0.01; STATE_SIZE];
+        process_noise[VELOCITY..VELOCITY + 3].fill(0.5);
+        process_noise[ANGULAR_VELOCITY..ANGULAR_VELOCITY + 3].fill(0.5);
+        process_noise[ACCELERATION..ACCELERATION + 3].fill(1.0);
+        let mut initial_variance = [1.0; STATE_SIZE];
+        initial_variance[..6].fill(1e-6);
+        Self {
+            filter: FilterKind::Extended,
+            process_noise,
+            initial_variance,
+            drive,
+            wheel_odometry: InputConfig::new(&[1e-3, 1e-3, 1e-3, 1e-2], Some(18.5)),
+            gyroscope: InputConfig::new(&[1e-4; 3], Some(16.3)),
+            accelerometer: InputConfig::new(&[0.1; 3], Some(16.3)),
+            pose: InputConfig::new(&[0.0; 6], Some(22.5)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpatialEstimate {
+    pub pose: PoseWithCovariance3D,
+    pub twist: Twist3D,
+}
+
+/// Fuses wheel odometry, IMU and pose fixes for a robot moving in 3D, such as
+/// a wheeled base on uneven ground.
+pub struct SpatialEstimator {
+    pub config: SpatialEstimatorConfig,
+    core: FilterCore,
+    wheels: WheelSpeeds,
+}
+
+impl SpatialEstimator {
+    pub fn new(config: SpatialEstimatorConfig) -> Self {
+        let core = FilterCore::new(
+            config.filter,
+            SpatialMotionModel {
+                process_noise: config.process_noise,
+            },
+            vec![0.0; STATE_SIZE],
+            Matrix::from_diagonal(&config.initial_variance),
+        );
+        Self {
+            config,
+            core,
+            wheels: WheelSpeeds::default(),
+        }
+    }
+
+    /// Restarts from `transform` at rest.
+    pub fn initialize(&mut self, transform: &Transform) {
+        let mut state = vec![0.0; STATE_SIZE];
+        let position = transform.translation();
+        let (roll, pitch, yaw) = transform.euler();
+        for (index, value) in [position.x, position.y, position.z, roll, pitch, yaw]
+            .into_iter()
+            .enumerate()
+        {
+            state[index] = value.into();
+        }
+        self.core
+            .reset(state, Matrix::from_diagonal(&self.config.initial_variance));
+        self.wheels = WheelSpeeds::default();
+    }
+
+    pub fn core(&self) -> &FilterCore {
+        &self.core
+    }
+}
+
+impl StateEstimator for SpatialEstimator {
+    type Estimate = SpatialEstimate;
+
+    fn fuse(&mut self, time: f64, input: &EstimatorInput) -> Option<UpdateOutcome> {
+        let config = &self.config;
+        let yaw = ORIENTATION + 2;
+        match input {
+            EstimatorInput::WheelEncoders { left, right } => {
+                let (left, right) = self.wheels.update(time, left, right)?;
+                let twist = config.drive.twist(left, right);
+                Some(self.core.fuse(
+                    time,
+                    &DirectObservation::new(&[
+                        VELOCITY,
+                        VELOCITY + 1,
+                        VELOCITY + 2,
+                        ANGULAR_VELOCITY + 2,
+                    ]),
+                    &[twist.linear.into(), 0.0, 0.0, twist.angular.into()],
+                    &config.wheel_odometry.covariance(),
+                    config.wheel_odometry.gate,
+                ))
+            }
+            EstimatorInput::AngularVelocity(rate) => Some(self.core.fuse(
+                time,
+                &DirectObservation::new(&[
+                    ANGULAR_VELOCITY,
+                    ANGULAR_VELOCITY + 1,
+                    ANGULAR_VELOCITY + 2,
+                ]),
+                &rate.as_dvec3().to_array(),
+                &config.gyroscope.covariance(),
+                config.gyroscope.gate,
+            )),
+            EstimatorInput::LinearAcceleration(acceleration) => Some(self.core.fuse(
+                time,
+                &SpecificForce,
+                &acceleration.as_dvec3().to_array(),
+                &config.accelerometer.covariance(),
+                config.accelerometer.gate,
+            )),
+            EstimatorInput::PlanarPose(fix) => {
+                let mut covariance = Matrix::from_diagonal(&[
+                    config.pose.variance[0],
+                    config.pose.variance[1],
+                    config.pose.variance[5],
+                ]);
+                add_covariance(&mut covariance, &fix.covariance, &[0, 1, 2]);
+                let pose = fix.pose;
+                Some(self.core.fuse(
+                    time,
+                    &DirectObservation::new(&[POSITION, POSITION + 1, yaw]).with_angles(&[yaw]),
+                    &[pose.x.into(), pose.y.into(), pose.theta.into()],
+                    &covariance,
+                    config.pose.gate,
+                ))
+            }
+            EstimatorInput::Pose(fix) => {
+                let mut covariance = config.pose.covariance();
+                add_covariance(&mut covariance, &fix.covariance, &[0, 1, 2, 3, 4, 5]);
+                let position = fix.transform.translation();
+                let (roll, pitch, yaw) = fix.transform.euler();
+                let measured = [position.x, position.y, position.z, roll, pitch, yaw];
+                Some(self.core.fuse(
+                    time,
+                    &DirectObservation::new(&[0, 1, 2, 3, 4, 5]).with_angles(&[3, 4, 5]),
+                    &measured.map(f64::from),
+                    &covariance,
+                    config.pose.gate,
+                ))
+            }
+        }
+    }
+
+    fn estimate(&self) -> SpatialEstimate {
+        let state = self.core.state();
+        let covariance = self.core.covariance();
+        let mut pose_covariance = [[0.0; 6]; 6];
+        for (row, values) in pose_covariance.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = covariance[(row, column)] as f32;
+            }
+        }
+        let component = |index: usize| state[index] as f32;
+        SpatialEstimate {
+            pose: PoseWithCovariance3D {
+                transform: Transform::from_translation_and_euler(
+                    vector(state, POSITION).as_vec3(),
+                    component(ORIENTATION),
+                    component(ORIENTATION + 1),
+                    component(ORIENTATION + 2),
+                ),
+                covariance: pose_covariance,
+            },
+            twist: Twist3D::new(
+                vector(state, VELOCITY).as_vec3(),
+                vector(state, ANGULAR_VELOCITY).as_vec3(),
+            ),
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        self.estimate().pose.transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drive::EncoderFeedback;
+
+    #[test]
+    fn encoders_with_only_positions_are_differenced() {
+        let mut estimator = SpatialEstimator::new(SpatialEstimatorConfig::new(
+            DifferentialDrive::new(0.1, 0.4),
+        ));
+        let encoders = |left: f32, right: f32| EstimatorInput::WheelEncoders {
+            left: EncoderFeedback {
+                position: Some(left),
+                velocity: None,
+            },
+            right: EncoderFeedback {
+                position: Some(right),
+                velocity: None,
+            },
+        };
+
+        assert_eq!(estimator.fuse(0.0, &encoders(0.0, 0.0)), None);
+        // Left at 4 rad/s and right at 6 rad/s: 0.5 m/s turning at 0.5 rad/s.
+        for step in 1..=50 {
+            let time = 0.02 * step as f32;
+            let outcome = estimator.fuse(f64::from(time), &encoders(4.0 * time, 6.0 * time));
+            assert!(outcome.is_some_and(|outcome| outcome.is_accepted()));
+        }
+
+        let twist = estimator.estimate().twist;
+        assert!((twist.linear.x - 0.5).abs() < 0.01, "{twist:?}");
+        assert!((twist.angular.z - 0.5).abs() < 0.02, "{twist:?}");
+    }
+}
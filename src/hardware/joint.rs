@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+
+use super::{CommandHandle, HardwareController, InterfaceKind, InterfaceName, StateHandle};
+use crate::control::{ActuatorCommand, JointController};
+use crate::drive::EncoderFeedback;
+use crate::joints::{FrameId, Joint};
+use crate::primitives::JointCommand;
+
+/// Setpoint of a [`JointLoop`], shared with whatever commands the joint, e.g.
+/// a [`crate::control::trajectory::TrajectoryController`].
+#[derive(Clone, Debug, Default)]
+pub struct JointSetpoint {
+    frames: (FrameId, FrameId),
+    command: Arc<Mutex<JointCommand>>,
+}
+
+impl JointSetpoint {
+    pub fn get(&self) -> JointCommand {
+        *self.command.lock().expect("Setpoint lock poisoned")
+    }
+}
+
+impl Joint for JointSetpoint {
+    fn get_joint(&self) -> (FrameId, FrameId) {
+        self.frames
+    }
+
+    fn set_joint(&mut self, parent: FrameId, child: FrameId) {
+        self.frames = (parent, child);
+    }
+
+    fn update_joint(&mut self, command: JointCommand) {
+        *self.command.lock().expect("Setpoint lock poisoned") = command;
+    }
+}
+
+/// Runs a [`JointController`] on one joint's interfaces: it reads the
+/// position state, and the velocity state if asked to, and claims the
+/// command interface of the kind the controller outputs. Outputs of any other
+/// kind leave the command unset.
+pub struct JointLoop {
+    name: String,
+    joint: String,
+    output: InterfaceKind,
+    measured_velocity: bool,
+    controller: JointController,
+    setpoint: JointSetpoint,
+    states: Vec<StateHandle>,
+    command: Option<CommandHandle>,
+}
+
+impl JointLoop {
+    pub fn new(
+        name: &str,
+        joint: &str,
+        controller: JointController,
+        output: InterfaceKind,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            joint: joint.to_string(),
+            output,
+            measured_velocity: false,
+            controller,
+            setpoint: JointSetpoint::default(),
+            states: Vec::new(),
+            command: None,
+        }
+    }
+
+    /// Reads the joint's velocity state rather than differentiating
+    /// positions.
+    pub fn with_measured_velocity(mut self) -> Self {
+        self.measured_velocity = true;
+        self
+    }
+
+    /// A handle for commanding the joint.
+    pub fn setpoint(&self) -> JointSetpoint {
+        self.setpoint.clone()
+    }
+}
+
+impl HardwareController for JointLoop {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn state_interfaces(&self) -> Vec<InterfaceName> {
+        let mut names = vec![InterfaceName::new(&self.joint, InterfaceKind::Position)];
+        if self.measured_velocity {
+            names.push(InterfaceName::new(&self.joint, InterfaceKind::Velocity));
+        }
+        names
+    }
+
+    fn command_interfaces(&self) -> Vec<InterfaceName> {
+        vec![InterfaceName::new(&self.joint, self.output)]
+    }
+
+    fn activate(&mut self, states: Vec<StateHandle>, commands: Vec<CommandHandle>) {
+        self.controller.reset();
+        self.states = states;
+        self.command = commands.into_iter().next();
+    }
+
+    fn update(&mut self, dt: f32) {
+        let Some(command) = &self.command else {
+            return;
+        };
+        let feedback = EncoderFeedback {
+            position: self.states.first().and_then(StateHandle::get),
+            velocity: self.states.get(1).and_then(StateHandle::get),
+        };
+        let output = match self.controller.update(&self.setpoint.get(), &feedback, dt) {
+            Some(ActuatorCommand::Position(value)) if self.output == InterfaceKind::Position => {
+                Some(value)
+            }
+            Some(ActuatorCommand::Velocity(value)) if self.output == InterfaceKind::Velocity => {
+                Some(value)
+            }
+            Some(ActuatorCommand::Effort(value)) if self.output == InterfaceKind::Effort => {
+                Some(value)
+            }
+            _ => None,
+        };
+        match output {
+            Some(value) => command.set(value),
+            None => command.clear(),
+        };
+    }
+
+    fn deactivate(&mut self) {
+        if let Some(command) = self.command.take() {
+            command.clear();
+        }
+        self.states.clear();
+    }
+}
@@ -0,0 +1,616 @@
+pub mod joint;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub use joint::{JointLoop, JointSetpoint};
+
+/// What an interface carries, in the units of [`crate::primitives::JointCommand`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InterfaceKind {
+    Position,
+    Velocity,
+    Effort,
+}
+
+impl fmt::Display for InterfaceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InterfaceKind::Position => "position",
+            InterfaceKind::Velocity => "velocity",
+            InterfaceKind::Effort => "effort",
+        })
+    }
+}
+
+/// A joint's interface, shown as e.g. `left_wheel/velocity`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InterfaceName {
+    pub joint: String,
+    pub kind: InterfaceKind,
+}
+
+impl InterfaceName {
+    pub fn new(joint: &str, kind: InterfaceKind) -> Self {
+        Self {
+            joint: joint.to_string(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for InterfaceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.joint, self.kind)
+    }
+}
+
+#[derive(Debug)]
+pub enum HalError {
+    DuplicateInterface(InterfaceName),
+    UnknownInterface(InterfaceName),
+    /// The command interface is already claimed by `owner`.
+    AlreadyClaimed {
+        interface: InterfaceName,
+        owner: String,
+    },
+    DuplicateController(String),
+    /// A driver failed to read or write.
+    Hardware {
+        name: String,
+        error: io::Error,
+    },
+}
+
+impl fmt::Display for HalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HalError::DuplicateInterface(name) => write!(f, "interface {name} already exported"),
+            HalError::UnknownInterface(name) => write!(f, "no interface {name}"),
+            HalError::AlreadyClaimed { interface, owner } => {
+                write!(f, "interface {interface} already claimed by {owner:?}")
+            }
+            HalError::DuplicateController(name) => write!(f, "controller {name:?} already loaded"),
+            HalError::Hardware { name, error } => write!(f, "hardware {name:?} failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for HalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HalError::Hardware { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// A value shared between the manager and handles without locking. NaN means
+// unset.
+#[derive(Clone, Debug)]
+struct Value(Arc<AtomicU32>);
+
+impl Value {
+    fn new() -> Self {
+        Self(Arc::new(AtomicU32::new(f32::NAN.to_bits())))
+    }
+
+    fn get(&self) -> Option<f32> {
+        let value = f32::from_bits(self.0.load(Ordering::Acquire));
+        (!value.is_nan()).then_some(value)
+    }
+
+    fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Release);
+    }
+}
+
+/// Read access to a state interface, updated on every
+/// [`HardwareManager::read`].
+#[derive(Clone, Debug)]
+pub struct StateHandle {
+    name: InterfaceName,
+    value: Value,
+}
+
+impl StateHandle {
+    pub fn name(&self) -> &InterfaceName {
+        &self.name
+    }
+
+    /// `None` until the hardware has reported a value.
+    pub fn get(&self) -> Option<f32> {
+        self.value.get()
+    }
+}
+
+// A command value in the low half, and in the high half the generation of
+// the claim allowed to write it. Releasing a claim moves the generation on,
+// so handles from earlier claims can no longer write. Both halves share one
+// atomic so a write cannot slip in after a release.
+#[derive(Clone, Debug)]
+struct Command(Arc<AtomicU64>);
+
+fn pack(generation: u32, value: f32) -> u64 {
+    (generation as u64) << 32 | value.to_bits() as u64
+}
+
+impl Command {
+    fn new() -> Self {
+        Self(Arc::new(AtomicU64::new(pack(0, f32::NAN))))
+    }
+
+    fn generation(&self) -> u32 {
+        (self.0.load(Ordering::Acquire) >> 32) as u32
+    }
+
+    fn get(&self) -> Option<f32> {
+        let value = f32::from_bits(self.0.load(Ordering::Acquire) as u32);
+        (!value.is_nan()).then_some(value)
+    }
+
+    fn set(&self, generation: u32, value: f32) -> bool {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                ((current >> 32) as u32 == generation).then_some(pack(generation, value))
+            })
+            .is_ok()
+    }
+
+    // Ends the current claim and clears the value.
+    fn revoke(&self) {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                Some(pack(((current >> 32) as u32).wrapping_add(1), f32::NAN))
+            })
+            .expect("revoking always succeeds");
+    }
+}
+
+/// Exclusive write access to a command interface, sent to the hardware on
+/// every [`HardwareManager::write`]. Once the claim is released the handle
+/// can no longer write, even if the interface is claimed again.
+#[derive(Debug)]
+pub struct CommandHandle {
+    name: InterfaceName,
+    command: Command,
+    generation: u32,
+}
+
+impl CommandHandle {
+    pub fn name(&self) -> &InterfaceName {
+        &self.name
+    }
+
+    /// Whether the claim is still held.
+    pub fn is_held(&self) -> bool {
+        self.command.generation() == self.generation
+    }
+
+    /// `None` once the claim is released.
+    pub fn get(&self) -> Option<f32> {
+        self.command.get().filter(|_| self.is_held())
+    }
+
+    /// Returns `false`, changing nothing, once the claim is released.
+    pub fn set(&self, value: f32) -> bool {
+        self.command.set(self.generation, value)
+    }
+
+    /// Sends nothing until set again.
+    pub fn clear(&self) -> bool {
+        self.set(f32::NAN)
+    }
+}
+
+/// A driver exporting state and command interfaces, e.g. a motor board.
+pub trait Hardware: Send {
+    fn name(&self) -> &str;
+    /// In the order [`Hardware::read`] fills them.
+    fn state_interfaces(&self) -> Vec<InterfaceName>;
+    /// In the order [`Hardware::write`] receives them.
+    fn command_interfaces(&self) -> Vec<InterfaceName>;
+    /// Overwrites the states it has fresh values for; the rest hold their
+    /// previous value, NaN if never read.
+    fn read(&mut self, states: &mut [f32]) -> io::Result<()>;
+    /// `None` for commands that are unclaimed or unset.
+    fn write(&mut self, commands: &[Option<f32>]) -> io::Result<()>;
+}
+
+/// A controller run by the [`HardwareManager`] between reading and writing
+/// the hardware.
+pub trait HardwareController: Send {
+    fn name(&self) -> &str;
+    fn state_interfaces(&self) -> Vec<InterfaceName>;
+    /// Claimed exclusively while the controller is loaded.
+    fn command_interfaces(&self) -> Vec<InterfaceName>;
+    /// Called on loading with handles in the order asked for.
+    fn activate(&mut self, states: Vec<StateHandle>, commands: Vec<CommandHandle>);
+    fn update(&mut self, dt: f32);
+    /// Called on unloading, before the claims are released.
+    fn deactivate(&mut self) {}
+}
+
+// The first of `names` already in `table` or repeated in `names`.
+fn duplicate<'a, V>(
+    names: &'a [InterfaceName],
+    table: &HashMap<InterfaceName, V>,
+) -> Option<&'a InterfaceName> {
+    names
+        .iter()
+        .enumerate()
+        .find(|(index, name)| table.contains_key(name) || names[..*index].contains(name))
+        .map(|(_, name)| name)
+}
+
+struct Registered {
+    hardware: Box<dyn Hardware>,
+    states: Vec<Value>,
+    commands: Vec<Command>,
+}
+
+/// Owns the hardware and the values of their interfaces, hands out state
+/// handles to anyone and command handles to one owner at a time, and runs the
+/// read → update → write cycle on the control thread.
+#[derive(Default)]
+pub struct HardwareManager {
+    hardware: Vec<Registered>,
+    states: HashMap<InterfaceName, Value>,
+    commands: HashMap<InterfaceName, Command>,
+    claims: HashMap<InterfaceName, String>,
+    controllers: Vec<Box<dyn HardwareController>>,
+}
+
+impl HardwareManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a driver's interfaces. Fails without registering anything if
+    /// one is already exported.
+    pub fn add_hardware(&mut self, hardware: impl Hardware + 'static) -> Result<(), HalError> {
+        let (states, commands) = (hardware.state_interfaces(), hardware.command_interfaces());
+        let duplicate =
+            duplicate(&states, &self.states).or_else(|| duplicate(&commands, &self.commands));
+        if let Some(name) = duplicate {
+            return Err(HalError::DuplicateInterface(name.clone()));
+        }
+        let states = states
+            .into_iter()
+            .map(|name| self.states.entry(name).or_insert_with(Value::new).clone())
+            .collect();
+        let commands = commands
+            .into_iter()
+            .map(|name| {
+                self.commands
+                    .entry(name)
+                    .or_insert_with(Command::new)
+                    .clone()
+            })
+            .collect();
+        self.hardware.push(Registered {
+            hardware: Box::new(hardware),
+            states,
+            commands,
+        });
+        Ok(())
+    }
+
+    pub fn state_interfaces(&self) -> Vec<InterfaceName> {
+        let mut names: Vec<InterfaceName> = self.states.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn command_interfaces(&self) -> Vec<InterfaceName> {
+        let mut names: Vec<InterfaceName> = self.commands.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn state(&self, name: &InterfaceName) -> Result<StateHandle, HalError> {
+        self.states
+            .get(name)
+            .map(|value| StateHandle {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .ok_or_else(|| HalError::UnknownInterface(name.clone()))
+    }
+
+    /// Who holds a command interface.
+    pub fn owner(&self, name: &InterfaceName) -> Option<&str> {
+        self.claims.get(name).map(String::as_str)
+    }
+
+    /// Exclusive access to a command interface for `owner`.
+    pub fn claim(&mut self, owner: &str, name: &InterfaceName) -> Result<CommandHandle, HalError> {
+        let command = self
+            .commands
+            .get(name)
+            .ok_or_else(|| HalError::UnknownInterface(name.clone()))?;
+        if let Some(holder) = self.claims.get(name) {
+            return Err(HalError::AlreadyClaimed {
+                interface: name.clone(),
+                owner: holder.clone(),
+            });
+        }
+        self.claims.insert(name.clone(), owner.to_string());
+        Ok(CommandHandle {
+            name: name.clone(),
+            command: command.clone(),
+            generation: command.generation(),
+        })
+    }
+
+    /// Gives up the claim `handle` holds and clears the command. Returns
+    /// `false` if the claim had already ended, e.g. with its controller
+    /// unloading.
+    pub fn release(&mut self, handle: CommandHandle) -> bool {
+        if !handle.is_held() {
+            return false;
+        }
+        self.revoke(&handle.name);
+        true
+    }
+
+    fn revoke(&mut self, name: &InterfaceName) {
+        self.claims.remove(name);
+        if let Some(command) = self.commands.get(name) {
+            command.revoke();
+        }
+    }
+
+    /// Claims the controller's interfaces and activates it. Fails without
+    /// claiming anything if an interface is unknown or taken.
+    pub fn load_controller(
+        &mut self,
+        mut controller: impl HardwareController + 'static,
+    ) -> Result<(), HalError> {
+        let name = controller.name().to_string();
+        if self.controllers.iter().any(|loaded| loaded.name() == name) {
+            return Err(HalError::DuplicateController(name));
+        }
+        let states = controller
+            .state_interfaces()
+            .iter()
+            .map(|interface| self.state(interface))
+            .collect::<Result<Vec<_>, _>>()?;
+        let wanted = controller.command_interfaces();
+        let mut commands = Vec::with_capacity(wanted.len());
+        for interface in &wanted {
+            match self.claim(&name, interface) {
+                Ok(handle) => commands.push(handle),
+                Err(error) => {
+                    for handle in commands {
+                        self.release(handle);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        controller.activate(states, commands);
+        self.controllers.push(Box::new(controller));
+        Ok(())
+    }
+
+    /// Deactivates a controller and releases its claims. Returns `false` if
+    /// it was not loaded.
+    pub fn unload_controller(&mut self, name: &str) -> bool {
+        let Some(index) = self
+            .controllers
+            .iter()
+            .position(|controller| controller.name() == name)
+        else {
+            return false;
+        };
+        let mut controller = self.controllers.remove(index);
+        controller.deactivate();
+        for interface in controller.command_interfaces() {
+            if self.owner(&interface) == Some(name) {
+                self.revoke(&interface);
+            }
+        }
+        true
+    }
+
+    pub fn controllers(&self) -> impl Iterator<Item = &str> {
+        self.controllers.iter().map(|controller| controller.name())
+    }
+
+    /// Refreshes every state from the hardware.
+    pub fn read(&mut self) -> Result<(), HalError> {
+        for registered in &mut self.hardware {
+            let mut states: Vec<f32> = registered
+                .states
+                .iter()
+                .map(|value| value.get().unwrap_or(f32::NAN))
+                .collect();
+            registered
+                .hardware
+                .read(&mut states)
+                .map_err(|error| HalError::Hardware {
+                    name: registered.hardware.name().to_string(),
+                    error,
+                })?;
+            for (value, state) in registered.states.iter().zip(states) {
+                value.set(state);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every loaded controller in load order.
+    pub fn update(&mut self, dt: f32) {
+        for controller in &mut self.controllers {
+            controller.update(dt);
+        }
+    }
+
+    /// Sends every command to the hardware.
+    pub fn write(&mut self) -> Result<(), HalError> {
+        for registered in &mut self.hardware {
+            let commands: Vec<Option<f32>> = registered.commands.iter().map(Command::get).collect();
+            registered
+                .hardware
+                .write(&commands)
+                .map_err(|error| HalError::Hardware {
+                    name: registered.hardware.name().to_string(),
+                    error,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// One control cycle of `dt` seconds. A failed read skips the update and
+    /// write, so no controller acts on stale states.
+    pub fn cycle(&mut self, dt: f32) -> Result<(), HalError> {
+        self.read()?;
+        self.update(dt);
+        self.write()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // A wheel whose last written commands can be inspected.
+    struct Wheel {
+        written: Arc<Mutex<Vec<Option<f32>>>>,
+    }
+
+    impl Hardware for Wheel {
+        fn name(&self) -> &str {
+            "wheel"
+        }
+
+        fn state_interfaces(&self) -> Vec<InterfaceName> {
+            vec![InterfaceName::new("wheel", InterfaceKind::Velocity)]
+        }
+
+        fn command_interfaces(&self) -> Vec<InterfaceName> {
+            vec![InterfaceName::new("wheel", InterfaceKind::Velocity)]
+        }
+
+        fn read(&mut self, states: &mut [f32]) -> io::Result<()> {
+            states[0] = 1.0;
+            Ok(())
+        }
+
+        fn write(&mut self, commands: &[Option<f32>]) -> io::Result<()> {
+            *self.written.lock().unwrap() = commands.to_vec();
+            Ok(())
+        }
+    }
+
+    // Holds on to its command handle after being unloaded.
+    struct Spinner {
+        command: Arc<Mutex<Option<CommandHandle>>>,
+    }
+
+    impl HardwareController for Spinner {
+        fn name(&self) -> &str {
+            "spinner"
+        }
+
+        fn state_interfaces(&self) -> Vec<InterfaceName> {
+            Vec::new()
+        }
+
+        fn command_interfaces(&self) -> Vec<InterfaceName> {
+            vec![velocity()]
+        }
+
+        fn activate(&mut self, _states: Vec<StateHandle>, commands: Vec<CommandHandle>) {
+            *self.command.lock().unwrap() = commands.into_iter().next();
+        }
+
+        fn update(&mut self, _dt: f32) {}
+    }
+
+    fn velocity() -> InterfaceName {
+        InterfaceName::new("wheel", InterfaceKind::Velocity)
+    }
+
+    fn manager() -> (HardwareManager, Arc<Mutex<Vec<Option<f32>>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = HardwareManager::new();
+        manager
+            .add_hardware(Wheel {
+                written: written.clone(),
+            })
+            .unwrap();
+        (manager, written)
+    }
+
+    #[test]
+    fn commands_reach_the_hardware() {
+        let (mut manager, written) = manager();
+        let state = manager.state(&velocity()).unwrap();
+        let command = manager.claim("teleop", &velocity()).unwrap();
+        assert!(command.set(0.5));
+        manager.cycle(0.01).unwrap();
+        assert_eq!(state.get(), Some(1.0));
+        assert_eq!(*written.lock().unwrap(), [Some(0.5)]);
+        assert!(matches!(
+            manager.claim("planner", &velocity()),
+            Err(HalError::AlreadyClaimed { .. })
+        ));
+    }
+
+    #[test]
+    fn released_handles_cannot_write() {
+        let (mut manager, written) = manager();
+        let first = manager.claim("teleop", &velocity()).unwrap();
+        first.set(0.5);
+        // A copy of the handle's command survives the release inside the
+        // manager, as a controller might keep one around.
+        let stale = CommandHandle {
+            name: first.name.clone(),
+            command: first.command.clone(),
+            generation: first.generation,
+        };
+        assert!(manager.release(first));
+        assert_eq!(manager.owner(&velocity()), None);
+        manager.write().unwrap();
+        assert_eq!(*written.lock().unwrap(), [None]);
+
+        let second = manager.claim("planner", &velocity()).unwrap();
+        assert!(!stale.set(2.0));
+        assert!(!stale.is_held());
+        assert_eq!(stale.get(), None);
+        assert!(second.set(0.25));
+        manager.write().unwrap();
+        assert_eq!(*written.lock().unwrap(), [Some(0.25)]);
+
+        // Nor can they give up someone else's claim.
+        assert!(!manager.release(stale));
+        assert_eq!(manager.owner(&velocity()), Some("planner"));
+    }
+
+    #[test]
+    fn unloading_a_controller_ends_its_claims() {
+        let (mut manager, written) = manager();
+        let command = Arc::new(Mutex::new(None));
+        manager
+            .load_controller(Spinner {
+                command: command.clone(),
+            })
+            .unwrap();
+        assert_eq!(manager.owner(&velocity()), Some("spinner"));
+        let handle = command.lock().unwrap().take().unwrap();
+        assert!(handle.set(3.0));
+
+        assert!(manager.unload_controller("spinner"));
+        assert_eq!(manager.owner(&velocity()), None);
+        assert!(!handle.set(3.0));
+        manager.write().unwrap();
+        assert_eq!(*written.lock().unwrap(), [None]);
+    }
+}
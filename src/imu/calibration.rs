@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+
+use crate::estimation::matrix::Matrix;
+use crate::primitives::Vec3;
+
+/// Per-axis offset and scale correction: `(raw - offset) * scale`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorCalibration {
+    pub offset: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for SensorCalibration {
+    fn default() -> Self {
+        Self {
+            offset: Vec3::ZERO,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl SensorCalibration {
+    pub fn apply(&self, raw: Vec3) -> Vec3 {
+        (raw - self.offset) * self.scale
+    }
+
+    /// Fits an axis-aligned ellipsoid to readings of a field of known
+    /// `magnitude` taken in many orientations: gravity for an accelerometer
+    /// held still, or the earth's field for a magnetometer (hard iron offset
+    /// and diagonal soft iron scale). Returns `None` if the readings don't
+    /// span enough directions.
+    pub fn fit(samples: &[Vec3], magnitude: f32) -> Option<Self> {
+        if samples.len() < 6 {
+            return None;
+        }
+        // Least squares on a x² + b y² + c z² + d x + e y + f z = 1.
+        let mut normal = Matrix::zeros(6, 6);
+        let mut right = vec![0.0; 6];
+        for sample in samples {
+            let (x, y, z) = (
+                f64::from(sample.x),
+                f64::from(sample.y),
+                f64::from(sample.z),
+            );
+            let row = [x * x, y * y, z * z, x, y, z];
+            for (i, a) in row.iter().enumerate() {
+                right[i] += a;
+                for (j, b) in row.iter().enumerate() {
+                    normal[(i, j)] += a * b;
+                }
+            }
+        }
+        let [a, b, c, d, e, f]: [f64; 6] = normal.inverse()?.mul_vector(&right).try_into().ok()?;
+        if a <= 0.0 || b <= 0.0 || c <= 0.0 {
+            return None;
+        }
+        let offset = [-d / (2.0 * a), -e / (2.0 * b), -f / (2.0 * c)];
+        let constant = 1.0 + a * offset[0].powi(2) + b * offset[1].powi(2) + c * offset[2].powi(2);
+        if constant <= 0.0 {
+            return None;
+        }
+        let radii = [a, b, c].map(|coefficient| (constant / coefficient).sqrt());
+        let magnitude = f64::from(magnitude);
+        Some(Self {
+            offset: Vec3::new(offset[0] as f32, offset[1] as f32, offset[2] as f32),
+            scale: Vec3::new(
+                (magnitude / radii[0]) as f32,
+                (magnitude / radii[1]) as f32,
+                (magnitude / radii[2]) as f32,
+            ),
+        })
+    }
+}
+
+/// Learns the gyroscope bias while the IMU is standing still, judged by low
+/// spread of both gyroscope and accelerometer over a window of samples.
+#[derive(Clone, Debug)]
+pub struct GyroBiasEstimator {
+    pub window: usize,
+    /// Largest per-axis gyroscope standard deviation, rad/s, considered still.
+    pub angular_velocity_threshold: f32,
+    /// Largest per-axis accelerometer standard deviation, m/s², considered still.
+    pub acceleration_threshold: f32,
+    /// Weight of each new stationary window mean in the running bias.
+    pub update_rate: f32,
+    samples: VecDeque<(Vec3, Vec3)>,
+    bias: Vec3,
+    calibrated: bool,
+    stationary: bool,
+}
+
+impl Default for GyroBiasEstimator {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl GyroBiasEstimator {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(2),
+            angular_velocity_threshold: 0.01,
+            acceleration_threshold: 0.05,
+            update_rate: 0.1,
+            samples: VecDeque::new(),
+            bias: Vec3::ZERO,
+            calibrated: false,
+            stationary: false,
+        }
+    }
+
+    pub fn bias(&self) -> Vec3 {
+        self.bias
+    }
+
+    /// Whether any stationary window has been seen yet.
+    pub fn is_calibrated(&self) -> bool {
+        self.calibrated
+    }
+
+    pub fn is_stationary(&self) -> bool {
+        self.stationary
+    }
+
+    /// Adds a sample and returns the bias-corrected angular velocity.
+    pub fn update(&mut self, angular_velocity: Vec3, acceleration: Vec3) -> Vec3 {
+        self.samples.push_back((angular_velocity, acceleration));
+        while self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+        self.stationary = false;
+        if self.samples.len() == self.window {
+            let (gyro_mean, gyro_deviation) = statistics(self.samples.iter().map(|s| s.0));
+            let (_, acceleration_deviation) = statistics(self.samples.iter().map(|s| s.1));
+            self.stationary = gyro_deviation.max_element() <= self.angular_velocity_threshold
+                && acceleration_deviation.max_element() <= self.acceleration_threshold;
+            if self.stationary {
+                self.bias = if self.calibrated {
+                    self.bias.lerp(gyro_mean, self.update_rate)
+                } else {
+                    gyro_mean
+                };
+                self.calibrated = true;
+            }
+        }
+        angular_velocity - self.bias
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.bias = Vec3::ZERO;
+        self.calibrated = false;
+        self.stationary = false;
+    }
+}
+
+// Mean and per-axis standard deviation.
+fn statistics(values: impl Iterator<Item = Vec3> + Clone) -> (Vec3, Vec3) {
+    let count = values.clone().count().max(1) as f32;
+    let mean = values.clone().fold(Vec3::ZERO, |sum, v| sum + v) / count;
+    let variance = values.fold(Vec3::ZERO, |sum, v| sum + (v - mean) * (v - mean)) / count;
+    (mean, variance.map(f32::sqrt))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rand_distr::StandardNormal;
+
+    use super::*;
+
+    fn noise(rng: &mut StdRng, deviation: f32) -> Vec3 {
+        Vec3::new(
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+        ) * deviation
+    }
+
+    // Evenly spread unit vectors.
+    fn directions(count: usize) -> impl Iterator<Item = Vec3> {
+        let golden = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        (0..count).map(move |i| {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let radius = (1.0 - z * z).sqrt();
+            let angle = golden * i as f32;
+            Vec3::new(radius * angle.cos(), radius * angle.sin(), z)
+        })
+    }
+
+    #[test]
+    fn fit_recovers_offset_and_scale() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let truth = SensorCalibration {
+            offset: Vec3::new(0.3, -0.2, 0.5),
+            scale: Vec3::new(1.05, 0.97, 1.02),
+        };
+        let samples: Vec<Vec3> = directions(60)
+            .map(|direction| direction * 9.81 / truth.scale + truth.offset + noise(&mut rng, 0.01))
+            .collect();
+
+        let fitted = SensorCalibration::fit(&samples, 9.81).unwrap();
+
+        assert!(
+            (fitted.offset - truth.offset).abs().max_element() < 0.01,
+            "{fitted:?}"
+        );
+        assert!(
+            (fitted.scale - truth.scale).abs().max_element() < 0.005,
+            "{fitted:?}"
+        );
+        for sample in &samples {
+            assert!((fitted.apply(*sample).length() - 9.81).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn fit_needs_readings_in_many_directions() {
+        let one_way = vec![Vec3::new(0.0, 0.0, 9.81); 20];
+        assert_eq!(SensorCalibration::fit(&one_way, 9.81), None);
+
+        let few: Vec<Vec3> = directions(5).collect();
+        assert_eq!(SensorCalibration::fit(&few, 1.0), None);
+    }
+
+    #[test]
+    fn gyro_bias_is_learned_at_standstill_and_kept_while_moving() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let bias = Vec3::new(0.02, -0.01, 0.005);
+        let gravity = Vec3::new(0.0, 0.0, 9.81);
+        let mut estimator = GyroBiasEstimator::new(50);
+
+        let mut corrected = Vec3::ZERO;
+        for _ in 0..200 {
+            corrected = estimator.update(
+                bias + noise(&mut rng, 0.002),
+                gravity + noise(&mut rng, 0.01),
+            );
+        }
+        assert!(estimator.is_calibrated() && estimator.is_stationary());
+        assert!((estimator.bias() - bias).abs().max_element() < 0.002);
+        assert!(corrected.abs().max_element() < 0.01);
+
+        let learned = estimator.bias();
+        for i in 0..200 {
+            let turning = Vec3::new(0.0, 0.0, 0.5 + 0.3 * (i as f32 * 0.1).sin());
+            estimator.update(bias + turning, gravity + noise(&mut rng, 0.01));
+        }
+        assert!(!estimator.is_stationary());
+        assert_eq!(estimator.bias(), learned);
+    }
+}
@@ -0,0 +1,254 @@
+pub mod calibration;
+pub mod orientation;
+
+use std::sync::Mutex;
+
+use crate::clock::{default_clock, seconds_between, SharedClock};
+use crate::estimation::EstimatorInput;
+use crate::joints::FrameId;
+use crate::links::{CarbonData, CarbonMetadata, CarbonTaskConfiguration, Sensor, Task};
+use crate::ports::PortReader;
+use crate::primitives::{Quat, Vec3};
+use calibration::{GyroBiasEstimator, SensorCalibration};
+use orientation::OrientationFilter;
+
+/// Raw sample from an IMU driver: gyroscope in rad/s, accelerometer in m/s²
+/// including gravity, and magnetometer in any unit if fitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImuReading {
+    pub angular_velocity: Vec3,
+    pub linear_acceleration: Vec3,
+    pub magnetic_field: Option<Vec3>,
+}
+
+/// Calibrated IMU output with row-major covariances.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImuMeasurement {
+    /// Rotation from the sensor frame into an earth frame with z up.
+    pub orientation: Quat,
+    pub orientation_covariance: [[f32; 3]; 3],
+    pub angular_velocity: Vec3,
+    pub angular_velocity_covariance: [[f32; 3]; 3],
+    pub linear_acceleration: Vec3,
+    pub linear_acceleration_covariance: [[f32; 3]; 3],
+    /// Frame the sensor is mounted in.
+    pub frame: FrameId,
+}
+
+impl ImuMeasurement {
+    /// The rates and accelerations as inputs for a state estimator.
+    pub fn estimator_inputs(&self) -> [EstimatorInput; 2] {
+        [
+            EstimatorInput::AngularVelocity(self.angular_velocity),
+            EstimatorInput::LinearAcceleration(self.linear_acceleration),
+        ]
+    }
+}
+
+pub trait IMU: PortReader<Output = ImuReading> {}
+
+fn diagonal(variance: f32) -> [[f32; 3]; 3] {
+    [
+        [variance, 0.0, 0.0],
+        [0.0, variance, 0.0],
+        [0.0, 0.0, variance],
+    ]
+}
+
+#[derive(Clone, Debug)]
+pub struct ImuConfig {
+    pub accelerometer: SensorCalibration,
+    pub magnetometer: SensorCalibration,
+    /// Per-axis noise variances reported with each measurement.
+    pub orientation_variance: f32,
+    pub angular_velocity_variance: f32,
+    pub linear_acceleration_variance: f32,
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        Self {
+            accelerometer: SensorCalibration::default(),
+            magnetometer: SensorCalibration::default(),
+            orientation_variance: 1e-3,
+            angular_velocity_variance: 1e-4,
+            linear_acceleration_variance: 1e-2,
+        }
+    }
+}
+
+/// Turns raw readings into measurements: applies calibration, removes the
+/// gyroscope bias learned at standstill and tracks orientation.
+pub struct ImuProcessor {
+    pub config: ImuConfig,
+    pub bias: GyroBiasEstimator,
+    filter: Box<dyn OrientationFilter + Send>,
+}
+
+impl ImuProcessor {
+    pub fn new(config: ImuConfig, filter: impl OrientationFilter + Send + 'static) -> Self {
+        Self {
+            config,
+            bias: GyroBiasEstimator::default(),
+            filter: Box::new(filter),
+        }
+    }
+
+    pub fn with_bias_estimator(mut self, bias: GyroBiasEstimator) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Processes a reading taken `dt` seconds after the previous one.
+    pub fn process(&mut self, reading: &ImuReading, dt: f32, frame: FrameId) -> ImuMeasurement {
+        let acceleration = self.config.accelerometer.apply(reading.linear_acceleration);
+        let magnetic_field = reading
+            .magnetic_field
+            .map(|field| self.config.magnetometer.apply(field));
+        let angular_velocity = self.bias.update(reading.angular_velocity, acceleration);
+        let orientation = self
+            .filter
+            .update(angular_velocity, acceleration, magnetic_field, dt);
+        ImuMeasurement {
+            orientation,
+            orientation_covariance: diagonal(self.config.orientation_variance),
+            angular_velocity,
+            angular_velocity_covariance: diagonal(self.config.angular_velocity_variance),
+            linear_acceleration: acceleration,
+            linear_acceleration_covariance: diagonal(self.config.linear_acceleration_variance),
+            frame,
+        }
+    }
+}
+
+/// Reads an IMU driver and processes each reading into a measurement,
+/// stamped with the time on its clock.
+pub struct ImuSensor<I: IMU> {
+    driver: Mutex<I>,
+    processor: Mutex<ImuProcessor>,
+    clock: SharedClock,
+    last_reading: Mutex<Option<u64>>,
+    frame: FrameId,
+    name: String,
+}
+
+impl<I: IMU> ImuSensor<I> {
+    pub fn new(driver: I, processor: ImuProcessor, frame: FrameId, name: &str) -> Self {
+        Self {
+            driver: Mutex::new(driver),
+            processor: Mutex::new(processor),
+            clock: default_clock(),
+            last_reading: Mutex::new(None),
+            frame,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl<I: IMU> Task for ImuSensor<I> {
+    type Input = ();
+    type Output = CarbonData<Option<ImuMeasurement>>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    // `None` means the driver had no new reading.
+    fn process(&self, _input: Self::Input) -> Self::Output {
+        let reading = self
+            .driver
+            .lock()
+            .expect("IMU driver lock poisoned")
+            .read_data();
+        let now = self.clock.now();
+        let measurement = reading.map(|reading| {
+            let mut last_reading = self.last_reading.lock().expect("Timestamp lock poisoned");
+            let dt = last_reading.map_or(0.0, |last| seconds_between(last, now));
+            *last_reading = Some(now);
+            self.processor
+                .lock()
+                .expect("ImuProcessor lock poisoned")
+                .process(&reading, dt, self.frame)
+        });
+        CarbonData::new(
+            measurement,
+            CarbonMetadata {
+                name: self.name.clone(),
+                timestamp: now,
+                ..Default::default()
+            },
+        )
+    }
+}
+
+impl<I: IMU> Sensor<Option<ImuMeasurement>> for ImuSensor<I> {}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rand_distr::StandardNormal;
+
+    use super::*;
+    use crate::estimation::spatial::STANDARD_GRAVITY;
+    use orientation::MadgwickFilter;
+
+    fn noise(rng: &mut StdRng, deviation: f32) -> Vec3 {
+        Vec3::new(
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+        ) * deviation
+    }
+
+    #[test]
+    fn calibrated_readings_recover_the_tilt_of_a_resting_imu() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let gravity = STANDARD_GRAVITY as f32;
+        let offset = Vec3::new(0.6, -0.4, 0.3);
+        let scale = Vec3::new(1.04, 0.95, 1.01);
+        let gyro_bias = Vec3::new(0.03, -0.02, 0.01);
+        let raw_accelerometer =
+            |up: Vec3, rng: &mut StdRng| up * gravity / scale + offset + noise(rng, 0.02);
+
+        // Calibrate the accelerometer from the IMU tumbled through many poses.
+        let poses: Vec<Vec3> = (0..100)
+            .map(|i| {
+                let yaw = i as f32 * 2.4;
+                let pitch = (i as f32 / 50.0 - 1.0).clamp(-1.0, 1.0).acos();
+                Quat::from_euler(glam::EulerRot::ZYX, yaw, pitch, 0.0) * Vec3::Z
+            })
+            .collect();
+        let tumble: Vec<Vec3> = poses
+            .iter()
+            .map(|&up| raw_accelerometer(up, &mut rng))
+            .collect();
+        let config = ImuConfig {
+            accelerometer: SensorCalibration::fit(&tumble, gravity).unwrap(),
+            ..Default::default()
+        };
+
+        let truth = Quat::from_euler(glam::EulerRot::ZYX, 0.7, 0.25, -0.35);
+        let up = truth.inverse() * Vec3::Z;
+        let mut processor = ImuProcessor::new(config, MadgwickFilter::new(0.3));
+        let mut measurement = ImuMeasurement::default();
+        for _ in 0..2000 {
+            let reading = ImuReading {
+                angular_velocity: gyro_bias + noise(&mut rng, 0.002),
+                linear_acceleration: raw_accelerometer(up, &mut rng),
+                magnetic_field: None,
+            };
+            measurement = processor.process(&reading, 0.01, FrameId::root());
+        }
+
+        assert!((processor.bias.bias() - gyro_bias).abs().max_element() < 0.002);
+        assert!(measurement.angular_velocity.abs().max_element() < 0.01);
+        assert!((measurement.linear_acceleration.length() - gravity).abs() < 0.1);
+        let estimated_up = measurement.orientation.inverse() * Vec3::Z;
+        let tilt_error = estimated_up.angle_between(up);
+        assert!(tilt_error < 0.01, "{tilt_error} rad off");
+    }
+}
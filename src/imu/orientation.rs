@@ -0,0 +1,316 @@
+use crate::estimation::spatial::STANDARD_GRAVITY;
+use crate::primitives::{Quat, Vec3};
+
+/// Tracks orientation from gyroscope rates, corrected by the gravity direction
+/// and optionally magnetic north. Orientations rotate the body frame into an
+/// earth frame with z up and, when a magnetometer is used, x towards magnetic
+/// north.
+pub trait OrientationFilter {
+    /// Advances by `dt` seconds of gyroscope rates (rad/s) and corrects with
+    /// accelerometer and magnetometer readings, in any consistent units.
+    fn update(
+        &mut self,
+        angular_velocity: Vec3,
+        acceleration: Vec3,
+        magnetic_field: Option<Vec3>,
+        dt: f32,
+    ) -> Quat;
+
+    fn orientation(&self) -> Quat;
+
+    fn reset(&mut self, orientation: Quat);
+}
+
+fn integrate_gyroscope(orientation: Quat, angular_velocity: Vec3, dt: f32) -> Quat {
+    (orientation * Quat::from_scaled_axis(angular_velocity * dt)).normalize()
+}
+
+/// Blends integrated gyroscope rates with the tilt implied by gravity and the
+/// heading implied by the magnetometer.
+#[derive(Clone, Debug)]
+pub struct ComplementaryFilter {
+    /// Fraction of the accelerometer tilt error corrected per update.
+    pub accelerometer_gain: f32,
+    /// Fraction of the magnetometer heading error corrected per update.
+    pub magnetometer_gain: f32,
+    /// Accelerometer corrections are skipped when the reading's magnitude
+    /// differs from gravity by more than this fraction, e.g. while braking.
+    pub acceleration_rejection: f32,
+    orientation: Quat,
+}
+
+impl Default for ComplementaryFilter {
+    fn default() -> Self {
+        Self {
+            accelerometer_gain: 0.02,
+            magnetometer_gain: 0.01,
+            acceleration_rejection: 0.1,
+            orientation: Quat::IDENTITY,
+        }
+    }
+}
+
+impl ComplementaryFilter {
+    pub fn new(accelerometer_gain: f32, magnetometer_gain: f32) -> Self {
+        Self {
+            accelerometer_gain,
+            magnetometer_gain,
+            ..Default::default()
+        }
+    }
+}
+
+impl OrientationFilter for ComplementaryFilter {
+    fn update(
+        &mut self,
+        angular_velocity: Vec3,
+        acceleration: Vec3,
+        magnetic_field: Option<Vec3>,
+        dt: f32,
+    ) -> Quat {
+        let mut orientation = integrate_gyroscope(self.orientation, angular_velocity, dt);
+
+        let norm = acceleration.length();
+        let gravity = STANDARD_GRAVITY as f32;
+        if norm > 0.0 && ((norm - gravity) / gravity).abs() <= self.acceleration_rejection {
+            let measured_up = orientation * (acceleration / norm);
+            let correction = Quat::from_rotation_arc(measured_up, Vec3::Z);
+            orientation = (Quat::IDENTITY.slerp(correction, self.accelerometer_gain) * orientation)
+                .normalize();
+        }
+
+        if let Some(field) = magnetic_field.filter(|field| *field != Vec3::ZERO) {
+            let north = orientation * field;
+            if north.x != 0.0 || north.y != 0.0 {
+                let heading_error = north.y.atan2(north.x);
+                orientation = (Quat::from_rotation_z(-heading_error * self.magnetometer_gain)
+                    * orientation)
+                    .normalize();
+            }
+        }
+
+        self.orientation = orientation;
+        orientation
+    }
+
+    fn orientation(&self) -> Quat {
+        self.orientation
+    }
+
+    fn reset(&mut self, orientation: Quat) {
+        self.orientation = orientation;
+    }
+}
+
+/// Gradient-descent orientation filter (Madgwick, "An efficient orientation
+/// filter for inertial and inertial/magnetic sensor arrays", 2010).
+#[derive(Clone, Debug)]
+pub struct MadgwickFilter {
+    /// Gradient step size; larger trusts the accelerometer and magnetometer
+    /// more.
+    pub beta: f32,
+    orientation: Quat,
+}
+
+impl Default for MadgwickFilter {
+    fn default() -> Self {
+        Self::new(0.1)
+    }
+}
+
+impl MadgwickFilter {
+    pub fn new(beta: f32) -> Self {
+        Self {
+            beta,
+            orientation: Quat::IDENTITY,
+        }
+    }
+}
+
+impl OrientationFilter for MadgwickFilter {
+    fn update(
+        &mut self,
+        angular_velocity: Vec3,
+        acceleration: Vec3,
+        magnetic_field: Option<Vec3>,
+        dt: f32,
+    ) -> Quat {
+        let q = self.orientation;
+        let (q0, q1, q2, q3) = (q.w, q.x, q.y, q.z);
+        let rate = q * Quat::from_xyzw(
+            angular_velocity.x,
+            angular_velocity.y,
+            angular_velocity.z,
+            0.0,
+        );
+        let mut derivative = [0.5 * rate.w, 0.5 * rate.x, 0.5 * rate.y, 0.5 * rate.z];
+
+        if acceleration != Vec3::ZERO {
+            let a = acceleration.normalize();
+            // Objective and Jacobian for gravity along earth z (eqs. 25, 26).
+            let mut residuals = vec![
+                2.0 * (q1 * q3 - q0 * q2) - a.x,
+                2.0 * (q0 * q1 + q2 * q3) - a.y,
+                2.0 * (0.5 - q1 * q1 - q2 * q2) - a.z,
+            ];
+            let mut jacobian = vec![
+                [-2.0 * q2, 2.0 * q3, -2.0 * q0, 2.0 * q1],
+                [2.0 * q1, 2.0 * q0, 2.0 * q3, 2.0 * q2],
+                [0.0, -4.0 * q1, -4.0 * q2, 0.0],
+            ];
+
+            if let Some(m) = magnetic_field
+                .filter(|field| *field != Vec3::ZERO)
+                .map(Vec3::normalize)
+            {
+                // Earth field direction, with heading removed (eqs. 45, 46).
+                let h = q * m;
+                let (bx, bz) = (h.x.hypot(h.y), h.z);
+                residuals.extend([
+                    2.0 * bx * (0.5 - q2 * q2 - q3 * q3) + 2.0 * bz * (q1 * q3 - q0 * q2) - m.x,
+                    2.0 * bx * (q1 * q2 - q0 * q3) + 2.0 * bz * (q0 * q1 + q2 * q3) - m.y,
+                    2.0 * bx * (q0 * q2 + q1 * q3) + 2.0 * bz * (0.5 - q1 * q1 - q2 * q2) - m.z,
+                ]);
+                jacobian.extend([
+                    [
+                        -2.0 * bz * q2,
+                        2.0 * bz * q3,
+                        -4.0 * bx * q2 - 2.0 * bz * q0,
+                        -4.0 * bx * q3 + 2.0 * bz * q1,
+                    ],
+                    [
+                        -2.0 * bx * q3 + 2.0 * bz * q1,
+                        2.0 * bx * q2 + 2.0 * bz * q0,
+                        2.0 * bx * q1 + 2.0 * bz * q3,
+                        -2.0 * bx * q0 + 2.0 * bz * q2,
+                    ],
+                    [
+                        2.0 * bx * q2,
+                        2.0 * bx * q3 - 4.0 * bz * q1,
+                        2.0 * bx * q0 - 4.0 * bz * q2,
+                        2.0 * bx * q1,
+                    ],
+                ]);
+            }
+
+            let mut gradient = [0.0f32; 4];
+            for (row, residual) in jacobian.iter().zip(&residuals) {
+                for (value, partial) in gradient.iter_mut().zip(row) {
+                    *value += partial * residual;
+                }
+            }
+            let norm = gradient.iter().map(|g| g * g).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                for (value, step) in derivative.iter_mut().zip(gradient) {
+                    *value -= self.beta * step / norm;
+                }
+            }
+        }
+
+        let [w, x, y, z] = derivative;
+        self.orientation =
+            Quat::from_xyzw(q.x + x * dt, q.y + y * dt, q.z + z * dt, q.w + w * dt).normalize();
+        self.orientation
+    }
+
+    fn orientation(&self) -> Quat {
+        self.orientation
+    }
+
+    fn reset(&mut self, orientation: Quat) {
+        self.orientation = orientation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rand_distr::StandardNormal;
+
+    use super::*;
+
+    const RATE: f32 = 100.0;
+
+    // Earth field pointing north and down, as in the northern hemisphere.
+    const EARTH_FIELD: Vec3 = Vec3::new(0.4, 0.0, -0.9);
+
+    fn noise(rng: &mut StdRng, deviation: f32) -> Vec3 {
+        Vec3::new(
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+        ) * deviation
+    }
+
+    // Runs `filter` on the noisy gyroscope, accelerometer and magnetometer
+    // readings of a body starting at `truth` and turning at the body rate
+    // `angular_velocity`, and returns the angle between the estimated and true
+    // orientations at the end.
+    fn track(
+        filter: &mut impl OrientationFilter,
+        mut truth: Quat,
+        angular_velocity: Vec3,
+        seconds: f32,
+    ) -> f32 {
+        let mut rng = StdRng::seed_from_u64(11);
+        let gravity = STANDARD_GRAVITY as f32;
+        let dt = 1.0 / RATE;
+        for _ in 0..(seconds * RATE) as usize {
+            truth = (truth * Quat::from_scaled_axis(angular_velocity * dt)).normalize();
+            let to_body = truth.inverse();
+            filter.update(
+                angular_velocity + noise(&mut rng, 0.005),
+                to_body * Vec3::Z * gravity + noise(&mut rng, 0.05),
+                Some(to_body * EARTH_FIELD + noise(&mut rng, 0.01)),
+                dt,
+            );
+        }
+        filter.orientation().angle_between(truth)
+    }
+
+    fn tilted() -> Quat {
+        Quat::from_euler(glam::EulerRot::ZYX, 2.0, -0.3, 0.4)
+    }
+
+    #[test]
+    fn complementary_filter_converges_from_a_wrong_start() {
+        let mut filter = ComplementaryFilter::new(0.05, 0.05);
+        let error = track(&mut filter, tilted(), Vec3::ZERO, 10.0);
+        assert!(error < 0.02, "{error} rad off");
+    }
+
+    #[test]
+    fn complementary_filter_tracks_a_turning_body() {
+        let mut filter = ComplementaryFilter::default();
+        filter.reset(tilted());
+        let error = track(&mut filter, tilted(), Vec3::new(0.2, -0.1, 0.8), 10.0);
+        assert!(error < 0.03, "{error} rad off");
+    }
+
+    #[test]
+    fn madgwick_filter_converges_from_a_wrong_start() {
+        let mut filter = MadgwickFilter::new(0.5);
+        let error = track(&mut filter, tilted(), Vec3::ZERO, 10.0);
+        assert!(error < 0.02, "{error} rad off");
+    }
+
+    #[test]
+    fn madgwick_filter_tracks_a_turning_body() {
+        let mut filter = MadgwickFilter::default();
+        filter.reset(tilted());
+        let error = track(&mut filter, tilted(), Vec3::new(0.2, -0.1, 0.8), 10.0);
+        assert!(error < 0.03, "{error} rad off");
+    }
+
+    #[test]
+    fn accelerations_far_from_gravity_leave_the_complementary_filter_on_the_gyroscope() {
+        let mut filter = ComplementaryFilter::new(0.5, 0.0);
+        let rate = Vec3::new(0.0, 0.0, 0.5);
+        for _ in 0..100 {
+            filter.update(rate, Vec3::new(5.0, 0.0, 9.81), None, 1.0 / RATE);
+        }
+        let expected = Quat::from_rotation_z(0.5);
+        assert!(filter.orientation().angle_between(expected) < 1e-3);
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(unix)]
+pub mod shm;
+pub mod udp;
+pub mod wire;
+
+#[cfg(unix)]
+pub use shm::{SharedCloud, SharedCloudReader, SharedCloudWriter};
+pub use udp::{UdpReceiver, UdpSender};
+pub use wire::{decode, encode, WireError, WireFormat, WireMessage};
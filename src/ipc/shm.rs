@@ -0,0 +1,687 @@
+use std::cell::Cell;
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::wire::{Reader, WireError, WireFormat, Writer};
+use crate::joints::TransformTree;
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonMetadata};
+use crate::primitives::Point;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"CRBNSHM2");
+/// Room for the encoded metadata and frame name of each cloud.
+pub const METADATA_BYTES: usize = 256;
+// Each reader pins slots with one bit of a `u64`.
+const MAX_SLOTS: usize = 64;
+/// Most readers that can have a region open at once.
+pub const MAX_READERS: usize = 32;
+// Marks a reader entry being cleared after its process died.
+const RECLAIMING: u32 = u32::MAX;
+
+// Layout of the region: a header, then `slots` slots each followed by room
+// for `capacity` points.
+#[repr(C)]
+struct Header {
+    // Written last, so readers never see a half-initialised region.
+    magic: AtomicU64,
+    slots: u32,
+    capacity: u32,
+    // Sequence number of the newest cloud, shifted left by 8, with its slot in
+    // the low byte. Zero before the first cloud.
+    latest: AtomicU64,
+    reserved: u64,
+    readers: [ReaderEntry; MAX_READERS],
+}
+
+// The slots one reader holds, recorded with its process id so the writer can
+// release the pins of a reader that crashed.
+#[repr(C)]
+struct ReaderEntry {
+    // Zero while the entry is free.
+    pid: AtomicU32,
+    reserved: u32,
+    // Bit `i` is set while the reader holds slot `i`.
+    pins: AtomicU64,
+}
+
+impl ReaderEntry {
+    // Frees the entry if the process holding it has gone. Pins are cleared
+    // before the entry is handed back, so a new reader never inherits them.
+    fn reclaim_if_dead(&self) -> bool {
+        let pid = self.pid.load(Ordering::SeqCst);
+        if pid == 0 || process_alive(pid) {
+            return false;
+        }
+        if self
+            .pid
+            .compare_exchange(pid, RECLAIMING, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return false;
+        }
+        self.pins.store(0, Ordering::SeqCst);
+        self.pid.store(0, Ordering::SeqCst);
+        true
+    }
+}
+
+// Process ids are only meaningful within one pid namespace, so readers and
+// the writer must share one. An entry being reclaimed counts as alive.
+fn process_alive(pid: u32) -> bool {
+    if pid == RECLAIMING || pid > i32::MAX as u32 {
+        return true;
+    }
+    let signalled = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[repr(C)]
+struct Slot {
+    // Sequence number of the cloud held, shifted left by 1; the low bit is set
+    // while the writer fills the slot.
+    state: AtomicU64,
+    len: u32,
+    metadata_len: u32,
+    // The encoded metadata followed by the frame name.
+    metadata: [u8; METADATA_BYTES],
+}
+
+fn slot_size(capacity: usize) -> usize {
+    (size_of::<Slot>() + capacity * size_of::<Point>()).next_multiple_of(8)
+}
+
+fn region_size(slots: usize, capacity: usize) -> usize {
+    size_of::<Header>() + slots * slot_size(capacity)
+}
+
+// A named POSIX shared memory object mapped into this process.
+struct Mapping {
+    name: CString,
+    base: NonNull<u8>,
+    len: usize,
+    // The creator removes the name when it goes away.
+    owner: bool,
+}
+
+// The region is only touched through atomics, or by the writer in slots no
+// reader holds.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn create(name: &str, len: usize) -> io::Result<Self> {
+        let name = shm_name(name)?;
+        // A region left behind by a writer that crashed is replaced; readers
+        // still mapping it keep the old one.
+        unsafe { libc::shm_unlink(name.as_ptr()) };
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                0o600 as libc::mode_t,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::ftruncate(fd, len as libc::off_t) } < 0 {
+            let error = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+                libc::shm_unlink(name.as_ptr());
+            }
+            return Err(error);
+        }
+        Self::map(name, fd, len, true)
+    }
+
+    fn open(name: &str) -> io::Result<Self> {
+        let name = shm_name(name)?;
+        // Readers write their pins, so they map the region writable too.
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+            let error = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(error);
+        }
+        Self::map(name, fd, stat.st_size as usize, false)
+    }
+
+    fn map(name: CString, fd: libc::c_int, len: usize, owner: bool) -> io::Result<Self> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len.max(1),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        let error = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        if base == libc::MAP_FAILED {
+            if owner {
+                unsafe { libc::shm_unlink(name.as_ptr()) };
+            }
+            return Err(error);
+        }
+        Ok(Self {
+            name,
+            base: NonNull::new(base.cast()).expect("mmap returned null"),
+            len,
+            owner,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.base.as_ptr().cast::<Header>() }
+    }
+
+    fn header_mut(&mut self) -> *mut Header {
+        self.base.as_ptr().cast()
+    }
+
+    // Callers check `index` against the slot count in the header.
+    fn slot(&self, index: usize) -> *mut Slot {
+        let capacity = self.header().capacity as usize;
+        unsafe {
+            self.base
+                .as_ptr()
+                .add(size_of::<Header>() + index * slot_size(capacity))
+                .cast()
+        }
+    }
+
+    fn points(&self, index: usize) -> *mut Point {
+        unsafe { self.slot(index).add(1).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base.as_ptr().cast(), self.len.max(1));
+            if self.owner {
+                libc::shm_unlink(self.name.as_ptr());
+            }
+        }
+    }
+}
+
+fn shm_name(name: &str) -> io::Result<CString> {
+    let name = if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/{name}")
+    };
+    if name[1..].contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("shared memory name {name:?} contains '/'"),
+        ));
+    }
+    CString::new(name).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+}
+
+/// Publishes point clouds into named shared memory for
+/// [`SharedCloudReader`]s on the same host, which read them in place. Clouds
+/// go round a ring of slots; slots readers are holding are skipped, so a
+/// slow reader never sees its cloud change underneath it. Slots held by a
+/// reader whose process has died are taken back.
+pub struct SharedCloudWriter {
+    mapping: Mapping,
+    sequence: u64,
+    next_slot: usize,
+}
+
+impl SharedCloudWriter {
+    /// Creates the region `name`, with `slots` slots of up to `capacity`
+    /// points each. At least two slots are needed, so readers always find the
+    /// newest cloud complete.
+    pub fn create(name: &str, capacity: usize, slots: usize) -> io::Result<Self> {
+        if !(2..=MAX_SLOTS).contains(&slots) || capacity > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("need 2 to {MAX_SLOTS} slots of at most {} points", u32::MAX),
+            ));
+        }
+        let mut mapping = Mapping::create(name, region_size(slots, capacity))?;
+        let header = mapping.header_mut();
+        unsafe {
+            (*header).slots = slots as u32;
+            (*header).capacity = capacity as u32;
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+        Ok(Self {
+            mapping,
+            sequence: 0,
+            next_slot: 0,
+        })
+    }
+
+    /// Most points a cloud can have.
+    pub fn capacity(&self) -> usize {
+        self.mapping.header().capacity as usize
+    }
+
+    /// Copies `cloud` into shared memory and returns its sequence number. The
+    /// frame is stored by its name in `frames`.
+    pub fn publish(
+        &mut self,
+        cloud: &CarbonData<PointCloud>,
+        frames: &TransformTree,
+    ) -> io::Result<u64> {
+        let frame = cloud.data().frame;
+        let name = frames.name(frame).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                WireError::UnknownFrame(format!("#{}", frame.index())),
+            )
+        })?;
+        let points = &cloud.data().points;
+        self.publish_with(&cloud.metadata, name, points.len(), |slot| {
+            slot.copy_from_slice(points)
+        })
+    }
+
+    /// Has `fill` write `len` points straight into shared memory, saving a
+    /// copy for drivers that can produce points in place.
+    pub fn publish_with(
+        &mut self,
+        metadata: &CarbonMetadata,
+        frame: &str,
+        len: usize,
+        fill: impl FnOnce(&mut [Point]),
+    ) -> io::Result<u64> {
+        if len > self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{len} points do not fit in {}", self.capacity()),
+            ));
+        }
+        let mut writer = Writer::new(None);
+        metadata.encode(&mut writer);
+        frame.to_string().encode(&mut writer);
+        let encoded = writer
+            .finish()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        if encoded.len() > METADATA_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("metadata and frame name take over {METADATA_BYTES} bytes"),
+            ));
+        }
+        let index = self.claim_slot()?;
+        let slot = self.mapping.slot(index);
+        self.sequence += 1;
+        unsafe {
+            fill(slice::from_raw_parts_mut(self.mapping.points(index), len));
+            (*slot).len = len as u32;
+            (*slot).metadata_len = encoded.len() as u32;
+            (&mut (*slot).metadata)[..encoded.len()].copy_from_slice(&encoded);
+            (*slot).state.store(self.sequence << 1, Ordering::SeqCst);
+        }
+        self.mapping
+            .header()
+            .latest
+            .store(self.sequence << 8 | index as u64, Ordering::SeqCst);
+        self.next_slot = (index + 1) % self.mapping.header().slots as usize;
+        Ok(self.sequence)
+    }
+
+    // Marks the next free slot as being written. The newest cloud's slot is
+    // never reused, and neither is one a live reader pins: the writer marks
+    // first and checks pins second, and readers pin first and check the mark
+    // second, so one of them always backs off.
+    fn claim_slot(&mut self) -> io::Result<usize> {
+        let header = self.mapping.header();
+        let slots = header.slots as usize;
+        let latest = header.latest.load(Ordering::SeqCst);
+        for offset in 0..slots {
+            let index = (self.next_slot + offset) % slots;
+            if latest != 0 && index == (latest & 0xff) as usize {
+                continue;
+            }
+            let state = unsafe { &(*self.mapping.slot(index)).state };
+            let previous = state.load(Ordering::SeqCst);
+            state.store(previous | 1, Ordering::SeqCst);
+            let pinned = header.readers.iter().any(|entry| {
+                entry.pins.load(Ordering::SeqCst) & 1 << index != 0 && !entry.reclaim_if_dead()
+            });
+            if !pinned {
+                return Ok(index);
+            }
+            state.store(previous, Ordering::SeqCst);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "every shared memory slot is being read",
+        ))
+    }
+}
+
+/// Reads point clouds published by a [`SharedCloudWriter`] without copying
+/// them. Each reader takes one of [`MAX_READERS`] entries in the region for
+/// as long as it is open.
+pub struct SharedCloudReader {
+    mapping: Mapping,
+    entry: usize,
+    // Views of each slot handed out; the slot's pin is cleared with the last.
+    held: Vec<Cell<u32>>,
+    last_sequence: Cell<u64>,
+}
+
+impl SharedCloudReader {
+    pub fn open(name: &str) -> io::Result<Self> {
+        let mapping = Mapping::open(name)?;
+        if mapping.len < size_of::<Header>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory region too small",
+            ));
+        }
+        let header = mapping.header();
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a point cloud region, or not ready yet",
+            ));
+        }
+        let (slots, capacity) = (header.slots as usize, header.capacity as usize);
+        if !(2..=MAX_SLOTS).contains(&slots) || mapping.len < region_size(slots, capacity) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory region does not match its header",
+            ));
+        }
+        let entry = Self::claim_entry(header).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{MAX_READERS} readers already have the region open"),
+            )
+        })?;
+        Ok(Self {
+            mapping,
+            entry,
+            held: (0..slots).map(|_| Cell::new(0)).collect(),
+            last_sequence: Cell::new(0),
+        })
+    }
+
+    // Takes a free entry, freeing those of dead readers if none is left.
+    fn claim_entry(header: &Header) -> Option<usize> {
+        let pid = std::process::id();
+        for _ in 0..2 {
+            let free = header.readers.iter().position(|entry| {
+                entry
+                    .pid
+                    .compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            });
+            if free.is_some() {
+                return free;
+            }
+            for entry in &header.readers {
+                entry.reclaim_if_dead();
+            }
+        }
+        None
+    }
+
+    fn entry(&self) -> &ReaderEntry {
+        &self.mapping.header().readers[self.entry]
+    }
+
+    fn pin(&self, index: usize) {
+        let held = &self.held[index];
+        if held.get() == 0 {
+            self.entry().pins.fetch_or(1 << index, Ordering::SeqCst);
+        }
+        held.set(held.get() + 1);
+    }
+
+    fn unpin(&self, index: usize) {
+        let held = &self.held[index];
+        held.set(held.get() - 1);
+        if held.get() == 0 {
+            self.entry().pins.fetch_and(!(1 << index), Ordering::SeqCst);
+        }
+    }
+
+    /// The newest cloud, held until the returned view is dropped.
+    pub fn latest(&self) -> Option<SharedCloud<'_>> {
+        // The writer can lap a reader between loading `latest` and pinning;
+        // give up rather than spin if it keeps doing so.
+        for _ in 0..16 {
+            let latest = self.mapping.header().latest.load(Ordering::SeqCst);
+            if latest == 0 {
+                return None;
+            }
+            let (sequence, index) = (latest >> 8, (latest & 0xff) as usize);
+            if index >= self.held.len() {
+                return None;
+            }
+            // The writer may be filling the slot until the state check passes,
+            // so only its atomic state is touched before then.
+            let slot = self.mapping.slot(index).cast_const();
+            self.pin(index);
+            if unsafe { &(*slot).state }.load(Ordering::SeqCst) != sequence << 1 {
+                self.unpin(index);
+                continue;
+            }
+            let (len, metadata) = unsafe {
+                let len = ptr::addr_of!((*slot).len).read() as usize;
+                let metadata_len = ptr::addr_of!((*slot).metadata_len).read() as usize;
+                let metadata = slice::from_raw_parts(
+                    ptr::addr_of!((*slot).metadata).cast::<u8>(),
+                    metadata_len.min(METADATA_BYTES),
+                );
+                (len.min(self.mapping.header().capacity as usize), metadata)
+            };
+            let mut reader = Reader::new(metadata, None);
+            let metadata = CarbonMetadata::decode(&mut reader).unwrap_or_default();
+            let frame = String::decode(&mut reader).unwrap_or_default();
+            return Some(SharedCloud {
+                reader: self,
+                index,
+                points: unsafe { slice::from_raw_parts(self.mapping.points(index), len) },
+                metadata,
+                frame,
+                sequence,
+            });
+        }
+        None
+    }
+
+    /// The newest cloud if it was published since the last call.
+    pub fn next(&self) -> Option<SharedCloud<'_>> {
+        let cloud = self.latest()?;
+        if cloud.sequence <= self.last_sequence.get() {
+            return None;
+        }
+        self.last_sequence.set(cloud.sequence);
+        Some(cloud)
+    }
+}
+
+impl Drop for SharedCloudReader {
+    fn drop(&mut self) {
+        let entry = self.entry();
+        entry.pins.store(0, Ordering::SeqCst);
+        entry.pid.store(0, Ordering::SeqCst);
+    }
+}
+
+/// A cloud in shared memory. The writer leaves it alone until this is
+/// dropped, so hold it only as long as it is being read.
+pub struct SharedCloud<'a> {
+    reader: &'a SharedCloudReader,
+    index: usize,
+    points: &'a [Point],
+    metadata: CarbonMetadata,
+    frame: String,
+    sequence: u64,
+}
+
+impl SharedCloud<'_> {
+    pub fn points(&self) -> &[Point] {
+        self.points
+    }
+
+    pub fn metadata(&self) -> &CarbonMetadata {
+        &self.metadata
+    }
+
+    /// Name of the frame the points are in.
+    pub fn frame(&self) -> &str {
+        &self.frame
+    }
+
+    /// Counts up from 1 with every cloud published.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Copies the cloud out of shared memory, resolving its frame against
+    /// `frames`.
+    pub fn to_cloud(&self, frames: &TransformTree) -> Result<CarbonData<PointCloud>, WireError> {
+        let frame = frames
+            .frame(&self.frame)
+            .ok_or_else(|| WireError::UnknownFrame(self.frame.clone()))?;
+        Ok(CarbonData::new(
+            PointCloud::in_frame(self.points.to_vec(), frame),
+            self.metadata.clone(),
+        ))
+    }
+}
+
+impl Drop for SharedCloud<'_> {
+    fn drop(&mut self) {
+        self.reader.unpin(self.index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::joints::FrameId;
+    use crate::primitives::{Transform, Vec3};
+
+    // Regions are host-wide, so names carry the process id.
+    fn region(test: &str) -> String {
+        format!("carbon-test-{}-{test}", std::process::id())
+    }
+
+    fn frames() -> TransformTree {
+        let mut frames = TransformTree::new("base");
+        frames.add_frame("imu", FrameId::root(), Transform::default());
+        frames.add_frame("lidar", FrameId::root(), Transform::default());
+        frames
+    }
+
+    fn cloud(frames: &TransformTree, x: f32) -> CarbonData<PointCloud> {
+        let points = (0..100)
+            .map(|index| Point::new(Vec3::new(x, index as f32, 0.0), 1.0))
+            .collect();
+        CarbonData::new(
+            PointCloud::in_frame(points, frames.frame("lidar").unwrap()),
+            CarbonMetadata {
+                name: "scan".to_string(),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn readers_see_the_newest_cloud() {
+        let frames = frames();
+        let name = region("newest");
+        let mut writer = SharedCloudWriter::create(&name, 100, 3).unwrap();
+        let reader = SharedCloudReader::open(&name).unwrap();
+        assert!(reader.latest().is_none());
+
+        writer.publish(&cloud(&frames, 1.0), &frames).unwrap();
+        writer.publish(&cloud(&frames, 2.0), &frames).unwrap();
+        let latest = reader.next().unwrap();
+        assert_eq!(latest.sequence(), 2);
+        assert_eq!(latest.points()[0].position.x, 2.0);
+        assert_eq!(latest.metadata().name, "scan");
+        assert_eq!(latest.frame(), "lidar");
+        drop(latest);
+        assert!(reader.next().is_none());
+
+        // The frame resolves by name in a tree numbered differently.
+        let mut other = TransformTree::new("base");
+        let lidar = other.add_frame("lidar", FrameId::root(), Transform::default());
+        let copy = reader.latest().unwrap().to_cloud(&other).unwrap();
+        assert_eq!(copy.data().frame, lidar);
+        assert_eq!(copy.data().points, cloud(&frames, 2.0).data().points);
+    }
+
+    #[test]
+    fn pinned_slots_are_left_alone() {
+        let frames = frames();
+        let name = region("pinned");
+        let mut writer = SharedCloudWriter::create(&name, 100, 2).unwrap();
+        let reader = SharedCloudReader::open(&name).unwrap();
+        writer.publish(&cloud(&frames, 1.0), &frames).unwrap();
+        let first = reader.latest().unwrap();
+        writer.publish(&cloud(&frames, 2.0), &frames).unwrap();
+        let second = reader.latest().unwrap();
+        let error = writer.publish(&cloud(&frames, 3.0), &frames).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(first.points()[0].position.x, 1.0);
+
+        drop(first);
+        writer.publish(&cloud(&frames, 3.0), &frames).unwrap();
+        assert_eq!(second.points()[0].position.x, 2.0);
+    }
+
+    #[test]
+    fn pins_of_dead_readers_are_released() {
+        let frames = frames();
+        let name = region("dead");
+        let mut writer = SharedCloudWriter::create(&name, 100, 2).unwrap();
+        writer.publish(&cloud(&frames, 1.0), &frames).unwrap();
+
+        // A reader that pins the first slot and then dies without unpinning,
+        // recorded under the id of a process that has exited.
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let crashed = SharedCloudReader::open(&name).unwrap();
+        std::mem::forget(crashed.latest().unwrap());
+        let entry = crashed.entry;
+        crashed.entry().pid.store(child.id(), Ordering::SeqCst);
+        std::mem::forget(crashed);
+
+        writer.publish(&cloud(&frames, 2.0), &frames).unwrap();
+        writer.publish(&cloud(&frames, 3.0), &frames).unwrap();
+        let readers = &writer.mapping.header().readers;
+        assert_eq!(readers[entry].pid.load(Ordering::SeqCst), 0);
+        assert_eq!(readers[entry].pins.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn closing_a_reader_frees_its_entry() {
+        let name = region("entries");
+        let _writer = SharedCloudWriter::create(&name, 10, 2).unwrap();
+        let readers: Vec<_> = (0..MAX_READERS)
+            .map(|_| SharedCloudReader::open(&name).unwrap())
+            .collect();
+        let error = SharedCloudReader::open(&name).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        drop(readers);
+        SharedCloudReader::open(&name).unwrap();
+    }
+}
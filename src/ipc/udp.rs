@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::wire::{self, WireMessage};
+use crate::clock::{default_clock, nanoseconds, SharedClock};
+use crate::joints::TransformTree;
+use crate::links::CarbonData;
+
+const FRAGMENT_MAGIC: [u8; 4] = *b"CRBF";
+// Magic, message id, fragment index, fragment count.
+const FRAGMENT_HEADER: usize = 4 + 4 + 2 + 2;
+/// Payload per datagram that fits an Ethernet frame with room for IP and UDP
+/// headers.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1400;
+const MAX_DATAGRAM: usize = 65_507;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+/// Sends encoded messages to one address, split into datagrams no larger
+/// than `fragment_size` bytes of payload. UDP may drop or reorder datagrams;
+/// a message missing any fragment is lost as a whole.
+pub struct UdpSender {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    fragment_size: usize,
+    next_id: u32,
+}
+
+impl UdpSender {
+    /// Binds `local`, e.g. `"0.0.0.0:0"`, and sends to `destination`.
+    pub fn new(local: impl ToSocketAddrs, destination: impl ToSocketAddrs) -> io::Result<Self> {
+        let destination = destination
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("no destination address"))?;
+        Ok(Self {
+            socket: UdpSocket::bind(local)?,
+            destination,
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            next_id: 0,
+        })
+    }
+
+    pub fn with_fragment_size(mut self, fragment_size: usize) -> Self {
+        self.fragment_size = fragment_size.clamp(1, MAX_DATAGRAM - FRAGMENT_HEADER);
+        self
+    }
+
+    pub fn local_address(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends `message`, naming its frames from `frames`, and returns how many
+    /// datagrams it took.
+    pub fn send<T: WireMessage>(
+        &mut self,
+        message: &CarbonData<T>,
+        frames: &TransformTree,
+    ) -> io::Result<usize> {
+        let bytes = wire::encode(message, frames)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        self.send_encoded(&bytes)
+    }
+
+    pub fn send_encoded(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let count = bytes.len().div_ceil(self.fragment_size).max(1);
+        if count > u16::MAX as usize {
+            return Err(invalid(format!(
+                "{} bytes need more than {} fragments",
+                bytes.len(),
+                u16::MAX
+            )));
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut datagram = Vec::with_capacity(FRAGMENT_HEADER + self.fragment_size);
+        for index in 0..count {
+            let start = index * self.fragment_size;
+            let end = (start + self.fragment_size).min(bytes.len());
+            datagram.clear();
+            datagram.extend_from_slice(&FRAGMENT_MAGIC);
+            datagram.extend_from_slice(&id.to_le_bytes());
+            datagram.extend_from_slice(&(index as u16).to_le_bytes());
+            datagram.extend_from_slice(&(count as u16).to_le_bytes());
+            datagram.extend_from_slice(&bytes[start..end]);
+            self.socket.send_to(&datagram, self.destination)?;
+        }
+        Ok(count)
+    }
+}
+
+// A message whose fragments are still arriving.
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    started: u64,
+}
+
+/// Receives messages from [`UdpSender`]s, putting fragments back together.
+/// Messages still incomplete after `reassembly_timeout` are dropped.
+pub struct UdpReceiver {
+    socket: UdpSocket,
+    clock: SharedClock,
+    pub reassembly_timeout: Duration,
+    /// Most messages reassembled at once; the oldest is dropped beyond it.
+    pub max_pending: usize,
+    pending: HashMap<(SocketAddr, u32), Partial>,
+    dropped: u64,
+    buffer: Vec<u8>,
+}
+
+impl UdpReceiver {
+    pub fn bind(local: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(local)?,
+            clock: default_clock(),
+            reassembly_timeout: Duration::from_millis(500),
+            max_pending: 16,
+            pending: HashMap::new(),
+            dropped: 0,
+            buffer: vec![0; MAX_DATAGRAM],
+        })
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_reassembly(mut self, timeout: Duration, max_pending: usize) -> Self {
+        self.reassembly_timeout = timeout;
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// Address the socket is bound to, useful when binding to port 0.
+    pub fn local_address(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Messages given up on because fragments went missing.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Waits up to `timeout` between datagrams for a whole message, resolving
+    /// its frames against `frames`. Messages that do not decode as `T` fail
+    /// with [`io::ErrorKind::InvalidData`].
+    pub fn recv<T: WireMessage>(
+        &mut self,
+        timeout: Duration,
+        frames: &TransformTree,
+    ) -> io::Result<Option<CarbonData<T>>> {
+        let Some(bytes) = self.recv_encoded(timeout)? else {
+            return Ok(None);
+        };
+        wire::decode(&bytes, frames)
+            .map(Some)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Like [`UdpReceiver::recv`], leaving the message encoded.
+    pub fn recv_encoded(&mut self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        self.socket
+            .set_read_timeout(Some(timeout.max(Duration::from_micros(1))))?;
+        loop {
+            let (length, sender) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    self.expire();
+                    return Ok(None);
+                }
+                Err(error) => return Err(error),
+            };
+            if let Some(message) = self.accept(sender, length) {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    // Files a datagram, returning the message it completes. Datagrams that
+    // are not fragments are ignored.
+    fn accept(&mut self, sender: SocketAddr, length: usize) -> Option<Vec<u8>> {
+        let datagram = &self.buffer[..length];
+        if length < FRAGMENT_HEADER || datagram[..4] != FRAGMENT_MAGIC {
+            return None;
+        }
+        let id = u32::from_le_bytes(datagram[4..8].try_into().expect("4 bytes"));
+        let index = u16::from_le_bytes(datagram[8..10].try_into().expect("2 bytes")) as usize;
+        let count = u16::from_le_bytes(datagram[10..12].try_into().expect("2 bytes")) as usize;
+        let payload = datagram[FRAGMENT_HEADER..].to_vec();
+        if index >= count {
+            return None;
+        }
+        if count == 1 {
+            return Some(payload);
+        }
+        self.expire();
+        let now = self.clock.now();
+        if !self.pending.contains_key(&(sender, id)) && self.pending.len() >= self.max_pending {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, partial)| partial.started)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+                self.dropped += 1;
+            }
+        }
+        let partial = self.pending.entry((sender, id)).or_insert_with(|| Partial {
+            fragments: vec![None; count],
+            received: 0,
+            started: now,
+        });
+        if partial.fragments.len() != count {
+            return None;
+        }
+        if partial.fragments[index].is_none() {
+            partial.fragments[index] = Some(payload);
+            partial.received += 1;
+        }
+        if partial.received < count {
+            return None;
+        }
+        let partial = self.pending.remove(&(sender, id))?;
+        Some(partial.fragments.into_iter().flatten().flatten().collect())
+    }
+
+    fn expire(&mut self) {
+        let now = self.clock.now();
+        let timeout = nanoseconds(self.reassembly_timeout);
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, partial| now.saturating_sub(partial.started) < timeout);
+        self.dropped += (before - self.pending.len()) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::joints::FrameId;
+    use crate::lidar::PointCloud;
+    use crate::links::CarbonMetadata;
+    use crate::primitives::{Point, Transform, Vec3};
+
+    fn frames() -> TransformTree {
+        let mut frames = TransformTree::new("base");
+        frames.add_frame("lidar", FrameId::root(), Transform::default());
+        frames
+    }
+
+    fn cloud(frames: &TransformTree, count: usize) -> CarbonData<PointCloud> {
+        let points = (0..count)
+            .map(|index| Point::new(Vec3::new(index as f32, 0.5, -1.0), 1.0))
+            .collect();
+        CarbonData::new(
+            PointCloud::in_frame(points, frames.frame("lidar").unwrap()),
+            CarbonMetadata::default(),
+        )
+    }
+
+    fn pair() -> (UdpSender, UdpReceiver) {
+        let receiver = UdpReceiver::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSender::new("127.0.0.1:0", receiver.local_address().unwrap()).unwrap();
+        (sender, receiver)
+    }
+
+    #[test]
+    fn reassembles_fragmented_messages() {
+        let frames = frames();
+        let (sender, receiver) = pair();
+        let (mut sender, mut receiver) = (sender.with_fragment_size(512), receiver);
+        let message = cloud(&frames, 1000);
+        let fragments = sender.send(&message, &frames).unwrap();
+        assert!(fragments > 20);
+        let received = receiver
+            .recv::<PointCloud>(Duration::from_secs(2), &frames)
+            .unwrap()
+            .expect("message lost on loopback");
+        assert_eq!(received.data().points, message.data().points);
+        assert_eq!(received.data().frame, message.data().frame);
+        assert_eq!(receiver.dropped(), 0);
+    }
+
+    #[test]
+    fn small_messages_take_one_datagram() {
+        let frames = frames();
+        let (mut sender, mut receiver) = pair();
+        assert_eq!(sender.send(&cloud(&frames, 3), &frames).unwrap(), 1);
+        let received = receiver
+            .recv::<PointCloud>(Duration::from_secs(2), &frames)
+            .unwrap()
+            .expect("message lost on loopback");
+        assert_eq!(received.data().points.len(), 3);
+    }
+
+    #[test]
+    fn drops_messages_missing_fragments() {
+        let frames = frames();
+        let clock = Arc::new(SimulatedClock::new());
+        let (sender, receiver) = pair();
+        let mut receiver = receiver
+            .with_clock(clock.clone())
+            .with_reassembly(Duration::from_millis(100), 4);
+        let mut sender = sender.with_fragment_size(64);
+        let bytes = wire::encode(&cloud(&frames, 20), &frames).unwrap();
+        // Send all but the last fragment by hand.
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let destination = receiver.local_address().unwrap();
+        let count = bytes.len().div_ceil(64);
+        for (index, chunk) in bytes.chunks(64).take(count - 1).enumerate() {
+            let mut datagram = FRAGMENT_MAGIC.to_vec();
+            datagram.extend_from_slice(&7u32.to_le_bytes());
+            datagram.extend_from_slice(&(index as u16).to_le_bytes());
+            datagram.extend_from_slice(&(count as u16).to_le_bytes());
+            datagram.extend_from_slice(chunk);
+            socket.send_to(&datagram, destination).unwrap();
+        }
+        assert!(receiver
+            .recv_encoded(Duration::from_millis(200))
+            .unwrap()
+            .is_none());
+        assert_eq!(receiver.dropped(), 0);
+        clock.step(Duration::from_millis(100));
+        assert!(receiver
+            .recv_encoded(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+        assert_eq!(receiver.dropped(), 1);
+
+        // Whole messages still get through.
+        sender.send(&cloud(&frames, 20), &frames).unwrap();
+        assert!(receiver
+            .recv::<PointCloud>(Duration::from_secs(2), &frames)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_the_wrong_type() {
+        let frames = frames();
+        let (mut sender, mut receiver) = pair();
+        sender.send(&cloud(&frames, 3), &frames).unwrap();
+        let error = receiver
+            .recv::<crate::primitives::Pose2D>(Duration::from_secs(2), &frames)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,583 @@
+use std::fmt;
+
+use crate::drive::{CommandVelocity, EncoderFeedback};
+use crate::imu::{ImuMeasurement, ImuReading};
+use crate::joints::{FrameId, TransformTree};
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonMetadata};
+use crate::primitives::{
+    JointCommand, Point, Pose2D, PoseWithCovariance2D, PoseWithCovariance3D, Quat, Transform,
+    Twist2D, Twist3D, Vec2, Vec3,
+};
+
+/// First bytes of every encoded message.
+pub const MAGIC: [u8; 4] = *b"CRBN";
+/// Bumped whenever the layout of an existing type changes.
+pub const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WireError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    WrongType {
+        expected: &'static str,
+        found: String,
+    },
+    InvalidUtf8,
+    InvalidValue(&'static str),
+    TrailingBytes(usize),
+    /// A frame missing from the sending or receiving transform tree.
+    UnknownFrame(String),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Truncated => write!(f, "message ends early"),
+            WireError::BadMagic => write!(f, "not a carbon message"),
+            WireError::UnsupportedVersion(version) => {
+                write!(f, "unsupported wire version {version}")
+            }
+            WireError::WrongType { expected, found } => {
+                write!(f, "expected a {expected} message, got {found}")
+            }
+            WireError::InvalidUtf8 => write!(f, "string is not UTF-8"),
+            WireError::InvalidValue(what) => write!(f, "invalid {what}"),
+            WireError::TrailingBytes(count) => write!(f, "{count} bytes after the message"),
+            WireError::UnknownFrame(frame) => write!(f, "unknown frame {frame:?}"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// Collects encoded values. Frames are written by name, looked up in
+/// `frames`.
+pub struct Writer<'a> {
+    bytes: Vec<u8>,
+    frames: Option<&'a TransformTree>,
+    error: Option<WireError>,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(frames: Option<&'a TransformTree>) -> Self {
+        Self {
+            bytes: Vec::new(),
+            frames,
+            error: None,
+        }
+    }
+
+    pub fn put(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Records a value that cannot be encoded; the first failure is reported
+    /// by [`Writer::finish`].
+    pub fn fail(&mut self, error: WireError) {
+        self.error.get_or_insert(error);
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, WireError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.bytes),
+        }
+    }
+}
+
+/// Reads values back in the order they were encoded. Frames are resolved by
+/// name against `frames`.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    frames: Option<&'a TransformTree>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], frames: Option<&'a TransformTree>) -> Self {
+        Self { bytes, frames }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], WireError> {
+        if count > self.bytes.len() {
+            return Err(WireError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    // A count of elements each at least `size` bytes long, checked against
+    // what is left so corrupt input cannot request huge allocations.
+    fn count(&mut self, size: usize) -> Result<usize, WireError> {
+        let count = u32::decode(self)? as usize;
+        if count.saturating_mul(size.max(1)) > self.remaining() {
+            return Err(WireError::Truncated);
+        }
+        Ok(count)
+    }
+}
+
+/// A value with a stable binary layout: fixed-width little-endian numbers,
+/// and strings and sequences prefixed by a `u32` length.
+pub trait WireFormat: Sized {
+    fn encode(&self, out: &mut Writer<'_>);
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError>;
+}
+
+/// A type that can be sent on its own as [`CarbonData`].
+pub trait WireMessage: WireFormat {
+    /// Identifies the type on the wire; never change it once released.
+    const TYPE_NAME: &'static str;
+}
+
+/// Encodes a message with its header, metadata and payload. Frames are sent
+/// by their names in `frames`, as indices differ between processes.
+pub fn encode<T: WireMessage>(
+    message: &CarbonData<T>,
+    frames: &TransformTree,
+) -> Result<Vec<u8>, WireError> {
+    let mut out = Writer::new(Some(frames));
+    out.put(&MAGIC);
+    out.put(&[VERSION]);
+    T::TYPE_NAME.to_string().encode(&mut out);
+    message.metadata.encode(&mut out);
+    message.data().encode(&mut out);
+    out.finish()
+}
+
+/// The type name in an encoded message's header.
+pub fn message_type(bytes: &[u8]) -> Result<String, WireError> {
+    let mut reader = Reader::new(bytes, None);
+    header(&mut reader)
+}
+
+/// Decodes a message, resolving frame names against `frames`.
+pub fn decode<T: WireMessage>(
+    bytes: &[u8],
+    frames: &TransformTree,
+) -> Result<CarbonData<T>, WireError> {
+    let mut reader = Reader::new(bytes, Some(frames));
+    let found = header(&mut reader)?;
+    if found != T::TYPE_NAME {
+        return Err(WireError::WrongType {
+            expected: T::TYPE_NAME,
+            found,
+        });
+    }
+    let metadata = CarbonMetadata::decode(&mut reader)?;
+    let data = T::decode(&mut reader)?;
+    match reader.remaining() {
+        0 => Ok(CarbonData::new(data, metadata)),
+        count => Err(WireError::TrailingBytes(count)),
+    }
+}
+
+fn header(reader: &mut Reader<'_>) -> Result<String, WireError> {
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(WireError::BadMagic);
+    }
+    match u8::decode(reader)? {
+        VERSION => String::decode(reader),
+        version => Err(WireError::UnsupportedVersion(version)),
+    }
+}
+
+macro_rules! impl_wire_number {
+    ($($number:ty),*) => {
+        $(
+            impl WireFormat for $number {
+                fn encode(&self, out: &mut Writer<'_>) {
+                    out.put(&self.to_le_bytes());
+                }
+
+                fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+                    Ok(<$number>::from_le_bytes(reader.array()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_number!(u8, u16, u32, u64, i32, i64, f32, f64);
+
+// Encodes the named fields in order; the order is part of the format.
+macro_rules! impl_wire_struct {
+    ($type:ty { $($field:ident),* }) => {
+        impl WireFormat for $type {
+            fn encode(&self, out: &mut Writer<'_>) {
+                $(self.$field.encode(out);)*
+            }
+
+            fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+                Ok(Self {
+                    $($field: WireFormat::decode(reader)?,)*
+                })
+            }
+        }
+    };
+}
+
+macro_rules! impl_wire_message {
+    ($($type:ty => $name:literal),* $(,)?) => {
+        $(
+            impl WireMessage for $type {
+                const TYPE_NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+impl WireFormat for bool {
+    fn encode(&self, out: &mut Writer<'_>) {
+        out.put(&[*self as u8]);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(WireError::InvalidValue("bool")),
+        }
+    }
+}
+
+impl WireFormat for String {
+    fn encode(&self, out: &mut Writer<'_>) {
+        (self.len() as u32).encode(out);
+        out.put(self.as_bytes());
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let length = reader.count(1)?;
+        let bytes = reader.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| WireError::InvalidUtf8)
+    }
+}
+
+impl<T: WireFormat> WireFormat for Option<T> {
+    fn encode(&self, out: &mut Writer<'_>) {
+        self.is_some().encode(out);
+        if let Some(value) = self {
+            value.encode(out);
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        match bool::decode(reader)? {
+            true => Ok(Some(T::decode(reader)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<T: WireFormat> WireFormat for Vec<T> {
+    fn encode(&self, out: &mut Writer<'_>) {
+        (self.len() as u32).encode(out);
+        for value in self {
+            value.encode(out);
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let count = reader.count(1)?;
+        (0..count).map(|_| T::decode(reader)).collect()
+    }
+}
+
+impl<T: WireFormat, const N: usize> WireFormat for [T; N] {
+    fn encode(&self, out: &mut Writer<'_>) {
+        for value in self {
+            value.encode(out);
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let values = (0..N)
+            .map(|_| T::decode(reader))
+            .collect::<Result<Vec<T>, _>>()?;
+        Ok(values
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("decoded N values")))
+    }
+}
+
+impl<A: WireFormat, B: WireFormat> WireFormat for (A, B) {
+    fn encode(&self, out: &mut Writer<'_>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+impl WireFormat for Vec2 {
+    fn encode(&self, out: &mut Writer<'_>) {
+        self.to_array().encode(out);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(Vec2::from_array(WireFormat::decode(reader)?))
+    }
+}
+
+impl WireFormat for Vec3 {
+    fn encode(&self, out: &mut Writer<'_>) {
+        self.to_array().encode(out);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(Vec3::from_array(WireFormat::decode(reader)?))
+    }
+}
+
+// As `x, y, z, w`.
+impl WireFormat for Quat {
+    fn encode(&self, out: &mut Writer<'_>) {
+        self.to_array().encode(out);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(Quat::from_array(WireFormat::decode(reader)?))
+    }
+}
+
+// The top three rows of the matrix, column by column.
+impl WireFormat for Transform {
+    fn encode(&self, out: &mut Writer<'_>) {
+        for column in self.to_matrix() {
+            [column[0], column[1], column[2]].encode(out);
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let columns = <[[f32; 3]; 4]>::decode(reader)?;
+        let mut matrix = [[0.0; 4]; 4];
+        for (column, [x, y, z]) in matrix.iter_mut().zip(columns) {
+            *column = [x, y, z, 0.0];
+        }
+        matrix[3][3] = 1.0;
+        Ok(Transform::from_matrix(&matrix))
+    }
+}
+
+// Frames are sent by name, since each process numbers its frames in the order
+// it added them.
+impl WireFormat for FrameId {
+    fn encode(&self, out: &mut Writer<'_>) {
+        match out.frames.and_then(|frames| frames.name(*self)) {
+            Some(name) => name.to_string().encode(out),
+            None => out.fail(WireError::UnknownFrame(format!("#{}", self.index()))),
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let name = String::decode(reader)?;
+        reader
+            .frames
+            .and_then(|frames| frames.frame(&name))
+            .ok_or(WireError::UnknownFrame(name))
+    }
+}
+
+impl WireFormat for CommandVelocity {
+    fn encode(&self, out: &mut Writer<'_>) {
+        self.0.encode(out);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(CommandVelocity(f32::decode(reader)?))
+    }
+}
+
+impl_wire_struct!(CarbonMetadata {
+    name,
+    description,
+    timestamp
+});
+impl_wire_struct!(Point {
+    position,
+    intensity
+});
+impl_wire_struct!(PointCloud { points, frame });
+impl_wire_struct!(Pose2D { x, y, theta });
+impl_wire_struct!(PoseWithCovariance2D { pose, covariance });
+impl_wire_struct!(PoseWithCovariance3D {
+    transform,
+    covariance
+});
+impl_wire_struct!(Twist2D { linear, angular });
+impl_wire_struct!(Twist3D { linear, angular });
+impl_wire_struct!(JointCommand {
+    position,
+    velocity,
+    effort
+});
+impl_wire_struct!(EncoderFeedback { position, velocity });
+impl_wire_struct!(ImuReading {
+    angular_velocity,
+    linear_acceleration,
+    magnetic_field
+});
+impl_wire_struct!(ImuMeasurement {
+    orientation,
+    orientation_covariance,
+    angular_velocity,
+    angular_velocity_covariance,
+    linear_acceleration,
+    linear_acceleration_covariance,
+    frame
+});
+
+impl_wire_message!(
+    PointCloud => "carbon/PointCloud",
+    Pose2D => "carbon/Pose2D",
+    PoseWithCovariance2D => "carbon/PoseWithCovariance2D",
+    PoseWithCovariance3D => "carbon/PoseWithCovariance3D",
+    Twist2D => "carbon/Twist2D",
+    Twist3D => "carbon/Twist3D",
+    JointCommand => "carbon/JointCommand",
+    EncoderFeedback => "carbon/EncoderFeedback",
+    CommandVelocity => "carbon/CommandVelocity",
+    ImuReading => "carbon/ImuReading",
+    ImuMeasurement => "carbon/ImuMeasurement",
+    (CommandVelocity, CommandVelocity) => "carbon/WheelCommands",
+    (EncoderFeedback, EncoderFeedback) => "carbon/WheelFeedback",
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> CarbonMetadata {
+        CarbonMetadata {
+            name: "scan".to_string(),
+            description: "front lidar".to_string(),
+            timestamp: 1_234_567_890,
+        }
+    }
+
+    // Two trees naming the same frames, added in a different order so their
+    // indices differ.
+    fn trees() -> (TransformTree, TransformTree) {
+        let mut sender = TransformTree::new("base");
+        sender.add_frame("imu", FrameId::root(), Transform::default());
+        sender.add_frame("lidar", FrameId::root(), Transform::default());
+        let mut receiver = TransformTree::new("base");
+        receiver.add_frame("lidar", FrameId::root(), Transform::default());
+        receiver.add_frame("imu", FrameId::root(), Transform::default());
+        (sender, receiver)
+    }
+
+    #[test]
+    fn round_trips_a_pose() {
+        let (frames, _) = trees();
+        let message = CarbonData::new(
+            Pose2D {
+                x: 1.5,
+                y: -2.0,
+                theta: 0.25,
+            },
+            metadata(),
+        );
+        let bytes = encode(&message, &frames).unwrap();
+        assert_eq!(message_type(&bytes).unwrap(), "carbon/Pose2D");
+        let decoded = decode::<Pose2D>(&bytes, &frames).unwrap();
+        assert_eq!(decoded.data(), message.data());
+        assert_eq!(decoded.metadata.name, "scan");
+        assert_eq!(decoded.metadata.description, "front lidar");
+        assert_eq!(decoded.metadata.timestamp, 1_234_567_890);
+    }
+
+    #[test]
+    fn sends_frames_by_name() {
+        let (sender, receiver) = trees();
+        let lidar = sender.frame("lidar").unwrap();
+        assert_ne!(lidar, receiver.frame("lidar").unwrap());
+        let points = vec![
+            Point::new(Vec3::new(1.0, 2.0, 3.0), 0.5),
+            Point::new(Vec3::new(-4.0, 0.0, 0.25), 1.0),
+        ];
+        let message = CarbonData::new(PointCloud::in_frame(points.clone(), lidar), metadata());
+        let bytes = encode(&message, &sender).unwrap();
+        let decoded = decode::<PointCloud>(&bytes, &receiver).unwrap();
+        assert_eq!(decoded.data().points, points);
+        assert_eq!(decoded.data().frame, receiver.frame("lidar").unwrap());
+    }
+
+    #[test]
+    fn rejects_frames_either_tree_lacks() {
+        let (sender, _) = trees();
+        let lidar = sender.frame("lidar").unwrap();
+        let message = CarbonData::new(PointCloud::in_frame(Vec::new(), lidar), metadata());
+        let bytes = encode(&message, &sender).unwrap();
+        let other = TransformTree::new("base");
+        assert_eq!(
+            decode::<PointCloud>(&bytes, &other).unwrap_err(),
+            WireError::UnknownFrame("lidar".to_string())
+        );
+        assert!(matches!(
+            encode(&message, &other),
+            Err(WireError::UnknownFrame(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let (frames, _) = trees();
+        let message = CarbonData::new(CommandVelocity(0.5), metadata());
+        let bytes = encode(&message, &frames).unwrap();
+        assert_eq!(
+            decode::<Pose2D>(&bytes, &frames).unwrap_err(),
+            WireError::WrongType {
+                expected: "carbon/Pose2D",
+                found: "carbon/CommandVelocity".to_string(),
+            }
+        );
+        assert_eq!(
+            decode::<CommandVelocity>(&bytes[..bytes.len() - 1], &frames).unwrap_err(),
+            WireError::Truncated
+        );
+        let mut longer = bytes.clone();
+        longer.extend_from_slice(&[0, 0]);
+        assert_eq!(
+            decode::<CommandVelocity>(&longer, &frames).unwrap_err(),
+            WireError::TrailingBytes(2)
+        );
+        let mut corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert_eq!(
+            decode::<CommandVelocity>(&corrupt, &frames).unwrap_err(),
+            WireError::BadMagic
+        );
+        let mut newer = bytes;
+        newer[MAGIC.len()] = VERSION + 1;
+        assert_eq!(
+            decode::<CommandVelocity>(&newer, &frames).unwrap_err(),
+            WireError::UnsupportedVersion(VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn huge_counts_do_not_allocate() {
+        let mut out = Writer::new(None);
+        u32::MAX.encode(&mut out);
+        let bytes = out.finish().unwrap();
+        assert_eq!(
+            Vec::<f64>::decode(&mut Reader::new(&bytes, None)).unwrap_err(),
+            WireError::Truncated
+        );
+    }
+}
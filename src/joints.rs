@@ -1,19 +1,306 @@
-use crate::links::{Source, Task};
-use petgraph::stable_graph::StableDiGraph;
+use std::collections::HashMap;
+
+use crate::primitives::{JointCommand, Transform, Vec3};
+use petgraph::stable_graph::{NodeIndex, StableDiGraph};
+use petgraph::Direction;
 
 type TransformTreeNodeIndex = u32;
 
-struct TransformTreeNode {
-    transform: (f64, f64, f64),
+/// Handle to a frame in a [`TransformTree`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FrameId(NodeIndex<TransformTreeNodeIndex>);
+
+impl FrameId {
+    /// The root frame of every tree.
+    pub fn root() -> Self {
+        Self(NodeIndex::new(0))
+    }
+
+    pub fn index(self) -> TransformTreeNodeIndex {
+        self.0.index() as TransformTreeNodeIndex
+    }
+
+    pub fn from_index(index: TransformTreeNodeIndex) -> Self {
+        Self(NodeIndex::new(index as usize))
+    }
 }
 
-struct TransformTreeEdge {
-    parent: TransformTreeNodeIndex,
-    child: TransformTreeNodeIndex,
+struct TransformTreeNode {
+    name: String,
+    // Pose of the frame relative to its parent, i.e. maps points in this frame
+    // into the parent frame.
+    transform: Transform,
 }
 
+/// Tree of named coordinate frames, e.g. world -> base_link -> lidar.
 pub struct TransformTree {
-    // graph: StableDiGraph<TransformTreeNode, TransformTreeEdge, TransformTreeNodeIndex>,
+    graph: StableDiGraph<TransformTreeNode, (), TransformTreeNodeIndex>,
+    names: HashMap<String, FrameId>,
+}
+
+impl TransformTree {
+    pub fn new(root_name: &str) -> Self {
+        let mut graph = StableDiGraph::default();
+        let root = FrameId(graph.add_node(TransformTreeNode {
+            name: root_name.to_string(),
+            transform: Transform::identity(),
+        }));
+        debug_assert_eq!(root, FrameId::root());
+        Self {
+            graph,
+            names: HashMap::from([(root_name.to_string(), root)]),
+        }
+    }
+
+    pub fn root(&self) -> FrameId {
+        FrameId::root()
+    }
+
+    /// Adds a frame posed by `transform` relative to `parent`. If a frame with
+    /// this name already exists it is moved under `parent` instead.
+    pub fn add_frame(&mut self, name: &str, parent: FrameId, transform: Transform) -> FrameId {
+        assert!(
+            self.contains(parent),
+            "Parent frame does not exist in the TransformTree"
+        );
+        if let Some(&frame) = self.names.get(name) {
+            assert!(
+                frame != parent && !self.is_ancestor(frame, parent),
+                "Moving frame {name:?} under {parent:?} would create a cycle"
+            );
+            if let Some(edge) = self
+                .parent(frame)
+                .and_then(|old_parent| self.graph.find_edge(old_parent.0, frame.0))
+            {
+                self.graph.remove_edge(edge);
+            }
+            self.graph.add_edge(parent.0, frame.0, ());
+            self.graph[frame.0].transform = transform;
+            return frame;
+        }
+        let frame = FrameId(self.graph.add_node(TransformTreeNode {
+            name: name.to_string(),
+            transform,
+        }));
+        self.graph.add_edge(parent.0, frame.0, ());
+        self.names.insert(name.to_string(), frame);
+        frame
+    }
+
+    /// Removes a frame together with all frames below it.
+    pub fn remove_frame(&mut self, frame: FrameId) {
+        if frame == FrameId::root() || !self.contains(frame) {
+            return;
+        }
+        for child in self.children(frame) {
+            self.remove_frame(child);
+        }
+        if let Some(node) = self.graph.remove_node(frame.0) {
+            self.names.remove(&node.name);
+        }
+    }
+
+    pub fn contains(&self, frame: FrameId) -> bool {
+        self.graph.contains_node(frame.0)
+    }
+
+    pub fn frame(&self, name: &str) -> Option<FrameId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn name(&self, frame: FrameId) -> Option<&str> {
+        self.graph
+            .node_weight(frame.0)
+            .map(|node| node.name.as_str())
+    }
+
+    pub fn parent(&self, frame: FrameId) -> Option<FrameId> {
+        self.graph
+            .neighbors_directed(frame.0, Direction::Incoming)
+            .next()
+            .map(FrameId)
+    }
+
+    pub fn children(&self, frame: FrameId) -> Vec<FrameId> {
+        self.graph
+            .neighbors_directed(frame.0, Direction::Outgoing)
+            .map(FrameId)
+            .collect()
+    }
+
+    /// Pose of `frame` relative to its parent.
+    pub fn transform(&self, frame: FrameId) -> Option<Transform> {
+        self.graph.node_weight(frame.0).map(|node| node.transform)
+    }
+
+    /// Updates the pose of `frame` relative to its parent. Returns `false` if the
+    /// frame does not exist.
+    pub fn set_transform(&mut self, frame: FrameId, transform: Transform) -> bool {
+        match self.graph.node_weight_mut(frame.0) {
+            Some(node) => {
+                node.transform = transform;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Transform mapping points in `source` into `target`, composed through the
+    /// closest common ancestor.
+    pub fn lookup(&self, target: FrameId, source: FrameId) -> Option<Transform> {
+        if !self.contains(target) || !self.contains(source) {
+            return None;
+        }
+        let target_chain = self.chain_to_root(target);
+        let source_chain = self.chain_to_root(source);
+        let common = *source_chain
+            .iter()
+            .find(|frame| target_chain.contains(frame))?;
+        let ancestor_from_source = self.transform_to_ancestor(&source_chain, common);
+        let ancestor_from_target = self.transform_to_ancestor(&target_chain, common);
+        Some(ancestor_from_target.inverse().apply(ancestor_from_source))
+    }
+
+    /// Lookup by frame name.
+    pub fn lookup_by_name(&self, target: &str, source: &str) -> Option<Transform> {
+        self.lookup(self.frame(target)?, self.frame(source)?)
+    }
+
+    fn chain_to_root(&self, frame: FrameId) -> Vec<FrameId> {
+        let mut chain = vec![frame];
+        let mut current = frame;
+        while let Some(parent) = self.parent(current) {
+            chain.push(parent);
+            current = parent;
+        }
+        chain
+    }
+
+    fn transform_to_ancestor(&self, chain: &[FrameId], ancestor: FrameId) -> Transform {
+        chain
+            .iter()
+            .take_while(|&&frame| frame != ancestor)
+            .fold(Transform::identity(), |ancestor_from_frame, &frame| {
+                self.graph[frame.0].transform.apply(ancestor_from_frame)
+            })
+    }
+
+    fn is_ancestor(&self, ancestor: FrameId, frame: FrameId) -> bool {
+        self.chain_to_root(frame).contains(&ancestor)
+    }
 }
 
 pub struct TransformModel {}
+
+/// How a joint lets its child link move relative to its parent. Axes are unit
+/// vectors in the joint frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JointKind {
+    Fixed,
+    /// Rotation about `axis` within the joint limits.
+    Revolute {
+        axis: Vec3,
+    },
+    /// Unbounded rotation about `axis`.
+    Continuous {
+        axis: Vec3,
+    },
+    /// Translation along `axis`.
+    Prismatic {
+        axis: Vec3,
+    },
+}
+
+/// Position bounds in radians or metres, and bounds on the rate of change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JointLimits {
+    pub lower: f32,
+    pub upper: f32,
+    pub velocity: f32,
+    pub acceleration: f32,
+    pub jerk: f32,
+    pub effort: f32,
+}
+
+impl Default for JointLimits {
+    fn default() -> Self {
+        Self {
+            lower: f32::NEG_INFINITY,
+            upper: f32::INFINITY,
+            velocity: 1.0,
+            acceleration: 2.0,
+            jerk: 10.0,
+            effort: f32::INFINITY,
+        }
+    }
+}
+
+impl JointLimits {
+    pub fn new(lower: f32, upper: f32, velocity: f32, acceleration: f32) -> Self {
+        Self {
+            lower,
+            upper,
+            velocity,
+            acceleration,
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, position: f32) -> bool {
+        (self.lower..=self.upper).contains(&position)
+    }
+
+    pub fn clamp(&self, position: f32) -> f32 {
+        position.clamp(self.lower, self.upper)
+    }
+}
+
+/// A joint connecting a parent link to a child link.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JointModel {
+    pub name: String,
+    pub kind: JointKind,
+    /// Pose of the joint frame in the parent link frame at zero position.
+    pub origin: Transform,
+    pub limits: JointLimits,
+}
+
+impl JointModel {
+    pub fn new(name: &str, kind: JointKind, origin: Transform, limits: JointLimits) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            origin,
+            limits,
+        }
+    }
+
+    pub fn fixed(name: &str, origin: Transform) -> Self {
+        Self::new(name, JointKind::Fixed, origin, JointLimits::default())
+    }
+
+    /// Whether the joint has a position to command.
+    pub fn is_actuated(&self) -> bool {
+        self.kind != JointKind::Fixed
+    }
+
+    /// Pose of the child link in the parent link frame at `position`.
+    pub fn transform(&self, position: f32) -> Transform {
+        let motion = match self.kind {
+            JointKind::Fixed => return self.origin,
+            JointKind::Revolute { axis } | JointKind::Continuous { axis } => {
+                Transform::from_axis_angle(axis, position)
+            }
+            JointKind::Prismatic { axis } => Transform::from_translation(axis * position),
+        };
+        self.origin.apply(motion)
+    }
+}
+
+/// A commandable joint between two frames of a [`TransformTree`].
+pub trait Joint {
+    /// The parent and child frames.
+    fn get_joint(&self) -> (FrameId, FrameId);
+    fn set_joint(&mut self, parent: FrameId, child: FrameId);
+    fn update_joint(&mut self, command: JointCommand);
+}
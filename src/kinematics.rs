@@ -0,0 +1,498 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::description::PlacedGeometry;
+use crate::joints::{FrameId, JointLimits, JointModel, TransformTree};
+use crate::planning::PlanError;
+use crate::primitives::{Transform, Vec3};
+
+/// A link and the joint attaching it to the previous link of a chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainLink {
+    pub name: String,
+    pub joint: JointModel,
+    /// Collision shapes placed in the link frame.
+    pub collision: Vec<PlacedGeometry>,
+}
+
+/// A serial chain of links from a fixed base, e.g. a manipulator arm. Joint
+/// positions are given for the actuated joints only, in chain order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KinematicChain {
+    base_collision: Vec<PlacedGeometry>,
+    links: Vec<ChainLink>,
+}
+
+impl KinematicChain {
+    pub fn new(base_collision: Vec<PlacedGeometry>) -> Self {
+        Self {
+            base_collision,
+            links: Vec::new(),
+        }
+    }
+
+    pub fn with_link(
+        mut self,
+        name: &str,
+        joint: JointModel,
+        collision: Vec<PlacedGeometry>,
+    ) -> Self {
+        self.links.push(ChainLink {
+            name: name.to_string(),
+            joint,
+            collision,
+        });
+        self
+    }
+
+    pub fn base_collision(&self) -> &[PlacedGeometry] {
+        &self.base_collision
+    }
+
+    pub fn links(&self) -> &[ChainLink] {
+        &self.links
+    }
+
+    fn actuated(&self) -> impl Iterator<Item = &JointModel> {
+        self.links
+            .iter()
+            .map(|link| &link.joint)
+            .filter(|joint| joint.is_actuated())
+    }
+
+    /// Number of actuated joints.
+    pub fn dof(&self) -> usize {
+        self.actuated().count()
+    }
+
+    pub fn joint_names(&self) -> Vec<String> {
+        self.actuated().map(|joint| joint.name.clone()).collect()
+    }
+
+    pub fn limits(&self) -> Vec<JointLimits> {
+        self.actuated().map(|joint| joint.limits).collect()
+    }
+
+    pub fn within_limits(&self, positions: &[f32]) -> bool {
+        positions.len() == self.dof()
+            && self
+                .actuated()
+                .zip(positions)
+                .all(|(joint, position)| joint.limits.contains(*position))
+    }
+
+    /// Pose of every link in the base frame. Panics unless there is one
+    /// position per actuated joint.
+    pub fn forward_kinematics(&self, positions: &[f32]) -> Vec<Transform> {
+        assert_eq!(
+            positions.len(),
+            self.dof(),
+            "wrong number of joint positions"
+        );
+        let mut positions = positions.iter();
+        let mut pose = Transform::identity();
+        self.links
+            .iter()
+            .map(|link| {
+                let position = if link.joint.is_actuated() {
+                    positions.next().copied().unwrap_or_default()
+                } else {
+                    0.0
+                };
+                pose = pose.apply(link.joint.transform(position));
+                pose
+            })
+            .collect()
+    }
+
+    /// Pose of the last link in the base frame.
+    pub fn end_effector(&self, positions: &[f32]) -> Transform {
+        self.forward_kinematics(positions)
+            .last()
+            .copied()
+            .unwrap_or_else(Transform::identity)
+    }
+
+    /// Adds a frame per link below `base`, at zero joint positions.
+    pub fn add_to_tree(&self, tree: &mut TransformTree, base: FrameId) -> Vec<FrameId> {
+        let mut parent = base;
+        self.links
+            .iter()
+            .map(|link| {
+                parent = tree.add_frame(&link.name, parent, link.joint.transform(0.0));
+                parent
+            })
+            .collect()
+    }
+
+    /// Moves the frames added by [`KinematicChain::add_to_tree`] to `positions`.
+    pub fn update_tree(&self, tree: &mut TransformTree, frames: &[FrameId], positions: &[f32]) {
+        let mut positions = positions.iter();
+        for (link, frame) in self.links.iter().zip(frames) {
+            let position = if link.joint.is_actuated() {
+                positions.next().copied().unwrap_or_default()
+            } else {
+                0.0
+            };
+            tree.set_transform(*frame, link.joint.transform(position));
+        }
+    }
+}
+
+// A rigid body's collision shapes with surface samples and a bounding sphere,
+// all in the body frame.
+#[derive(Clone, Debug)]
+struct Body {
+    geometry: Vec<PlacedGeometry>,
+    points: Vec<Vec3>,
+    center: Vec3,
+    radius: f32,
+}
+
+impl Body {
+    fn new(geometry: Vec<PlacedGeometry>, spacing: f32) -> Self {
+        let points: Vec<Vec3> = geometry
+            .iter()
+            .flat_map(|placed| {
+                placed
+                    .geometry
+                    .surface_points(spacing)
+                    .into_iter()
+                    .map(|point| placed.transform.transform_point(point))
+            })
+            .collect();
+        let center = if points.is_empty() {
+            Vec3::ZERO
+        } else {
+            points.iter().copied().sum::<Vec3>() / points.len() as f32
+        };
+        let radius = points
+            .iter()
+            .map(|point| point.distance(center))
+            .fold(0.0, f32::max);
+        Self {
+            geometry,
+            points,
+            center,
+            radius,
+        }
+    }
+
+    // Whether this body at `pose` overlaps `other` at `other_pose`, judged by
+    // the surface samples of each lying inside the other.
+    fn collides(
+        &self,
+        pose: &Transform,
+        other: &Body,
+        other_pose: &Transform,
+        padding: f32,
+    ) -> bool {
+        if self.points.is_empty() || other.points.is_empty() {
+            return false;
+        }
+        let distance = pose
+            .transform_point(self.center)
+            .distance(other_pose.transform_point(other.center));
+        if distance > self.radius + other.radius + padding {
+            return false;
+        }
+        let other_from_self = other_pose.inverse().apply(*pose);
+        let self_from_other = other_from_self.inverse();
+        self.points.iter().any(|point| {
+            let point = other_from_self.transform_point(*point);
+            other
+                .geometry
+                .iter()
+                .any(|placed| placed.contains(point, padding))
+        }) || other.points.iter().any(|point| {
+            let point = self_from_other.transform_point(*point);
+            self.geometry
+                .iter()
+                .any(|placed| placed.contains(point, padding))
+        })
+    }
+}
+
+/// Checks chain configurations against joint limits, self-collision and
+/// obstacles placed in the base frame. Links joined directly are never
+/// checked against each other.
+#[derive(Clone, Debug)]
+pub struct CollisionChecker {
+    chain: KinematicChain,
+    /// Shapes are grown by this much in every direction.
+    pub padding: f32,
+    spacing: f32,
+    // Index 0 is the base, then one per link.
+    bodies: Vec<Body>,
+    obstacles: Vec<Body>,
+    ignored: HashSet<(usize, usize)>,
+    revision: u64,
+}
+
+// Source of checker revisions, unique across all checkers.
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+impl CollisionChecker {
+    /// Shapes are compared through surface samples about `spacing` apart, so
+    /// obstacles thinner than that may be missed. Fails with
+    /// [`PlanError::InvalidResolution`] if `spacing` is not positive.
+    pub fn new(chain: KinematicChain, spacing: f32) -> Result<Self, PlanError> {
+        if spacing.is_nan() || spacing <= 0.0 {
+            return Err(PlanError::InvalidResolution);
+        }
+        let bodies: Vec<Body> = std::iter::once(chain.base_collision.clone())
+            .chain(chain.links.iter().map(|link| link.collision.clone()))
+            .map(|geometry| Body::new(geometry, spacing))
+            .collect();
+        let ignored = (1..bodies.len()).map(|index| (index - 1, index)).collect();
+        Ok(Self {
+            chain,
+            padding: 0.0,
+            spacing,
+            bodies,
+            obstacles: Vec::new(),
+            ignored,
+            revision: next_revision(),
+        })
+    }
+
+    pub fn with_padding(mut self, padding: f32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_obstacle(mut self, obstacle: PlacedGeometry) -> Self {
+        self.add_obstacle(obstacle);
+        self
+    }
+
+    pub fn add_obstacle(&mut self, obstacle: PlacedGeometry) {
+        self.obstacles.push(Body::new(vec![obstacle], self.spacing));
+        self.revision = next_revision();
+    }
+
+    pub fn clear_obstacles(&mut self) {
+        self.obstacles.clear();
+        self.revision = next_revision();
+    }
+
+    /// Identifies the chain, obstacles and allowed collisions; it changes
+    /// whenever they do, so anything computed against the checker can tell
+    /// when it is stale. Padding is not covered.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Stops checking the named links, or the base by an empty name, against
+    /// each other. Returns `false` if either is unknown.
+    pub fn allow_collision(&mut self, a: &str, b: &str) -> bool {
+        let index = |name: &str| {
+            if name.is_empty() {
+                Some(0)
+            } else {
+                self.chain
+                    .links
+                    .iter()
+                    .position(|link| link.name == name)
+                    .map(|index| index + 1)
+            }
+        };
+        match (index(a), index(b)) {
+            (Some(a), Some(b)) => {
+                self.ignored.insert((a.min(b), a.max(b)));
+                self.revision = next_revision();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn chain(&self) -> &KinematicChain {
+        &self.chain
+    }
+
+    pub fn in_collision(&self, positions: &[f32]) -> bool {
+        let poses: Vec<Transform> = std::iter::once(Transform::identity())
+            .chain(self.chain.forward_kinematics(positions))
+            .collect();
+        let bodies = self.bodies.iter().zip(&poses);
+        for (index, (body, pose)) in bodies.clone().enumerate() {
+            for (other_index, (other, other_pose)) in bodies.clone().enumerate().skip(index + 1) {
+                if !self.ignored.contains(&(index, other_index))
+                    && body.collides(pose, other, other_pose, self.padding)
+                {
+                    return true;
+                }
+            }
+            // The base is assumed to be placed clear of obstacles.
+            if index > 0
+                && self.obstacles.iter().any(|obstacle| {
+                    body.collides(pose, obstacle, &Transform::identity(), self.padding)
+                })
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Within joint limits and collision free.
+    pub fn is_valid(&self, positions: &[f32]) -> bool {
+        self.chain.within_limits(positions) && !self.in_collision(positions)
+    }
+
+    /// Whether the straight joint-space motion between two configurations is
+    /// valid, checked at most `resolution` apart in any joint. Fails with
+    /// [`PlanError::InvalidResolution`] if `resolution` is not positive.
+    pub fn motion_valid(
+        &self,
+        from: &[f32],
+        to: &[f32],
+        resolution: f32,
+    ) -> Result<bool, PlanError> {
+        if resolution.is_nan() || resolution <= 0.0 {
+            return Err(PlanError::InvalidResolution);
+        }
+        let largest = from
+            .iter()
+            .zip(to)
+            .map(|(a, b)| (b - a).abs())
+            .fold(0.0, f32::max);
+        let steps = ((largest / resolution).ceil() as usize).max(1);
+        Ok((1..=steps).all(|step| {
+            let fraction = step as f32 / steps as f32;
+            let positions: Vec<f32> = from
+                .iter()
+                .zip(to)
+                .map(|(a, b)| a + (b - a) * fraction)
+                .collect();
+            self.is_valid(&positions)
+        }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::description::Geometry;
+    use crate::joints::JointKind;
+
+    // A planar arm of a 0.5 m and a 0.4 m link turning about z, thin enough
+    // to be sampled at 2 cm.
+    pub(crate) fn arm() -> KinematicChain {
+        let limits = JointLimits {
+            lower: -PI,
+            upper: PI,
+            ..Default::default()
+        };
+        let joint = |name: &str, x: f32| {
+            JointModel::new(
+                name,
+                JointKind::Revolute { axis: Vec3::Z },
+                Transform::from_translation(Vec3::new(x, 0.0, 0.0)),
+                limits,
+            )
+        };
+        let link = |length: f32| {
+            vec![PlacedGeometry::new(
+                Geometry::Box {
+                    height: 0.04,
+                    width: 0.04,
+                    depth: length,
+                },
+                Transform::from_translation(Vec3::new(length / 2.0, 0.0, 0.0)),
+            )]
+        };
+        KinematicChain::new(Vec::new())
+            .with_link("upper", joint("shoulder", 0.0), link(0.5))
+            .with_link("fore", joint("elbow", 0.5), link(0.4))
+    }
+
+    pub(crate) fn obstacle(x: f32, y: f32) -> PlacedGeometry {
+        PlacedGeometry::new(
+            Geometry::Box {
+                height: 0.1,
+                width: 0.1,
+                depth: 0.1,
+            },
+            Transform::from_translation(Vec3::new(x, y, 0.0)),
+        )
+    }
+
+    #[test]
+    fn links_hitting_obstacles_collide() {
+        let checker = CollisionChecker::new(arm(), 0.02)
+            .unwrap()
+            .with_obstacle(obstacle(0.7, 0.35));
+        let towards = 0.35f32.atan2(0.7);
+
+        assert!(checker.in_collision(&[towards, 0.0]));
+        assert!(!checker.in_collision(&[-0.5, 0.0]));
+        // Folding the elbow keeps the forearm short of the obstacle.
+        assert!(!checker.in_collision(&[towards, 2.5]));
+        // Only the forearm reaches it here.
+        assert!(checker.in_collision(&[0.0, 1.0]));
+        assert!(!checker.is_valid(&[0.0, 4.0]));
+        assert!(!checker.is_valid(&[0.0]));
+    }
+
+    #[test]
+    fn padding_grows_the_shapes() {
+        let mut checker = CollisionChecker::new(arm(), 0.02)
+            .unwrap()
+            .with_obstacle(obstacle(0.5, 0.15));
+        assert!(!checker.in_collision(&[0.0, 0.0]));
+        checker.padding = 0.1;
+        assert!(checker.in_collision(&[0.0, 0.0]));
+    }
+
+    #[test]
+    fn motions_are_checked_along_the_way() {
+        let checker = CollisionChecker::new(arm(), 0.02)
+            .unwrap()
+            .with_obstacle(obstacle(0.7, 0.35));
+        assert_eq!(
+            checker.motion_valid(&[-0.5, 0.0], &[1.2, 0.0], 0.01),
+            Ok(false)
+        );
+        assert_eq!(
+            checker.motion_valid(&[-0.5, 2.5], &[1.2, 2.5], 0.01),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn non_positive_resolutions_are_errors() {
+        assert_eq!(
+            CollisionChecker::new(arm(), 0.0).err(),
+            Some(PlanError::InvalidResolution)
+        );
+        assert!(CollisionChecker::new(arm(), f32::NAN).is_err());
+        let checker = CollisionChecker::new(arm(), 0.02).unwrap();
+        assert_eq!(
+            checker.motion_valid(&[0.0, 0.0], &[1.0, 0.0], -0.1),
+            Err(PlanError::InvalidResolution)
+        );
+    }
+
+    #[test]
+    fn revisions_change_with_the_obstacles() {
+        let mut checker = CollisionChecker::new(arm(), 0.02).unwrap();
+        let other = CollisionChecker::new(arm(), 0.02).unwrap();
+        assert_ne!(checker.revision(), other.revision());
+
+        let before = checker.revision();
+        checker.add_obstacle(obstacle(1.0, 1.0));
+        assert_ne!(checker.revision(), before);
+        let with_obstacle = checker.revision();
+        assert_eq!(checker.clone().revision(), with_obstacle);
+        checker.clear_obstacles();
+        assert_ne!(checker.revision(), with_obstacle);
+    }
+}
@@ -1,2 +1,26 @@
+pub mod bus;
+pub mod clock;
+pub mod control;
+pub mod costmap;
+pub mod description;
+pub mod diagnostics;
+pub mod drive;
+pub mod estimation;
+pub mod hardware;
+pub mod imu;
+pub mod ipc;
 pub mod joints;
+pub mod kinematics;
+pub mod lidar;
 pub mod links;
+pub mod localization;
+pub mod mapping;
+pub mod navigation;
+pub mod planning;
+pub mod ports;
+pub mod primitives;
+pub mod safety;
+pub mod scan_matching;
+pub mod simulation;
+pub mod slam;
+pub mod trajectory;
@@ -0,0 +1,147 @@
+pub mod filters;
+pub mod spatial;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::clock::{default_clock, SharedClock};
+use crate::joints::{FrameId, TransformTree};
+use crate::links::{CarbonData, CarbonMetadata, CarbonTaskConfiguration, Controller, Sensor, Task};
+use crate::ports::PortReader;
+use crate::primitives::{Point, Transform};
+
+// Points are kept in the order the sensor produced them, which filters working on
+// neighbouring beams rely on.
+#[derive(Clone, Debug, Default)]
+pub struct PointCloud {
+    pub points: Vec<Point>,
+    /// Frame the points are expressed in.
+    pub frame: FrameId,
+}
+
+impl PointCloud {
+    pub fn new(points: Vec<Point>) -> Self {
+        Self {
+            points,
+            frame: FrameId::root(),
+        }
+    }
+
+    pub fn in_frame(points: Vec<Point>, frame: FrameId) -> Self {
+        Self { points, frame }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Applies `transform` to every point and relabels the cloud as being in `frame`.
+    pub fn transform(mut self, transform: &Transform, frame: FrameId) -> Self {
+        for point in &mut self.points {
+            point.position = transform.transform_point(point.position);
+        }
+        self.frame = frame;
+        self
+    }
+
+    /// Re-expresses the cloud in `target`. Returns `None` if either frame is
+    /// missing from the tree.
+    pub fn transformed_to(self, tree: &TransformTree, target: FrameId) -> Option<Self> {
+        if self.frame == target {
+            return Some(self);
+        }
+        let transform = tree.lookup(target, self.frame)?;
+        Some(self.transform(&transform, target))
+    }
+}
+
+pub trait LIDAR: PortReader<Output = Vec<Point>> {}
+
+/// Reads a lidar driver and stamps its points with the frame the sensor is
+/// mounted in and the time on its clock.
+pub struct LidarSensor<L: LIDAR> {
+    driver: Mutex<L>,
+    clock: SharedClock,
+    frame: FrameId,
+    name: String,
+}
+
+impl<L: LIDAR> LidarSensor<L> {
+    pub fn new(driver: L, frame: FrameId, name: &str) -> Self {
+        Self {
+            driver: Mutex::new(driver),
+            clock: default_clock(),
+            frame,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl<L: LIDAR> Task for LidarSensor<L> {
+    type Input = ();
+    type Output = CarbonData<PointCloud>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    // An empty cloud means the driver had no new scan.
+    fn process(&self, _input: Self::Input) -> Self::Output {
+        let points = self
+            .driver
+            .lock()
+            .expect("LIDAR driver lock poisoned")
+            .read_data()
+            .unwrap_or_default();
+        CarbonData::new(
+            PointCloud::in_frame(points, self.frame),
+            CarbonMetadata {
+                name: self.name.clone(),
+                timestamp: self.clock.now(),
+                ..Default::default()
+            },
+        )
+    }
+}
+
+impl<L: LIDAR> Sensor<PointCloud> for LidarSensor<L> {}
+
+/// Re-expresses clouds in a fixed target frame through the shared transform
+/// tree. Clouds whose frame cannot be resolved pass through unchanged, still
+/// labelled with their original frame.
+pub struct FrameTransformer {
+    tree: Arc<RwLock<TransformTree>>,
+    target: FrameId,
+}
+
+impl FrameTransformer {
+    pub fn new(tree: Arc<RwLock<TransformTree>>, target: FrameId) -> Self {
+        Self { tree, target }
+    }
+}
+
+impl Task for FrameTransformer {
+    type Input = CarbonData<PointCloud>;
+    type Output = CarbonData<PointCloud>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let tree = self.tree.read().expect("TransformTree lock poisoned");
+        input.map(|cloud| {
+            let transform = tree.lookup(self.target, cloud.frame);
+            match transform {
+                Some(transform) => cloud.transform(&transform, self.target),
+                None => cloud,
+            }
+        })
+    }
+}
+
+impl Controller<PointCloud, PointCloud> for FrameTransformer {}
@@ -0,0 +1,421 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use glam::IVec3;
+
+use crate::primitives::{Point, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Neighbour {
+    /// Index into the points the index was built from.
+    pub index: usize,
+    pub distance_squared: f32,
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_squared
+            .total_cmp(&other.distance_squared)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+/// Nearest-neighbour queries over a fixed set of points. Results are sorted by
+/// increasing distance.
+pub trait SpatialIndex {
+    fn nearest(&self, query: Vec3, k: usize) -> Vec<Neighbour>;
+    fn within_radius(&self, query: Vec3, radius: f32) -> Vec<Neighbour>;
+
+    fn nearest_one(&self, query: Vec3) -> Option<Neighbour> {
+        self.nearest(query, 1).into_iter().next()
+    }
+}
+
+/// Bounded max-heap keeping the `k` closest candidates seen so far.
+struct KNearest {
+    k: usize,
+    heap: BinaryHeap<Neighbour>,
+}
+
+impl KNearest {
+    fn new(k: usize) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    fn worst_distance_squared(&self) -> f32 {
+        if self.heap.len() < self.k {
+            f32::INFINITY
+        } else {
+            self.heap
+                .peek()
+                .map_or(f32::INFINITY, |n| n.distance_squared)
+        }
+    }
+
+    fn offer(&mut self, index: usize, distance_squared: f32) {
+        if self.k == 0 || distance_squared >= self.worst_distance_squared() {
+            return;
+        }
+        self.heap.push(Neighbour {
+            index,
+            distance_squared,
+        });
+        if self.heap.len() > self.k {
+            self.heap.pop();
+        }
+    }
+
+    fn into_sorted(self) -> Vec<Neighbour> {
+        self.heap.into_sorted_vec()
+    }
+}
+
+const LEAF_SIZE: usize = 8;
+
+/// Balanced 3D k-d tree. Splits on the axis of greatest spread at the median, stored
+/// implicitly: for every range of `order`, the middle element is the split point.
+pub struct KdTree {
+    positions: Vec<Vec3>,
+    order: Vec<usize>,
+    axes: Vec<u8>,
+}
+
+impl KdTree {
+    pub fn new(points: &[Point]) -> Self {
+        Self::from_positions(points.iter().map(|point| point.position).collect())
+    }
+
+    pub fn from_positions(positions: Vec<Vec3>) -> Self {
+        let mut tree = Self {
+            order: (0..positions.len()).collect(),
+            axes: vec![0; positions.len()],
+            positions,
+        };
+        tree.build(0, tree.order.len());
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn position(&self, index: usize) -> Vec3 {
+        self.positions[index]
+    }
+
+    fn build(&mut self, start: usize, end: usize) {
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+        let (min, max) = self.order[start..end].iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &index| {
+                (
+                    min.min(self.positions[index]),
+                    max.max(self.positions[index]),
+                )
+            },
+        );
+        let spread = max - min;
+        let axis = if spread.x >= spread.y && spread.x >= spread.z {
+            0
+        } else if spread.y >= spread.z {
+            1
+        } else {
+            2
+        };
+        let middle = (start + end) / 2;
+        let positions = &self.positions;
+        self.order[start..end].select_nth_unstable_by(middle - start, |&a, &b| {
+            positions[a][axis].total_cmp(&positions[b][axis])
+        });
+        self.axes[middle] = axis as u8;
+        self.build(start, middle);
+        self.build(middle + 1, end);
+    }
+
+    fn search_nearest(&self, query: Vec3, start: usize, end: usize, best: &mut KNearest) {
+        if end - start <= LEAF_SIZE {
+            for &index in &self.order[start..end] {
+                best.offer(index, self.positions[index].distance_squared(query));
+            }
+            return;
+        }
+        let middle = (start + end) / 2;
+        let split_index = self.order[middle];
+        let axis = self.axes[middle] as usize;
+        let offset = query[axis] - self.positions[split_index][axis];
+        best.offer(
+            split_index,
+            self.positions[split_index].distance_squared(query),
+        );
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search_nearest(query, near.0, near.1, best);
+        if offset * offset < best.worst_distance_squared() {
+            self.search_nearest(query, far.0, far.1, best);
+        }
+    }
+
+    fn search_radius(
+        &self,
+        query: Vec3,
+        radius_squared: f32,
+        start: usize,
+        end: usize,
+        found: &mut Vec<Neighbour>,
+    ) {
+        let mut offer = |index: usize| {
+            let distance_squared = self.positions[index].distance_squared(query);
+            if distance_squared <= radius_squared {
+                found.push(Neighbour {
+                    index,
+                    distance_squared,
+                });
+            }
+        };
+        if end - start <= LEAF_SIZE {
+            self.order[start..end]
+                .iter()
+                .for_each(|&index| offer(index));
+            return;
+        }
+        let middle = (start + end) / 2;
+        let split_index = self.order[middle];
+        let axis = self.axes[middle] as usize;
+        let offset = query[axis] - self.positions[split_index][axis];
+        offer(split_index);
+        if offset <= 0.0 || offset * offset <= radius_squared {
+            self.search_radius(query, radius_squared, start, middle, found);
+        }
+        if offset >= 0.0 || offset * offset <= radius_squared {
+            self.search_radius(query, radius_squared, middle + 1, end, found);
+        }
+    }
+}
+
+impl SpatialIndex for KdTree {
+    fn nearest(&self, query: Vec3, k: usize) -> Vec<Neighbour> {
+        let mut best = KNearest::new(k);
+        self.search_nearest(query, 0, self.order.len(), &mut best);
+        best.into_sorted()
+    }
+
+    fn within_radius(&self, query: Vec3, radius: f32) -> Vec<Neighbour> {
+        let mut found = Vec::new();
+        self.search_radius(query, radius * radius, 0, self.order.len(), &mut found);
+        found.sort_unstable();
+        found
+    }
+}
+
+/// Points bucketed into cubic cells. Cheaper to build than a [`KdTree`] and
+/// fastest for radius queries close to the cell size.
+pub struct VoxelHash {
+    positions: Vec<Vec3>,
+    cell_size: f32,
+    cells: HashMap<IVec3, Vec<usize>>,
+    // Corners of the box of occupied cells.
+    lower: IVec3,
+    upper: IVec3,
+}
+
+impl VoxelHash {
+    pub fn new(points: &[Point], cell_size: f32) -> Self {
+        Self::from_positions(
+            points.iter().map(|point| point.position).collect(),
+            cell_size,
+        )
+    }
+
+    pub fn from_positions(positions: Vec<Vec3>, cell_size: f32) -> Self {
+        let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        let (mut lower, mut upper) = (IVec3::MAX, IVec3::MIN);
+        for (index, &position) in positions.iter().enumerate() {
+            let key = voxel_key(position, Vec3::splat(cell_size));
+            lower = lower.min(key);
+            upper = upper.max(key);
+            cells.entry(key).or_default().push(index);
+        }
+        Self {
+            positions,
+            cell_size,
+            cells,
+            lower,
+            upper,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    fn visit_shell(&self, center: IVec3, radius: i32, mut visit: impl FnMut(usize)) {
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let on_shell = x.abs() == radius || y.abs() == radius || z.abs() == radius;
+                    if !on_shell {
+                        continue;
+                    }
+                    if let Some(indices) = self.cells.get(&(center + IVec3::new(x, y, z))) {
+                        indices.iter().for_each(|&index| visit(index));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl SpatialIndex for VoxelHash {
+    fn nearest(&self, query: Vec3, k: usize) -> Vec<Neighbour> {
+        if k == 0 || self.positions.is_empty() || !query.is_finite() {
+            return Vec::new();
+        }
+        let mut best = KNearest::new(k.min(self.positions.len()));
+        let center = voxel_key(query, Vec3::splat(self.cell_size));
+        // Shells closer in than the occupied cells are empty.
+        let mut shell = self
+            .lower
+            .saturating_sub(center)
+            .max(center.saturating_sub(self.upper))
+            .max(IVec3::ZERO)
+            .max_element();
+        loop {
+            // Once a shell's cube spans more cells than there are points,
+            // checking every point is cheaper than walking further out.
+            if (2.0 * shell as f64 + 1.0).powi(3) > self.positions.len() as f64 {
+                let mut best = KNearest::new(k.min(self.positions.len()));
+                for (index, position) in self.positions.iter().enumerate() {
+                    best.offer(index, position.distance_squared(query));
+                }
+                return best.into_sorted();
+            }
+            self.visit_shell(center, shell, |index| {
+                best.offer(index, self.positions[index].distance_squared(query))
+            });
+            // Anything in a further shell is at least `shell * cell_size` away.
+            let covered = shell as f32 * self.cell_size;
+            if best.worst_distance_squared() <= covered * covered {
+                break;
+            }
+            shell += 1;
+        }
+        best.into_sorted()
+    }
+
+    fn within_radius(&self, query: Vec3, radius: f32) -> Vec<Neighbour> {
+        let radius_squared = radius * radius;
+        let center = voxel_key(query, Vec3::splat(self.cell_size));
+        let reach = (radius / self.cell_size).ceil() as i32;
+        let mut found = Vec::new();
+        for shell in 0..=reach {
+            self.visit_shell(center, shell, |index| {
+                let distance_squared = self.positions[index].distance_squared(query);
+                if distance_squared <= radius_squared {
+                    found.push(Neighbour {
+                        index,
+                        distance_squared,
+                    });
+                }
+            });
+        }
+        found.sort_unstable();
+        found
+    }
+}
+
+pub(crate) fn voxel_key(position: Vec3, leaf_size: Vec3) -> IVec3 {
+    (position / leaf_size).floor().as_ivec3()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn cloud(count: usize) -> Vec<Point> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..count)
+            .map(|_| {
+                let position = Vec3::new(
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+                Point::new(position, 1.0)
+            })
+            .collect()
+    }
+
+    fn brute_force(points: &[Point], query: Vec3, k: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        order.sort_by(|&a, &b| {
+            let a = points[a].position.distance_squared(query);
+            a.total_cmp(&points[b].position.distance_squared(query))
+        });
+        order.truncate(k);
+        order
+    }
+
+    fn indices(neighbours: Vec<Neighbour>) -> Vec<usize> {
+        neighbours.into_iter().map(|n| n.index).collect()
+    }
+
+    #[test]
+    fn indices_agree_with_brute_force() {
+        let points = cloud(2000);
+        let tree = KdTree::new(&points);
+        let hash = VoxelHash::new(&points, 0.5);
+        for query in [
+            Vec3::ZERO,
+            Vec3::new(4.9, -4.9, 0.5),
+            Vec3::new(0.3, 2.0, -0.7),
+        ] {
+            let expected = brute_force(&points, query, 8);
+            assert_eq!(indices(tree.nearest(query, 8)), expected);
+            assert_eq!(indices(hash.nearest(query, 8)), expected);
+            let within: Vec<usize> = indices(tree.within_radius(query, 0.8));
+            assert_eq!(indices(hash.within_radius(query, 0.8)), within);
+            assert!(within
+                .iter()
+                .all(|&index| { points[index].position.distance(query) <= 0.8 }));
+        }
+    }
+
+    #[test]
+    fn voxel_hash_handles_far_and_non_finite_queries() {
+        let points = cloud(500);
+        let hash = VoxelHash::new(&points, 0.1);
+        let far = Vec3::new(1.0e6, -2.0e6, 3.0e5);
+        assert_eq!(indices(hash.nearest(far, 3)), brute_force(&points, far, 3));
+        assert!(hash.nearest(Vec3::splat(f32::NAN), 3).is_empty());
+        assert!(hash
+            .nearest(Vec3::new(f32::INFINITY, 0.0, 0.0), 3)
+            .is_empty());
+    }
+}
@@ -1,20 +1,56 @@
-type TransformTreeNodeIndex = u32;
-
+#[derive(Clone, Debug, Default)]
 pub struct CarbonMetadata {
     pub name: String,
     pub description: String,
+    /// Nanoseconds since an arbitrary epoch.
     pub timestamp: u64,
 }
 
+#[derive(Clone, Debug)]
 pub struct CarbonData<T> {
     data: T,
     pub metadata: CarbonMetadata,
 }
 
-trait CarbonDataPacket {}
+impl<T> CarbonData<T> {
+    pub fn new(data: T, metadata: CarbonMetadata) -> Self {
+        Self { data, metadata }
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    /// Transforms the payload while keeping the metadata.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> CarbonData<U> {
+        CarbonData {
+            data: f(self.data),
+            metadata: self.metadata,
+        }
+    }
+}
+
+pub trait CarbonDataPacket {
+    /// Nanoseconds, for packets carrying metadata.
+    fn timestamp(&self) -> Option<u64> {
+        None
+    }
+}
 
 impl CarbonDataPacket for () {}
-impl<T> CarbonDataPacket for CarbonData<T> {}
+impl<T> CarbonDataPacket for CarbonData<T> {
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.metadata.timestamp)
+    }
+}
 
 pub struct CarbonTaskConfiguration;
 
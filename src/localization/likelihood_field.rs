@@ -0,0 +1,230 @@
+use crate::mapping::occupancy_grid::{CellState, OccupancyGrid};
+use crate::primitives::{IVec2, Pose2D, Vec2};
+
+/// Distance from every cell of a map to the nearest occupied cell, capped at
+/// `max_distance`.
+#[derive(Clone, Debug)]
+pub struct LikelihoodField {
+    resolution: f32,
+    width: usize,
+    height: usize,
+    origin: Vec2,
+    max_distance: f32,
+    distances: Vec<f32>,
+}
+
+impl LikelihoodField {
+    pub fn new(grid: &OccupancyGrid, max_distance: f32) -> Self {
+        let (width, height) = (grid.width(), grid.height());
+        let far = (max_distance / grid.resolution()).powi(2) + 1.0;
+        let mut squared = vec![0.0; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                squared[y * width + x] = match grid.state(IVec2::new(x as i32, y as i32)) {
+                    CellState::Occupied => 0.0,
+                    _ => far,
+                };
+            }
+        }
+
+        // Exact Euclidean distance transform, one axis at a time.
+        let mut line = Vec::new();
+        let mut transformed = Vec::new();
+        for y in 0..height {
+            line.clear();
+            line.extend_from_slice(&squared[y * width..(y + 1) * width]);
+            squared_distance_transform(&line, &mut transformed);
+            squared[y * width..(y + 1) * width].copy_from_slice(&transformed);
+        }
+        for x in 0..width {
+            line.clear();
+            line.extend((0..height).map(|y| squared[y * width + x]));
+            squared_distance_transform(&line, &mut transformed);
+            for (y, value) in transformed.iter().enumerate() {
+                squared[y * width + x] = *value;
+            }
+        }
+
+        let distances = squared
+            .into_iter()
+            .map(|value| (value.sqrt() * grid.resolution()).min(max_distance))
+            .collect();
+        Self {
+            resolution: grid.resolution(),
+            width,
+            height,
+            origin: grid.origin(),
+            max_distance,
+            distances,
+        }
+    }
+
+    pub fn max_distance(&self) -> f32 {
+        self.max_distance
+    }
+
+    /// Distance to the nearest obstacle; `max_distance` outside the map.
+    pub fn distance(&self, position: Vec2) -> f32 {
+        let cell = ((position - self.origin) / self.resolution).floor();
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return self.max_distance;
+        }
+        let (x, y) = (cell.x as usize, cell.y as usize);
+        if x >= self.width || y >= self.height {
+            return self.max_distance;
+        }
+        self.distances[y * self.width + x]
+    }
+}
+
+// Felzenszwalb & Huttenlocher's lower envelope of parabolas.
+fn squared_distance_transform(input: &[f32], output: &mut Vec<f32>) {
+    let n = input.len();
+    output.clear();
+    output.resize(n, 0.0);
+    if n == 0 {
+        return;
+    }
+    let mut vertices = vec![0usize; n];
+    let mut boundaries = vec![0f32; n + 1];
+    let mut k = 0;
+    boundaries[0] = f32::NEG_INFINITY;
+    boundaries[1] = f32::INFINITY;
+    for q in 1..n {
+        let parabola_intersection = |v: usize| {
+            ((input[q] + (q * q) as f32) - (input[v] + (v * v) as f32))
+                / (2.0 * (q as f32 - v as f32))
+        };
+        let mut s = parabola_intersection(vertices[k]);
+        // boundaries[0] is -inf, so this never steps below the first parabola.
+        while s <= boundaries[k] {
+            k -= 1;
+            s = parabola_intersection(vertices[k]);
+        }
+        k += 1;
+        vertices[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f32::INFINITY;
+    }
+    k = 0;
+    for (q, value) in output.iter_mut().enumerate() {
+        while boundaries[k + 1] < q as f32 {
+            k += 1;
+        }
+        let v = vertices[k];
+        *value = (q as f32 - v as f32).powi(2) + input[v];
+    }
+}
+
+/// Beam-endpoint sensor model scoring scans against a [`LikelihoodField`].
+#[derive(Clone, Debug)]
+pub struct LikelihoodFieldModel {
+    pub z_hit: f32,
+    pub z_random: f32,
+    pub sigma_hit: f32,
+    pub max_range: f32,
+    /// Beams are subsampled evenly down to this many.
+    pub max_beams: usize,
+}
+
+impl Default for LikelihoodFieldModel {
+    fn default() -> Self {
+        Self {
+            z_hit: 0.95,
+            z_random: 0.05,
+            sigma_hit: 0.2,
+            max_range: 12.0,
+            max_beams: 60,
+        }
+    }
+}
+
+impl LikelihoodFieldModel {
+    /// Log-likelihood of `beams` (endpoints in the sensor frame) observed from
+    /// `sensor_pose` in the map frame.
+    pub fn log_likelihood(
+        &self,
+        field: &LikelihoodField,
+        beams: &[Vec2],
+        sensor_pose: &Pose2D,
+    ) -> f32 {
+        let step = (beams.len() / self.max_beams.max(1)).max(1);
+        let denominator = 2.0 * self.sigma_hit * self.sigma_hit;
+        let random = self.z_random / self.max_range;
+        beams
+            .iter()
+            .step_by(step)
+            .filter(|beam| {
+                let range = beam.length();
+                range.is_finite() && range < self.max_range
+            })
+            .map(|beam| {
+                let distance = field.distance(sensor_pose.transform_point(*beam));
+                (self.z_hit * (-distance * distance / denominator).exp() + random).ln()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_with(occupied: &[(i32, i32)]) -> OccupancyGrid {
+        let mut grid = OccupancyGrid::new(1.0, 5, 5, Vec2::ZERO);
+        for &(x, y) in occupied {
+            grid.set_log_odds(IVec2::new(x, y), 5.0);
+        }
+        grid
+    }
+
+    #[test]
+    fn distances_on_a_small_grid_are_euclidean() {
+        let field = LikelihoodField::new(&grid_with(&[(2, 2)]), 10.0);
+        let at = |x: f32, y: f32| field.distance(Vec2::new(x + 0.5, y + 0.5));
+
+        assert_eq!(at(2.0, 2.0), 0.0);
+        assert_eq!(at(4.0, 2.0), 2.0);
+        assert_eq!(at(2.0, 0.0), 2.0);
+        assert!((at(0.0, 0.0) - 8f32.sqrt()).abs() < 1e-6);
+        assert!((at(3.0, 4.0) - 5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_nearest_of_several_obstacles_counts() {
+        let field = LikelihoodField::new(&grid_with(&[(0, 0), (4, 4)]), 10.0);
+        let at = |x: f32, y: f32| field.distance(Vec2::new(x + 0.5, y + 0.5));
+
+        assert_eq!(at(1.0, 0.0), 1.0);
+        assert_eq!(at(4.0, 3.0), 1.0);
+        assert!((at(2.0, 2.0) - 8f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn distances_are_capped_and_far_outside_the_map() {
+        let field = LikelihoodField::new(&grid_with(&[(0, 0)]), 1.5);
+        assert_eq!(field.distance(Vec2::new(4.5, 4.5)), 1.5);
+        assert_eq!(field.distance(Vec2::new(-3.0, 0.5)), 1.5);
+        assert_eq!(field.distance(Vec2::new(0.5, 7.0)), 1.5);
+
+        // Without obstacles everything is far.
+        let empty = LikelihoodField::new(&grid_with(&[]), 2.0);
+        assert_eq!(empty.distance(Vec2::new(2.5, 2.5)), 2.0);
+    }
+
+    #[test]
+    fn beams_ending_on_obstacles_score_higher() {
+        let field =
+            LikelihoodField::new(&grid_with(&[(4, 0), (4, 1), (4, 2), (4, 3), (4, 4)]), 2.0);
+        let model = LikelihoodFieldModel::default();
+        let beams = [
+            Vec2::new(3.5, 0.0),
+            Vec2::new(3.5, 1.0),
+            Vec2::new(3.5, -1.0),
+        ];
+
+        let aligned = model.log_likelihood(&field, &beams, &Pose2D::new(1.0, 2.5, 0.0));
+        let shifted = model.log_likelihood(&field, &beams, &Pose2D::new(0.0, 2.5, 0.0));
+        assert!(aligned > shifted);
+    }
+}
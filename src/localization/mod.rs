@@ -0,0 +1,324 @@
+pub mod likelihood_field;
+pub mod motion_model;
+pub mod particle_filter;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::joints::{FrameId, TransformTree};
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::mapping::occupancy_grid::OccupancyGrid;
+use crate::primitives::{Pose2D, PoseWithCovariance2D, Vec2};
+use likelihood_field::{LikelihoodField, LikelihoodFieldModel};
+use motion_model::OdometryMotionModel;
+use particle_filter::{ParticleFilter, ParticleFilterConfig};
+
+#[derive(Clone, Debug)]
+pub struct AmclConfig {
+    pub particle_filter: ParticleFilterConfig,
+    pub motion_model: OdometryMotionModel,
+    pub sensor_model: LikelihoodFieldModel,
+    /// Obstacle distances beyond this are treated as equally unlikely.
+    pub max_obstacle_distance: f32,
+    /// Filter updates only run after the base moved or turned this much.
+    pub update_min_distance: f32,
+    pub update_min_angle: f32,
+    /// Pose of the lidar in the base frame.
+    pub sensor_mount: Pose2D,
+    pub seed: u64,
+}
+
+impl Default for AmclConfig {
+    fn default() -> Self {
+        Self {
+            particle_filter: ParticleFilterConfig::default(),
+            motion_model: OdometryMotionModel::default(),
+            sensor_model: LikelihoodFieldModel::default(),
+            max_obstacle_distance: 2.0,
+            update_min_distance: 0.2,
+            update_min_angle: 0.5,
+            sensor_mount: Pose2D::identity(),
+            seed: 0,
+        }
+    }
+}
+
+/// A scan in the sensor frame and the odometry pose (odom -> base) it was taken at.
+#[derive(Clone, Debug, Default)]
+pub struct LocalizationInput {
+    pub scan: PointCloud,
+    pub odometry: Pose2D,
+}
+
+/// Monte Carlo localization against a known map (AMCL-style).
+pub struct Amcl {
+    pub config: AmclConfig,
+    map: OccupancyGrid,
+    field: LikelihoodField,
+    filter: ParticleFilter,
+    last_update_odometry: Option<Pose2D>,
+    estimate: PoseWithCovariance2D,
+    correction: Pose2D,
+}
+
+impl Amcl {
+    pub fn new(map: OccupancyGrid, config: AmclConfig) -> Self {
+        let field = LikelihoodField::new(&map, config.max_obstacle_distance);
+        let filter = ParticleFilter::new(config.particle_filter.clone(), config.seed);
+        Self {
+            config,
+            map,
+            field,
+            filter,
+            last_update_odometry: None,
+            estimate: PoseWithCovariance2D::default(),
+            correction: Pose2D::identity(),
+        }
+    }
+
+    pub fn map(&self) -> &OccupancyGrid {
+        &self.map
+    }
+
+    pub fn particle_filter(&self) -> &ParticleFilter {
+        &self.filter
+    }
+
+    /// Base pose in the map frame as of the last filter update.
+    pub fn estimate(&self) -> PoseWithCovariance2D {
+        self.estimate
+    }
+
+    /// The map -> odom correction as of the last filter update.
+    pub fn correction(&self) -> Pose2D {
+        self.correction
+    }
+
+    /// Base pose in the map frame at `odometry`, applying the latest correction
+    /// to motion since the last filter update.
+    pub fn pose_at(&self, odometry: &Pose2D) -> PoseWithCovariance2D {
+        PoseWithCovariance2D {
+            pose: self.correction.compose(odometry),
+            covariance: self.estimate.covariance,
+        }
+    }
+
+    /// Starts tracking from a known pose in the map frame.
+    pub fn set_initial_pose(&mut self, pose: &Pose2D, standard_deviation: [f32; 3]) {
+        self.filter.initialize_gaussian(pose, standard_deviation);
+        self.last_update_odometry = None;
+        self.estimate = self.filter.estimate();
+    }
+
+    /// Starts from no prior: particles cover all free space.
+    pub fn global_localization(&mut self) {
+        self.filter.initialize_uniform(&self.map);
+        self.last_update_odometry = None;
+        self.estimate = self.filter.estimate();
+    }
+
+    /// Processes one scan. Returns `true` if the filter was updated, which only
+    /// happens on the first scan and after enough motion.
+    pub fn update(&mut self, scan: &PointCloud, odometry: &Pose2D) -> bool {
+        if self.filter.particles().is_empty() {
+            self.global_localization();
+        }
+        if let Some(previous) = self.last_update_odometry {
+            let delta = previous.between(odometry);
+            if delta.position().length() < self.config.update_min_distance
+                && delta.theta.abs() < self.config.update_min_angle
+            {
+                return false;
+            }
+            self.filter
+                .predict(&self.config.motion_model, &previous, odometry);
+        }
+        self.last_update_odometry = Some(*odometry);
+
+        let beams: Vec<Vec2> = scan
+            .points
+            .iter()
+            .map(|point| point.position.truncate())
+            .collect();
+        let (field, model, mount) = (
+            &self.field,
+            &self.config.sensor_model,
+            &self.config.sensor_mount,
+        );
+        self.filter
+            .update(|pose| model.log_likelihood(field, &beams, &pose.compose(mount)));
+        self.estimate = self.filter.estimate();
+        self.correction = self.estimate.pose.compose(&odometry.inverse());
+        if self.filter.needs_resampling() {
+            self.filter.resample(Some(&self.map));
+        }
+        true
+    }
+}
+
+/// Runs [`Amcl`] as a task and publishes the map -> odom correction into the
+/// transform tree. `odometry_frame` must be a child of the map frame.
+pub struct Localizer {
+    amcl: Mutex<Amcl>,
+    tree: Arc<RwLock<TransformTree>>,
+    odometry_frame: FrameId,
+}
+
+impl Localizer {
+    pub fn new(amcl: Amcl, tree: Arc<RwLock<TransformTree>>, odometry_frame: FrameId) -> Self {
+        Self {
+            amcl: Mutex::new(amcl),
+            tree,
+            odometry_frame,
+        }
+    }
+
+    pub fn set_initial_pose(&self, pose: &Pose2D, standard_deviation: [f32; 3]) {
+        self.amcl
+            .lock()
+            .expect("Amcl lock poisoned")
+            .set_initial_pose(pose, standard_deviation);
+    }
+}
+
+impl Task for Localizer {
+    type Input = CarbonData<LocalizationInput>;
+    type Output = CarbonData<PoseWithCovariance2D>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let mut amcl = self.amcl.lock().expect("Amcl lock poisoned");
+        input.map(|LocalizationInput { scan, odometry }| {
+            if amcl.update(&scan, &odometry) {
+                self.tree
+                    .write()
+                    .expect("TransformTree lock poisoned")
+                    .set_transform(self.odometry_frame, amcl.correction().to_transform());
+            }
+            amcl.pose_at(&odometry)
+        })
+    }
+}
+
+impl Controller<LocalizationInput, PoseWithCovariance2D> for Localizer {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::links::CarbonMetadata;
+    use crate::primitives::{IVec2, Transform};
+    use crate::simulation::lidar::SimulatedLidar;
+
+    // A 12 m by 3 m corridor with a pillar on the left wall and a box on the
+    // right one, so that positions along it can be told apart.
+    fn corridor() -> OccupancyGrid {
+        let mut grid = OccupancyGrid::covering(0.05, Vec2::ZERO, Vec2::new(12.0, 3.0));
+        let solid = |point: Vec2| {
+            point.x < 0.1
+                || point.x > 11.9
+                || point.y < 0.1
+                || point.y > 2.9
+                || (point.x > 4.0 && point.x < 4.4 && point.y > 2.2)
+                || (point.x > 8.0 && point.x < 8.5 && point.y < 0.8)
+        };
+        for y in 0..grid.height() as i32 {
+            for x in 0..grid.width() as i32 {
+                let cell = IVec2::new(x, y);
+                let log_odds = if solid(grid.cell_to_world(cell)) {
+                    5.0
+                } else {
+                    -2.0
+                };
+                grid.set_log_odds(cell, log_odds);
+            }
+        }
+        grid
+    }
+
+    fn lidar(map: &OccupancyGrid, truth: &Arc<RwLock<Pose2D>>) -> SimulatedLidar<OccupancyGrid> {
+        SimulatedLidar::new(Arc::new(RwLock::new(map.clone())), truth.clone(), 1)
+            .with_beams(180, -std::f32::consts::PI, std::f32::consts::PI)
+            .with_range(0.1, 8.0)
+            .with_noise(0.01, 0.0)
+    }
+
+    // Ground truth after `step` moves of 0.3 m down the corridor, and the
+    // odometry for it from wheels that overshoot by a twentieth.
+    fn drive(step: usize) -> (Pose2D, Pose2D) {
+        let travelled = 0.3 * step as f32;
+        (
+            Pose2D::new(1.5 + travelled, 1.5, 0.0),
+            Pose2D::new(1.05 * travelled, 0.0, 0.0),
+        )
+    }
+
+    #[test]
+    fn amcl_converges_in_a_corridor() {
+        let map = corridor();
+        let truth = Arc::new(RwLock::new(Pose2D::identity()));
+        let mut lidar = lidar(&map, &truth);
+        let mut amcl = Amcl::new(map, AmclConfig::default());
+        amcl.set_initial_pose(&Pose2D::new(1.75, 1.3, 0.08), [0.3, 0.3, 0.1]);
+
+        let mut updates = 0;
+        for step in 0..=28 {
+            let (pose, odometry) = drive(step);
+            *truth.write().unwrap() = pose;
+            if amcl.update(&PointCloud::new(lidar.scan()), &odometry) {
+                updates += 1;
+            }
+        }
+
+        let (pose, odometry) = drive(28);
+        let estimate = amcl.estimate().pose;
+        assert!(updates > 20, "{updates}");
+        assert!(
+            estimate.position().distance(pose.position()) < 0.15,
+            "{estimate:?}"
+        );
+        assert!(estimate.theta.abs() < 0.05, "{estimate:?}");
+        // The correction maps odometry onto the estimate, undoing the overshoot.
+        let corrected = amcl.correction().compose(&odometry);
+        assert!(corrected.position().distance(estimate.position()) < 1e-4);
+        assert!(amcl.estimate().covariance[0][0] < 0.05);
+    }
+
+    #[test]
+    fn localizer_publishes_the_correction_to_the_tree() {
+        let map = corridor();
+        let truth = Arc::new(RwLock::new(Pose2D::identity()));
+        let mut lidar = lidar(&map, &truth);
+        let mut tree = TransformTree::new("map");
+        let odom = tree.add_frame("odom", FrameId::root(), Transform::identity());
+        let tree = Arc::new(RwLock::new(tree));
+        let localizer = Localizer::new(Amcl::new(map, AmclConfig::default()), tree.clone(), odom);
+        localizer.set_initial_pose(&Pose2D::new(1.5, 1.5, 0.0), [0.1, 0.1, 0.05]);
+
+        for step in 0..=6 {
+            let (pose, odometry) = drive(step);
+            *truth.write().unwrap() = pose;
+            let input = LocalizationInput {
+                scan: PointCloud::new(lidar.scan()),
+                odometry,
+            };
+            let metadata = CarbonMetadata {
+                timestamp: step as u64,
+                ..Default::default()
+            };
+            let output = localizer.process(CarbonData::new(input, metadata));
+            assert_eq!(output.metadata.timestamp, step as u64);
+            assert!(output.data().pose.position().distance(pose.position()) < 0.2);
+        }
+
+        let correction = localizer.amcl.lock().unwrap().correction();
+        let published = Pose2D::from_transform(&tree.read().unwrap().transform(odom).unwrap());
+        assert!(
+            published.position().distance(correction.position()) < 1e-4,
+            "{published:?}"
+        );
+        assert!((published.theta - correction.theta).abs() < 1e-4);
+        // The wheels overshot by 9 cm, which the correction takes back.
+        assert!(correction.x > 1.3 && correction.x < 1.5, "{correction:?}");
+    }
+}
@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use crate::primitives::{normalize_angle, Pose2D};
+
+/// Odometry motion model for a differential drive (Thrun et al., Probabilistic
+/// Robotics, table 5.6). Motion between two odometry poses is decomposed into a
+/// rotation, a translation and a second rotation, each perturbed by noise.
+#[derive(Clone, Debug)]
+pub struct OdometryMotionModel {
+    /// Rotation noise from rotation.
+    pub alpha1: f32,
+    /// Rotation noise from translation.
+    pub alpha2: f32,
+    /// Translation noise from translation.
+    pub alpha3: f32,
+    /// Translation noise from rotation.
+    pub alpha4: f32,
+}
+
+impl Default for OdometryMotionModel {
+    fn default() -> Self {
+        Self {
+            alpha1: 0.2,
+            alpha2: 0.2,
+            alpha3: 0.2,
+            alpha4: 0.2,
+        }
+    }
+}
+
+impl OdometryMotionModel {
+    /// Samples where a robot at `pose` ends up after odometry reported a move
+    /// from `previous_odometry` to `odometry`.
+    pub fn sample(
+        &self,
+        pose: &Pose2D,
+        previous_odometry: &Pose2D,
+        odometry: &Pose2D,
+        rng: &mut impl Rng,
+    ) -> Pose2D {
+        let dx = odometry.x - previous_odometry.x;
+        let dy = odometry.y - previous_odometry.y;
+        let translation = dx.hypot(dy);
+        // Rotating in place gives a meaningless heading for the translation.
+        let rotation1 = if translation < 0.01 {
+            0.0
+        } else {
+            normalize_angle(dy.atan2(dx) - previous_odometry.theta)
+        };
+        let rotation2 = normalize_angle(odometry.theta - previous_odometry.theta - rotation1);
+
+        // Driving backwards shows up as a half-turn; don't let it inflate noise.
+        let rotation1_noise = rotation1.abs().min((PI - rotation1.abs()).abs());
+        let rotation2_noise = rotation2.abs().min((PI - rotation2.abs()).abs());
+
+        let mut noise = |variance: f32| variance.sqrt() * rng.sample::<f32, _>(StandardNormal);
+        let rotation1 = rotation1
+            - noise(self.alpha1 * rotation1_noise.powi(2) + self.alpha2 * translation.powi(2));
+        let translation = translation
+            - noise(
+                self.alpha3 * translation.powi(2)
+                    + self.alpha4 * (rotation1_noise.powi(2) + rotation2_noise.powi(2)),
+            );
+        let rotation2 = rotation2
+            - noise(self.alpha1 * rotation2_noise.powi(2) + self.alpha2 * translation.powi(2));
+
+        let heading = pose.theta + rotation1;
+        Pose2D::new(
+            pose.x + translation * heading.cos(),
+            pose.y + translation * heading.sin(),
+            normalize_angle(heading + rotation2),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn noiseless_motion_is_replayed_in_the_particle_frame() {
+        let model = OdometryMotionModel {
+            alpha1: 0.0,
+            alpha2: 0.0,
+            alpha3: 0.0,
+            alpha4: 0.0,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let moved = model.sample(
+            &Pose2D::new(1.0, 1.0, FRAC_PI_2),
+            &Pose2D::new(5.0, 5.0, 0.0),
+            &Pose2D::new(6.0, 5.0, 0.3),
+            &mut rng,
+        );
+        assert!(
+            (moved.x - 1.0).abs() < 1e-5 && (moved.y - 2.0).abs() < 1e-5,
+            "{moved:?}"
+        );
+        assert!((moved.theta - (FRAC_PI_2 + 0.3)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn translation_noise_scales_with_distance() {
+        let model = OdometryMotionModel {
+            alpha1: 0.0,
+            alpha2: 0.0,
+            alpha3: 0.04,
+            alpha4: 0.0,
+        };
+        let mut rng = StdRng::seed_from_u64(4);
+        let samples: Vec<Pose2D> = (0..2000)
+            .map(|_| {
+                model.sample(
+                    &Pose2D::identity(),
+                    &Pose2D::identity(),
+                    &Pose2D::new(1.0, 0.0, 0.0),
+                    &mut rng,
+                )
+            })
+            .collect();
+        let count = samples.len() as f32;
+        let mean_x = samples.iter().map(|pose| pose.x).sum::<f32>() / count;
+        let spread_x = (samples
+            .iter()
+            .map(|pose| (pose.x - mean_x).powi(2))
+            .sum::<f32>()
+            / count)
+            .sqrt();
+        let mean_y = samples.iter().map(|pose| pose.y).sum::<f32>() / count;
+
+        assert!((mean_x - 1.0).abs() < 0.02, "{mean_x}");
+        assert!(mean_y.abs() < 1e-5, "{mean_y}");
+        // Translation noise of sqrt(alpha3) per metre.
+        assert!((spread_x - 0.2).abs() < 0.02, "{spread_x}");
+    }
+
+    #[test]
+    fn driving_backwards_does_not_inflate_rotation_noise() {
+        let model = OdometryMotionModel::default();
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..200 {
+            let moved = model.sample(
+                &Pose2D::identity(),
+                &Pose2D::identity(),
+                &Pose2D::new(-0.2, 0.0, 0.0),
+                &mut rng,
+            );
+            assert!(normalize_angle(moved.theta).abs() < 0.5, "{moved:?}");
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+use super::motion_model::OdometryMotionModel;
+use crate::mapping::occupancy_grid::{CellState, OccupancyGrid};
+use crate::primitives::{normalize_angle, IVec2, Pose2D, PoseWithCovariance2D};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    pub pose: Pose2D,
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParticleFilterConfig {
+    pub particle_count: usize,
+    /// Resample once the effective sample size drops below this fraction of the
+    /// particle count.
+    pub resample_threshold: f32,
+    /// Decay rates of the slow and fast average likelihoods that trigger random
+    /// particle injection for recovery from kidnapping. Set both to zero to
+    /// disable recovery.
+    pub recovery_alpha_slow: f32,
+    pub recovery_alpha_fast: f32,
+}
+
+impl Default for ParticleFilterConfig {
+    fn default() -> Self {
+        Self {
+            particle_count: 500,
+            resample_threshold: 0.5,
+            recovery_alpha_slow: 0.001,
+            recovery_alpha_fast: 0.1,
+        }
+    }
+}
+
+pub struct ParticleFilter {
+    pub config: ParticleFilterConfig,
+    particles: Vec<Particle>,
+    rng: StdRng,
+    // Moving averages of the log of the mean particle likelihood.
+    log_average_slow: Option<f32>,
+    log_average_fast: Option<f32>,
+}
+
+impl ParticleFilter {
+    pub fn new(config: ParticleFilterConfig, seed: u64) -> Self {
+        Self {
+            config,
+            particles: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+            log_average_slow: None,
+            log_average_fast: None,
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Spreads particles normally around `mean` with per-axis standard deviations.
+    pub fn initialize_gaussian(&mut self, mean: &Pose2D, standard_deviation: [f32; 3]) {
+        let weight = 1.0 / self.config.particle_count as f32;
+        let rng = &mut self.rng;
+        self.particles = (0..self.config.particle_count)
+            .map(|_| {
+                let mut noise = || rng.sample::<f32, _>(StandardNormal);
+                Particle {
+                    pose: Pose2D::new(
+                        mean.x + standard_deviation[0] * noise(),
+                        mean.y + standard_deviation[1] * noise(),
+                        normalize_angle(mean.theta + standard_deviation[2] * noise()),
+                    ),
+                    weight,
+                }
+            })
+            .collect();
+        self.reset_averages();
+    }
+
+    /// Spreads particles uniformly over the free space of `map`, for global
+    /// localization.
+    pub fn initialize_uniform(&mut self, map: &OccupancyGrid) {
+        let free_cells = free_cells(map);
+        if free_cells.is_empty() {
+            return;
+        }
+        let weight = 1.0 / self.config.particle_count as f32;
+        self.particles = (0..self.config.particle_count)
+            .map(|_| Particle {
+                pose: random_free_pose(map, &free_cells, &mut self.rng),
+                weight,
+            })
+            .collect();
+        self.reset_averages();
+    }
+
+    fn reset_averages(&mut self) {
+        self.log_average_slow = None;
+        self.log_average_fast = None;
+    }
+
+    pub fn predict(
+        &mut self,
+        model: &OdometryMotionModel,
+        previous_odometry: &Pose2D,
+        odometry: &Pose2D,
+    ) {
+        for particle in &mut self.particles {
+            particle.pose =
+                model.sample(&particle.pose, previous_odometry, odometry, &mut self.rng);
+        }
+    }
+
+    /// Reweights particles by an observation's log-likelihood at each pose.
+    pub fn update(&mut self, log_likelihood: impl Fn(&Pose2D) -> f32) {
+        if self.particles.is_empty() {
+            return;
+        }
+        let log_likelihoods: Vec<f32> = self
+            .particles
+            .iter()
+            .map(|particle| log_likelihood(&particle.pose))
+            .collect();
+        let max = log_likelihoods
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mut total = 0.0;
+        for (particle, log_likelihood) in self.particles.iter_mut().zip(&log_likelihoods) {
+            particle.weight *= (log_likelihood - max).exp();
+            total += particle.weight;
+        }
+        if total <= 0.0 || !total.is_finite() {
+            let weight = 1.0 / self.particles.len() as f32;
+            self.particles.iter_mut().for_each(|p| p.weight = weight);
+            return;
+        }
+        self.particles.iter_mut().for_each(|p| p.weight /= total);
+
+        // Absolute likelihoods underflow, so averages are tracked in log space.
+        let log_average = max + (total / self.particles.len() as f32).ln();
+        let smooth = |average: Option<f32>, alpha: f32| {
+            Some(average.map_or(log_average, |average| {
+                average + alpha * (log_average - average)
+            }))
+        };
+        self.log_average_slow = smooth(self.log_average_slow, self.config.recovery_alpha_slow);
+        self.log_average_fast = smooth(self.log_average_fast, self.config.recovery_alpha_fast);
+    }
+
+    pub fn effective_sample_size(&self) -> f32 {
+        1.0 / self
+            .particles
+            .iter()
+            .map(|p| p.weight * p.weight)
+            .sum::<f32>()
+    }
+
+    pub fn needs_resampling(&self) -> bool {
+        self.effective_sample_size() < self.config.resample_threshold * self.particles.len() as f32
+    }
+
+    /// Low-variance resampling. If `map` is given and the fast likelihood average
+    /// has dropped below the slow one, a share of particles is replaced with
+    /// random free-space poses.
+    pub fn resample(&mut self, map: Option<&OccupancyGrid>) {
+        let count = self.config.particle_count;
+        if self.particles.is_empty() || count == 0 {
+            return;
+        }
+        let random_probability = match (self.log_average_slow, self.log_average_fast) {
+            (Some(slow), Some(fast)) => (1.0 - (fast - slow).exp()).max(0.0),
+            _ => 0.0,
+        };
+        let free_cells = match map {
+            Some(map) if random_probability > 0.0 => free_cells(map),
+            _ => Vec::new(),
+        };
+
+        let step = 1.0 / count as f32;
+        let mut threshold = self.rng.gen::<f32>() * step;
+        let mut cumulative = self.particles[0].weight;
+        let mut index = 0;
+        let mut resampled = Vec::with_capacity(count);
+        for _ in 0..count {
+            while threshold > cumulative && index + 1 < self.particles.len() {
+                index += 1;
+                cumulative += self.particles[index].weight;
+            }
+            threshold += step;
+            let pose = match map {
+                Some(map)
+                    if !free_cells.is_empty() && self.rng.gen::<f32>() < random_probability =>
+                {
+                    random_free_pose(map, &free_cells, &mut self.rng)
+                }
+                _ => self.particles[index].pose,
+            };
+            resampled.push(Particle { pose, weight: step });
+        }
+        self.particles = resampled;
+        if random_probability > 0.0 {
+            self.reset_averages();
+        }
+    }
+
+    /// Weighted mean pose and covariance of the particle set.
+    pub fn estimate(&self) -> PoseWithCovariance2D {
+        let (mut x, mut y, mut sin, mut cos) = (0.0, 0.0, 0.0, 0.0);
+        for particle in &self.particles {
+            x += particle.weight * particle.pose.x;
+            y += particle.weight * particle.pose.y;
+            sin += particle.weight * particle.pose.theta.sin();
+            cos += particle.weight * particle.pose.theta.cos();
+        }
+        let mean = Pose2D::new(x, y, sin.atan2(cos));
+        let mut covariance = [[0.0; 3]; 3];
+        for particle in &self.particles {
+            let delta = [
+                particle.pose.x - mean.x,
+                particle.pose.y - mean.y,
+                normalize_angle(particle.pose.theta - mean.theta),
+            ];
+            for (row, covariance_row) in covariance.iter_mut().enumerate() {
+                for (column, value) in covariance_row.iter_mut().enumerate() {
+                    *value += particle.weight * delta[row] * delta[column];
+                }
+            }
+        }
+        PoseWithCovariance2D {
+            pose: mean,
+            covariance,
+        }
+    }
+}
+
+fn free_cells(map: &OccupancyGrid) -> Vec<IVec2> {
+    (0..map.height() as i32)
+        .flat_map(|y| (0..map.width() as i32).map(move |x| IVec2::new(x, y)))
+        .filter(|&cell| map.state(cell) == CellState::Free)
+        .collect()
+}
+
+fn random_free_pose(map: &OccupancyGrid, free_cells: &[IVec2], rng: &mut StdRng) -> Pose2D {
+    let cell = free_cells[rng.gen_range(0..free_cells.len())];
+    let center = map.cell_to_world(cell);
+    let mut jitter = || (rng.gen::<f32>() - 0.5) * map.resolution();
+    Pose2D::new(
+        center.x + jitter(),
+        center.y + jitter(),
+        rng.gen_range(-std::f32::consts::PI..std::f32::consts::PI),
+    )
+}
@@ -1,7 +1,4 @@
 // pseudocode for api
-use carbon_rs::joints;
-use carbon_rs::links::*;
-
 fn main() {}
 
 // fn main(){
@@ -0,0 +1,306 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::occupancy_grid::{CellState, OccupancyGrid};
+use crate::primitives::{IVec2, Vec2};
+
+const OCCUPIED_PIXEL: u8 = 0;
+const FREE_PIXEL: u8 = 254;
+const UNKNOWN_PIXEL: u8 = 205;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Writes the grid as a map_server-style pair: `path` (YAML metadata) and an
+/// 8-bit PGM image next to it with the same stem.
+pub fn save_map(grid: &OccupancyGrid, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let image_path = path.with_extension("pgm");
+    let image_name = image_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| invalid_data("map path has no file name"))?;
+
+    let mut image = format!("P5\n{} {}\n255\n", grid.width(), grid.height()).into_bytes();
+    image.reserve(grid.width() * grid.height());
+    // Image rows run top to bottom, grid rows bottom to top.
+    for y in (0..grid.height()).rev() {
+        for x in 0..grid.width() {
+            image.push(match grid.state(IVec2::new(x as i32, y as i32)) {
+                CellState::Occupied => OCCUPIED_PIXEL,
+                CellState::Free => FREE_PIXEL,
+                CellState::Unknown => UNKNOWN_PIXEL,
+            });
+        }
+    }
+    fs::write(&image_path, image)?;
+
+    let origin = grid.origin();
+    let metadata = format!(
+        "image: {image_name}\n\
+         resolution: {:.6}\n\
+         origin: [{:.6}, {:.6}, 0.000000]\n\
+         negate: 0\n\
+         occupied_thresh: {}\n\
+         free_thresh: {}\n",
+        grid.resolution(),
+        origin.x,
+        origin.y,
+        grid.parameters.occupied_threshold,
+        grid.parameters.free_threshold,
+    );
+    fs::write(path, metadata)
+}
+
+/// Loads a map saved by [`save_map`] or by ROS map_server. Occupied and free
+/// cells are set to the log-odds limits; map yaw is not supported.
+pub fn load_map(path: impl AsRef<Path>) -> io::Result<OccupancyGrid> {
+    let path = path.as_ref();
+    let metadata = MapMetadata::parse(&fs::read_to_string(path)?)?;
+    let image_path = match path.parent() {
+        Some(directory) if metadata.image.is_relative() => directory.join(&metadata.image),
+        _ => metadata.image.clone(),
+    };
+    let image = Pgm::parse(&fs::read(image_path)?)?;
+
+    let mut grid = OccupancyGrid::new(
+        metadata.resolution,
+        image.width,
+        image.height,
+        metadata.origin,
+    );
+    grid.parameters.occupied_threshold = metadata.occupied_threshold;
+    grid.parameters.free_threshold = metadata.free_threshold;
+    let (free, occupied) = (grid.parameters.min, grid.parameters.max);
+    for row in 0..image.height {
+        for x in 0..image.width {
+            let value = image.pixels[row * image.width + x] as f32 / image.max_value as f32;
+            let occupancy = if metadata.negate { value } else { 1.0 - value };
+            let cell = IVec2::new(x as i32, (image.height - 1 - row) as i32);
+            if occupancy > metadata.occupied_threshold {
+                grid.set_log_odds(cell, occupied);
+            } else if occupancy < metadata.free_threshold {
+                grid.set_log_odds(cell, free);
+            }
+        }
+    }
+    Ok(grid)
+}
+
+struct MapMetadata {
+    image: PathBuf,
+    resolution: f32,
+    origin: Vec2,
+    negate: bool,
+    occupied_threshold: f32,
+    free_threshold: f32,
+}
+
+impl MapMetadata {
+    // Only the flat `key: value` subset of YAML that map files use.
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut image = None;
+        let mut resolution = None;
+        let mut origin = None;
+        let mut negate = false;
+        let mut occupied_threshold = 0.65;
+        let mut free_threshold = 0.196;
+
+        let number = |key: &str, value: &str| {
+            value
+                .parse::<f32>()
+                .map_err(|_| invalid_data(format!("bad value for {key}: {value:?}")))
+        };
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            match key.trim() {
+                "image" => image = Some(PathBuf::from(value)),
+                "resolution" => resolution = Some(number(key, value)?),
+                "origin" => {
+                    let values = value
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .split(',')
+                        .map(|v| number(key, v.trim()))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    if values.len() < 2 {
+                        return Err(invalid_data("origin needs at least x and y"));
+                    }
+                    origin = Some(Vec2::new(values[0], values[1]));
+                }
+                "negate" => negate = value == "1" || value == "true",
+                "occupied_thresh" => occupied_threshold = number(key, value)?,
+                "free_thresh" => free_threshold = number(key, value)?,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            image: image.ok_or_else(|| invalid_data("map metadata is missing image"))?,
+            resolution: resolution
+                .ok_or_else(|| invalid_data("map metadata is missing resolution"))?,
+            origin: origin.ok_or_else(|| invalid_data("map metadata is missing origin"))?,
+            negate,
+            occupied_threshold,
+            free_threshold,
+        })
+    }
+}
+
+struct Pgm {
+    width: usize,
+    height: usize,
+    max_value: u16,
+    pixels: Vec<u16>,
+}
+
+impl Pgm {
+    // Binary (P5) and ASCII (P2) greymaps.
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut cursor = 0;
+        let mut next_token = || -> io::Result<&[u8]> {
+            loop {
+                while cursor < bytes.len() && bytes[cursor].is_ascii_whitespace() {
+                    cursor += 1;
+                }
+                if bytes.get(cursor) == Some(&b'#') {
+                    while cursor < bytes.len() && bytes[cursor] != b'\n' {
+                        cursor += 1;
+                    }
+                    continue;
+                }
+                let start = cursor;
+                while cursor < bytes.len() && !bytes[cursor].is_ascii_whitespace() {
+                    cursor += 1;
+                }
+                if start == cursor {
+                    return Err(invalid_data("truncated PGM header"));
+                }
+                return Ok(&bytes[start..cursor]);
+            }
+        };
+        let parse_number = |token: &[u8]| -> io::Result<usize> {
+            std::str::from_utf8(token)
+                .ok()
+                .and_then(|token| token.parse().ok())
+                .ok_or_else(|| invalid_data("bad number in PGM"))
+        };
+
+        let magic = next_token()?.to_vec();
+        let width = parse_number(next_token()?)?;
+        let height = parse_number(next_token()?)?;
+        let max_value = parse_number(next_token()?)?;
+        if max_value == 0 || max_value > u16::MAX as usize {
+            return Err(invalid_data("bad PGM max value"));
+        }
+        let count = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_data("PGM dimensions overflow"))?;
+
+        let pixels = match magic.as_slice() {
+            b"P5" => {
+                // Exactly one whitespace byte separates the header from the raster.
+                let raster = &bytes[(cursor + 1).min(bytes.len())..];
+                if max_value < 256 {
+                    if raster.len() < count {
+                        return Err(invalid_data("truncated PGM raster"));
+                    }
+                    raster[..count].iter().map(|&p| p as u16).collect()
+                } else {
+                    let length = count
+                        .checked_mul(2)
+                        .ok_or_else(|| invalid_data("PGM dimensions overflow"))?;
+                    if raster.len() < length {
+                        return Err(invalid_data("truncated PGM raster"));
+                    }
+                    raster[..length]
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect()
+                }
+            }
+            b"P2" => (0..count)
+                .map(|_| next_token().and_then(parse_number).map(|p| p as u16))
+                .collect::<io::Result<Vec<_>>>()?,
+            _ => return Err(invalid_data("not a PGM image")),
+        };
+        if pixels.iter().any(|&pixel| pixel as usize > max_value) {
+            return Err(invalid_data("PGM pixel above max value"));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            max_value: max_value as u16,
+            pixels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_maps_load_back_with_unknown_cells() {
+        let mut grid = OccupancyGrid::new(0.05, 4, 3, Vec2::new(-1.0, 2.0));
+        let (free, occupied) = (grid.parameters.min, grid.parameters.max);
+        grid.set_log_odds(IVec2::new(0, 0), occupied);
+        grid.set_log_odds(IVec2::new(3, 2), occupied);
+        for x in 0..4 {
+            grid.set_log_odds(IVec2::new(x, 1), free);
+        }
+        let directory = std::env::temp_dir().join(format!("carbon_map_io_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("map.yaml");
+
+        save_map(&grid, &path).unwrap();
+        let image = fs::read(directory.join("map.pgm")).unwrap();
+        let loaded = load_map(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(image.contains(&UNKNOWN_PIXEL));
+        assert_eq!((loaded.width(), loaded.height()), (4, 3));
+        assert!((loaded.resolution() - 0.05).abs() < 1e-6);
+        assert!(loaded.origin().distance(grid.origin()) < 1e-6);
+        for y in 0..3 {
+            for x in 0..4 {
+                let cell = IVec2::new(x, y);
+                assert_eq!(loaded.state(cell), grid.state(cell), "{cell}");
+            }
+        }
+        assert_eq!(loaded.state(IVec2::new(1, 0)), CellState::Unknown);
+        assert!(!loaded.is_observed(IVec2::new(1, 0)));
+    }
+
+    #[test]
+    fn ascii_pgms_parse_with_comments() {
+        let image = Pgm::parse(b"P2\n# a comment\n3 2\n255\n0 205 254\n254 254 0\n").unwrap();
+        assert_eq!((image.width, image.height, image.max_value), (3, 2, 255));
+        assert_eq!(image.pixels, [0, 205, 254, 254, 254, 0]);
+    }
+
+    #[test]
+    fn pixels_above_the_max_value_are_rejected() {
+        assert!(Pgm::parse(b"P2 2 1 100 50 101").is_err());
+        assert!(Pgm::parse(b"P5 2 1 100\n\x32\x65").is_err());
+        assert!(Pgm::parse(b"P5 2 1 100\n\x32\x64").is_ok());
+    }
+
+    #[test]
+    fn huge_or_truncated_images_are_rejected() {
+        let huge = format!("P5 {} {} 255\n", usize::MAX, 2);
+        assert!(Pgm::parse(huge.as_bytes()).is_err());
+        let wide = format!("P5 {} 1 65535\n", usize::MAX / 2 + 1);
+        assert!(Pgm::parse(wide.as_bytes()).is_err());
+        assert!(Pgm::parse(b"P5 2 2 255\n\x00\x00\x00").is_err());
+        assert!(Pgm::parse(b"P2 2 2 255 0 0 0").is_err());
+    }
+}
@@ -0,0 +1,61 @@
+pub mod map_io;
+pub mod occupancy_grid;
+
+use std::sync::{Arc, RwLock};
+
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Task};
+use crate::primitives::{Pose2D, Vec2};
+use occupancy_grid::OccupancyGrid;
+
+pub use map_io::{load_map, save_map};
+
+/// A scan in the sensor frame together with the base pose it was taken from.
+#[derive(Clone, Debug, Default)]
+pub struct PosedScan {
+    pub scan: PointCloud,
+    pub base_pose: Pose2D,
+}
+
+/// Integrates posed scans into a shared occupancy grid, growing it as the robot
+/// explores.
+pub struct OccupancyMapper {
+    grid: Arc<RwLock<OccupancyGrid>>,
+    /// Pose of the sensor in the base frame.
+    sensor_mount: Pose2D,
+    max_range: f32,
+}
+
+impl OccupancyMapper {
+    pub fn new(grid: Arc<RwLock<OccupancyGrid>>, sensor_mount: Pose2D, max_range: f32) -> Self {
+        Self {
+            grid,
+            sensor_mount,
+            max_range,
+        }
+    }
+
+    pub fn grid(&self) -> Arc<RwLock<OccupancyGrid>> {
+        self.grid.clone()
+    }
+}
+
+impl Task for OccupancyMapper {
+    type Input = CarbonData<PosedScan>;
+    type Output = ();
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let PosedScan { scan, base_pose } = input.into_data();
+        let sensor_pose = base_pose.compose(&self.sensor_mount);
+        let reach = Vec2::splat(self.max_range);
+        let mut grid = self.grid.write().expect("OccupancyGrid lock poisoned");
+        grid.expand_to_include(
+            sensor_pose.position() - reach,
+            sensor_pose.position() + reach,
+            1,
+        );
+        grid.integrate_scan(&scan, &sensor_pose, self.max_range);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::lidar::PointCloud;
+use crate::primitives::{IVec2, Pose2D, Vec2};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellState {
+    Unknown,
+    Free,
+    Occupied,
+}
+
+/// Log-odds increments and limits used when integrating observations.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogOddsParameters {
+    pub hit: f32,
+    pub miss: f32,
+    pub min: f32,
+    pub max: f32,
+    /// Cells above this probability are occupied.
+    pub occupied_threshold: f32,
+    /// Cells below this probability are free.
+    pub free_threshold: f32,
+}
+
+impl Default for LogOddsParameters {
+    fn default() -> Self {
+        Self {
+            hit: logit(0.7),
+            miss: logit(0.4),
+            min: logit(0.12),
+            max: logit(0.97),
+            occupied_threshold: 0.65,
+            free_threshold: 0.196,
+        }
+    }
+}
+
+pub fn logit(probability: f32) -> f32 {
+    (probability / (1.0 - probability)).ln()
+}
+
+pub fn probability(log_odds: f32) -> f32 {
+    1.0 - 1.0 / (1.0 + log_odds.exp())
+}
+
+/// Axis-aligned 2D occupancy grid storing log-odds per cell. Cells that have
+/// never been observed or set are unknown whatever their log-odds.
+///
+/// Cell `(0, 0)` is the lower-left cell; `origin` is the world position of its
+/// lower-left corner.
+#[derive(Clone, Debug)]
+pub struct OccupancyGrid {
+    resolution: f32,
+    width: usize,
+    height: usize,
+    origin: Vec2,
+    log_odds: Vec<f32>,
+    observed: Vec<bool>,
+    pub parameters: LogOddsParameters,
+}
+
+impl OccupancyGrid {
+    pub fn new(resolution: f32, width: usize, height: usize, origin: Vec2) -> Self {
+        Self {
+            resolution,
+            width,
+            height,
+            origin,
+            log_odds: vec![0.0; width * height],
+            observed: vec![false; width * height],
+            parameters: LogOddsParameters::default(),
+        }
+    }
+
+    /// Grid covering `[min, max]` in world coordinates.
+    pub fn covering(resolution: f32, min: Vec2, max: Vec2) -> Self {
+        let size = ((max - min) / resolution).ceil().max(Vec2::ONE);
+        Self::new(resolution, size.x as usize, size.y as usize, min)
+    }
+
+    pub fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn origin(&self) -> Vec2 {
+        self.origin
+    }
+
+    /// World position of the upper-right corner.
+    pub fn extent(&self) -> Vec2 {
+        self.origin + Vec2::new(self.width as f32, self.height as f32) * self.resolution
+    }
+
+    pub fn world_to_cell(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / self.resolution)
+            .floor()
+            .as_ivec2()
+    }
+
+    /// World position of the cell centre.
+    pub fn cell_to_world(&self, cell: IVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + 0.5) * self.resolution
+    }
+
+    pub fn contains_cell(&self, cell: IVec2) -> bool {
+        cell.x >= 0
+            && cell.y >= 0
+            && (cell.x as usize) < self.width
+            && (cell.y as usize) < self.height
+    }
+
+    pub fn index(&self, cell: IVec2) -> Option<usize> {
+        self.contains_cell(cell)
+            .then(|| cell.y as usize * self.width + cell.x as usize)
+    }
+
+    pub fn log_odds(&self, cell: IVec2) -> Option<f32> {
+        self.index(cell).map(|index| self.log_odds[index])
+    }
+
+    pub fn set_log_odds(&mut self, cell: IVec2, value: f32) {
+        if let Some(index) = self.index(cell) {
+            self.log_odds[index] = value;
+            self.observed[index] = true;
+        }
+    }
+
+    /// Whether the cell has been observed or set since the grid was created.
+    pub fn is_observed(&self, cell: IVec2) -> bool {
+        self.index(cell).is_some_and(|index| self.observed[index])
+    }
+
+    /// Row-major log-odds, starting at the lower-left cell.
+    pub fn cells(&self) -> &[f32] {
+        &self.log_odds
+    }
+
+    pub fn probability(&self, cell: IVec2) -> Option<f32> {
+        self.log_odds(cell).map(probability)
+    }
+
+    pub fn state(&self, cell: IVec2) -> CellState {
+        match self.index(cell) {
+            Some(index) if self.observed[index] => self.classify(self.log_odds[index]),
+            _ => CellState::Unknown,
+        }
+    }
+
+    pub fn classify(&self, log_odds: f32) -> CellState {
+        let probability = probability(log_odds);
+        if probability > self.parameters.occupied_threshold {
+            CellState::Occupied
+        } else if probability < self.parameters.free_threshold {
+            CellState::Free
+        } else {
+            CellState::Unknown
+        }
+    }
+
+    pub fn state_at(&self, position: Vec2) -> CellState {
+        self.state(self.world_to_cell(position))
+    }
+
+    fn update(&mut self, cell: IVec2, delta: f32) {
+        if let Some(index) = self.index(cell) {
+            self.log_odds[index] =
+                (self.log_odds[index] + delta).clamp(self.parameters.min, self.parameters.max);
+            self.observed[index] = true;
+        }
+    }
+
+    /// Integrates a scan given in the sensor frame, with the sensor at
+    /// `sensor_pose` in the map frame. Cells along each beam are marked free and
+    /// the end cell occupied. Returns beyond `max_range` only clear space.
+    pub fn integrate_scan(&mut self, scan: &PointCloud, sensor_pose: &Pose2D, max_range: f32) {
+        let start = self.world_to_cell(sensor_pose.position());
+        for point in &scan.points {
+            let local = point.position.truncate();
+            let range = local.length();
+            if !range.is_finite() || range <= f32::EPSILON {
+                continue;
+            }
+            let hit = range <= max_range;
+            let end = sensor_pose.transform_point(local * (range.min(max_range) / range));
+            let end = self.world_to_cell(end);
+            let (miss, hit_delta) = (self.parameters.miss, self.parameters.hit);
+            raytrace(start, end, |cell| {
+                if cell != end {
+                    self.update(cell, miss);
+                }
+                true
+            });
+            if hit {
+                self.update(end, hit_delta);
+            } else {
+                self.update(end, miss);
+            }
+        }
+    }
+
+    /// Re-allocates the grid to `width` x `height` cells with a new origin,
+    /// keeping every overlapping cell. The origin is snapped so existing cells
+    /// stay aligned.
+    pub fn resize(&mut self, origin: Vec2, width: usize, height: usize) {
+        let offset = ((origin - self.origin) / self.resolution)
+            .round()
+            .as_ivec2();
+        let mut log_odds = vec![0.0; width * height];
+        let mut observed = vec![false; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let old = IVec2::new(x as i32, y as i32) + offset;
+                if let Some(index) = self.index(old) {
+                    log_odds[y * width + x] = self.log_odds[index];
+                    observed[y * width + x] = self.observed[index];
+                }
+            }
+        }
+        self.origin += offset.as_vec2() * self.resolution;
+        self.width = width;
+        self.height = height;
+        self.log_odds = log_odds;
+        self.observed = observed;
+    }
+
+    /// Moves the grid window so its origin lands near `origin`, keeping its size.
+    /// Cells that scroll out are dropped and new ones start unknown.
+    pub fn shift_origin(&mut self, origin: Vec2) {
+        self.resize(origin, self.width, self.height);
+    }
+
+    /// Grows the grid, if needed, so it covers `[min, max]` plus `margin` cells.
+    pub fn expand_to_include(&mut self, min: Vec2, max: Vec2, margin: usize) {
+        let margin = margin as f32 * self.resolution;
+        let new_min = self.origin.min(min - margin);
+        let new_max = self.extent().max(max + margin);
+        if new_min == self.origin && new_max == self.extent() {
+            return;
+        }
+        let offset = ((new_min - self.origin) / self.resolution).floor();
+        let origin = self.origin + offset * self.resolution;
+        let size = ((new_max - origin) / self.resolution).ceil();
+        self.resize(origin, size.x as usize, size.y as usize);
+    }
+}
+
+/// Visits the cells on the line from `start` to `end` (inclusive) using
+/// Bresenham's algorithm, stopping early if `visit` returns `false`.
+pub fn raytrace(start: IVec2, end: IVec2, mut visit: impl FnMut(IVec2) -> bool) {
+    let delta = (end - start).abs();
+    let step = IVec2::new((end.x - start.x).signum(), (end.y - start.y).signum());
+    let mut error = delta.x - delta.y;
+    let mut cell = start;
+    loop {
+        if !visit(cell) || cell == end {
+            return;
+        }
+        let doubled = 2 * error;
+        if doubled > -delta.y {
+            error -= delta.y;
+            cell.x += step.x;
+        }
+        if doubled < delta.x {
+            error += delta.x;
+            cell.y += step.y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{Point, Vec3};
+
+    #[test]
+    fn observed_cells_stay_known_at_even_odds() {
+        let mut grid = OccupancyGrid::new(1.0, 3, 1, Vec2::ZERO);
+        let cell = IVec2::new(1, 0);
+        assert!(!grid.is_observed(cell));
+
+        // A hit and a miss of equal weight cancel exactly.
+        grid.update(cell, 0.5);
+        grid.update(cell, -0.5);
+        assert_eq!(grid.log_odds(cell), Some(0.0));
+        assert!(grid.is_observed(cell));
+        assert_eq!(grid.state(cell), CellState::Unknown);
+
+        grid.update(cell, grid.parameters.max);
+        assert_eq!(grid.state(cell), CellState::Occupied);
+        assert!(!grid.is_observed(IVec2::new(0, 0)));
+        assert!(!grid.is_observed(IVec2::new(5, 0)));
+    }
+
+    #[test]
+    fn scans_clear_the_beam_and_mark_the_end() {
+        let mut grid = OccupancyGrid::new(1.0, 6, 3, Vec2::ZERO);
+        let scan = PointCloud::new(vec![Point::new(Vec3::new(4.0, 0.0, 0.0), 1.0)]);
+        for _ in 0..5 {
+            grid.integrate_scan(&scan, &Pose2D::new(0.5, 1.5, 0.0), 10.0);
+        }
+
+        for x in 0..4 {
+            assert_eq!(grid.state(IVec2::new(x, 1)), CellState::Free, "{x}");
+        }
+        assert_eq!(grid.state(IVec2::new(4, 1)), CellState::Occupied);
+        assert!(!grid.is_observed(IVec2::new(5, 1)));
+        assert!(!grid.is_observed(IVec2::new(2, 0)));
+    }
+
+    #[test]
+    fn resizing_keeps_what_was_observed() {
+        let mut grid = OccupancyGrid::new(1.0, 2, 2, Vec2::ZERO);
+        grid.set_log_odds(IVec2::new(1, 1), 0.0);
+        grid.expand_to_include(Vec2::new(-2.0, -2.0), Vec2::ONE, 0);
+
+        assert_eq!((grid.width(), grid.height()), (4, 4));
+        assert!(grid.is_observed(IVec2::new(3, 3)));
+        assert!(!grid.is_observed(IVec2::new(2, 2)));
+    }
+}
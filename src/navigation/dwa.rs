@@ -0,0 +1,227 @@
+use super::{lookahead, rollout, LocalController};
+use crate::costmap::{Costmap, INSCRIBED};
+use crate::drive::TwistLimits;
+use crate::primitives::{Pose2D, Twist2D};
+
+/// Dynamic window approach: samples twists reachable within one control
+/// period, simulates each for a short horizon and picks the trajectory that
+/// best trades off staying on the path, progressing towards a point down the
+/// path, and keeping clear of obstacles.
+#[derive(Clone, Debug)]
+pub struct Dwa {
+    /// Seconds each sample is simulated for.
+    pub horizon: f32,
+    /// Seconds between simulated poses.
+    pub step: f32,
+    pub linear_samples: usize,
+    pub angular_samples: usize,
+    /// Weight of the final pose's distance from the path, per metre.
+    pub path_weight: f32,
+    /// Weight of the final pose's distance to the local goal, per metre.
+    pub goal_weight: f32,
+    /// Weight of the highest cost crossed, per unit of cost.
+    pub obstacle_weight: f32,
+    /// Weight of the final heading's deviation from the local goal direction,
+    /// per radian.
+    pub heading_weight: f32,
+}
+
+impl Default for Dwa {
+    fn default() -> Self {
+        Self {
+            horizon: 1.5,
+            step: 0.1,
+            linear_samples: 8,
+            angular_samples: 21,
+            path_weight: 3.0,
+            goal_weight: 2.0,
+            obstacle_weight: 0.01,
+            heading_weight: 0.3,
+        }
+    }
+}
+
+// Samples spread evenly over `[low, high]`.
+fn samples(low: f32, high: f32, count: usize) -> impl Iterator<Item = f32> {
+    let count = count.max(2);
+    (0..count).map(move |index| low + (high - low) * index as f32 / (count - 1) as f32)
+}
+
+impl Dwa {
+    /// Score of a simulated trajectory, lower is better; `None` if it
+    /// collides.
+    fn score(
+        &self,
+        trajectory: &[Pose2D],
+        path: &[Pose2D],
+        goal: &Pose2D,
+        costmap: &Costmap,
+    ) -> Option<f32> {
+        let mut highest = 0u8;
+        for pose in trajectory {
+            let cost = costmap.cost_at(pose.position());
+            if cost >= INSCRIBED {
+                return None;
+            }
+            highest = highest.max(cost);
+        }
+        let end = trajectory.last()?;
+        let path_distance = path
+            .iter()
+            .map(|pose| pose.position().distance(end.position()))
+            .fold(f32::INFINITY, f32::min);
+        let goal_distance = end.position().distance(goal.position());
+        let heading_error = end
+            .inverse()
+            .transform_point(goal.position())
+            .to_angle()
+            .abs();
+        Some(
+            self.path_weight * path_distance
+                + self.goal_weight * goal_distance
+                + self.obstacle_weight * f32::from(highest)
+                + self.heading_weight * heading_error,
+        )
+    }
+}
+
+impl LocalController for Dwa {
+    fn compute(
+        &mut self,
+        pose: &Pose2D,
+        velocity: &Twist2D,
+        path: &[Pose2D],
+        costmap: &Costmap,
+        limits: &TwistLimits,
+        dt: f32,
+    ) -> Option<Twist2D> {
+        let reach = limits.max_linear * self.horizon;
+        let goal = lookahead(path, pose.position(), reach)?;
+        let window = |current: f32, acceleration: f32, low: f32, high: f32| {
+            let current = current.clamp(low, high);
+            let step = acceleration * dt;
+            ((current - step).max(low), (current + step).min(high))
+        };
+        let (linear_low, linear_high) = window(
+            velocity.linear,
+            limits.max_linear_acceleration,
+            limits.min_linear,
+            limits.max_linear,
+        );
+        let (angular_low, angular_high) = window(
+            velocity.angular,
+            limits.max_angular_acceleration,
+            -limits.max_angular,
+            limits.max_angular,
+        );
+        let mut best: Option<(f32, Twist2D)> = None;
+        for linear in samples(linear_low, linear_high, self.linear_samples) {
+            for angular in samples(angular_low, angular_high, self.angular_samples) {
+                let twist = Twist2D::new(linear, angular);
+                let trajectory = rollout(pose, &twist, self.step, self.horizon);
+                if let Some(score) = self.score(&trajectory, path, &goal, costmap) {
+                    if best.is_none_or(|(lowest, _)| score < lowest) {
+                        best = Some((score, twist));
+                    }
+                }
+            }
+        }
+        best.map(|(_, twist)| twist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::costmap::layers::InflationLayer;
+    use crate::costmap::{CostmapLayer, LETHAL};
+    use crate::navigation::poses_clear;
+    use crate::primitives::{IVec2, Vec2};
+
+    // Open floor with a 40 cm pillar at (2, 0), inflated by a 20 cm robot.
+    fn pillar() -> Costmap {
+        let mut costmap = Costmap::new(0.05, 120, 80, Vec2::new(-1.0, -2.0));
+        let centre = costmap.world_to_cell(Vec2::new(2.0, 0.0));
+        for y in -4..4 {
+            for x in -4..4 {
+                costmap.set_cost(centre + IVec2::new(x, y), LETHAL);
+            }
+        }
+        InflationLayer::new(0.2, 0.35, 10.0).update_costs(&mut costmap, &Pose2D::identity());
+        costmap
+    }
+
+    // Drives `path` with DWA for up to 20 s, checking that every chosen
+    // trajectory and every pose reached is clear. Returns where it ended.
+    fn drive(path: &[Pose2D], costmap: &Costmap) -> Pose2D {
+        let limits = TwistLimits::default();
+        let mut dwa = Dwa::default();
+        let (mut pose, mut velocity) = (Pose2D::identity(), Twist2D::default());
+        let dt = 0.1;
+        for _ in 0..200 {
+            let remaining: Vec<Pose2D> = path
+                .iter()
+                .copied()
+                .filter(|waypoint| waypoint.x > pose.x)
+                .collect();
+            if remaining.is_empty() {
+                break;
+            }
+            let Some(twist) = dwa.compute(&pose, &velocity, &remaining, costmap, &limits, dt)
+            else {
+                break;
+            };
+            let trajectory = rollout(&pose, &twist, dwa.step, dwa.horizon);
+            assert!(poses_clear(costmap, trajectory), "{pose:?} {twist:?}");
+            velocity = twist;
+            pose = pose.compose(&twist.integrate(dt));
+            assert!(costmap.cost_at(pose.position()) < INSCRIBED, "{pose:?}");
+        }
+        pose
+    }
+
+    #[test]
+    fn dwa_follows_a_path_around_an_obstacle_without_cutting_it() {
+        let path: Vec<Pose2D> = (0..=40)
+            .map(|index| {
+                let x = index as f32 * 0.1;
+                Pose2D::new(x, 0.7 * (-((x - 2.0) / 0.6).powi(2)).exp(), 0.0)
+            })
+            .collect();
+        let end = drive(&path, &pillar());
+        assert!(end.x > 3.5, "stuck at {end:?}");
+    }
+
+    #[test]
+    fn dwa_never_drives_through_an_obstacle_on_its_path() {
+        let path: Vec<Pose2D> = (0..=40)
+            .map(|index| Pose2D::new(index as f32 * 0.1, 0.0, 0.0))
+            .collect();
+        let end = drive(&path, &pillar());
+        assert!(end.x < 1.6 || end.y.abs() > 0.4, "{end:?}");
+    }
+
+    #[test]
+    fn dwa_keeps_within_the_dynamic_window() {
+        let costmap = pillar();
+        let path: Vec<Pose2D> = (0..=10)
+            .map(|index| Pose2D::new(index as f32 * 0.1, -1.5, 0.0))
+            .collect();
+        let limits = TwistLimits::default();
+        let velocity = Twist2D::new(0.2, 0.0);
+
+        let twist = Dwa::default()
+            .compute(
+                &Pose2D::new(0.0, -1.5, 0.0),
+                &velocity,
+                &path,
+                &costmap,
+                &limits,
+                0.1,
+            )
+            .unwrap();
+        assert!((twist.linear - velocity.linear).abs() <= 0.1 + 1e-6);
+        assert!(twist.angular.abs() <= 0.3 + 1e-6);
+        assert!(twist.linear > velocity.linear, "{twist:?}");
+    }
+}
@@ -0,0 +1,386 @@
+pub mod dwa;
+pub mod mppi;
+pub mod pure_pursuit;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::clock::TimestampDelta;
+use crate::costmap::{Costmap, LayeredCostmap, INSCRIBED};
+use crate::drive::TwistLimits;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::planning::Path;
+use crate::primitives::{normalize_angle, Pose2D, Twist2D, Vec2};
+
+/// Computes body velocity commands that track a path.
+pub trait LocalController: Send {
+    /// Command for the robot at `pose` moving at `velocity`, given the part of
+    /// the path not yet passed. `None` if no safe command exists.
+    fn compute(
+        &mut self,
+        pose: &Pose2D,
+        velocity: &Twist2D,
+        path: &[Pose2D],
+        costmap: &Costmap,
+        limits: &TwistLimits,
+        dt: f32,
+    ) -> Option<Twist2D>;
+
+    /// Forgets state carried between commands, e.g. when the path changes.
+    fn reset(&mut self) {}
+}
+
+/// Whether a point robot may stand at each pose of `poses`.
+pub fn poses_clear(costmap: &Costmap, poses: impl IntoIterator<Item = Pose2D>) -> bool {
+    poses
+        .into_iter()
+        .all(|pose| costmap.cost_at(pose.position()) < INSCRIBED)
+}
+
+/// Poses reached by holding `twist` from `start`, every `step` seconds for
+/// `duration` seconds.
+pub fn rollout(start: &Pose2D, twist: &Twist2D, step: f32, duration: f32) -> Vec<Pose2D> {
+    let delta = twist.integrate(step);
+    let steps = (duration / step).ceil() as usize;
+    std::iter::successors(Some(*start), |pose| Some(pose.compose(&delta)))
+        .skip(1)
+        .take(steps)
+        .collect()
+}
+
+/// The first pose of `path` at least `distance` from `position`, or the last
+/// pose if none is that far.
+pub fn lookahead(path: &[Pose2D], position: Vec2, distance: f32) -> Option<Pose2D> {
+    path.iter()
+        .find(|pose| pose.position().distance(position) >= distance)
+        .or(path.last())
+        .copied()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GoalTolerance {
+    /// Metres.
+    pub position: f32,
+    /// Radians.
+    pub heading: f32,
+}
+
+impl Default for GoalTolerance {
+    fn default() -> Self {
+        Self {
+            position: 0.1,
+            heading: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FollowStatus {
+    /// No path to follow.
+    Idle,
+    Following,
+    /// Within position tolerance, turning to the goal heading.
+    Aligning,
+    Arrived,
+    /// The controller found no safe command; the robot is stopping.
+    Blocked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FollowCommand {
+    pub twist: Twist2D,
+    pub status: FollowStatus,
+}
+
+/// Follows a global path with a [`LocalController`], keeping track of
+/// progress along it and turning in place to the goal heading on arrival.
+pub struct PathFollower {
+    pub limits: TwistLimits,
+    pub tolerance: GoalTolerance,
+    /// Yaw rate per radian of heading error while aligning.
+    pub alignment_gain: f32,
+    controller: Box<dyn LocalController>,
+    path: Path,
+    progress: usize,
+    command: Twist2D,
+    aligning: bool,
+}
+
+// Poses ahead of the current progress searched for the closest one.
+const PROGRESS_WINDOW: usize = 50;
+
+impl PathFollower {
+    pub fn new(controller: impl LocalController + 'static, limits: TwistLimits) -> Self {
+        Self {
+            limits,
+            tolerance: GoalTolerance::default(),
+            alignment_gain: 1.5,
+            controller: Box::new(controller),
+            path: Path::default(),
+            progress: 0,
+            command: Twist2D::default(),
+            aligning: false,
+        }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: GoalTolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Poses of the path not yet passed.
+    pub fn remaining(&self) -> &[Pose2D] {
+        &self.path.poses[self.progress.min(self.path.len())..]
+    }
+
+    pub fn set_path(&mut self, path: Path) {
+        self.path = path;
+        self.progress = 0;
+        self.aligning = false;
+        self.controller.reset();
+    }
+
+    pub fn clear(&mut self) {
+        self.set_path(Path::default());
+    }
+
+    /// Command for the next `dt` seconds with the robot at `pose` moving at
+    /// `velocity`. Commands always respect the limits, including when
+    /// stopping.
+    pub fn update(
+        &mut self,
+        pose: &Pose2D,
+        velocity: &Twist2D,
+        costmap: &Costmap,
+        dt: f32,
+    ) -> FollowCommand {
+        let Some(goal) = self.path.poses.last().copied() else {
+            return self.stop(FollowStatus::Idle, dt);
+        };
+
+        let window = &self.path.poses[self.progress..];
+        self.progress += window
+            .iter()
+            .take(PROGRESS_WINDOW)
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let (a, b) = (a.position(), b.position());
+                a.distance_squared(pose.position())
+                    .total_cmp(&b.distance_squared(pose.position()))
+            })
+            .map_or(0, |(index, _)| index);
+
+        // Once inside the position tolerance, stay aligning until arrival even
+        // if turning drifts the base slightly.
+        let distance = pose.position().distance(goal.position());
+        if distance <= self.tolerance.position
+            || (self.aligning && distance <= 2.0 * self.tolerance.position)
+        {
+            self.aligning = true;
+            let error = normalize_angle(goal.theta - pose.theta);
+            if error.abs() <= self.tolerance.heading {
+                self.clear();
+                return self.stop(FollowStatus::Arrived, dt);
+            }
+            let turn = Twist2D::new(0.0, self.alignment_gain * error);
+            self.command = self.limits.apply(&turn, &self.command, dt);
+            return FollowCommand {
+                twist: self.command,
+                status: FollowStatus::Aligning,
+            };
+        }
+        self.aligning = false;
+
+        let remaining = &self.path.poses[self.progress..];
+        match self
+            .controller
+            .compute(pose, velocity, remaining, costmap, &self.limits, dt)
+        {
+            Some(twist) => {
+                self.command = self.limits.apply(&twist, &self.command, dt);
+                FollowCommand {
+                    twist: self.command,
+                    status: FollowStatus::Following,
+                }
+            }
+            None => self.stop(FollowStatus::Blocked, dt),
+        }
+    }
+
+    fn stop(&mut self, status: FollowStatus, dt: f32) -> FollowCommand {
+        self.command = self.limits.apply(&Twist2D::default(), &self.command, dt);
+        FollowCommand {
+            twist: self.command,
+            status,
+        }
+    }
+}
+
+/// A robot pose and velocity for the local controller, in the costmap frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ControllerState {
+    pub pose: Pose2D,
+    pub velocity: Twist2D,
+}
+
+/// Runs a [`PathFollower`] against a shared costmap. Paths are handed over
+/// with [`LocalPlanner::set_path`]; each state input yields a command for
+/// the time until the next, taken from their timestamps or else `period`.
+pub struct LocalPlanner {
+    follower: Mutex<PathFollower>,
+    costmap: Arc<RwLock<LayeredCostmap>>,
+    period: f32,
+    last_timestamp: TimestampDelta,
+}
+
+impl LocalPlanner {
+    pub fn new(follower: PathFollower, costmap: Arc<RwLock<LayeredCostmap>>, period: f32) -> Self {
+        Self {
+            follower: Mutex::new(follower),
+            costmap,
+            period,
+            last_timestamp: TimestampDelta::new(),
+        }
+    }
+
+    pub fn set_path(&self, path: Path) {
+        self.follower
+            .lock()
+            .expect("PathFollower lock poisoned")
+            .set_path(path);
+    }
+}
+
+impl Task for LocalPlanner {
+    type Input = CarbonData<ControllerState>;
+    type Output = CarbonData<FollowCommand>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let dt = self
+            .last_timestamp
+            .step(input.metadata.timestamp, self.period);
+        let costmap = self.costmap.read().expect("LayeredCostmap lock poisoned");
+        let mut follower = self.follower.lock().expect("PathFollower lock poisoned");
+        input.map(|ControllerState { pose, velocity }| {
+            follower.update(&pose, &velocity, costmap.costmap(), dt)
+        })
+    }
+}
+
+impl Controller<ControllerState, FollowCommand> for LocalPlanner {}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::pure_pursuit::PurePursuit;
+    use super::*;
+    use crate::costmap::LETHAL;
+    use crate::primitives::IVec2;
+
+    // An empty 10 m by 5 m costmap at 5 cm, spanning x from -1 and y from -2.5.
+    fn open_floor() -> Costmap {
+        Costmap::new(0.05, 200, 100, Vec2::new(-1.0, -2.5))
+    }
+
+    // Poses every 10 cm along the x axis up to `length`, ending at `heading`.
+    fn straight_path(length: f32, heading: f32) -> Path {
+        let count = (length / 0.1).round() as usize;
+        let mut poses: Vec<Pose2D> = (0..=count)
+            .map(|index| Pose2D::new(index as f32 * 0.1, 0.0, 0.0))
+            .collect();
+        poses.last_mut().unwrap().theta = heading;
+        Path::new(poses)
+    }
+
+    #[test]
+    fn pure_pursuit_converges_onto_a_straight_path_and_arrives() {
+        let mut follower = PathFollower::new(PurePursuit::default(), TwistLimits::default());
+        follower.set_path(straight_path(5.0, FRAC_PI_2));
+        let costmap = open_floor();
+        let (mut pose, mut velocity) = (Pose2D::new(0.0, 0.6, 0.4), Twist2D::default());
+        let dt = 0.1;
+
+        let mut statuses = Vec::new();
+        for _ in 0..600 {
+            let command = follower.update(&pose, &velocity, &costmap, dt);
+            statuses.push(command.status);
+            if command.status == FollowStatus::Arrived {
+                break;
+            }
+            velocity = command.twist;
+            pose = pose.compose(&velocity.integrate(dt));
+            if pose.x > 2.5 && pose.x < 4.0 {
+                assert!(pose.y.abs() < 0.05, "{pose:?}");
+            }
+        }
+
+        assert_eq!(statuses.last(), Some(&FollowStatus::Arrived));
+        assert!(statuses.contains(&FollowStatus::Aligning));
+        assert!(!statuses.contains(&FollowStatus::Blocked));
+        assert!(
+            pose.position().distance(Vec2::new(5.0, 0.0)) < 2.0 * 0.1,
+            "{pose:?}"
+        );
+        assert!((pose.theta - FRAC_PI_2).abs() <= 0.1, "{pose:?}");
+        assert!(follower.path().poses.is_empty());
+        assert_eq!(
+            follower.update(&pose, &velocity, &costmap, dt).status,
+            FollowStatus::Idle
+        );
+    }
+
+    #[test]
+    fn a_wall_across_the_path_blocks_and_stops_within_limits() {
+        let limits = TwistLimits::default();
+        let mut follower = PathFollower::new(PurePursuit::default(), limits);
+        follower.set_path(straight_path(5.0, 0.0));
+        let mut costmap = open_floor();
+        let wall = costmap.world_to_cell(Vec2::new(1.0, 0.0)).x;
+        for y in 0..costmap.height() as i32 {
+            costmap.set_cost(IVec2::new(wall, y), LETHAL);
+        }
+
+        let mut pose = Pose2D::new(0.0, 0.0, 0.0);
+        let mut velocity = Twist2D::default();
+        let mut blocked = None;
+        for _ in 0..100 {
+            let command = follower.update(&pose, &velocity, &costmap, 0.1);
+            assert!((command.twist.linear - velocity.linear).abs() <= 0.1 + 1e-6);
+            if command.status == FollowStatus::Blocked {
+                blocked = Some(velocity);
+                velocity = command.twist;
+                break;
+            }
+            velocity = command.twist;
+            pose = pose.compose(&velocity.integrate(0.1));
+            assert!(pose.x < 1.0, "{pose:?}");
+        }
+
+        let before = blocked.expect("never blocked");
+        assert!(velocity.linear < before.linear || before.linear == 0.0);
+        for _ in 0..10 {
+            let command = follower.update(&pose, &velocity, &costmap, 0.1);
+            assert_eq!(command.status, FollowStatus::Blocked);
+            velocity = command.twist;
+        }
+        assert_eq!(velocity, Twist2D::default());
+    }
+
+    #[test]
+    fn lookahead_and_rollout() {
+        let path = straight_path(2.0, 0.0).poses;
+        assert_eq!(lookahead(&path, Vec2::ZERO, 0.55), Some(path[6]));
+        assert_eq!(lookahead(&path, Vec2::ZERO, 5.0), path.last().copied());
+        assert_eq!(lookahead(&[], Vec2::ZERO, 1.0), None);
+
+        let arc = rollout(&Pose2D::identity(), &Twist2D::new(1.0, 0.0), 0.1, 1.0);
+        assert_eq!(arc.len(), 10);
+        assert!((arc[9].x - 1.0).abs() < 1e-5);
+    }
+}
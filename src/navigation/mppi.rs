@@ -0,0 +1,171 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+use super::{lookahead, LocalController};
+use crate::costmap::{Costmap, INSCRIBED};
+use crate::drive::TwistLimits;
+use crate::primitives::{Pose2D, Twist2D};
+
+/// Model predictive path integral control: perturbs the previous control
+/// sequence with noise, rolls out every sample and averages them weighted by
+/// the exponential of their negative cost.
+#[derive(Clone, Debug)]
+pub struct Mppi {
+    /// Number of control steps in the horizon.
+    pub steps: usize,
+    /// Seconds per control step.
+    pub step: f32,
+    pub samples: usize,
+    /// Lower values follow the best samples more closely.
+    pub temperature: f32,
+    /// Standard deviations of the control noise, m/s and rad/s.
+    pub linear_noise: f32,
+    pub angular_noise: f32,
+    /// Weight of each pose's distance from the path, per metre.
+    pub path_weight: f32,
+    /// Weight of the final pose's distance to the local goal, per metre.
+    pub goal_weight: f32,
+    /// Weight of cell costs along the rollout, per unit of cost.
+    pub obstacle_weight: f32,
+    sequence: Vec<Twist2D>,
+    rng: StdRng,
+}
+
+// Added to the cost of rollouts entering an inscribed or lethal cell.
+const COLLISION_COST: f32 = 1e6;
+
+impl Mppi {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            steps: 20,
+            step: 0.1,
+            samples: 300,
+            temperature: 0.3,
+            linear_noise: 0.15,
+            angular_noise: 0.5,
+            path_weight: 5.0,
+            goal_weight: 3.0,
+            obstacle_weight: 0.02,
+            sequence: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // Cost of a control sequence rolled out from `start`.
+    fn cost(
+        &self,
+        start: &Pose2D,
+        controls: &[Twist2D],
+        path: &[Pose2D],
+        goal: &Pose2D,
+        costmap: &Costmap,
+    ) -> f32 {
+        let mut pose = *start;
+        let mut total = 0.0;
+        for control in controls {
+            pose = pose.compose(&control.integrate(self.step));
+            let cell_cost = costmap.cost_at(pose.position());
+            if cell_cost >= INSCRIBED {
+                total += COLLISION_COST;
+            }
+            total += self.obstacle_weight * f32::from(cell_cost.min(INSCRIBED));
+            total += self.path_weight
+                * path
+                    .iter()
+                    .map(|point| point.position().distance(pose.position()))
+                    .fold(f32::INFINITY, f32::min);
+        }
+        total + self.goal_weight * pose.position().distance(goal.position()) * controls.len() as f32
+    }
+}
+
+impl Default for Mppi {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl LocalController for Mppi {
+    fn compute(
+        &mut self,
+        pose: &Pose2D,
+        velocity: &Twist2D,
+        path: &[Pose2D],
+        costmap: &Costmap,
+        limits: &TwistLimits,
+        _dt: f32,
+    ) -> Option<Twist2D> {
+        let horizon = self.steps as f32 * self.step;
+        let goal = lookahead(path, pose.position(), limits.max_linear * horizon)?;
+        // Warm start from the previous solution, shifted by one step.
+        if self.sequence.len() == self.steps {
+            self.sequence.rotate_left(1);
+        } else {
+            self.sequence = vec![*velocity; self.steps];
+        }
+
+        let mut rollouts = Vec::with_capacity(self.samples);
+        for _ in 0..self.samples {
+            let mut previous = *velocity;
+            let controls: Vec<Twist2D> = self
+                .sequence
+                .iter()
+                .map(|nominal| {
+                    let noisy = Twist2D::new(
+                        nominal.linear
+                            + self.linear_noise * self.rng.sample::<f32, _>(StandardNormal),
+                        nominal.angular
+                            + self.angular_noise * self.rng.sample::<f32, _>(StandardNormal),
+                    );
+                    previous = limits.apply(&noisy, &previous, self.step);
+                    previous
+                })
+                .collect();
+            let cost = self.cost(pose, &controls, path, &goal, costmap);
+            rollouts.push((cost, controls));
+        }
+        // The unperturbed sequence competes too, so a good plan isn't lost.
+        let nominal_cost = self.cost(pose, &self.sequence, path, &goal, costmap);
+        rollouts.push((nominal_cost, self.sequence.clone()));
+
+        let lowest = rollouts
+            .iter()
+            .map(|(cost, _)| *cost)
+            .fold(f32::INFINITY, f32::min);
+        if lowest >= COLLISION_COST {
+            self.sequence.clear();
+            return None;
+        }
+        let weights: Vec<f32> = rollouts
+            .iter()
+            .map(|(cost, _)| (-(cost - lowest) / self.temperature).exp())
+            .collect();
+        let total: f32 = weights.iter().sum();
+        for (index, nominal) in self.sequence.iter_mut().enumerate() {
+            let (linear, angular) = rollouts.iter().zip(&weights).fold(
+                (0.0, 0.0),
+                |(linear, angular), ((_, controls), weight)| {
+                    (
+                        linear + weight * controls[index].linear,
+                        angular + weight * controls[index].angular,
+                    )
+                },
+            );
+            *nominal = Twist2D::new(linear / total, angular / total);
+        }
+        // A weighted average of safe sequences needn't be safe itself.
+        if self.cost(pose, &self.sequence, path, &goal, costmap) >= COLLISION_COST {
+            let best = rollouts
+                .iter()
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, controls)| controls.clone())?;
+            self.sequence = best;
+        }
+        self.sequence.first().copied()
+    }
+
+    fn reset(&mut self) {
+        self.sequence.clear();
+    }
+}
@@ -0,0 +1,89 @@
+use super::{lookahead, poses_clear, rollout, LocalController};
+use crate::costmap::Costmap;
+use crate::drive::TwistLimits;
+use crate::primitives::{Pose2D, Twist2D};
+
+/// Steers along the arc through a point a lookahead distance down the path.
+/// The lookahead grows with speed, the robot slows on tight turns and near
+/// the goal, and the arc is checked against the costmap before being used.
+#[derive(Clone, Debug)]
+pub struct PurePursuit {
+    pub desired_speed: f32,
+    pub min_lookahead: f32,
+    pub max_lookahead: f32,
+    /// Seconds of travel at the current speed to look ahead.
+    pub lookahead_time: f32,
+    /// Turn in place first when the lookahead point is further off the
+    /// heading than this, in radians.
+    pub rotate_threshold: f32,
+    /// Slow down proportionally within this distance of the path's end.
+    pub approach_distance: f32,
+    pub min_approach_speed: f32,
+    /// Slow down on arcs with a turning radius below this, in metres.
+    pub min_turning_radius: f32,
+    /// Seconds of the commanded arc checked for collisions.
+    pub collision_horizon: f32,
+}
+
+impl Default for PurePursuit {
+    fn default() -> Self {
+        Self {
+            desired_speed: 0.4,
+            min_lookahead: 0.3,
+            max_lookahead: 1.2,
+            lookahead_time: 1.5,
+            rotate_threshold: 0.8,
+            approach_distance: 0.8,
+            min_approach_speed: 0.05,
+            min_turning_radius: 0.6,
+            collision_horizon: 1.0,
+        }
+    }
+}
+
+// Seconds between poses checked along a candidate arc.
+const COLLISION_STEP: f32 = 0.05;
+
+impl LocalController for PurePursuit {
+    fn compute(
+        &mut self,
+        pose: &Pose2D,
+        velocity: &Twist2D,
+        path: &[Pose2D],
+        costmap: &Costmap,
+        limits: &TwistLimits,
+        _dt: f32,
+    ) -> Option<Twist2D> {
+        let distance = (velocity.linear.abs() * self.lookahead_time)
+            .clamp(self.min_lookahead, self.max_lookahead);
+        let target = lookahead(path, pose.position(), distance)?;
+        let local = pose.inverse().transform_point(target.position());
+        let bearing = local.to_angle();
+
+        if bearing.abs() > self.rotate_threshold {
+            let turn = Twist2D::new(0.0, bearing.signum() * limits.max_angular);
+            let arc = rollout(pose, &turn, COLLISION_STEP, self.collision_horizon);
+            return poses_clear(costmap, arc).then_some(turn);
+        }
+
+        let curvature = 2.0 * local.y / local.length_squared().max(f32::EPSILON);
+        let mut speed = self.desired_speed.min(limits.max_linear);
+        if curvature.abs() * self.min_turning_radius > 1.0 {
+            speed /= curvature.abs() * self.min_turning_radius;
+        }
+        if let Some(end) = path.last() {
+            let remaining = pose.position().distance(end.position());
+            if remaining < self.approach_distance {
+                speed = (speed * remaining / self.approach_distance).max(self.min_approach_speed);
+            }
+        }
+        // Keep to the arc if the yaw rate limit would otherwise cut it short.
+        if (speed * curvature).abs() > limits.max_angular {
+            speed = limits.max_angular / curvature.abs();
+        }
+
+        let twist = Twist2D::new(speed, speed * curvature);
+        let arc = rollout(pose, &twist, COLLISION_STEP, self.collision_horizon);
+        poses_clear(costmap, arc).then_some(twist)
+    }
+}
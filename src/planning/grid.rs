@@ -0,0 +1,467 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+use super::PlanError;
+use crate::clock::{default_clock, Deadline, SharedClock};
+use crate::costmap::{Costmap, INSCRIBED, NO_INFORMATION};
+use crate::mapping::occupancy_grid::raytrace;
+use crate::primitives::{IVec2, Vec2};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchAlgorithm {
+    /// Uniform-cost search; explores the most but needs no heuristic.
+    Dijkstra,
+    /// Eight-connected search guided by the octile distance to the goal.
+    AStar,
+    /// Any-angle A*: a node may take its grandparent as parent when the
+    /// straight line between them is clear, giving paths not tied to the grid.
+    ThetaStar,
+}
+
+/// Order among open nodes with equal estimated total cost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreaking {
+    /// Expand the node closest to the goal first.
+    LowestHeuristic,
+    /// Also slightly prefer nodes near the straight line from start to goal,
+    /// which keeps paths across open space from zig-zagging.
+    StraightLine,
+}
+
+/// How cells without information are treated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownSpace {
+    Forbidden,
+    /// Traversable at the given cost.
+    Traversable(u8),
+}
+
+#[derive(Clone, Debug)]
+pub struct GridSearchConfig {
+    pub algorithm: SearchAlgorithm,
+    pub tie_breaking: TieBreaking,
+    pub unknown_space: UnknownSpace,
+    /// Cells at or above this cost are never entered.
+    pub lethal_cost: u8,
+    /// Crossing a cell of cost `c` costs `1 + cost_weight * c / 252` per metre.
+    pub cost_weight: f32,
+    /// A blocked goal is moved to the nearest traversable cell within this
+    /// distance.
+    pub goal_tolerance: f32,
+    pub time_limit: Option<Duration>,
+    /// The clock the time limit is measured on.
+    pub clock: SharedClock,
+}
+
+impl Default for GridSearchConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: SearchAlgorithm::AStar,
+            tie_breaking: TieBreaking::LowestHeuristic,
+            unknown_space: UnknownSpace::Traversable(0),
+            lethal_cost: INSCRIBED,
+            cost_weight: 3.0,
+            goal_tolerance: 0.0,
+            time_limit: None,
+            clock: default_clock(),
+        }
+    }
+}
+
+impl GridSearchConfig {
+    /// Cost per metre of crossing `cell`, or `None` if it can't be entered.
+    pub fn traversal_cost(&self, costmap: &Costmap, cell: IVec2) -> Option<f32> {
+        let cost = match costmap.cost(cell)? {
+            NO_INFORMATION => match self.unknown_space {
+                UnknownSpace::Forbidden => return None,
+                UnknownSpace::Traversable(cost) => cost,
+            },
+            cost => cost,
+        };
+        (cost < self.lethal_cost)
+            .then(|| 1.0 + self.cost_weight * f32::from(cost) / f32::from(INSCRIBED - 1))
+    }
+
+    /// Cost of driving straight from `from` to `to`, or `None` if the line
+    /// crosses a cell that can't be entered.
+    pub fn segment_cost(&self, costmap: &Costmap, from: Vec2, to: Vec2) -> Option<f32> {
+        let (start, end) = (costmap.world_to_cell(from), costmap.world_to_cell(to));
+        let (mut total, mut count) = (0.0, 0);
+        let mut clear = true;
+        raytrace(start, end, |cell| {
+            match self.traversal_cost(costmap, cell) {
+                Some(cost) => {
+                    total += cost;
+                    count += 1;
+                    true
+                }
+                None => {
+                    clear = false;
+                    false
+                }
+            }
+        });
+        clear.then(|| from.distance(to) * total / count as f32)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OpenNode {
+    estimate: f32,
+    heuristic: f32,
+    index: usize,
+}
+
+impl PartialEq for OpenNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed so the binary heap pops the lowest estimate first.
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then(other.heuristic.total_cmp(&self.heuristic))
+    }
+}
+
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+// Expansions between checks of the time limit.
+const CLOCK_INTERVAL: usize = 1024;
+
+/// Searches `costmap` for the cheapest path from `start` to `goal`, given in
+/// world coordinates. Returns waypoints from `start` to the goal, with cell
+/// centres in between.
+pub fn search(
+    costmap: &Costmap,
+    start: Vec2,
+    goal: Vec2,
+    config: &GridSearchConfig,
+) -> Result<Vec<Vec2>, PlanError> {
+    let deadline = Deadline::after(&config.clock, config.time_limit);
+    let start_cell = costmap.world_to_cell(start);
+    if config.traversal_cost(costmap, start_cell).is_none() {
+        return Err(PlanError::StartBlocked);
+    }
+    let requested_goal = costmap.world_to_cell(goal);
+    let goal_cell =
+        nearest_traversable(costmap, requested_goal, config).ok_or(PlanError::GoalBlocked)?;
+    let goal = if goal_cell == requested_goal {
+        goal
+    } else {
+        costmap.cell_to_world(goal_cell)
+    };
+
+    let resolution = costmap.resolution();
+    let heuristic = |cell: IVec2| -> f32 {
+        let delta = (goal_cell - cell).abs().as_vec2();
+        let distance = match config.algorithm {
+            SearchAlgorithm::Dijkstra => return 0.0,
+            SearchAlgorithm::AStar => {
+                delta.max_element() + (2f32.sqrt() - 1.0) * delta.min_element()
+            }
+            SearchAlgorithm::ThetaStar => delta.length(),
+        };
+        distance * resolution
+    };
+    let straight_line = (start_cell - goal_cell).as_vec2();
+    let tie_breaker = |cell: IVec2| -> f32 {
+        match config.tie_breaking {
+            TieBreaking::LowestHeuristic => 0.0,
+            TieBreaking::StraightLine => {
+                let offset = (cell - goal_cell).as_vec2();
+                1e-3 * resolution * offset.perp_dot(straight_line).abs()
+                    / straight_line.length().max(1.0)
+            }
+        }
+    };
+
+    let size = costmap.width() * costmap.height();
+    let mut cost_to_come = vec![f32::INFINITY; size];
+    let mut parent = vec![usize::MAX; size];
+    let mut closed = vec![false; size];
+    let mut open = BinaryHeap::new();
+    let cell_of = |index: usize| {
+        IVec2::new(
+            (index % costmap.width()) as i32,
+            (index / costmap.width()) as i32,
+        )
+    };
+
+    let start_index = costmap.index(start_cell).ok_or(PlanError::StartBlocked)?;
+    let goal_index = costmap.index(goal_cell).ok_or(PlanError::GoalBlocked)?;
+    cost_to_come[start_index] = 0.0;
+    parent[start_index] = start_index;
+    open.push(OpenNode {
+        estimate: heuristic(start_cell),
+        heuristic: heuristic(start_cell),
+        index: start_index,
+    });
+
+    let mut expansions = 0;
+    while let Some(OpenNode { index, .. }) = open.pop() {
+        if closed[index] {
+            continue;
+        }
+        if index == goal_index {
+            break;
+        }
+        closed[index] = true;
+        expansions += 1;
+        if expansions % CLOCK_INTERVAL == 0 && deadline.expired() {
+            return Err(PlanError::TimedOut);
+        }
+
+        let cell = cell_of(index);
+        let Some(cell_cost) = config.traversal_cost(costmap, cell) else {
+            continue;
+        };
+        for step in NEIGHBOURS {
+            let next = cell + step;
+            let Some(next_index) = costmap.index(next) else {
+                continue;
+            };
+            if closed[next_index] {
+                continue;
+            }
+            let Some(next_cost) = config.traversal_cost(costmap, next) else {
+                continue;
+            };
+            // No cutting corners past blocked cells.
+            if step.x != 0
+                && step.y != 0
+                && (config
+                    .traversal_cost(costmap, cell + IVec2::new(step.x, 0))
+                    .is_none()
+                    || config
+                        .traversal_cost(costmap, cell + IVec2::new(0, step.y))
+                        .is_none())
+            {
+                continue;
+            }
+
+            let mut via = index;
+            let mut cost = cost_to_come[index]
+                + step.as_vec2().length() * resolution * 0.5 * (cell_cost + next_cost);
+            if config.algorithm == SearchAlgorithm::ThetaStar && parent[index] != index {
+                let grandparent = parent[index];
+                if let Some(line) = config.segment_cost(
+                    costmap,
+                    costmap.cell_to_world(cell_of(grandparent)),
+                    costmap.cell_to_world(next),
+                ) {
+                    let through = cost_to_come[grandparent] + line;
+                    if through <= cost {
+                        via = grandparent;
+                        cost = through;
+                    }
+                }
+            }
+
+            if cost < cost_to_come[next_index] {
+                cost_to_come[next_index] = cost;
+                parent[next_index] = via;
+                let remaining = heuristic(next);
+                open.push(OpenNode {
+                    estimate: cost + remaining + tie_breaker(next),
+                    heuristic: remaining,
+                    index: next_index,
+                });
+            }
+        }
+    }
+
+    if parent[goal_index] == usize::MAX {
+        return Err(PlanError::NoPath);
+    }
+    let mut cells = vec![goal_index];
+    let mut index = goal_index;
+    while parent[index] != index {
+        index = parent[index];
+        cells.push(index);
+    }
+    cells.reverse();
+    let mut waypoints: Vec<Vec2> = cells
+        .into_iter()
+        .map(|index| costmap.cell_to_world(cell_of(index)))
+        .collect();
+    if waypoints.len() == 1 {
+        waypoints.push(goal);
+    }
+    waypoints[0] = start;
+    if let Some(last) = waypoints.last_mut() {
+        *last = goal;
+    }
+    Ok(waypoints)
+}
+
+// The traversable cell nearest `cell` within the goal tolerance.
+fn nearest_traversable(costmap: &Costmap, cell: IVec2, config: &GridSearchConfig) -> Option<IVec2> {
+    if config.traversal_cost(costmap, cell).is_some() {
+        return Some(cell);
+    }
+    let reach = (config.goal_tolerance / costmap.resolution()).floor() as i32;
+    (-reach..=reach)
+        .flat_map(|y| (-reach..=reach).map(move |x| IVec2::new(x, y)))
+        .filter(|offset| offset.length_squared() <= reach * reach)
+        .map(|offset| cell + offset)
+        .filter(|&candidate| config.traversal_cost(costmap, candidate).is_some())
+        .min_by_key(|candidate| (*candidate - cell).length_squared())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::costmap::{FREE, LETHAL};
+
+    // A 6 × 4 m room split by a wall at x = 3 m, open only along the top
+    // metre, where the gap holds `gap` cost.
+    fn walled(gap: u8) -> Costmap {
+        let mut costmap = Costmap::new(0.1, 60, 40, Vec2::ZERO);
+        for y in 0..40 {
+            let cost = if y < 30 { LETHAL } else { gap };
+            costmap.set_cost(IVec2::new(30, y), cost);
+        }
+        costmap
+    }
+
+    fn config(algorithm: SearchAlgorithm) -> GridSearchConfig {
+        GridSearchConfig {
+            algorithm,
+            ..Default::default()
+        }
+    }
+
+    const START: Vec2 = Vec2::new(1.0, 1.0);
+    const GOAL: Vec2 = Vec2::new(5.0, 1.0);
+
+    // Every straight segment of `path` stays clear of the wall.
+    fn assert_clear(costmap: &Costmap, path: &[Vec2], config: &GridSearchConfig) {
+        for pair in path.windows(2) {
+            assert!(
+                config.segment_cost(costmap, pair[0], pair[1]).is_some(),
+                "{pair:?} crosses the wall"
+            );
+        }
+    }
+
+    #[test]
+    fn every_algorithm_finds_the_way_around_a_wall() {
+        let costmap = walled(FREE);
+        for algorithm in [
+            SearchAlgorithm::Dijkstra,
+            SearchAlgorithm::AStar,
+            SearchAlgorithm::ThetaStar,
+        ] {
+            let config = config(algorithm);
+            let path = search(&costmap, START, GOAL, &config).unwrap();
+            assert_eq!(path.first(), Some(&START));
+            assert_eq!(path.last(), Some(&GOAL));
+            assert_clear(&costmap, &path, &config);
+            assert!(
+                path.iter().any(|point| point.y > 3.0),
+                "{algorithm:?} never went through the gap"
+            );
+            let length: f32 = path.windows(2).map(|pair| pair[0].distance(pair[1])).sum();
+            // Up to the gap and back down, with some slack for the grid.
+            assert!(length < 8.5, "{algorithm:?} took {length} m");
+        }
+    }
+
+    #[test]
+    fn unknown_space_is_refused_when_forbidden() {
+        let costmap = walled(NO_INFORMATION);
+        let forbidden = GridSearchConfig {
+            unknown_space: UnknownSpace::Forbidden,
+            ..Default::default()
+        };
+        assert_eq!(
+            search(&costmap, START, GOAL, &forbidden),
+            Err(PlanError::NoPath)
+        );
+        let traversable = GridSearchConfig {
+            unknown_space: UnknownSpace::Traversable(50),
+            ..Default::default()
+        };
+        assert!(search(&costmap, START, GOAL, &traversable).is_ok());
+    }
+
+    #[test]
+    fn blocked_ends_are_reported() {
+        let costmap = walled(FREE);
+        let on_wall = Vec2::new(3.05, 1.0);
+        assert_eq!(
+            search(&costmap, on_wall, GOAL, &Default::default()),
+            Err(PlanError::StartBlocked)
+        );
+        assert_eq!(
+            search(&costmap, START, on_wall, &Default::default()),
+            Err(PlanError::GoalBlocked)
+        );
+        // Unless the goal may move to a free cell nearby.
+        let tolerant = GridSearchConfig {
+            goal_tolerance: 0.15,
+            ..Default::default()
+        };
+        let path = search(&costmap, START, on_wall, &tolerant).unwrap();
+        assert!(path.last().unwrap().distance(on_wall) <= 0.15);
+    }
+
+    #[test]
+    fn theta_star_takes_shortcuts_a_star_cannot() {
+        let costmap = walled(FREE);
+        let grid = search(&costmap, START, GOAL, &config(SearchAlgorithm::AStar)).unwrap();
+        let any_angle = search(&costmap, START, GOAL, &config(SearchAlgorithm::ThetaStar)).unwrap();
+        assert!(
+            any_angle.len() < grid.len() / 4,
+            "{} waypoints against {}",
+            any_angle.len(),
+            grid.len()
+        );
+    }
+
+    #[test]
+    fn searches_time_out_on_their_own_clock() {
+        let costmap = Costmap::new(0.05, 200, 200, Vec2::ZERO);
+        let (start, goal) = (Vec2::new(0.5, 0.5), Vec2::new(9.5, 9.5));
+        let clock: SharedClock = Arc::new(SimulatedClock::new());
+        let mut config = GridSearchConfig {
+            algorithm: SearchAlgorithm::Dijkstra,
+            time_limit: Some(Duration::ZERO),
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            search(&costmap, start, goal, &config),
+            Err(PlanError::TimedOut)
+        );
+        // Simulated time stands still however long the search really takes.
+        config.time_limit = Some(Duration::from_nanos(1));
+        assert!(search(&costmap, start, goal, &config).is_ok());
+    }
+}
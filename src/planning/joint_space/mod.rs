@@ -0,0 +1,246 @@
+pub mod prm;
+pub mod rrt_connect;
+pub mod shortcut;
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use rand::Rng;
+
+use super::PlanError;
+use crate::clock::{default_clock, SharedClock};
+use crate::joints::JointLimits;
+use crate::kinematics::CollisionChecker;
+use crate::trajectory::{JointTrajectory, TrajectoryGenerator};
+
+#[derive(Clone, Debug)]
+pub struct JointSpaceConfig {
+    /// Longest joint-space step, by Euclidean norm, when growing a tree.
+    pub step_size: f32,
+    /// Largest change of any joint between collision checks along a motion.
+    /// Links far from a joint sweep further per step, so this should be
+    /// below the collision padding divided by the reach of the chain.
+    pub collision_resolution: f32,
+    pub max_iterations: usize,
+    pub time_limit: Option<Duration>,
+    /// The clock the time limit is measured on.
+    pub clock: SharedClock,
+}
+
+impl Default for JointSpaceConfig {
+    fn default() -> Self {
+        Self {
+            step_size: 0.3,
+            collision_resolution: 0.01,
+            max_iterations: 20_000,
+            time_limit: Some(Duration::from_secs(5)),
+            clock: default_clock(),
+        }
+    }
+}
+
+/// Finds collision-free paths between joint configurations.
+pub trait JointSpacePlanner {
+    /// Waypoints from `start` to `goal`, joined by straight joint-space
+    /// motions that are valid for `checker`.
+    fn plan(
+        &mut self,
+        checker: &CollisionChecker,
+        start: &[f32],
+        goal: &[f32],
+    ) -> Result<Vec<Vec<f32>>, PlanError>;
+}
+
+pub(crate) fn check_endpoints(
+    checker: &CollisionChecker,
+    start: &[f32],
+    goal: &[f32],
+) -> Result<(), PlanError> {
+    if !checker.is_valid(start) {
+        return Err(PlanError::StartBlocked);
+    }
+    if !checker.is_valid(goal) {
+        return Err(PlanError::GoalBlocked);
+    }
+    Ok(())
+}
+
+pub(crate) fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// The configuration at most `step` from `from` towards `to`.
+pub(crate) fn steer(from: &[f32], to: &[f32], step: f32) -> Vec<f32> {
+    let length = distance(from, to);
+    if length <= step {
+        return to.to_vec();
+    }
+    let fraction = step / length;
+    from.iter()
+        .zip(to)
+        .map(|(a, b)| a + (b - a) * fraction)
+        .collect()
+}
+
+/// A uniformly random configuration within the limits; unbounded joints are
+/// sampled over one turn.
+pub(crate) fn sample(limits: &[JointLimits], rng: &mut impl Rng) -> Vec<f32> {
+    limits
+        .iter()
+        .map(|limits| {
+            let lower = if limits.lower.is_finite() {
+                limits.lower
+            } else {
+                -PI
+            };
+            let upper = if limits.upper.is_finite() {
+                limits.upper
+            } else {
+                PI
+            };
+            if upper > lower {
+                rng.gen_range(lower..=upper)
+            } else {
+                lower
+            }
+        })
+        .collect()
+}
+
+/// Plans, shortcuts and times a motion for the chain in `checker`.
+pub fn plan_trajectory(
+    planner: &mut impl JointSpacePlanner,
+    smoother: &mut shortcut::Shortcut,
+    checker: &CollisionChecker,
+    start: &[f32],
+    goal: &[f32],
+    generator: &TrajectoryGenerator,
+) -> Result<JointTrajectory, PlanError> {
+    let path = planner.plan(checker, start, goal)?;
+    let path = smoother.smooth(checker, &path)?;
+    let chain = checker.chain();
+    Ok(generator.generate(chain.joint_names(), &path, &chain.limits()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prm::Prm;
+    use super::rrt_connect::RrtConnect;
+    use super::shortcut::Shortcut;
+    use super::*;
+    use crate::kinematics::tests::{arm, obstacle};
+
+    // Sweeping the stretched arm from `START` to `GOAL` hits the obstacle;
+    // the way round folds the elbow.
+    const START: [f32; 2] = [-0.5, 0.0];
+    const GOAL: [f32; 2] = [1.2, 0.0];
+
+    fn checker() -> CollisionChecker {
+        CollisionChecker::new(arm(), 0.02)
+            .unwrap()
+            .with_obstacle(obstacle(0.7, 0.35))
+    }
+
+    fn assert_valid_path(checker: &CollisionChecker, path: &[Vec<f32>]) {
+        assert_eq!(path.first().map(Vec::as_slice), Some(&START[..]));
+        assert_eq!(path.last().map(Vec::as_slice), Some(&GOAL[..]));
+        for pair in path.windows(2) {
+            assert_eq!(
+                checker.motion_valid(&pair[0], &pair[1], 0.01),
+                Ok(true),
+                "{pair:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rrt_connect_finds_a_way_around_an_obstacle() {
+        let checker = checker();
+        assert_eq!(checker.motion_valid(&START, &GOAL, 0.01), Ok(false));
+
+        let path = RrtConnect::new(JointSpaceConfig::default(), 1)
+            .plan(&checker, &START, &GOAL)
+            .unwrap();
+        assert!(path.len() > 2);
+        assert_valid_path(&checker, &path);
+    }
+
+    #[test]
+    fn prm_finds_a_way_around_an_obstacle() {
+        let checker = checker();
+        let mut prm = Prm::new(JointSpaceConfig::default(), 300, 10, 1);
+
+        let path = prm.plan(&checker, &START, &GOAL).unwrap();
+        assert!(path.len() > 2);
+        assert_valid_path(&checker, &path);
+        // Query nodes don't stay in the roadmap.
+        assert_eq!(prm.roadmap().node_count(), 300);
+    }
+
+    #[test]
+    fn prm_rebuilds_its_roadmap_when_the_obstacles_change() {
+        let mut checker = CollisionChecker::new(arm(), 0.02).unwrap();
+        let mut prm = Prm::new(JointSpaceConfig::default(), 200, 10, 2);
+        prm.build(&checker).unwrap();
+
+        checker.add_obstacle(obstacle(0.7, 0.35));
+        let path = prm.plan(&checker, &START, &GOAL).unwrap();
+        assert_valid_path(&checker, &path);
+        for node in prm.roadmap().node_indices() {
+            assert!(checker.is_valid(&prm.roadmap()[node]));
+        }
+        for edge in prm.roadmap().edge_indices() {
+            let (a, b) = prm.roadmap().edge_endpoints(edge).unwrap();
+            let (a, b) = (&prm.roadmap()[a], &prm.roadmap()[b]);
+            assert_eq!(checker.motion_valid(a, b, 0.01), Ok(true));
+        }
+    }
+
+    #[test]
+    fn shortcutting_keeps_paths_valid_and_shortens_them() {
+        let checker = checker();
+        let path = RrtConnect::new(JointSpaceConfig::default(), 3)
+            .plan(&checker, &START, &GOAL)
+            .unwrap();
+        let length = |path: &[Vec<f32>]| {
+            path.windows(2)
+                .map(|pair| distance(&pair[0], &pair[1]))
+                .sum::<f32>()
+        };
+
+        let smoothed = Shortcut::new(100, 0.01, 3).smooth(&checker, &path).unwrap();
+        assert_valid_path(&checker, &smoothed);
+        assert!(length(&smoothed) <= length(&path) + 1e-4);
+    }
+
+    #[test]
+    fn planners_reject_bad_resolutions_and_blocked_ends() {
+        let checker = checker();
+        let config = JointSpaceConfig {
+            collision_resolution: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            RrtConnect::new(config.clone(), 0).plan(&checker, &START, &GOAL),
+            Err(PlanError::InvalidResolution)
+        );
+        assert_eq!(
+            Prm::new(config, 50, 5, 0).plan(&checker, &START, &GOAL),
+            Err(PlanError::InvalidResolution)
+        );
+        let blocked = [0.35f32.atan2(0.7), 0.0];
+        let mut rrt = RrtConnect::new(JointSpaceConfig::default(), 0);
+        assert_eq!(
+            rrt.plan(&checker, &blocked, &GOAL),
+            Err(PlanError::StartBlocked)
+        );
+        assert_eq!(
+            rrt.plan(&checker, &START, &blocked),
+            Err(PlanError::GoalBlocked)
+        );
+    }
+}
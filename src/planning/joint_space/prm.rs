@@ -0,0 +1,134 @@
+use petgraph::algo::astar;
+use petgraph::graph::{NodeIndex, UnGraph};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use super::{check_endpoints, distance, sample, JointSpaceConfig, JointSpacePlanner};
+use crate::clock::Deadline;
+use crate::kinematics::CollisionChecker;
+use crate::planning::PlanError;
+
+/// Probabilistic roadmap: a graph of valid configurations joined by valid
+/// straight motions, built once and searched for each query. The roadmap is
+/// rebuilt when planning against a checker other than the one it was built
+/// for, or after that checker changed.
+pub struct Prm {
+    pub config: JointSpaceConfig,
+    /// Valid configurations sampled by [`Prm::build`].
+    pub samples: usize,
+    /// Each node is joined to up to this many nearest nodes within the
+    /// connection radius.
+    pub neighbours: usize,
+    /// Longest roadmap edge in joint space, by Euclidean norm.
+    pub connection_radius: f32,
+    roadmap: UnGraph<Vec<f32>, f32>,
+    // Revision and padding of the checker the roadmap was built for.
+    built_for: Option<(u64, f32)>,
+    rng: StdRng,
+}
+
+impl Prm {
+    pub fn new(config: JointSpaceConfig, samples: usize, neighbours: usize, seed: u64) -> Self {
+        Self {
+            config,
+            samples,
+            neighbours,
+            connection_radius: 1.0,
+            roadmap: UnGraph::default(),
+            built_for: None,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn roadmap(&self) -> &UnGraph<Vec<f32>, f32> {
+        &self.roadmap
+    }
+
+    /// Samples a fresh roadmap for the chain and obstacles in `checker`.
+    pub fn build(&mut self, checker: &CollisionChecker) -> Result<(), PlanError> {
+        let deadline = Deadline::after(&self.config.clock, self.config.time_limit);
+        let limits = checker.chain().limits();
+        self.roadmap.clear();
+        self.built_for = None;
+        let mut attempts = 0;
+        while self.roadmap.node_count() < self.samples {
+            attempts += 1;
+            if attempts > self.config.max_iterations {
+                break;
+            }
+            if deadline.expired() {
+                return Err(PlanError::TimedOut);
+            }
+            let configuration = sample(&limits, &mut self.rng);
+            if checker.is_valid(&configuration) {
+                self.insert(checker, configuration)?;
+            }
+        }
+        self.built_for = Some((checker.revision(), checker.padding));
+        Ok(())
+    }
+
+    // Adds a node joined to its valid nearest neighbours.
+    fn insert(
+        &mut self,
+        checker: &CollisionChecker,
+        configuration: Vec<f32>,
+    ) -> Result<NodeIndex, PlanError> {
+        let mut nearby: Vec<(NodeIndex, f32)> = self
+            .roadmap
+            .node_indices()
+            .map(|node| (node, distance(&self.roadmap[node], &configuration)))
+            .filter(|(_, length)| *length <= self.connection_radius)
+            .collect();
+        nearby.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let node = self.roadmap.add_node(configuration);
+        for (other, length) in nearby.into_iter().take(self.neighbours) {
+            if checker.motion_valid(
+                &self.roadmap[node],
+                &self.roadmap[other],
+                self.config.collision_resolution,
+            )? {
+                self.roadmap.add_edge(node, other, length);
+            }
+        }
+        Ok(node)
+    }
+}
+
+impl JointSpacePlanner for Prm {
+    /// Builds the roadmap first unless it was built for `checker` as it is.
+    fn plan(
+        &mut self,
+        checker: &CollisionChecker,
+        start: &[f32],
+        goal: &[f32],
+    ) -> Result<Vec<Vec<f32>>, PlanError> {
+        check_endpoints(checker, start, goal)?;
+        if checker.motion_valid(start, goal, self.config.collision_resolution)? {
+            return Ok(vec![start.to_vec(), goal.to_vec()]);
+        }
+        if self.built_for != Some((checker.revision(), checker.padding)) {
+            self.build(checker)?;
+        }
+        // Query nodes are removed again afterwards; removing the last node
+        // leaves the other indices untouched.
+        let start_node = self.insert(checker, start.to_vec())?;
+        let goal_node = self.insert(checker, goal.to_vec())?;
+        let found = astar(
+            &self.roadmap,
+            start_node,
+            |node| node == goal_node,
+            |edge| *edge.weight(),
+            |node| distance(&self.roadmap[node], goal),
+        );
+        let path = found.map(|(_, nodes)| {
+            nodes
+                .into_iter()
+                .map(|node| self.roadmap[node].clone())
+                .collect()
+        });
+        self.roadmap.remove_node(goal_node);
+        self.roadmap.remove_node(start_node);
+        path.ok_or(PlanError::NoPath)
+    }
+}
@@ -0,0 +1,148 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use super::{check_endpoints, distance, sample, steer, JointSpaceConfig, JointSpacePlanner};
+use crate::clock::Deadline;
+use crate::kinematics::CollisionChecker;
+use crate::planning::PlanError;
+
+struct Tree {
+    nodes: Vec<Vec<f32>>,
+    parents: Vec<usize>,
+}
+
+enum Extension {
+    Reached(usize),
+    Advanced(usize),
+    Trapped,
+}
+
+impl Tree {
+    fn new(root: &[f32]) -> Self {
+        Self {
+            nodes: vec![root.to_vec()],
+            parents: vec![0],
+        }
+    }
+
+    fn nearest(&self, target: &[f32]) -> usize {
+        self.nodes
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| distance(a, target).total_cmp(&distance(b, target)))
+            .map_or(0, |(index, _)| index)
+    }
+
+    // One step from the nearest node towards `target`.
+    fn extend(
+        &mut self,
+        target: &[f32],
+        checker: &CollisionChecker,
+        config: &JointSpaceConfig,
+    ) -> Result<Extension, PlanError> {
+        let nearest = self.nearest(target);
+        let next = steer(&self.nodes[nearest], target, config.step_size);
+        if !checker.motion_valid(&self.nodes[nearest], &next, config.collision_resolution)? {
+            return Ok(Extension::Trapped);
+        }
+        let reached = next == target;
+        self.nodes.push(next);
+        self.parents.push(nearest);
+        let index = self.nodes.len() - 1;
+        Ok(if reached {
+            Extension::Reached(index)
+        } else {
+            Extension::Advanced(index)
+        })
+    }
+
+    // Steps towards `target` until it is reached or blocked.
+    fn connect(
+        &mut self,
+        target: &[f32],
+        checker: &CollisionChecker,
+        config: &JointSpaceConfig,
+    ) -> Result<Extension, PlanError> {
+        loop {
+            match self.extend(target, checker, config)? {
+                Extension::Advanced(_) => continue,
+                other => return Ok(other),
+            }
+        }
+    }
+
+    // Configurations from the root to `index`.
+    fn path_to(&self, mut index: usize) -> Vec<Vec<f32>> {
+        let mut path = vec![self.nodes[index].clone()];
+        while index != 0 {
+            index = self.parents[index];
+            path.push(self.nodes[index].clone());
+        }
+        path.reverse();
+        path
+    }
+}
+
+/// Bidirectional RRT (Kuffner and LaValle, 2000): grows trees from the start
+/// and the goal, each extending towards random samples and the other greedily
+/// trying to connect to the newest node.
+pub struct RrtConnect {
+    pub config: JointSpaceConfig,
+    rng: StdRng,
+}
+
+impl RrtConnect {
+    pub fn new(config: JointSpaceConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl JointSpacePlanner for RrtConnect {
+    fn plan(
+        &mut self,
+        checker: &CollisionChecker,
+        start: &[f32],
+        goal: &[f32],
+    ) -> Result<Vec<Vec<f32>>, PlanError> {
+        let deadline = Deadline::after(&self.config.clock, self.config.time_limit);
+        check_endpoints(checker, start, goal)?;
+        if checker.motion_valid(start, goal, self.config.collision_resolution)? {
+            return Ok(vec![start.to_vec(), goal.to_vec()]);
+        }
+        let limits = checker.chain().limits();
+        // The active tree extends towards samples and the other tries to
+        // connect to it; they swap roles every iteration.
+        let mut active = Tree::new(start);
+        let mut other = Tree::new(goal);
+        let mut swapped = false;
+
+        for _ in 0..self.config.max_iterations {
+            if deadline.expired() {
+                return Err(PlanError::TimedOut);
+            }
+            let target = sample(&limits, &mut self.rng);
+            let new = match active.extend(&target, checker, &self.config)? {
+                Extension::Trapped => None,
+                Extension::Advanced(index) | Extension::Reached(index) => Some(index),
+            };
+            if let Some(new) = new {
+                let node = active.nodes[new].clone();
+                if let Extension::Reached(joined) = other.connect(&node, checker, &self.config)? {
+                    let (mut path, mut rest) = (active.path_to(new), other.path_to(joined));
+                    if swapped {
+                        std::mem::swap(&mut path, &mut rest);
+                    }
+                    rest.pop();
+                    path.extend(rest.into_iter().rev());
+                    return Ok(path);
+                }
+            }
+            std::mem::swap(&mut active, &mut other);
+            swapped = !swapped;
+        }
+        Err(PlanError::NoPath)
+    }
+}
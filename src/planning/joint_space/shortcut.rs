@@ -0,0 +1,97 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::distance;
+use crate::kinematics::CollisionChecker;
+use crate::planning::PlanError;
+
+/// Random shortcutting: repeatedly picks two points along the path and, if
+/// the straight motion between them is valid, replaces the stretch between
+/// them with it.
+pub struct Shortcut {
+    pub iterations: usize,
+    /// Largest change of any joint between collision checks.
+    pub collision_resolution: f32,
+    rng: StdRng,
+}
+
+impl Shortcut {
+    pub fn new(iterations: usize, collision_resolution: f32, seed: u64) -> Self {
+        Self {
+            iterations,
+            collision_resolution,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn smooth(
+        &mut self,
+        checker: &CollisionChecker,
+        path: &[Vec<f32>],
+    ) -> Result<Vec<Vec<f32>>, PlanError> {
+        let mut path = path.to_vec();
+        for _ in 0..self.iterations {
+            let along = cumulative_length(&path);
+            let Some(&total) = along.last().filter(|total| **total > 0.0) else {
+                break;
+            };
+            let (a, b) = (
+                self.rng.gen_range(0.0..total),
+                self.rng.gen_range(0.0..total),
+            );
+            let (from, to) = (a.min(b), a.max(b));
+            let (from_segment, from_point) = point_at(&path, &along, from);
+            let (to_segment, to_point) = point_at(&path, &along, to);
+            // Both on one straight segment already.
+            if from_segment == to_segment {
+                continue;
+            }
+            let before = along[to_segment] - along[from_segment + 1];
+            let shortened = distance(&from_point, &to_point);
+            let original = distance(&from_point, &path[from_segment + 1])
+                + before
+                + distance(&path[to_segment], &to_point);
+            if shortened >= original - 1e-6
+                || !checker.motion_valid(&from_point, &to_point, self.collision_resolution)?
+            {
+                continue;
+            }
+            let mut shortcut = path[..=from_segment].to_vec();
+            shortcut.push(from_point);
+            shortcut.push(to_point);
+            shortcut.extend_from_slice(&path[to_segment + 1..]);
+            shortcut.dedup();
+            path = shortcut;
+        }
+        Ok(path)
+    }
+}
+
+// Path length up to each waypoint.
+fn cumulative_length(path: &[Vec<f32>]) -> Vec<f32> {
+    let mut along = vec![0.0];
+    for pair in path.windows(2) {
+        along.push(along.last().copied().unwrap_or(0.0) + distance(&pair[0], &pair[1]));
+    }
+    along
+}
+
+// The segment containing `length` along the path and the point there.
+fn point_at(path: &[Vec<f32>], along: &[f32], length: f32) -> (usize, Vec<f32>) {
+    let segment = along
+        .partition_point(|value| *value <= length)
+        .saturating_sub(1)
+        .min(path.len().saturating_sub(2));
+    let span = along[segment + 1] - along[segment];
+    let fraction = if span > 0.0 {
+        ((length - along[segment]) / span).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let point = path[segment]
+        .iter()
+        .zip(&path[segment + 1])
+        .map(|(a, b)| a + (b - a) * fraction)
+        .collect();
+    (segment, point)
+}
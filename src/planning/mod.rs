@@ -0,0 +1,174 @@
+pub mod grid;
+pub mod joint_space;
+pub mod smoothing;
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::costmap::{Costmap, LayeredCostmap};
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::{Pose2D, Vec2};
+use grid::GridSearchConfig;
+use smoothing::SmoothingConfig;
+
+/// A sequence of poses to drive through, in the costmap frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path {
+    pub poses: Vec<Pose2D>,
+}
+
+impl Path {
+    pub fn new(poses: Vec<Pose2D>) -> Self {
+        Self { poses }
+    }
+
+    /// Poses through `waypoints`, each facing the next, with the first and
+    /// last headings taken from `start` and `goal`.
+    pub fn from_waypoints(waypoints: &[Vec2], start: &Pose2D, goal: &Pose2D) -> Self {
+        let mut poses: Vec<Pose2D> = waypoints
+            .iter()
+            .enumerate()
+            .map(|(index, point)| {
+                let heading = match waypoints.get(index + 1) {
+                    Some(next) => (*next - *point).to_angle(),
+                    None => goal.theta,
+                };
+                Pose2D::new(point.x, point.y, heading)
+            })
+            .collect();
+        if let Some(first) = poses.first_mut() {
+            first.theta = start.theta;
+        }
+        Self { poses }
+    }
+
+    pub fn len(&self) -> usize {
+        self.poses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.poses.is_empty()
+    }
+
+    /// Total distance along the path.
+    pub fn length(&self) -> f32 {
+        self.poses
+            .windows(2)
+            .map(|pair| pair[0].position().distance(pair[1].position()))
+            .sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    /// The start lies in or outside the costmap where it can't be entered.
+    StartBlocked,
+    /// The goal, and anything within the goal tolerance, can't be entered.
+    GoalBlocked,
+    NoPath,
+    TimedOut,
+    /// A collision sample spacing or motion check resolution is not positive.
+    InvalidResolution,
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            PlanError::StartBlocked => "start is blocked",
+            PlanError::GoalBlocked => "goal is blocked",
+            PlanError::NoPath => "no path to goal",
+            PlanError::TimedOut => "planning time limit exceeded",
+            PlanError::InvalidResolution => "collision check resolution must be positive",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+#[derive(Clone, Debug, Default)]
+pub struct GlobalPlannerConfig {
+    pub search: GridSearchConfig,
+    /// `None` returns the raw search waypoints.
+    pub smoothing: Option<SmoothingConfig>,
+}
+
+/// Plans smoothed paths between poses over a costmap.
+#[derive(Clone, Debug, Default)]
+pub struct GlobalPlanner {
+    pub config: GlobalPlannerConfig,
+}
+
+impl GlobalPlanner {
+    pub fn new(config: GlobalPlannerConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn plan(
+        &self,
+        costmap: &Costmap,
+        start: &Pose2D,
+        goal: &Pose2D,
+    ) -> Result<Path, PlanError> {
+        let search = &self.config.search;
+        let mut waypoints = grid::search(costmap, start.position(), goal.position(), search)?;
+        if let Some(smoothing) = &self.config.smoothing {
+            let segment_cost = |from, to| search.segment_cost(costmap, from, to);
+            if smoothing.shortcut {
+                waypoints = smoothing::shortcut(&waypoints, segment_cost);
+            }
+            waypoints = smoothing::resample(&waypoints, smoothing.spacing);
+            // Smoothing only checks waypoints, so fall back if it cut a corner.
+            let original = waypoints.clone();
+            waypoints = smoothing::smooth(&waypoints, smoothing, |point| {
+                search
+                    .traversal_cost(costmap, costmap.world_to_cell(point))
+                    .is_some()
+            });
+            if original
+                .windows(2)
+                .zip(waypoints.windows(2))
+                .any(|(before, after)| {
+                    segment_cost(after[0], after[1]).is_none()
+                        && segment_cost(before[0], before[1]).is_some()
+                })
+            {
+                waypoints = original;
+            }
+        }
+        Ok(Path::from_waypoints(&waypoints, start, goal))
+    }
+}
+
+/// A start and goal pose in the costmap frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlanRequest {
+    pub start: Pose2D,
+    pub goal: Pose2D,
+}
+
+/// Plans a path for each goal over the current state of a shared costmap.
+pub struct PathPlanner {
+    planner: GlobalPlanner,
+    costmap: Arc<RwLock<LayeredCostmap>>,
+}
+
+impl PathPlanner {
+    pub fn new(planner: GlobalPlanner, costmap: Arc<RwLock<LayeredCostmap>>) -> Self {
+        Self { planner, costmap }
+    }
+}
+
+impl Task for PathPlanner {
+    type Input = CarbonData<PlanRequest>;
+    type Output = CarbonData<Result<Path, PlanError>>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let costmap = self.costmap.read().expect("LayeredCostmap lock poisoned");
+        input.map(|PlanRequest { start, goal }| self.planner.plan(costmap.costmap(), &start, &goal))
+    }
+}
+
+impl Controller<PlanRequest, Result<Path, PlanError>> for PathPlanner {}
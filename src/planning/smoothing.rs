@@ -0,0 +1,143 @@
+use crate::primitives::Vec2;
+
+// Most waypoints `resample` inserts between two neighbours.
+const MAX_RESAMPLE_STEPS: f32 = 1e6;
+
+#[derive(Clone, Debug)]
+pub struct SmoothingConfig {
+    /// Replace runs of waypoints by straight segments where that costs no more.
+    pub shortcut: bool,
+    /// Distance between waypoints after resampling, in metres.
+    pub spacing: f32,
+    pub iterations: usize,
+    /// Pull of each waypoint towards its original position.
+    pub data_weight: f32,
+    /// Pull of each waypoint towards the midpoint of its neighbours.
+    pub smooth_weight: f32,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            shortcut: true,
+            spacing: 0.05,
+            iterations: 100,
+            data_weight: 0.1,
+            smooth_weight: 0.3,
+        }
+    }
+}
+
+/// Greedily joins each waypoint to the furthest later one whose straight
+/// segment costs no more than the waypoints in between. `segment_cost` returns
+/// `None` for segments that can't be driven.
+pub fn shortcut(waypoints: &[Vec2], segment_cost: impl Fn(Vec2, Vec2) -> Option<f32>) -> Vec<Vec2> {
+    if waypoints.len() <= 2 {
+        return waypoints.to_vec();
+    }
+    let mut along = vec![0.0];
+    for pair in waypoints.windows(2) {
+        let cost = segment_cost(pair[0], pair[1]).unwrap_or(f32::INFINITY);
+        along.push(along.last().copied().unwrap_or(0.0) + cost);
+    }
+    let mut result = vec![waypoints[0]];
+    let mut from = 0;
+    while from < waypoints.len() - 1 {
+        let mut to = from + 1;
+        for candidate in from + 2..waypoints.len() {
+            match segment_cost(waypoints[from], waypoints[candidate]) {
+                Some(cost) if cost <= (along[candidate] - along[from]) * (1.0 + 1e-4) => {
+                    to = candidate;
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        result.push(waypoints[to]);
+        from = to;
+    }
+    result
+}
+
+/// Inserts waypoints so that consecutive ones are at most `spacing` apart.
+/// A spacing that is not positive leaves the waypoints as they are, as does
+/// a gap too long to fill, e.g. to a non-finite waypoint.
+pub fn resample(waypoints: &[Vec2], spacing: f32) -> Vec<Vec2> {
+    if spacing.is_nan() || spacing <= 0.0 {
+        return waypoints.to_vec();
+    }
+    let Some(&first) = waypoints.first() else {
+        return Vec::new();
+    };
+    let mut result = vec![first];
+    for pair in waypoints.windows(2) {
+        let steps = (pair[0].distance(pair[1]) / spacing).ceil();
+        let steps = if steps.is_finite() && steps <= MAX_RESAMPLE_STEPS {
+            steps.max(1.0) as usize
+        } else {
+            1
+        };
+        result.extend((1..=steps).map(|step| pair[0].lerp(pair[1], step as f32 / steps as f32)));
+    }
+    result
+}
+
+/// Gradient-descent smoothing with fixed end points. Moves that would put a
+/// waypoint where `is_free` is false are rejected.
+pub fn smooth(
+    waypoints: &[Vec2],
+    config: &SmoothingConfig,
+    is_free: impl Fn(Vec2) -> bool,
+) -> Vec<Vec2> {
+    let mut smoothed = waypoints.to_vec();
+    for _ in 0..config.iterations {
+        let mut change = 0.0;
+        for index in 1..smoothed.len().saturating_sub(1) {
+            let current = smoothed[index];
+            let updated = current
+                + config.data_weight * (waypoints[index] - current)
+                + config.smooth_weight
+                    * (smoothed[index - 1] + smoothed[index + 1] - 2.0 * current);
+            if is_free(updated) {
+                change += current.distance(updated);
+                smoothed[index] = updated;
+            }
+        }
+        if change < 1e-6 {
+            break;
+        }
+    }
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resampling_spaces_waypoints_evenly() {
+        let waypoints = [Vec2::ZERO, Vec2::new(1.0, 0.0), Vec2::new(1.0, 0.5)];
+        let resampled = resample(&waypoints, 0.25);
+        assert_eq!(resampled.len(), 7);
+        assert_eq!(resampled.last(), Some(&Vec2::new(1.0, 0.5)));
+        for pair in resampled.windows(2) {
+            assert!(pair[0].distance(pair[1]) <= 0.25 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn resampling_without_a_positive_spacing_keeps_the_waypoints() {
+        let waypoints = [Vec2::ZERO, Vec2::new(1.0, 0.0)];
+        for spacing in [0.0, -0.1, f32::NAN] {
+            assert_eq!(resample(&waypoints, spacing), waypoints);
+        }
+    }
+
+    #[test]
+    fn resampling_skips_gaps_it_cannot_fill() {
+        let waypoints = [Vec2::ZERO, Vec2::new(f32::INFINITY, 0.0)];
+        assert_eq!(resample(&waypoints, 0.05), waypoints);
+        let waypoints = [Vec2::ZERO, Vec2::new(1.0, 0.0)];
+        assert_eq!(resample(&waypoints, 1e-12).len(), 2);
+    }
+}
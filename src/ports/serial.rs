@@ -0,0 +1,59 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use serialport::SerialPort;
+
+use super::Transport;
+
+pub struct SerialTransport {
+    path: String,
+    baud_rate: u32,
+    port: Option<Box<dyn SerialPort>>,
+}
+
+impl SerialTransport {
+    pub fn new(path: &str, baud_rate: u32) -> Self {
+        Self {
+            path: path.to_string(),
+            baud_rate,
+            port: None,
+        }
+    }
+
+    fn port(&mut self) -> io::Result<&mut Box<dyn SerialPort>> {
+        self.port
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+impl Transport for SerialTransport {
+    fn open(&mut self, read_timeout: Duration) -> io::Result<()> {
+        let port = serialport::new(&self.path, self.baud_rate)
+            .timeout(read_timeout)
+            .open()?;
+        self.port = Some(port);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.port = None;
+    }
+
+    fn is_open(&self) -> bool {
+        self.port.is_some()
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.port()?.read(buffer) {
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => Ok(0),
+            result => result,
+        }
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        let port = self.port()?;
+        port.write_all(buffer)?;
+        port.flush()
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::Transport;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct TcpTransport {
+    address: SocketAddr,
+    stream: Option<TcpStream>,
+}
+
+impl TcpTransport {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            stream: None,
+        }
+    }
+
+    fn stream(&mut self) -> io::Result<&mut TcpStream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+impl Transport for TcpTransport {
+    fn open(&mut self, read_timeout: Duration) -> io::Result<()> {
+        let stream = TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(read_timeout))?;
+        stream.set_nodelay(true)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.stream()?.read(buffer) {
+            Ok(0) if !buffer.is_empty() => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "connection closed by peer",
+            )),
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(0)
+            }
+            result => result,
+        }
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.stream()?.write_all(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::clock::SimulatedClock;
+    use crate::ports::{ConnectionState, Port, ReconnectPolicy};
+
+    fn read_some(port: &mut Port) -> Vec<u8> {
+        let mut buffer = [0; 64];
+        for _ in 0..50 {
+            let length = port.read(&mut buffer).expect("read failed");
+            if length > 0 {
+                return buffer[..length].to_vec();
+            }
+        }
+        panic!("nothing arrived");
+    }
+
+    #[test]
+    fn reconnects_with_backoff_after_the_peer_dies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let clock = Arc::new(SimulatedClock::new());
+        let mut port = Port::new(format!("tcp://{address}").parse().unwrap())
+            .with_clock(clock.clone())
+            .with_read_timeout(Duration::from_millis(20))
+            .with_reconnect_policy(ReconnectPolicy::Backoff {
+                initial_delay: Duration::from_millis(100),
+                max_delay: Duration::from_secs(1),
+                max_attempts: None,
+            });
+        port.connect().unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        peer.write_all(b"hello").unwrap();
+        assert_eq!(read_some(&mut port), b"hello");
+
+        drop(peer);
+        drop(listener);
+        let mut buffer = [0; 64];
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 1 });
+
+        // Nothing is attempted before the delay is up.
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 1 });
+
+        // The peer is still down, so the attempt fails and the delay doubles.
+        clock.step(Duration::from_millis(100));
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 2 });
+        clock.step(Duration::from_millis(100));
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 2 });
+
+        let listener = TcpListener::bind(address).unwrap();
+        clock.step(Duration::from_millis(100));
+        assert_eq!(port.read(&mut buffer).unwrap(), 0);
+        assert_eq!(port.state(), ConnectionState::Connected);
+        let (mut peer, _) = listener.accept().unwrap();
+        port.write(b"ping").unwrap();
+        let mut received = [0; 4];
+        peer.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"ping");
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let clock = Arc::new(SimulatedClock::new());
+        let mut port = Port::new(format!("tcp://{address}").parse().unwrap())
+            .with_clock(clock.clone())
+            .with_reconnect_policy(ReconnectPolicy::Backoff {
+                initial_delay: Duration::from_millis(100),
+                max_delay: Duration::from_secs(1),
+                max_attempts: Some(2),
+            });
+        assert!(port.connect().is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 1 });
+        clock.step(Duration::from_millis(100));
+        assert!(port.read(&mut [0; 8]).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 2 });
+        clock.step(Duration::from_millis(200));
+        assert!(port.read(&mut [0; 8]).is_err());
+        assert_eq!(port.state(), ConnectionState::Failed);
+    }
+}
@@ -0,0 +1,228 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::Duration;
+
+use super::Transport;
+
+enum Mode {
+    Unicast {
+        local: SocketAddr,
+        remote: Option<SocketAddr>,
+    },
+    Multicast {
+        group: Ipv4Addr,
+        port: u16,
+        interface: Ipv4Addr,
+    },
+}
+
+/// Datagram transport. Each `read` returns at most one datagram.
+///
+/// Unicast sockets with a remote address only accept datagrams from that peer.
+/// Without one, writes go to whoever sent the most recent datagram.
+pub struct UdpTransport {
+    mode: Mode,
+    socket: Option<UdpSocket>,
+    last_sender: Option<SocketAddr>,
+}
+
+impl UdpTransport {
+    pub fn unicast(local: SocketAddr, remote: Option<SocketAddr>) -> Self {
+        Self {
+            mode: Mode::Unicast { local, remote },
+            socket: None,
+            last_sender: None,
+        }
+    }
+
+    pub fn multicast(group: Ipv4Addr, port: u16, interface: Ipv4Addr) -> Self {
+        Self {
+            mode: Mode::Multicast {
+                group,
+                port,
+                interface,
+            },
+            socket: None,
+            last_sender: None,
+        }
+    }
+
+    /// Address the socket is bound to, useful when binding to port 0.
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.socket.as_ref()?.local_addr().ok()
+    }
+
+    fn socket(&self) -> io::Result<&UdpSocket> {
+        self.socket
+            .as_ref()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+impl Transport for UdpTransport {
+    fn open(&mut self, read_timeout: Duration) -> io::Result<()> {
+        let socket = match self.mode {
+            Mode::Unicast { local, remote } => {
+                let socket = UdpSocket::bind(local)?;
+                if let Some(remote) = remote {
+                    socket.connect(remote)?;
+                }
+                socket
+            }
+            Mode::Multicast {
+                group,
+                port,
+                interface,
+            } => {
+                let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))?;
+                socket.join_multicast_v4(&group, &interface)?;
+                socket.set_multicast_loop_v4(true)?;
+                socket
+            }
+        };
+        socket.set_read_timeout(Some(read_timeout))?;
+        self.socket = Some(socket);
+        self.last_sender = None;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let (
+            Some(socket),
+            Mode::Multicast {
+                group, interface, ..
+            },
+        ) = (&self.socket, &self.mode)
+        {
+            let _ = socket.leave_multicast_v4(group, interface);
+        }
+        self.socket = None;
+    }
+
+    fn is_open(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.socket()?.recv_from(buffer) {
+            Ok((length, sender)) => {
+                self.last_sender = Some(sender);
+                Ok(length)
+            }
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(0)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        let socket = self.socket()?;
+        let sent = match self.mode {
+            Mode::Unicast {
+                remote: Some(_), ..
+            } => socket.send(buffer)?,
+            Mode::Unicast { remote: None, .. } => {
+                let destination = self.last_sender.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "no remote address and no datagram received yet",
+                    )
+                })?;
+                socket.send_to(buffer, destination)?
+            }
+            Mode::Multicast { group, port, .. } => {
+                socket.send_to(buffer, SocketAddrV4::new(group, port))?
+            }
+        };
+        if sent < buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "datagram was truncated",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::clock::SimulatedClock;
+    use crate::ports::{ConnectionState, Port, ReconnectPolicy};
+
+    fn free_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn receive(peer: &UdpSocket) -> Vec<u8> {
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut buffer = [0; 64];
+        let length = peer.recv(&mut buffer).unwrap();
+        buffer[..length].to_vec()
+    }
+
+    #[test]
+    fn reconnects_with_backoff_after_the_peer_dies() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote = peer.local_addr().unwrap();
+        let local = free_port();
+        let clock = Arc::new(SimulatedClock::new());
+        let mut port = Port::new(format!("udp://127.0.0.1:{local}@{remote}").parse().unwrap())
+            .with_clock(clock.clone())
+            .with_read_timeout(Duration::from_millis(20))
+            .with_reconnect_policy(ReconnectPolicy::Backoff {
+                initial_delay: Duration::from_millis(100),
+                max_delay: Duration::from_secs(1),
+                max_attempts: None,
+            });
+        port.connect().unwrap();
+        port.write(b"ping").unwrap();
+        assert_eq!(receive(&peer), b"ping");
+
+        // With the peer gone, the port-unreachable reply fails the socket.
+        drop(peer);
+        let mut buffer = [0; 64];
+        for _ in 0..50 {
+            if port
+                .write(b"ping")
+                .and_then(|_| port.read(&mut buffer))
+                .is_err()
+            {
+                break;
+            }
+        }
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 1 });
+
+        // Hold the local address so the next attempt fails.
+        let blocker = UdpSocket::bind(("127.0.0.1", local)).unwrap();
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 1 });
+        clock.step(Duration::from_millis(100));
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 2 });
+        clock.step(Duration::from_millis(100));
+        assert!(port.read(&mut buffer).is_err());
+        assert_eq!(port.state(), ConnectionState::Reconnecting { attempt: 2 });
+
+        drop(blocker);
+        let peer = UdpSocket::bind(remote).unwrap();
+        clock.step(Duration::from_millis(100));
+        assert_eq!(port.read(&mut buffer).unwrap(), 0);
+        assert_eq!(port.state(), ConnectionState::Connected);
+        port.write(b"back").unwrap();
+        assert_eq!(receive(&peer), b"back");
+    }
+}
@@ -0,0 +1,309 @@
+use glam::f32 as glam_primitives;
+
+pub use glam::IVec2;
+pub use glam_primitives::{Quat, Vec2, Vec3};
+
+#[derive(Clone, Default, Debug)]
+pub struct Translation(glam_primitives::Vec3A);
+
+impl Translation {
+    fn to_glam_vec3(&self) -> glam_primitives::Vec3 {
+        glam_primitives::Vec3::from(self.0)
+    }
+
+    pub fn zero() -> Self {
+        Self(glam_primitives::Vec3A::ZERO)
+    }
+
+    pub fn from_vector(vector: &[f32; 3]) -> Self {
+        Self(glam_primitives::Vec3A::new(vector[0], vector[1], vector[2]))
+    }
+
+    pub fn to_vector(&self) -> [f32; 3] {
+        [self.0.x, self.0.y, self.0.z]
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Rotation(glam_primitives::Mat3A);
+
+impl Rotation {
+    pub fn from_quaternion(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self(glam_primitives::Mat3A::from_quat(
+            glam_primitives::Quat::from_xyzw(x, y, z, w),
+        ))
+    }
+
+    pub fn from_yaw(yaw: f32) -> Self {
+        Self(glam_primitives::Mat3A::from_rotation_z(yaw))
+    }
+
+    fn to_glam_quat(&self) -> glam_primitives::Quat {
+        glam_primitives::Quat::from_mat3a(&self.0)
+    }
+
+    pub fn to_quaternion(&self) -> [f32; 4] {
+        self.to_glam_quat().to_array()
+    }
+
+    pub fn identity() -> Self {
+        Self(glam_primitives::Mat3A::IDENTITY)
+    }
+
+    pub fn to_matrix(&self) -> [[f32; 3]; 3] {
+        self.0.to_cols_array_2d()
+    }
+
+    pub fn from_matrix(matrix: &[[f32; 3]; 3]) -> Self {
+        Self(glam_primitives::Mat3A::from_cols_array_2d(matrix))
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Transform(glam_primitives::Affine3A);
+
+impl Transform {
+    pub fn from_translation_and_rotation(translation: Translation, rotation: Rotation) -> Self {
+        Self(glam_primitives::Affine3A::from_rotation_translation(
+            rotation.to_glam_quat(),
+            translation.to_glam_vec3(),
+        ))
+    }
+
+    pub fn identity() -> Self {
+        Self(glam_primitives::Affine3A::IDENTITY)
+    }
+
+    pub fn to_matrix(&self) -> [[f32; 4]; 4] {
+        glam_primitives::Mat4::from(self.0).to_cols_array_2d()
+    }
+
+    pub fn from_matrix(matrix: &[[f32; 4]; 4]) -> Self {
+        Self(glam_primitives::Affine3A::from_mat4(
+            glam_primitives::Mat4::from_cols_array_2d(matrix),
+        ))
+    }
+
+    pub fn apply(&self, other: Transform) -> Transform {
+        Self(self.0 * other.0)
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Self(self.0.inverse())
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.0.transform_point3(point)
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.0.translation.into()
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self(glam_primitives::Affine3A::from_translation(translation))
+    }
+
+    /// Rotation by `angle` radians about the unit vector `axis`.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+        Self(glam_primitives::Affine3A::from_axis_angle(axis, angle))
+    }
+
+    /// Rotation given as roll, pitch and yaw about fixed X, Y and Z axes,
+    /// applied in that order.
+    pub fn from_translation_and_euler(translation: Vec3, roll: f32, pitch: f32, yaw: f32) -> Self {
+        Self(glam_primitives::Affine3A::from_rotation_translation(
+            glam_primitives::Quat::from_euler(glam::EulerRot::ZYX, yaw, pitch, roll),
+            translation,
+        ))
+    }
+
+    /// Roll, pitch and yaw of the rotation; see [`Transform::from_translation_and_euler`].
+    pub fn euler(&self) -> (f32, f32, f32) {
+        let (yaw, pitch, roll) =
+            glam_primitives::Quat::from_mat3a(&self.0.matrix3).to_euler(glam::EulerRot::ZYX);
+        (roll, pitch, yaw)
+    }
+}
+
+// C layout so clouds can be shared in place between processes.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct Point {
+    pub position: Vec3,
+    pub intensity: f32,
+}
+
+impl Point {
+    pub fn new(position: Vec3, intensity: f32) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+
+    /// Distance from the origin of the frame the point is expressed in.
+    pub fn range(&self) -> f32 {
+        self.position.length()
+    }
+
+    /// Bearing in the XY plane of the frame the point is expressed in.
+    pub fn bearing(&self) -> f32 {
+        self.position.y.atan2(self.position.x)
+    }
+}
+
+/// Planar pose: position in metres and heading in radians.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Pose2D {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+}
+
+impl Pose2D {
+    pub fn new(x: f32, y: f32, theta: f32) -> Self {
+        Self { x, y, theta }
+    }
+
+    pub fn identity() -> Self {
+        Self::default()
+    }
+
+    pub fn position(&self) -> Vec2 {
+        Vec2::new(self.x, self.y)
+    }
+
+    /// `self ∘ other`: `other` expressed relative to `self`, mapped into the frame
+    /// `self` is expressed in.
+    pub fn compose(&self, other: &Pose2D) -> Pose2D {
+        let (sin, cos) = self.theta.sin_cos();
+        Pose2D {
+            x: self.x + cos * other.x - sin * other.y,
+            y: self.y + sin * other.x + cos * other.y,
+            theta: normalize_angle(self.theta + other.theta),
+        }
+    }
+
+    pub fn inverse(&self) -> Pose2D {
+        let (sin, cos) = self.theta.sin_cos();
+        Pose2D {
+            x: -cos * self.x - sin * self.y,
+            y: sin * self.x - cos * self.y,
+            theta: normalize_angle(-self.theta),
+        }
+    }
+
+    /// Pose of `other` relative to `self`.
+    pub fn between(&self, other: &Pose2D) -> Pose2D {
+        self.inverse().compose(other)
+    }
+
+    pub fn transform_point(&self, point: Vec2) -> Vec2 {
+        let (sin, cos) = self.theta.sin_cos();
+        Vec2::new(
+            self.x + cos * point.x - sin * point.y,
+            self.y + sin * point.x + cos * point.y,
+        )
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        Transform::from_translation_and_rotation(
+            Translation::from_vector(&[self.x, self.y, 0.0]),
+            Rotation::from_yaw(self.theta),
+        )
+    }
+
+    /// Projects a 3D transform onto the XY plane.
+    pub fn from_transform(transform: &Transform) -> Pose2D {
+        let translation = transform.translation();
+        let x_axis = transform.transform_point(Vec3::X) - translation;
+        Pose2D::new(translation.x, translation.y, x_axis.y.atan2(x_axis.x))
+    }
+}
+
+/// Planar pose with a row-major covariance over `(x, y, theta)`.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct PoseWithCovariance2D {
+    pub pose: Pose2D,
+    pub covariance: [[f32; 3]; 3],
+}
+
+/// Planar body velocity: forward speed in m/s and yaw rate in rad/s.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Twist2D {
+    pub linear: f32,
+    pub angular: f32,
+}
+
+impl Twist2D {
+    pub fn new(linear: f32, angular: f32) -> Self {
+        Self { linear, angular }
+    }
+
+    /// Pose change from holding this twist for `dt` seconds, along an arc.
+    pub fn integrate(&self, dt: f32) -> Pose2D {
+        let theta = self.angular * dt;
+        let distance = self.linear * dt;
+        if theta.abs() < 1e-6 {
+            return Pose2D::new(distance, 0.0, theta);
+        }
+        let radius = distance / theta;
+        Pose2D::new(
+            radius * theta.sin(),
+            radius * (1.0 - theta.cos()),
+            normalize_angle(theta),
+        )
+    }
+}
+
+/// Pose with a row-major covariance over `(x, y, z, roll, pitch, yaw)`.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct PoseWithCovariance3D {
+    pub transform: Transform,
+    pub covariance: [[f32; 6]; 6],
+}
+
+/// Body velocity: linear in m/s and angular in rad/s, both in the body frame.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Twist3D {
+    pub linear: Vec3,
+    pub angular: Vec3,
+}
+
+impl Twist3D {
+    pub fn new(linear: Vec3, angular: Vec3) -> Self {
+        Self { linear, angular }
+    }
+}
+
+/// Setpoints for one joint, in radians or metres, per second, and newton
+/// metres or newtons. Unset fields are left to the joint's controller.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct JointCommand {
+    pub position: Option<f32>,
+    pub velocity: Option<f32>,
+    pub effort: Option<f32>,
+}
+
+/// Wraps an angle into `(-π, π]`.
+pub fn normalize_angle(angle: f32) -> f32 {
+    use std::f32::consts::PI;
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped <= -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+/// [`normalize_angle`] in double precision, for filters and optimizers.
+pub fn wrap_angle(angle: f64) -> f64 {
+    use std::f64::consts::{PI, TAU};
+    let wrapped = (angle + PI).rem_euclid(TAU) - PI;
+    if wrapped <= -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
@@ -0,0 +1,520 @@
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::costmap::footprint::Footprint;
+use crate::drive::{CommandVelocity, DifferentialDrive, TwistLimits};
+use crate::joints::{FrameId, TransformTree};
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::{Twist2D, Vec2};
+
+/// Distances from the footprint outline at which obstacles slow the base
+/// down and stop it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SafetyZone {
+    /// Obstacles closer than this stop the base.
+    pub stop_distance: f32,
+    /// Obstacles closer than this scale the forward speed down linearly, to
+    /// zero at the stop distance.
+    pub slow_distance: f32,
+}
+
+impl Default for SafetyZone {
+    fn default() -> Self {
+        Self {
+            stop_distance: 0.1,
+            slow_distance: 0.5,
+        }
+    }
+}
+
+/// Why the supervisor changed a command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InterventionReason {
+    /// The software e-stop is latched, with the reason given when engaging it.
+    EmergencyStop(String),
+    /// No command arrived within the command timeout.
+    CommandTimeout,
+    /// No scan arrived within the scan timeout.
+    ScanTimeout,
+    ObstacleStop {
+        distance: f32,
+    },
+    ObstacleSlowdown {
+        distance: f32,
+        scale: f32,
+    },
+    VelocityLimited,
+    AccelerationLimited,
+}
+
+impl fmt::Display for InterventionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterventionReason::EmergencyStop(reason) => write!(f, "emergency stop: {reason}"),
+            InterventionReason::CommandTimeout => f.write_str("commands went stale"),
+            InterventionReason::ScanTimeout => f.write_str("scans went stale"),
+            InterventionReason::ObstacleStop { distance } => {
+                write!(f, "obstacle {distance} m away, stopping")
+            }
+            InterventionReason::ObstacleSlowdown { distance, scale } => {
+                write!(f, "obstacle {distance} m away, slowing to {scale} of speed")
+            }
+            InterventionReason::VelocityLimited => f.write_str("velocity limited"),
+            InterventionReason::AccelerationLimited => f.write_str("acceleration limited"),
+        }
+    }
+}
+
+/// A logged change to the commands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Intervention {
+    /// Nanoseconds, as in [`crate::links::CarbonMetadata`].
+    pub timestamp: u64,
+    pub reason: InterventionReason,
+    /// The body twist asked for by the controller.
+    pub requested: Twist2D,
+    /// The body twist sent to the wheels.
+    pub commanded: Twist2D,
+}
+
+/// Sits between the drive controller and the wheel actuators and rewrites
+/// wheel commands that are unsafe. Stops (e-stop, stale commands or scans, an
+/// obstacle in the stop zone) zero the wheels at once; otherwise the command
+/// is limited in speed, slowed near obstacles and limited in acceleration.
+///
+/// Obstacles are taken from the latest scan, moved into the base frame.
+/// While driving, only points on the side the base is driving towards count,
+/// so it can back away from an obstacle. Turning in place checks the circle
+/// swept by the footprint's farthest corner.
+///
+/// Each reason is logged when it starts applying, and again after it has
+/// lapsed for at least one update.
+#[derive(Clone, Debug)]
+pub struct SafetySupervisor {
+    pub drive: DifferentialDrive,
+    pub footprint: Footprint,
+    pub limits: TwistLimits,
+    pub zone: SafetyZone,
+    /// Frame the footprint and the wheel commands are expressed in.
+    pub base_frame: FrameId,
+    /// Fastest wheel angular velocity, rad/s. Commands beyond it are scaled
+    /// down, keeping the turning radius.
+    pub max_wheel_velocity: Option<f32>,
+    /// Seconds after the last command before the wheels are stopped.
+    pub command_timeout: f32,
+    /// Seconds after the last scan before the wheels are stopped; `None`
+    /// drives on without scans.
+    pub scan_timeout: Option<f32>,
+    emergency_stop: Option<String>,
+    command: Option<(u64, Twist2D)>,
+    obstacles: Option<(u64, Vec<Vec2>)>,
+    output: Twist2D,
+    last_update: Option<u64>,
+    active: Vec<InterventionReason>,
+    log: Vec<Intervention>,
+}
+
+impl SafetySupervisor {
+    pub fn new(drive: DifferentialDrive, footprint: Footprint, limits: TwistLimits) -> Self {
+        Self {
+            drive,
+            footprint,
+            limits,
+            zone: SafetyZone::default(),
+            base_frame: FrameId::root(),
+            max_wheel_velocity: None,
+            command_timeout: 0.5,
+            scan_timeout: None,
+            emergency_stop: None,
+            command: None,
+            obstacles: None,
+            output: Twist2D::default(),
+            last_update: None,
+            active: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    pub fn with_zone(mut self, zone: SafetyZone) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    pub fn with_base_frame(mut self, base_frame: FrameId) -> Self {
+        self.base_frame = base_frame;
+        self
+    }
+
+    pub fn with_max_wheel_velocity(mut self, max_wheel_velocity: f32) -> Self {
+        self.max_wheel_velocity = Some(max_wheel_velocity);
+        self
+    }
+
+    pub fn with_command_timeout(mut self, command_timeout: f32) -> Self {
+        self.command_timeout = command_timeout;
+        self
+    }
+
+    pub fn with_scan_timeout(mut self, scan_timeout: f32) -> Self {
+        self.scan_timeout = Some(scan_timeout);
+        self
+    }
+
+    /// Latches the e-stop until [`SafetySupervisor::release`]. A second call
+    /// keeps the first reason.
+    pub fn emergency_stop(&mut self, reason: &str) {
+        self.emergency_stop
+            .get_or_insert_with(|| reason.to_string());
+    }
+
+    /// Unlatches the e-stop. Returns `false` if it was not engaged.
+    pub fn release(&mut self) -> bool {
+        self.emergency_stop.take().is_some()
+    }
+
+    pub fn is_emergency_stopped(&self) -> bool {
+        self.emergency_stop.is_some()
+    }
+
+    /// Records the controller's wheel commands received at `timestamp`.
+    pub fn command(&mut self, left: CommandVelocity, right: CommandVelocity, timestamp: u64) {
+        self.command = Some((timestamp, self.drive.twist(left.0, right.0)));
+    }
+
+    /// Records a scan taken at `timestamp`, moving its points into the base
+    /// frame through `frames`. Returns `false`, keeping the previous scan, if
+    /// the cloud's frame cannot be related to the base frame.
+    pub fn scan(&mut self, cloud: &PointCloud, frames: &TransformTree, timestamp: u64) -> bool {
+        let transform = if cloud.frame == self.base_frame {
+            Default::default()
+        } else {
+            match frames.lookup(self.base_frame, cloud.frame) {
+                Some(transform) => transform,
+                None => return false,
+            }
+        };
+        let points = cloud
+            .points
+            .iter()
+            .map(|point| transform.transform_point(point.position).truncate())
+            .filter(|point| point.is_finite())
+            .collect();
+        self.obstacles = Some((timestamp, points));
+        true
+    }
+
+    /// The last body twist sent to the wheels.
+    pub fn twist(&self) -> Twist2D {
+        self.output
+    }
+
+    /// Interventions logged since the last call, oldest first.
+    pub fn take_interventions(&mut self) -> Vec<Intervention> {
+        mem::take(&mut self.log)
+    }
+
+    /// The wheel commands to send at `timestamp`, in nanoseconds.
+    pub fn update(&mut self, timestamp: u64) -> (CommandVelocity, CommandVelocity) {
+        let age = |since: u64| timestamp.saturating_sub(since) as f32 * 1e-9;
+        let dt = self.last_update.map(&age);
+        self.last_update = Some(timestamp);
+
+        let mut reasons = Vec::new();
+        if let Some(reason) = &self.emergency_stop {
+            reasons.push(InterventionReason::EmergencyStop(reason.clone()));
+        }
+        let requested = match self.command {
+            Some((received, twist)) if age(received) <= self.command_timeout => twist,
+            Some(_) => {
+                reasons.push(InterventionReason::CommandTimeout);
+                Twist2D::default()
+            }
+            None => Twist2D::default(),
+        };
+        let scan_stale = match (self.scan_timeout, &self.obstacles) {
+            (Some(timeout), Some((received, _))) => age(*received) > timeout,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if scan_stale && requested != Twist2D::default() {
+            reasons.push(InterventionReason::ScanTimeout);
+        }
+
+        let mut twist = if reasons.is_empty() {
+            self.limited(requested, dt, &mut reasons)
+        } else {
+            Twist2D::default()
+        };
+        if let Some(limit) = self.max_wheel_velocity {
+            let (left, right) = self.drive.wheel_commands(&twist);
+            let fastest = left.0.abs().max(right.0.abs());
+            if fastest > limit {
+                let scale = limit / fastest;
+                twist = Twist2D::new(twist.linear * scale, twist.angular * scale);
+                reasons.push(InterventionReason::VelocityLimited);
+            }
+        }
+        self.output = twist;
+        self.record(reasons, timestamp, requested);
+        self.drive.wheel_commands(&twist)
+    }
+
+    // Speed, obstacle and acceleration limiting of a command not already
+    // stopped.
+    fn limited(
+        &self,
+        requested: Twist2D,
+        dt: Option<f32>,
+        reasons: &mut Vec<InterventionReason>,
+    ) -> Twist2D {
+        let mut twist = self.limits.clamp_velocity(&requested);
+        if twist != requested {
+            reasons.push(InterventionReason::VelocityLimited);
+        }
+        if let Some(distance) = self.nearest_obstacle(&twist) {
+            if distance <= self.zone.stop_distance {
+                reasons.push(InterventionReason::ObstacleStop { distance });
+                return Twist2D::default();
+            }
+            if distance < self.zone.slow_distance {
+                let scale = (distance - self.zone.stop_distance)
+                    / (self.zone.slow_distance - self.zone.stop_distance);
+                if twist.linear != 0.0 {
+                    twist.linear *= scale;
+                } else {
+                    twist.angular *= scale;
+                }
+                reasons.push(InterventionReason::ObstacleSlowdown { distance, scale });
+            }
+        }
+        // Nothing to limit against before the first update.
+        let Some(dt) = dt else {
+            return twist;
+        };
+        let reachable = self.limits.apply(&twist, &self.output, dt);
+        if reachable != twist {
+            reasons.push(InterventionReason::AccelerationLimited);
+        }
+        reachable
+    }
+
+    // Distance from the footprint to the nearest point on the side the base
+    // drives towards or, turning in place, from the swept circle.
+    fn nearest_obstacle(&self, twist: &Twist2D) -> Option<f32> {
+        let (_, points) = self.obstacles.as_ref()?;
+        if twist.linear != 0.0 {
+            points
+                .iter()
+                .filter(|point| point.x * twist.linear > 0.0)
+                .map(|&point| self.footprint.distance(point))
+                .min_by(f32::total_cmp)
+        } else if twist.angular != 0.0 {
+            let reach = self.footprint.circumscribed_radius();
+            points
+                .iter()
+                .map(|point| point.length() - reach)
+                .min_by(f32::total_cmp)
+        } else {
+            None
+        }
+    }
+
+    // Logs the reasons not in effect on the previous update.
+    fn record(&mut self, reasons: Vec<InterventionReason>, timestamp: u64, requested: Twist2D) {
+        for reason in &reasons {
+            let ongoing = self
+                .active
+                .iter()
+                .any(|active| mem::discriminant(active) == mem::discriminant(reason));
+            if !ongoing {
+                self.log.push(Intervention {
+                    timestamp,
+                    reason: reason.clone(),
+                    requested,
+                    commanded: self.output,
+                });
+            }
+        }
+        self.active = reasons;
+    }
+}
+
+/// Runs a [`SafetySupervisor`] once per control cycle, with the controller's
+/// wheel commands when new ones arrived and `None` otherwise, so stale
+/// commands are caught. Scans and the e-stop go through the task; scans are
+/// moved into the base frame through the shared transform tree.
+pub struct SafetyMonitor {
+    supervisor: Mutex<SafetySupervisor>,
+    tree: Arc<RwLock<TransformTree>>,
+}
+
+impl SafetyMonitor {
+    pub fn new(supervisor: SafetySupervisor, tree: Arc<RwLock<TransformTree>>) -> Self {
+        Self {
+            supervisor: Mutex::new(supervisor),
+            tree,
+        }
+    }
+
+    /// Returns `false` if the cloud's frame is not linked to the base frame.
+    pub fn scan(&self, cloud: &CarbonData<PointCloud>) -> bool {
+        let tree = self.tree.read().expect("TransformTree lock poisoned");
+        self.supervisor
+            .lock()
+            .expect("SafetySupervisor lock poisoned")
+            .scan(cloud.data(), &tree, cloud.metadata.timestamp)
+    }
+
+    pub fn emergency_stop(&self, reason: &str) {
+        self.supervisor
+            .lock()
+            .expect("SafetySupervisor lock poisoned")
+            .emergency_stop(reason);
+    }
+
+    pub fn release(&self) -> bool {
+        self.supervisor
+            .lock()
+            .expect("SafetySupervisor lock poisoned")
+            .release()
+    }
+
+    pub fn take_interventions(&self) -> Vec<Intervention> {
+        self.supervisor
+            .lock()
+            .expect("SafetySupervisor lock poisoned")
+            .take_interventions()
+    }
+}
+
+impl Task for SafetyMonitor {
+    type Input = CarbonData<Option<(CommandVelocity, CommandVelocity)>>;
+    type Output = CarbonData<(CommandVelocity, CommandVelocity)>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let timestamp = input.metadata.timestamp;
+        let mut supervisor = self
+            .supervisor
+            .lock()
+            .expect("SafetySupervisor lock poisoned");
+        input.map(|command| {
+            if let Some((left, right)) = command {
+                supervisor.command(left, right, timestamp);
+            }
+            supervisor.update(timestamp)
+        })
+    }
+}
+
+impl Controller<Option<(CommandVelocity, CommandVelocity)>, (CommandVelocity, CommandVelocity)>
+    for SafetyMonitor
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::primitives::{Point, Transform, Vec3};
+
+    fn supervisor(footprint: Footprint) -> SafetySupervisor {
+        SafetySupervisor::new(
+            DifferentialDrive::new(0.1, 0.4),
+            footprint,
+            TwistLimits::default(),
+        )
+    }
+
+    fn request(supervisor: &mut SafetySupervisor, twist: Twist2D, timestamp: u64) -> Twist2D {
+        let (left, right) = supervisor.drive.wheel_commands(&twist);
+        supervisor.command(left, right, timestamp);
+        supervisor.update(timestamp);
+        supervisor.twist()
+    }
+
+    fn cloud(points: &[[f32; 2]], frame: FrameId) -> PointCloud {
+        let points = points
+            .iter()
+            .map(|&[x, y]| Point::new(Vec3::new(x, y, 0.0), 1.0))
+            .collect();
+        PointCloud::in_frame(points, frame)
+    }
+
+    #[test]
+    fn scans_are_moved_into_the_base_frame() {
+        let mut tree = TransformTree::new("base");
+        let sensor = tree.add_frame(
+            "rear_lidar",
+            FrameId::root(),
+            Transform::from_translation_and_euler(Vec3::new(0.3, 0.0, 0.0), 0.0, 0.0, PI),
+        );
+        let mut supervisor = supervisor(Footprint::circle(0.2));
+
+        // 0.2 m behind a lidar facing backwards is 0.5 m ahead of the base.
+        assert!(supervisor.scan(&cloud(&[[-0.2, 0.0]], sensor), &tree, 0));
+        let twist = request(&mut supervisor, Twist2D::new(0.4, 0.0), 0);
+
+        assert!((twist.linear - 0.2).abs() < 1e-4, "{twist:?}");
+        assert!(matches!(
+            supervisor.take_interventions()[0].reason,
+            InterventionReason::ObstacleSlowdown { .. }
+        ));
+    }
+
+    #[test]
+    fn scans_in_unknown_frames_are_rejected() {
+        let tree = TransformTree::new("base");
+        let mut supervisor = supervisor(Footprint::circle(0.2));
+
+        assert!(!supervisor.scan(&cloud(&[[0.25, 0.0]], FrameId::from_index(7)), &tree, 0));
+        let twist = request(&mut supervisor, Twist2D::new(0.4, 0.0), 0);
+
+        assert_eq!(twist, Twist2D::new(0.4, 0.0));
+    }
+
+    #[test]
+    fn turning_in_place_checks_the_swept_footprint() {
+        let tree = TransformTree::new("base");
+        let rectangle = Footprint::from_points(&[
+            Vec2::new(0.4, 0.2),
+            Vec2::new(-0.4, 0.2),
+            Vec2::new(-0.4, -0.2),
+            Vec2::new(0.4, -0.2),
+        ]);
+        let mut supervisor = supervisor(rectangle);
+
+        // Clear of the side, but inside the circle the corners sweep.
+        supervisor.scan(&cloud(&[[0.0, 0.3]], FrameId::root()), &tree, 0);
+        let twist = request(&mut supervisor, Twist2D::new(0.0, 1.0), 0);
+        assert_eq!(twist, Twist2D::default());
+        assert!(matches!(
+            supervisor.take_interventions()[0].reason,
+            InterventionReason::ObstacleStop { .. }
+        ));
+
+        supervisor.scan(&cloud(&[[0.0, 2.0]], FrameId::root()), &tree, 0);
+        let twist = request(&mut supervisor, Twist2D::new(0.0, 1.0), 100_000_000);
+        assert!((twist.angular - 0.3).abs() < 1e-4, "{twist:?}");
+    }
+
+    #[test]
+    fn the_first_update_is_not_acceleration_limited() {
+        let mut supervisor = supervisor(Footprint::circle(0.2));
+
+        let twist = request(&mut supervisor, Twist2D::new(0.4, 0.0), 0);
+        assert_eq!(twist, Twist2D::new(0.4, 0.0));
+        assert!(supervisor.take_interventions().is_empty());
+
+        let twist = request(&mut supervisor, Twist2D::default(), 100_000_000);
+        assert!((twist.linear - 0.3).abs() < 1e-4, "{twist:?}");
+        assert_eq!(
+            supervisor.take_interventions()[0].reason,
+            InterventionReason::AccelerationLimited
+        );
+    }
+}
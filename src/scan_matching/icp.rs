@@ -0,0 +1,455 @@
+use glam::{DMat3, DVec2, DVec3};
+
+use crate::lidar::spatial::{KdTree, SpatialIndex};
+use crate::primitives::{normalize_angle, Point, Pose2D, PoseWithCovariance2D, Vec2};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IcpMethod {
+    PointToPoint,
+    /// Minimises distance to the local surface line through each target point;
+    /// converges faster on structured scenes such as walls and corridors.
+    PointToLine,
+}
+
+#[derive(Clone, Debug)]
+pub struct IcpConfig {
+    pub method: IcpMethod,
+    pub max_iterations: usize,
+    /// Correspondences further apart than this are rejected.
+    pub max_correspondence_distance: f32,
+    /// Iteration stops once an update moves less than this.
+    pub translation_epsilon: f32,
+    pub rotation_epsilon: f32,
+    /// Neighbours used to fit each target line for point-to-line matching.
+    pub normal_neighbours: usize,
+    /// Matches with fewer inlier correspondences than this fail.
+    pub min_correspondences: usize,
+}
+
+impl Default for IcpConfig {
+    fn default() -> Self {
+        Self {
+            method: IcpMethod::PointToLine,
+            max_iterations: 30,
+            max_correspondence_distance: 0.5,
+            translation_epsilon: 1e-4,
+            rotation_epsilon: 1e-4,
+            normal_neighbours: 5,
+            min_correspondences: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IcpResult {
+    /// Pose of the source scan in the target scan's frame, with covariance.
+    pub estimate: PoseWithCovariance2D,
+    /// Root mean square residual over inlier correspondences, in metres.
+    pub fitness: f32,
+    /// Fraction of source points with an inlier correspondence.
+    pub inlier_ratio: f32,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+/// A reference scan prepared for repeated matching.
+pub struct IcpTarget {
+    points: Vec<Vec2>,
+    normals: Vec<Vec2>,
+    tree: KdTree,
+}
+
+impl IcpTarget {
+    pub fn new(points: Vec<Vec2>, normal_neighbours: usize) -> Self {
+        let tree = KdTree::from_positions(points.iter().map(|p| p.extend(0.0)).collect());
+        let normals = points
+            .iter()
+            .map(|point| {
+                let neighbours = tree.nearest(point.extend(0.0), normal_neighbours.max(2));
+                fit_normal(neighbours.iter().map(|n| points[n.index]))
+            })
+            .collect();
+        Self {
+            points,
+            normals,
+            tree,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+// Normal of the best-fit line through `points`: the eigenvector of their
+// covariance with the smallest eigenvalue.
+fn fit_normal(points: impl Iterator<Item = Vec2> + Clone) -> Vec2 {
+    let count = points.clone().count() as f32;
+    if count < 2.0 {
+        return Vec2::ZERO;
+    }
+    let mean = points.clone().fold(Vec2::ZERO, |sum, p| sum + p) / count;
+    let (mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0);
+    for point in points {
+        let d = point - mean;
+        xx += d.x * d.x;
+        xy += d.x * d.y;
+        yy += d.y * d.y;
+    }
+    // Orientation of the principal axis; the normal is perpendicular to it.
+    let angle = 0.5 * (2.0 * xy).atan2(xx - yy);
+    Vec2::new(-angle.sin(), angle.cos())
+}
+
+/// Estimates the pose of `source` relative to `target` by iterative closest
+/// point, starting from `initial_guess`.
+pub fn match_scans(
+    target: &IcpTarget,
+    source: &[Vec2],
+    initial_guess: &Pose2D,
+    config: &IcpConfig,
+) -> Option<IcpResult> {
+    if target.is_empty() || source.is_empty() {
+        return None;
+    }
+    let max_distance_squared = config.max_correspondence_distance.powi(2);
+    let mut pose = *initial_guess;
+    let mut iterations = 0;
+    let mut converged = false;
+
+    while iterations < config.max_iterations {
+        iterations += 1;
+        let correspondences = correspond(target, source, &pose, max_distance_squared);
+        if correspondences.len() < config.min_correspondences {
+            return None;
+        }
+        let step = match config.method {
+            IcpMethod::PointToPoint => point_to_point_step(target, &correspondences),
+            IcpMethod::PointToLine => point_to_line_step(target, &correspondences),
+        }?;
+        pose = step.compose(&pose);
+        if step.position().length() < config.translation_epsilon
+            && step.theta.abs() < config.rotation_epsilon
+        {
+            converged = true;
+            break;
+        }
+    }
+
+    let correspondences = correspond(target, source, &pose, max_distance_squared);
+    if correspondences.len() < config.min_correspondences {
+        return None;
+    }
+    let (fitness, covariance) = residual_statistics(target, &correspondences, config.method)?;
+    Some(IcpResult {
+        estimate: PoseWithCovariance2D { pose, covariance },
+        fitness,
+        inlier_ratio: correspondences.len() as f32 / source.len() as f32,
+        iterations,
+        converged,
+    })
+}
+
+struct Correspondence {
+    /// Source point transformed by the current estimate.
+    source: Vec2,
+    target: usize,
+}
+
+fn correspond(
+    target: &IcpTarget,
+    source: &[Vec2],
+    pose: &Pose2D,
+    max_distance_squared: f32,
+) -> Vec<Correspondence> {
+    source
+        .iter()
+        .filter_map(|point| {
+            let transformed = pose.transform_point(*point);
+            let nearest = target.tree.nearest_one(transformed.extend(0.0))?;
+            (nearest.distance_squared <= max_distance_squared).then_some(Correspondence {
+                source: transformed,
+                target: nearest.index,
+            })
+        })
+        .collect()
+}
+
+// Closed-form rigid alignment (2D Kabsch) of the correspondences.
+fn point_to_point_step(target: &IcpTarget, correspondences: &[Correspondence]) -> Option<Pose2D> {
+    let count = correspondences.len() as f64;
+    let (mut source_mean, mut target_mean) = (DVec2::ZERO, DVec2::ZERO);
+    for c in correspondences {
+        source_mean += c.source.as_dvec2();
+        target_mean += target.points[c.target].as_dvec2();
+    }
+    source_mean /= count;
+    target_mean /= count;
+    let (mut dot, mut cross) = (0.0, 0.0);
+    for c in correspondences {
+        let s = c.source.as_dvec2() - source_mean;
+        let t = target.points[c.target].as_dvec2() - target_mean;
+        dot += s.dot(t);
+        cross += s.perp_dot(t);
+    }
+    let theta = cross.atan2(dot);
+    let (sin, cos) = theta.sin_cos();
+    let rotated_mean = DVec2::new(
+        cos * source_mean.x - sin * source_mean.y,
+        sin * source_mean.x + cos * source_mean.y,
+    );
+    let translation = target_mean - rotated_mean;
+    Some(Pose2D::new(
+        translation.x as f32,
+        translation.y as f32,
+        theta as f32,
+    ))
+}
+
+// One Gauss-Newton step on the linearised point-to-line error.
+fn point_to_line_step(target: &IcpTarget, correspondences: &[Correspondence]) -> Option<Pose2D> {
+    let mut hessian = DMat3::ZERO;
+    let mut gradient = DVec3::ZERO;
+    for c in correspondences {
+        let normal = target.normals[c.target].as_dvec2();
+        if normal == DVec2::ZERO {
+            continue;
+        }
+        let source = c.source.as_dvec2();
+        let residual = normal.dot(source - target.points[c.target].as_dvec2());
+        // d(residual)/d(tx, ty, theta) for a small rotation about the origin.
+        let jacobian = DVec3::new(normal.x, normal.y, normal.dot(source.perp()));
+        hessian += outer(jacobian, jacobian);
+        gradient += jacobian * residual;
+    }
+    if hessian.determinant().abs() < 1e-12 {
+        return None;
+    }
+    let delta = -(hessian.inverse() * gradient);
+    Some(Pose2D::new(
+        delta.x as f32,
+        delta.y as f32,
+        normalize_angle(delta.z as f32),
+    ))
+}
+
+/// RMS residual and pose covariance `σ² (JᵀJ)⁻¹` at the final estimate.
+fn residual_statistics(
+    target: &IcpTarget,
+    correspondences: &[Correspondence],
+    method: IcpMethod,
+) -> Option<(f32, [[f32; 3]; 3])> {
+    let mut hessian = DMat3::ZERO;
+    let mut squared_error = 0.0;
+    let mut residual_count = 0usize;
+    for c in correspondences {
+        let source = c.source.as_dvec2();
+        let target_point = target.points[c.target].as_dvec2();
+        match method {
+            IcpMethod::PointToPoint => {
+                let error = source - target_point;
+                squared_error += error.length_squared();
+                residual_count += 2;
+                let perp = source.perp();
+                let jacobian_x = DVec3::new(1.0, 0.0, perp.x);
+                let jacobian_y = DVec3::new(0.0, 1.0, perp.y);
+                hessian += outer(jacobian_x, jacobian_x) + outer(jacobian_y, jacobian_y);
+            }
+            IcpMethod::PointToLine => {
+                let normal = target.normals[c.target].as_dvec2();
+                let residual = normal.dot(source - target_point);
+                squared_error += residual * residual;
+                residual_count += 1;
+                let jacobian = DVec3::new(normal.x, normal.y, normal.dot(source.perp()));
+                hessian += outer(jacobian, jacobian);
+            }
+        }
+    }
+    let degrees_of_freedom = residual_count.saturating_sub(3).max(1) as f64;
+    let variance = squared_error / degrees_of_freedom;
+    let fitness = (squared_error / correspondences.len() as f64).sqrt() as f32;
+    if hessian.determinant().abs() < 1e-12 {
+        return None;
+    }
+    let covariance = hessian.inverse() * variance;
+    let covariance = covariance
+        .to_cols_array_2d()
+        .map(|column| column.map(|v| v as f32));
+    Some((fitness, covariance))
+}
+
+fn outer(a: DVec3, b: DVec3) -> DMat3 {
+    DMat3::from_cols(a * b.x, a * b.y, a * b.z)
+}
+
+/// Projects scan points onto the XY plane.
+pub fn planar_points(points: &[Point]) -> Vec<Vec2> {
+    points
+        .iter()
+        .map(|point| point.position.truncate())
+        .filter(|point| point.is_finite())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Mat3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rand_distr::StandardNormal;
+
+    use super::*;
+
+    // Walls of a 6 m by 4 m room with a box in one corner, sampled every
+    // `spacing`, with Gaussian noise of `noise` metres on each point.
+    fn room(spacing: f32, noise: f32, rng: &mut StdRng) -> Vec<Vec2> {
+        let corners = [
+            Vec2::new(-3.0, -2.0),
+            Vec2::new(3.0, -2.0),
+            Vec2::new(3.0, 2.0),
+            Vec2::new(-3.0, 2.0),
+        ];
+        let mut segments: Vec<(Vec2, Vec2)> = (0..4)
+            .map(|index| (corners[index], corners[(index + 1) % 4]))
+            .collect();
+        segments.push((Vec2::new(1.5, 2.0), Vec2::new(1.5, 1.2)));
+        segments.push((Vec2::new(1.5, 1.2), Vec2::new(3.0, 1.2)));
+        let mut points = Vec::new();
+        for (from, to) in segments {
+            let steps = (from.distance(to) / spacing).round() as usize;
+            for step in 0..steps {
+                let jitter = Vec2::new(rng.sample(StandardNormal), rng.sample(StandardNormal));
+                points.push(from.lerp(to, step as f32 / steps as f32) + jitter * noise);
+            }
+        }
+        points
+    }
+
+    // The room as seen from `pose`.
+    fn seen_from(pose: &Pose2D, spacing: f32, noise: f32, rng: &mut StdRng) -> Vec<Vec2> {
+        let inverse = pose.inverse();
+        room(spacing, noise, rng)
+            .into_iter()
+            .map(|point| inverse.transform_point(point))
+            .collect()
+    }
+
+    fn config(method: IcpMethod) -> IcpConfig {
+        IcpConfig {
+            method,
+            max_iterations: 200,
+            translation_epsilon: 1e-6,
+            rotation_epsilon: 1e-6,
+            ..Default::default()
+        }
+    }
+
+    fn positive_definite(covariance: &[[f32; 3]; 3]) -> bool {
+        let minor = covariance[0][0] * covariance[1][1] - covariance[0][1] * covariance[1][0];
+        covariance[0][0] > 0.0
+            && minor > 0.0
+            && Mat3::from_cols_array_2d(covariance).determinant() > 0.0
+    }
+
+    fn trace(covariance: &[[f32; 3]; 3]) -> f32 {
+        covariance[0][0] + covariance[1][1] + covariance[2][2]
+    }
+
+    // The target is sampled more finely than the source, as two scans never
+    // hit the same spots; nearest neighbours are then within a centimetre.
+    fn recovers_offset(method: IcpMethod, tolerance: f32) {
+        let mut rng = StdRng::seed_from_u64(2);
+        let offset = Pose2D::new(0.2, -0.15, 0.06);
+        let target = IcpTarget::new(room(0.02, 0.0, &mut rng), 5);
+        let source = seen_from(&offset, 0.05, 0.0, &mut rng);
+
+        let result =
+            match_scans(&target, &source, &Pose2D::identity(), &config(method)).expect("match");
+
+        let pose = result.estimate.pose;
+        assert!(result.converged, "{result:?}");
+        assert!(
+            pose.position().distance(offset.position()) < tolerance,
+            "{pose:?}"
+        );
+        assert!((pose.theta - offset.theta).abs() < tolerance, "{pose:?}");
+        assert!(result.fitness < 0.01, "{}", result.fitness);
+        assert!(result.inlier_ratio > 0.99);
+    }
+
+    #[test]
+    fn point_to_point_recovers_a_rigid_offset() {
+        recovers_offset(IcpMethod::PointToPoint, 5e-3);
+    }
+
+    #[test]
+    fn point_to_line_recovers_a_rigid_offset() {
+        recovers_offset(IcpMethod::PointToLine, 1e-3);
+    }
+
+    #[test]
+    fn fitness_reflects_sensor_noise() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let offset = Pose2D::new(-0.1, 0.1, -0.04);
+        let target = IcpTarget::new(room(0.05, 0.0, &mut rng), 5);
+        let source = seen_from(&offset, 0.05, 0.02, &mut rng);
+
+        for method in [IcpMethod::PointToPoint, IcpMethod::PointToLine] {
+            let result =
+                match_scans(&target, &source, &Pose2D::identity(), &config(method)).expect("match");
+            let pose = result.estimate.pose;
+            assert!(
+                pose.position().distance(offset.position()) < 0.01,
+                "{method:?} {pose:?}"
+            );
+            assert!(
+                (pose.theta - offset.theta).abs() < 0.01,
+                "{method:?} {pose:?}"
+            );
+            assert!(
+                result.fitness > 0.005 && result.fitness < 0.05,
+                "{method:?} {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn covariance_is_positive_definite_and_shrinks_with_more_points() {
+        let offset = Pose2D::new(0.1, 0.05, 0.03);
+        for method in [IcpMethod::PointToPoint, IcpMethod::PointToLine] {
+            let covariance = |spacing: f32| {
+                let mut rng = StdRng::seed_from_u64(5);
+                let target = IcpTarget::new(room(spacing, 0.0, &mut rng), 5);
+                let source = seen_from(&offset, spacing, 0.01, &mut rng);
+                match_scans(&target, &source, &Pose2D::identity(), &config(method))
+                    .expect("match")
+                    .estimate
+                    .covariance
+            };
+            let sparse = covariance(0.2);
+            let dense = covariance(0.05);
+
+            assert!(positive_definite(&sparse), "{method:?} {sparse:?}");
+            assert!(positive_definite(&dense), "{method:?} {dense:?}");
+            assert!(
+                trace(&dense) < 0.5 * trace(&sparse),
+                "{method:?} {dense:?} {sparse:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn too_few_correspondences_fail() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let target = IcpTarget::new(room(0.05, 0.0, &mut rng), 5);
+        let far = seen_from(&Pose2D::new(20.0, 0.0, 0.0), 0.05, 0.0, &mut rng);
+
+        let result = match_scans(&target, &far, &Pose2D::identity(), &IcpConfig::default());
+        assert!(result.is_none());
+        assert!(match_scans(&target, &[], &Pose2D::identity(), &IcpConfig::default()).is_none());
+    }
+}
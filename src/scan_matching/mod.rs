@@ -0,0 +1,185 @@
+pub mod icp;
+
+use std::sync::Mutex;
+
+use glam::Mat3;
+
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::mapping::PosedScan;
+use crate::primitives::{Pose2D, PoseWithCovariance2D};
+use icp::{match_scans, planar_points, IcpConfig, IcpResult, IcpTarget};
+
+#[derive(Clone, Debug)]
+pub struct ScanMatcherConfig {
+    pub icp: IcpConfig,
+    /// Pose of the lidar in the base frame.
+    pub sensor_mount: Pose2D,
+    /// Matches with a larger RMS residual than this fall back to wheel odometry.
+    pub max_fitness: f32,
+    /// Matches where fewer source points found a partner fall back too.
+    pub min_inlier_ratio: f32,
+    /// Per-step variance of x, y and heading when wheel odometry is used.
+    pub odometry_variance: [f32; 3],
+}
+
+impl Default for ScanMatcherConfig {
+    fn default() -> Self {
+        Self {
+            icp: IcpConfig::default(),
+            sensor_mount: Pose2D::identity(),
+            max_fitness: 0.1,
+            min_inlier_ratio: 0.5,
+            odometry_variance: [1e-3, 1e-3, 1e-3],
+        }
+    }
+}
+
+/// Lidar odometry: matches each scan against the previous one, seeded with the
+/// motion wheel odometry reported in between, and accumulates the result.
+pub struct ScanMatcher {
+    pub config: ScanMatcherConfig,
+    previous: Option<(IcpTarget, Pose2D)>,
+    pose: PoseWithCovariance2D,
+    last_result: Option<IcpResult>,
+}
+
+impl ScanMatcher {
+    pub fn new(config: ScanMatcherConfig) -> Self {
+        Self {
+            config,
+            previous: None,
+            pose: PoseWithCovariance2D::default(),
+            last_result: None,
+        }
+    }
+
+    /// Accumulated base pose in the odometry frame of the first scan.
+    pub fn pose(&self) -> PoseWithCovariance2D {
+        self.pose
+    }
+
+    /// Result of the latest match, or `None` if it failed or was rejected.
+    pub fn last_result(&self) -> Option<IcpResult> {
+        self.last_result
+    }
+
+    pub fn reset(&mut self, pose: &Pose2D) {
+        self.previous = None;
+        self.pose = PoseWithCovariance2D {
+            pose: *pose,
+            ..Default::default()
+        };
+        self.last_result = None;
+    }
+
+    /// Processes one scan (in the sensor frame) posed by wheel odometry,
+    /// returning the updated base pose.
+    pub fn update(&mut self, scan: &PosedScan) -> PoseWithCovariance2D {
+        let points = planar_points(&scan.scan.points);
+        let target = IcpTarget::new(points.clone(), self.config.icp.normal_neighbours);
+        let Some((previous_target, previous_odometry)) =
+            self.previous.replace((target, scan.base_pose))
+        else {
+            return self.pose;
+        };
+
+        let mount = self.config.sensor_mount;
+        let wheel_delta = previous_odometry.between(&scan.base_pose);
+        let guess = mount.inverse().compose(&wheel_delta).compose(&mount);
+        self.last_result =
+            match_scans(&previous_target, &points, &guess, &self.config.icp).filter(|result| {
+                result.converged
+                    && result.fitness <= self.config.max_fitness
+                    && result.inlier_ratio >= self.config.min_inlier_ratio
+            });
+
+        let (delta, delta_covariance) = match &self.last_result {
+            Some(result) => (
+                mount
+                    .compose(&result.estimate.pose)
+                    .compose(&mount.inverse()),
+                adjoint(&mount)
+                    * covariance_matrix(&result.estimate.covariance)
+                    * adjoint(&mount).transpose(),
+            ),
+            None => (
+                wheel_delta,
+                Mat3::from_diagonal(self.config.odometry_variance.into()),
+            ),
+        };
+        self.pose = compose_with_covariance(&self.pose, &delta, delta_covariance);
+        self.pose
+    }
+}
+
+pub(crate) fn covariance_matrix(covariance: &[[f32; 3]; 3]) -> Mat3 {
+    Mat3::from_cols_array_2d(covariance).transpose()
+}
+
+// Maps a twist expressed in a child frame into its parent frame.
+pub(crate) fn adjoint(pose: &Pose2D) -> Mat3 {
+    let (sin, cos) = pose.theta.sin_cos();
+    Mat3::from_cols_array_2d(&[[cos, sin, 0.0], [-sin, cos, 0.0], [pose.y, -pose.x, 1.0]])
+}
+
+// First-order propagation of `pose ∘ delta`.
+fn compose_with_covariance(
+    pose: &PoseWithCovariance2D,
+    delta: &Pose2D,
+    delta_covariance: Mat3,
+) -> PoseWithCovariance2D {
+    let (sin, cos) = pose.pose.theta.sin_cos();
+    let pose_jacobian = Mat3::from_cols_array_2d(&[
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [
+            -sin * delta.x - cos * delta.y,
+            cos * delta.x - sin * delta.y,
+            1.0,
+        ],
+    ]);
+    let delta_jacobian =
+        Mat3::from_cols_array_2d(&[[cos, sin, 0.0], [-sin, cos, 0.0], [0.0, 0.0, 1.0]]);
+    let covariance =
+        pose_jacobian * covariance_matrix(&pose.covariance) * pose_jacobian.transpose()
+            + delta_jacobian * delta_covariance * delta_jacobian.transpose();
+    PoseWithCovariance2D {
+        pose: pose.pose.compose(delta),
+        covariance: covariance.transpose().to_cols_array_2d(),
+    }
+}
+
+/// Runs a [`ScanMatcher`] as a task. Input scans carry the wheel odometry pose
+/// they were taken at; output is the scan-matched base pose.
+pub struct LidarOdometry {
+    matcher: Mutex<ScanMatcher>,
+}
+
+impl LidarOdometry {
+    pub fn new(config: ScanMatcherConfig) -> Self {
+        Self {
+            matcher: Mutex::new(ScanMatcher::new(config)),
+        }
+    }
+
+    pub fn reset(&self, pose: &Pose2D) {
+        self.matcher
+            .lock()
+            .expect("ScanMatcher lock poisoned")
+            .reset(pose);
+    }
+}
+
+impl Task for LidarOdometry {
+    type Input = CarbonData<PosedScan>;
+    type Output = CarbonData<PoseWithCovariance2D>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let mut matcher = self.matcher.lock().expect("ScanMatcher lock poisoned");
+        input.map(|scan| matcher.update(&scan))
+    }
+}
+
+impl Controller<PosedScan, PoseWithCovariance2D> for LidarOdometry {}
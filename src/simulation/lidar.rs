@@ -0,0 +1,379 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, RwLock};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+use crate::description::{Geometry, PlacedGeometry};
+use crate::lidar::LIDAR;
+use crate::mapping::occupancy_grid::{CellState, OccupancyGrid};
+use crate::ports::PortReader;
+use crate::primitives::{IVec2, Point, Pose2D, Vec2, Vec3};
+
+/// A world a simulated beam can hit.
+pub trait RayCaster {
+    /// Distance along `direction`, a unit vector, from `origin` to the first
+    /// surface within `max_range`.
+    fn cast(&self, origin: Vec3, direction: Vec3, max_range: f32) -> Option<f32>;
+}
+
+// Hits the first occupied cell in the plane; unknown cells are see-through.
+impl RayCaster for OccupancyGrid {
+    fn cast(&self, origin: Vec3, direction: Vec3, max_range: f32) -> Option<f32> {
+        let (origin, direction) = (origin.truncate(), direction.truncate());
+        if direction.length_squared() < f32::EPSILON {
+            return None;
+        }
+        let mut hit = None;
+        traverse(
+            self,
+            origin,
+            direction.normalize(),
+            max_range,
+            |cell, entry| {
+                if !self.contains_cell(cell) && entry > 0.0 {
+                    return false;
+                }
+                if self.state(cell) == CellState::Occupied {
+                    hit = Some(entry);
+                    return false;
+                }
+                true
+            },
+        );
+        hit
+    }
+}
+
+// Visits every cell a ray passes through within `max_range`, in order, with
+// the distance at which it enters each (Amanatides and Woo), stopping early
+// if `visit` returns `false`.
+fn traverse(
+    grid: &OccupancyGrid,
+    origin: Vec2,
+    direction: Vec2,
+    max_range: f32,
+    mut visit: impl FnMut(IVec2, f32) -> bool,
+) {
+    let resolution = grid.resolution();
+    let local = (origin - grid.origin()) / resolution;
+    let mut cell = local.floor().as_ivec2();
+    let mut step = IVec2::ZERO;
+    let mut next = Vec2::INFINITY;
+    for axis in 0..2 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            next[axis] = (cell[axis] as f32 + 1.0 - local[axis]) * resolution / direction[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            next[axis] = (local[axis] - cell[axis] as f32) * resolution / -direction[axis];
+        }
+    }
+    let across = Vec2::splat(resolution) / direction.abs();
+    let mut entry = 0.0;
+    while entry <= max_range && visit(cell, entry) {
+        let axis = if next.x < next.y { 0 } else { 1 };
+        entry = next[axis];
+        next[axis] += across[axis];
+        cell[axis] += step[axis];
+    }
+}
+
+impl RayCaster for PlacedGeometry {
+    fn cast(&self, origin: Vec3, direction: Vec3, max_range: f32) -> Option<f32> {
+        let inverse = self.transform.inverse();
+        let local_origin = inverse.transform_point(origin);
+        let local_direction = inverse.transform_point(origin + direction) - local_origin;
+        intersect(&self.geometry, local_origin, local_direction)
+            .filter(|&distance| distance <= max_range)
+    }
+}
+
+impl<C: RayCaster> RayCaster for [C] {
+    fn cast(&self, origin: Vec3, direction: Vec3, max_range: f32) -> Option<f32> {
+        self.iter()
+            .filter_map(|caster| caster.cast(origin, direction, max_range))
+            .min_by(f32::total_cmp)
+    }
+}
+
+impl<C: RayCaster> RayCaster for Vec<C> {
+    fn cast(&self, origin: Vec3, direction: Vec3, max_range: f32) -> Option<f32> {
+        self.as_slice().cast(origin, direction, max_range)
+    }
+}
+
+// Nearest non-negative distance at which a ray in the shape's frame meets its
+// surface. Rays starting inside a shape hit it at once.
+fn intersect(geometry: &Geometry, origin: Vec3, direction: Vec3) -> Option<f32> {
+    match geometry {
+        Geometry::Sphere { radius } => {
+            let (near, far) = quadratic(
+                direction.length_squared(),
+                origin.dot(direction),
+                origin.length_squared() - radius * radius,
+            )?;
+            nearest(near, far)
+        }
+        Geometry::Cylinder { radius, height } => {
+            let half = height / 2.0;
+            // Between the caps, then within the curved side.
+            let (low, high) = if direction.z.abs() < f32::EPSILON {
+                if origin.z.abs() > half {
+                    return None;
+                }
+                (f32::NEG_INFINITY, f32::INFINITY)
+            } else {
+                let (a, b) = (
+                    (-half - origin.z) / direction.z,
+                    (half - origin.z) / direction.z,
+                );
+                (a.min(b), a.max(b))
+            };
+            let (flat_origin, flat_direction) = (origin.truncate(), direction.truncate());
+            let (near, far) = if flat_direction.length_squared() < f32::EPSILON {
+                if flat_origin.length() > *radius {
+                    return None;
+                }
+                (f32::NEG_INFINITY, f32::INFINITY)
+            } else {
+                quadratic(
+                    flat_direction.length_squared(),
+                    flat_origin.dot(flat_direction),
+                    flat_origin.length_squared() - radius * radius,
+                )?
+            };
+            let (entry, exit) = (low.max(near), high.min(far));
+            (entry <= exit).then_some(())?;
+            nearest(entry, exit)
+        }
+        Geometry::Plane { width, depth } => {
+            if direction.z.abs() < f32::EPSILON {
+                return None;
+            }
+            let distance = -origin.z / direction.z;
+            let point = origin + direction * distance;
+            (distance >= 0.0 && point.x.abs() <= depth / 2.0 && point.y.abs() <= width / 2.0)
+                .then_some(distance)
+        }
+        // Meshes are approximated by their bounding box, as in
+        // `Geometry::contains`.
+        Geometry::Box { .. } | Geometry::Mesh { .. } => {
+            let (min, max) = geometry.bounds()?;
+            let (entry, exit) = slab(origin, direction, min, max)?;
+            nearest(entry, exit)
+        }
+    }
+}
+
+// Roots of a t² + 2 b t + c, in increasing order.
+fn quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    let discriminant = b * b - a * c;
+    if a < f32::EPSILON || discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some(((-b - root) / a, (-b + root) / a))
+}
+
+// Entry and exit distances of a ray through an axis-aligned box.
+fn slab(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<(f32, f32)> {
+    let mut entry = f32::NEG_INFINITY;
+    let mut exit = f32::INFINITY;
+    for axis in 0..3 {
+        if direction[axis].abs() < f32::EPSILON {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let a = (min[axis] - origin[axis]) / direction[axis];
+        let b = (max[axis] - origin[axis]) / direction[axis];
+        entry = entry.max(a.min(b));
+        exit = exit.min(a.max(b));
+    }
+    (entry <= exit).then_some((entry, exit))
+}
+
+// The first crossing ahead of the origin, given entry and exit distances.
+fn nearest(entry: f32, exit: f32) -> Option<f32> {
+    if exit < 0.0 {
+        None
+    } else {
+        Some(entry.max(0.0))
+    }
+}
+
+/// A planar scanning lidar ray-cast against a simulated world. Its pose is
+/// read from a shared ground truth, which a [`super::SimulatedDrive`] can
+/// keep moving, so the driver can be swapped in for a real one.
+pub struct SimulatedLidar<W: RayCaster + ?Sized> {
+    world: Arc<RwLock<W>>,
+    ground_truth: Arc<RwLock<Pose2D>>,
+    /// Where the sensor is mounted on the base.
+    pub mount: Pose2D,
+    /// Height of the scan plane above the ground, for ray-casting geometry.
+    pub height: f32,
+    pub beams: usize,
+    /// Bearing of the first beam, radians; the rest follow counter-clockwise.
+    pub min_angle: f32,
+    pub max_angle: f32,
+    pub min_range: f32,
+    pub max_range: f32,
+    /// Standard deviation of the range noise, metres.
+    pub range_noise: f32,
+    /// Chance of a beam returning nothing.
+    pub dropout: f32,
+    rng: StdRng,
+}
+
+impl<W: RayCaster + ?Sized> SimulatedLidar<W> {
+    pub fn new(world: Arc<RwLock<W>>, ground_truth: Arc<RwLock<Pose2D>>, seed: u64) -> Self {
+        Self {
+            world,
+            ground_truth,
+            mount: Pose2D::identity(),
+            height: 0.2,
+            beams: 360,
+            min_angle: -PI,
+            max_angle: PI,
+            min_range: 0.15,
+            max_range: 12.0,
+            range_noise: 0.01,
+            dropout: 0.0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn with_mount(mut self, mount: Pose2D, height: f32) -> Self {
+        self.mount = mount;
+        self.height = height;
+        self
+    }
+
+    /// `beams` bearings spread over `[min_angle, max_angle)`.
+    pub fn with_beams(mut self, beams: usize, min_angle: f32, max_angle: f32) -> Self {
+        self.beams = beams;
+        self.min_angle = min_angle;
+        self.max_angle = max_angle;
+        self
+    }
+
+    pub fn with_range(mut self, min_range: f32, max_range: f32) -> Self {
+        self.min_range = min_range;
+        self.max_range = max_range;
+        self
+    }
+
+    pub fn with_noise(mut self, range_noise: f32, dropout: f32) -> Self {
+        self.range_noise = range_noise;
+        self.dropout = dropout;
+        self
+    }
+
+    /// One sweep, as points in the sensor frame.
+    pub fn scan(&mut self) -> Vec<Point> {
+        let base = *self
+            .ground_truth
+            .read()
+            .expect("Ground truth lock poisoned");
+        let sensor = base.compose(&self.mount);
+        let world = self.world.read().expect("World lock poisoned");
+        let origin = sensor.position().extend(self.height);
+        let step = (self.max_angle - self.min_angle) / self.beams.max(1) as f32;
+        let mut points = Vec::with_capacity(self.beams);
+        for beam in 0..self.beams {
+            let bearing = self.min_angle + beam as f32 * step;
+            let direction = Vec2::from_angle(sensor.theta + bearing).extend(0.0);
+            let Some(range) = world.cast(origin, direction, self.max_range) else {
+                continue;
+            };
+            if self.rng.gen::<f32>() < self.dropout {
+                continue;
+            }
+            let noise: f32 = self.rng.sample(StandardNormal);
+            let range = range + noise * self.range_noise;
+            if range < self.min_range || range > self.max_range {
+                continue;
+            }
+            points.push(Point::new(
+                (Vec2::from_angle(bearing) * range).extend(0.0),
+                1.0,
+            ));
+        }
+        points
+    }
+}
+
+impl<W: RayCaster + ?Sized> PortReader for SimulatedLidar<W> {
+    type Output = Vec<Point>;
+
+    fn read_data(&mut self) -> Option<Self::Output> {
+        Some(self.scan())
+    }
+}
+
+impl<W: RayCaster + ?Sized> LIDAR for SimulatedLidar<W> {}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+    use crate::primitives::Transform;
+
+    fn lidar<W: RayCaster>(world: W, pose: Pose2D) -> SimulatedLidar<W> {
+        SimulatedLidar::new(Arc::new(RwLock::new(world)), Arc::new(RwLock::new(pose)), 1)
+            .with_noise(0.0, 0.0)
+    }
+
+    #[test]
+    fn beams_stop_at_an_occupied_wall_in_the_grid() {
+        let mut grid = OccupancyGrid::covering(0.05, Vec2::new(-1.0, -3.0), Vec2::new(5.0, 3.0));
+        let wall = grid.world_to_cell(Vec2::new(3.01, 0.0)).x;
+        for y in 0..grid.height() as i32 {
+            grid.set_log_odds(IVec2::new(wall, y), 5.0);
+        }
+        let mut lidar = lidar(grid, Pose2D::new(0.5, 0.2, 0.0))
+            .with_mount(Pose2D::new(0.1, 0.0, 0.0), 0.2)
+            .with_beams(9, -FRAC_PI_4, FRAC_PI_4);
+
+        let points = lidar.scan();
+
+        assert_eq!(points.len(), 9);
+        for point in &points {
+            // The wall's near face is 2.4 m ahead of the sensor.
+            assert!((point.position.x - 2.4).abs() < 1e-3, "{point:?}");
+        }
+
+        lidar = lidar.with_range(0.15, 2.0);
+        assert!(lidar.scan().is_empty());
+    }
+
+    #[test]
+    fn beams_hit_the_face_of_a_placed_box() {
+        let crate_box = PlacedGeometry::new(
+            Geometry::Box {
+                height: 1.0,
+                width: 2.0,
+                depth: 0.5,
+            },
+            Transform::from_translation(Vec3::new(2.0, 0.0, 0.5)),
+        );
+        let mut lidar = lidar(crate_box, Pose2D::identity()).with_beams(16, -PI, PI);
+
+        let points = lidar.scan();
+
+        // Only bearings within atan(1 / 1.75) of straight ahead meet the box.
+        assert_eq!(points.len(), 3);
+        for point in &points {
+            assert!((point.position.x - 1.75).abs() < 1e-4, "{point:?}");
+            assert!(point.position.y.abs() <= 1.0);
+        }
+
+        // A scan plane above the box sees nothing.
+        lidar = lidar.with_mount(Pose2D::identity(), 1.5);
+        assert!(lidar.scan().is_empty());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod lidar;
+pub mod motor;
+
+pub use lidar::{RayCaster, SimulatedLidar};
+pub use motor::{SimulatedDrive, SimulatedMotor, SimulatedMotors};
@@ -0,0 +1,287 @@
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::clock::TimestampDelta;
+use crate::drive::{CommandVelocity, DifferentialDrive, EncoderFeedback};
+use crate::hardware::{Hardware, InterfaceKind, InterfaceName};
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::primitives::Pose2D;
+
+/// A velocity-controlled motor whose speed follows the command with a
+/// first-order lag, read back through an encoder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedMotor {
+    /// Seconds for the speed to cover 63% of a step in the command.
+    pub time_constant: f32,
+    /// Fastest speed, rad/s.
+    pub max_velocity: f32,
+    /// Radians per encoder count; zero reports exact positions.
+    pub encoder_resolution: f32,
+    command: f32,
+    velocity: f32,
+    position: f32,
+}
+
+impl SimulatedMotor {
+    pub fn new(time_constant: f32, max_velocity: f32) -> Self {
+        Self {
+            time_constant,
+            max_velocity,
+            encoder_resolution: 0.0,
+            command: 0.0,
+            velocity: 0.0,
+            position: 0.0,
+        }
+    }
+
+    pub fn with_encoder_resolution(mut self, encoder_resolution: f32) -> Self {
+        self.encoder_resolution = encoder_resolution;
+        self
+    }
+
+    pub fn command(&mut self, command: CommandVelocity) {
+        self.command = command.0.clamp(-self.max_velocity, self.max_velocity);
+    }
+
+    /// Advances `dt` seconds and returns the distance turned.
+    pub fn step(&mut self, dt: f32) -> f32 {
+        let dt = dt.max(0.0);
+        let previous = self.velocity;
+        let blend = if self.time_constant > 0.0 {
+            1.0 - (-dt / self.time_constant).exp()
+        } else {
+            1.0
+        };
+        self.velocity += (self.command - self.velocity) * blend;
+        // Exact integral of the exponential approach over the step.
+        let turned = if self.time_constant > 0.0 {
+            self.command * dt - (self.command - previous) * self.time_constant * blend
+        } else {
+            self.velocity * dt
+        };
+        self.position += turned;
+        turned
+    }
+
+    /// The true speed, rad/s.
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    /// The true angle, radians.
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
+    /// What the encoder reports: the angle, rounded to whole counts, and the
+    /// speed.
+    pub fn feedback(&self) -> EncoderFeedback {
+        let position = if self.encoder_resolution > 0.0 {
+            (self.position / self.encoder_resolution).round() * self.encoder_resolution
+        } else {
+            self.position
+        };
+        EncoderFeedback {
+            position: Some(position),
+            velocity: Some(self.velocity),
+        }
+    }
+}
+
+/// Simulated motors behind the hardware abstraction: each joint exports
+/// position and velocity states and a velocity command. Every read advances
+/// the simulation by `period` seconds; joints without a command coast to a
+/// stop.
+pub struct SimulatedMotors {
+    name: String,
+    pub period: f32,
+    joints: Vec<(String, SimulatedMotor)>,
+}
+
+impl SimulatedMotors {
+    pub fn new(name: &str, joints: Vec<(String, SimulatedMotor)>, period: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            period,
+            joints,
+        }
+    }
+
+    pub fn motor(&self, joint: &str) -> Option<&SimulatedMotor> {
+        self.joints
+            .iter()
+            .find(|(name, _)| name == joint)
+            .map(|(_, motor)| motor)
+    }
+}
+
+impl Hardware for SimulatedMotors {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn state_interfaces(&self) -> Vec<InterfaceName> {
+        self.joints
+            .iter()
+            .flat_map(|(joint, _)| {
+                [
+                    InterfaceName::new(joint, InterfaceKind::Position),
+                    InterfaceName::new(joint, InterfaceKind::Velocity),
+                ]
+            })
+            .collect()
+    }
+
+    fn command_interfaces(&self) -> Vec<InterfaceName> {
+        self.joints
+            .iter()
+            .map(|(joint, _)| InterfaceName::new(joint, InterfaceKind::Velocity))
+            .collect()
+    }
+
+    fn read(&mut self, states: &mut [f32]) -> io::Result<()> {
+        for ((_, motor), states) in self.joints.iter_mut().zip(states.chunks_mut(2)) {
+            motor.step(self.period);
+            let feedback = motor.feedback();
+            states[0] = feedback.position.unwrap_or(f32::NAN);
+            states[1] = feedback.velocity.unwrap_or(f32::NAN);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, commands: &[Option<f32>]) -> io::Result<()> {
+        for ((_, motor), command) in self.joints.iter_mut().zip(commands) {
+            motor.command(CommandVelocity(command.unwrap_or(0.0)));
+        }
+        Ok(())
+    }
+}
+
+/// A simulated differential drive standing in for the wheel motor
+/// controller: takes left and right wheel commands, reports the wheel
+/// encoders, and moves the shared ground-truth pose that a
+/// [`super::SimulatedLidar`] scans from. Each input advances the simulation to
+/// its timestamp, or by `period` seconds if that gives no time.
+pub struct SimulatedDrive {
+    pub drive: DifferentialDrive,
+    pub period: f32,
+    motors: Mutex<(SimulatedMotor, SimulatedMotor)>,
+    ground_truth: Arc<RwLock<Pose2D>>,
+    last_timestamp: TimestampDelta,
+}
+
+impl SimulatedDrive {
+    pub fn new(
+        drive: DifferentialDrive,
+        motor: SimulatedMotor,
+        ground_truth: Arc<RwLock<Pose2D>>,
+        period: f32,
+    ) -> Self {
+        Self {
+            drive,
+            period,
+            motors: Mutex::new((motor.clone(), motor)),
+            ground_truth,
+            last_timestamp: TimestampDelta::new(),
+        }
+    }
+
+    /// Applies wheel commands for `dt` seconds and returns the encoder
+    /// readings.
+    pub fn step(
+        &self,
+        left: CommandVelocity,
+        right: CommandVelocity,
+        dt: f32,
+    ) -> (EncoderFeedback, EncoderFeedback) {
+        let mut motors = self.motors.lock().expect("Motors lock poisoned");
+        let (left_motor, right_motor) = &mut *motors;
+        left_motor.command(left);
+        right_motor.command(right);
+        let (left_turned, right_turned) = (left_motor.step(dt), right_motor.step(dt));
+        if dt > 0.0 {
+            let twist = self.drive.twist(left_turned / dt, right_turned / dt);
+            let mut pose = self
+                .ground_truth
+                .write()
+                .expect("Ground truth lock poisoned");
+            *pose = pose.compose(&twist.integrate(dt));
+        }
+        (left_motor.feedback(), right_motor.feedback())
+    }
+}
+
+impl Task for SimulatedDrive {
+    type Input = CarbonData<(CommandVelocity, CommandVelocity)>;
+    type Output = CarbonData<(EncoderFeedback, EncoderFeedback)>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let dt = self
+            .last_timestamp
+            .step(input.metadata.timestamp, self.period);
+        input.map(|(left, right)| self.step(left, right, dt))
+    }
+}
+
+impl Controller<(CommandVelocity, CommandVelocity), (EncoderFeedback, EncoderFeedback)>
+    for SimulatedDrive
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    #[test]
+    fn speed_follows_a_step_with_a_first_order_lag() {
+        let mut motor = SimulatedMotor::new(0.1, 20.0);
+        motor.command(CommandVelocity(10.0));
+
+        let mut turned = 0.0;
+        for _ in 0..100 {
+            turned += motor.step(0.001);
+        }
+
+        // After one time constant: 63% of the step, and the integral of it.
+        let lag = 1.0 - (-1.0f32).exp();
+        assert!((motor.velocity() - 10.0 * lag).abs() < 1e-3);
+        assert!((motor.position() - (1.0 - lag)).abs() < 1e-3);
+        assert_eq!(turned, motor.position());
+
+        // One long step lands on the same curve as many short ones.
+        let mut coarse = SimulatedMotor::new(0.1, 20.0);
+        coarse.command(CommandVelocity(10.0));
+        coarse.step(0.1);
+        assert!((coarse.velocity() - motor.velocity()).abs() < 1e-3);
+        assert!((coarse.position() - motor.position()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn commands_are_clamped_and_zero_lag_is_immediate() {
+        let mut motor = SimulatedMotor::new(0.0, 5.0);
+        motor.command(CommandVelocity(-8.0));
+        assert_eq!(motor.step(0.5), -2.5);
+        assert_eq!(motor.velocity(), -5.0);
+    }
+
+    #[test]
+    fn the_encoder_reports_whole_counts() {
+        let resolution = TAU / 1024.0;
+        let mut motor = SimulatedMotor::new(0.05, 20.0).with_encoder_resolution(resolution);
+        motor.command(CommandVelocity(3.0));
+
+        for _ in 0..37 {
+            motor.step(0.013);
+            let feedback = motor.feedback();
+            let position = feedback.position.unwrap();
+            let counts = position / resolution;
+            assert!((counts - counts.round()).abs() < 1e-3);
+            assert!((position - motor.position()).abs() <= resolution / 2.0 + 1e-6);
+            assert_eq!(feedback.velocity, Some(motor.velocity()));
+        }
+    }
+}
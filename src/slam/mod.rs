@@ -0,0 +1,478 @@
+pub mod pose_graph;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use glam::Mat3;
+
+use crate::lidar::PointCloud;
+use crate::links::{CarbonData, CarbonTaskConfiguration, Controller, Task};
+use crate::mapping::occupancy_grid::OccupancyGrid;
+use crate::mapping::PosedScan;
+use crate::primitives::{Pose2D, Vec2};
+use crate::scan_matching::icp::{match_scans, planar_points, IcpConfig, IcpResult, IcpTarget};
+use crate::scan_matching::{adjoint, covariance_matrix};
+use pose_graph::{EdgeKind, OptimizationSummary, OptimizerConfig, PoseGraph, PoseGraphEdge};
+
+#[derive(Clone, Debug)]
+pub struct SlamConfig {
+    /// Matching of each keyframe against the previous one.
+    pub icp: IcpConfig,
+    /// Matching against loop closure candidates, which start from a worse
+    /// guess and usually need a wider correspondence distance.
+    pub loop_closure_icp: IcpConfig,
+    /// Pose of the lidar in the base frame.
+    pub sensor_mount: Pose2D,
+    /// A new keyframe is added once the base moved or turned this much.
+    pub keyframe_distance: f32,
+    pub keyframe_angle: f32,
+    /// Variance of x, y and heading of the odometry edge between keyframes.
+    pub odometry_variance: [f32; 3],
+    /// Added to scan match covariances so near-perfect matches don't dominate.
+    pub scan_match_min_variance: [f32; 3],
+    /// Scan matches worse than this are not added as edges.
+    pub max_fitness: f32,
+    pub min_inlier_ratio: f32,
+    /// Keyframes within this distance of a new one are loop closure candidates.
+    pub loop_closure_radius: f32,
+    /// The most recent keyframes are skipped as loop closure candidates.
+    pub loop_closure_min_separation: usize,
+    pub loop_closure_max_fitness: f32,
+    pub loop_closure_min_inlier_ratio: f32,
+    pub optimizer: OptimizerConfig,
+    /// Beams longer than this only clear space when rendering the map.
+    pub max_range: f32,
+}
+
+impl Default for SlamConfig {
+    fn default() -> Self {
+        Self {
+            icp: IcpConfig::default(),
+            loop_closure_icp: IcpConfig {
+                max_correspondence_distance: 1.0,
+                max_iterations: 50,
+                ..Default::default()
+            },
+            sensor_mount: Pose2D::identity(),
+            keyframe_distance: 0.5,
+            keyframe_angle: 0.5,
+            odometry_variance: [0.01, 0.01, 0.005],
+            scan_match_min_variance: [1e-4, 1e-4, 1e-5],
+            max_fitness: 0.1,
+            min_inlier_ratio: 0.5,
+            loop_closure_radius: 3.0,
+            loop_closure_min_separation: 10,
+            loop_closure_max_fitness: 0.05,
+            loop_closure_min_inlier_ratio: 0.7,
+            optimizer: OptimizerConfig {
+                huber_delta: Some(3.0),
+                ..Default::default()
+            },
+            max_range: 12.0,
+        }
+    }
+}
+
+pub struct Keyframe {
+    /// Index of this keyframe's pose in the [`PoseGraph`].
+    pub node: usize,
+    /// Wheel odometry pose the scan was taken at.
+    pub odometry: Pose2D,
+    /// Scan in the sensor frame.
+    pub scan: PointCloud,
+    target: IcpTarget,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlamEvent {
+    /// Not enough motion since the last keyframe.
+    Skipped,
+    KeyframeAdded,
+    /// A keyframe was added, closed at least one loop and the graph was
+    /// re-optimized.
+    LoopClosed(OptimizationSummary),
+}
+
+/// 2D graph SLAM: keyframes linked by odometry and scan match edges, with
+/// loop closures found by matching new keyframes against earlier nearby ones.
+pub struct GraphSlam {
+    pub config: SlamConfig,
+    graph: PoseGraph,
+    keyframes: Vec<Keyframe>,
+    latest_odometry: Pose2D,
+}
+
+impl GraphSlam {
+    pub fn new(config: SlamConfig) -> Self {
+        Self {
+            config,
+            graph: PoseGraph::new(),
+            keyframes: Vec::new(),
+            latest_odometry: Pose2D::identity(),
+        }
+    }
+
+    pub fn graph(&self) -> &PoseGraph {
+        &self.graph
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Pose of a keyframe's base in the map frame.
+    pub fn keyframe_pose(&self, keyframe: &Keyframe) -> Pose2D {
+        self.graph.nodes()[keyframe.node]
+    }
+
+    /// Current base pose in the map frame: the last keyframe's optimized pose
+    /// plus odometry since.
+    pub fn pose(&self) -> Pose2D {
+        match self.keyframes.last() {
+            Some(last) => self
+                .keyframe_pose(last)
+                .compose(&last.odometry.between(&self.latest_odometry)),
+            None => Pose2D::identity(),
+        }
+    }
+
+    /// The map -> odom correction implied by the current pose.
+    pub fn correction(&self) -> Pose2D {
+        self.pose().compose(&self.latest_odometry.inverse())
+    }
+
+    pub fn update(&mut self, scan: &PosedScan) -> SlamEvent {
+        self.latest_odometry = scan.base_pose;
+        let points = planar_points(&scan.scan.points);
+        let Some(last) = self.keyframes.last() else {
+            self.add_keyframe(Pose2D::identity(), scan, points);
+            return SlamEvent::KeyframeAdded;
+        };
+
+        let wheel_delta = last.odometry.between(&scan.base_pose);
+        if wheel_delta.position().length() < self.config.keyframe_distance
+            && wheel_delta.theta.abs() < self.config.keyframe_angle
+        {
+            return SlamEvent::Skipped;
+        }
+
+        let previous = last.node;
+        let sequential_match = self
+            .match_keyframe(last, &points, &wheel_delta, &self.config.icp)
+            .filter(|result| {
+                result.fitness <= self.config.max_fitness
+                    && result.inlier_ratio >= self.config.min_inlier_ratio
+            });
+        let previous_pose = self.graph.nodes()[previous];
+        let delta = sequential_match
+            .as_ref()
+            .map_or(wheel_delta, |result| self.base_delta(result));
+        let node = self.add_keyframe(previous_pose.compose(&delta), scan, points);
+
+        let odometry_covariance = Mat3::from_diagonal(self.config.odometry_variance.into());
+        self.add_edge(
+            previous,
+            node,
+            EdgeKind::Odometry,
+            wheel_delta,
+            odometry_covariance,
+        );
+        if let Some(result) = sequential_match {
+            self.add_match_edge(previous, node, EdgeKind::ScanMatch, &result);
+        }
+
+        let mut loop_closed = false;
+        for (candidate, result) in self.find_loop_closures() {
+            self.add_match_edge(candidate, node, EdgeKind::LoopClosure, &result);
+            loop_closed = true;
+        }
+        if loop_closed {
+            SlamEvent::LoopClosed(self.graph.optimize(&self.config.optimizer))
+        } else {
+            SlamEvent::KeyframeAdded
+        }
+    }
+
+    fn add_keyframe(&mut self, pose: Pose2D, scan: &PosedScan, points: Vec<Vec2>) -> usize {
+        let node = self.graph.add_node(pose);
+        self.keyframes.push(Keyframe {
+            node,
+            odometry: scan.base_pose,
+            scan: scan.scan.clone(),
+            target: IcpTarget::new(points, self.config.icp.normal_neighbours),
+        });
+        node
+    }
+
+    // Matches `points` against a keyframe, given a guess of the base motion
+    // from the keyframe to the new scan.
+    fn match_keyframe(
+        &self,
+        keyframe: &Keyframe,
+        points: &[Vec2],
+        base_guess: &Pose2D,
+        config: &IcpConfig,
+    ) -> Option<IcpResult> {
+        let mount = self.config.sensor_mount;
+        let guess = mount.inverse().compose(base_guess).compose(&mount);
+        match_scans(&keyframe.target, points, &guess, config).filter(|result| result.converged)
+    }
+
+    // Base motion measured by a match between sensor frames.
+    fn base_delta(&self, result: &IcpResult) -> Pose2D {
+        let mount = self.config.sensor_mount;
+        mount
+            .compose(&result.estimate.pose)
+            .compose(&mount.inverse())
+    }
+
+    fn add_match_edge(&mut self, from: usize, to: usize, kind: EdgeKind, result: &IcpResult) {
+        let mount = adjoint(&self.config.sensor_mount);
+        let covariance = mount * covariance_matrix(&result.estimate.covariance) * mount.transpose()
+            + Mat3::from_diagonal(self.config.scan_match_min_variance.into());
+        self.add_edge(from, to, kind, self.base_delta(result), covariance);
+    }
+
+    fn add_edge(
+        &mut self,
+        from: usize,
+        to: usize,
+        kind: EdgeKind,
+        measurement: Pose2D,
+        covariance: Mat3,
+    ) {
+        let covariance = covariance.transpose().to_cols_array_2d();
+        if let Some(edge) = PoseGraphEdge::from_covariance(from, to, kind, measurement, &covariance)
+        {
+            self.graph.add_edge(edge);
+        }
+    }
+
+    // Matches the newest keyframe against earlier keyframes near it.
+    fn find_loop_closures(&self) -> Vec<(usize, IcpResult)> {
+        let Some(newest) = self.keyframes.last() else {
+            return Vec::new();
+        };
+        let newest_pose = self.keyframe_pose(newest);
+        let points: Vec<Vec2> = planar_points(&newest.scan.points);
+        let candidates = self
+            .keyframes
+            .len()
+            .saturating_sub(self.config.loop_closure_min_separation + 1);
+        self.keyframes[..candidates]
+            .iter()
+            .filter_map(|keyframe| {
+                let pose = self.keyframe_pose(keyframe);
+                if pose.position().distance(newest_pose.position())
+                    > self.config.loop_closure_radius
+                {
+                    return None;
+                }
+                let guess = pose.between(&newest_pose);
+                self.match_keyframe(keyframe, &points, &guess, &self.config.loop_closure_icp)
+                    .filter(|result| {
+                        result.fitness <= self.config.loop_closure_max_fitness
+                            && result.inlier_ratio >= self.config.loop_closure_min_inlier_ratio
+                    })
+                    .map(|result| (keyframe.node, result))
+            })
+            .collect()
+    }
+
+    /// Re-optimizes the whole graph.
+    pub fn optimize(&mut self) -> OptimizationSummary {
+        self.graph.optimize(&self.config.optimizer)
+    }
+
+    /// Renders all keyframe scans at their optimized poses into a new grid.
+    pub fn render_map(&self, resolution: f32) -> OccupancyGrid {
+        let reach = Vec2::splat(self.config.max_range);
+        let (mut min, mut max) = (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY));
+        for keyframe in &self.keyframes {
+            let position = self.keyframe_pose(keyframe).position();
+            min = min.min(position - reach);
+            max = max.max(position + reach);
+        }
+        if self.keyframes.is_empty() {
+            (min, max) = (-reach, reach);
+        }
+        let mut grid = OccupancyGrid::covering(resolution, min, max);
+        for keyframe in &self.keyframes {
+            self.integrate_keyframe(&mut grid, keyframe);
+        }
+        grid
+    }
+
+    fn integrate_keyframe(&self, grid: &mut OccupancyGrid, keyframe: &Keyframe) {
+        let sensor_pose = self
+            .keyframe_pose(keyframe)
+            .compose(&self.config.sensor_mount);
+        grid.integrate_scan(&keyframe.scan, &sensor_pose, self.config.max_range);
+    }
+}
+
+/// Runs [`GraphSlam`] as a task, keeping a shared occupancy grid up to date.
+/// New keyframes are integrated as they arrive; the whole map is re-rendered
+/// after a loop closure moves earlier keyframes.
+pub struct Slam {
+    slam: Mutex<GraphSlam>,
+    map: Arc<RwLock<OccupancyGrid>>,
+    resolution: f32,
+}
+
+impl Slam {
+    pub fn new(slam: GraphSlam, resolution: f32) -> Self {
+        let map = slam.render_map(resolution);
+        Self {
+            slam: Mutex::new(slam),
+            map: Arc::new(RwLock::new(map)),
+            resolution,
+        }
+    }
+
+    pub fn map(&self) -> Arc<RwLock<OccupancyGrid>> {
+        self.map.clone()
+    }
+}
+
+impl Task for Slam {
+    type Input = CarbonData<PosedScan>;
+    type Output = CarbonData<Pose2D>;
+
+    fn setup(&self, _configuration: Option<&CarbonTaskConfiguration>) {}
+
+    fn process(&self, input: Self::Input) -> Self::Output {
+        let mut slam = self.slam.lock().expect("GraphSlam lock poisoned");
+        input.map(|scan| {
+            match slam.update(&scan) {
+                SlamEvent::Skipped => {}
+                SlamEvent::KeyframeAdded => {
+                    let mut map = self.map.write().expect("OccupancyGrid lock poisoned");
+                    let position = slam.pose().position();
+                    let reach = Vec2::splat(slam.config.max_range);
+                    map.expand_to_include(position - reach, position + reach, 1);
+                    if let Some(keyframe) = slam.keyframes().last() {
+                        slam.integrate_keyframe(&mut map, keyframe);
+                    }
+                }
+                SlamEvent::LoopClosed(_) => {
+                    *self.map.write().expect("OccupancyGrid lock poisoned") =
+                        slam.render_map(self.resolution);
+                }
+            }
+            slam.pose()
+        })
+    }
+}
+
+impl Controller<PosedScan, Pose2D> for Slam {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{normalize_angle, Point, Vec3};
+
+    // Walls of a 10 m by 6 m room with a pillar, sampled every 5 cm, as seen
+    // from `pose` within 8 m. The pillar makes the room asymmetric.
+    fn scan(pose: &Pose2D) -> PointCloud {
+        let mut walls = Vec::new();
+        let mut segment = |from: Vec2, to: Vec2| {
+            let steps = (from.distance(to) / 0.05).ceil() as usize;
+            walls.extend((0..steps).map(|step| from.lerp(to, step as f32 / steps as f32)));
+        };
+        let corners = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(10.0, 0.0),
+            Vec2::new(10.0, 6.0),
+            Vec2::new(0.0, 6.0),
+        ];
+        for index in 0..4 {
+            segment(corners[index], corners[(index + 1) % 4]);
+        }
+        segment(Vec2::new(6.0, 4.0), Vec2::new(6.5, 4.0));
+        segment(Vec2::new(6.5, 4.0), Vec2::new(6.5, 4.5));
+        let inverse = pose.inverse();
+        let points = walls
+            .into_iter()
+            .map(|point| inverse.transform_point(point))
+            .filter(|point| point.length() < 8.0)
+            .map(|point| Point::new(Vec3::new(point.x, point.y, 0.0), 1.0))
+            .collect();
+        PointCloud::new(points)
+    }
+
+    #[test]
+    fn scan_matching_corrects_drifting_odometry() {
+        let mut slam = GraphSlam::new(SlamConfig::default());
+        let start = Pose2D::new(2.0, 3.0, 0.0);
+        for step in 0..8 {
+            let truth = Pose2D::new(2.0 + 0.6 * step as f32, 3.0, 0.0);
+            // Wheels that overshoot by a tenth.
+            let odometry = Pose2D::new(0.66 * step as f32, 0.0, 0.0);
+            let event = slam.update(&PosedScan {
+                scan: scan(&truth),
+                base_pose: odometry,
+            });
+            assert_eq!(event, SlamEvent::KeyframeAdded, "step {step}");
+        }
+
+        let truth = start.between(&Pose2D::new(2.0 + 0.6 * 7.0, 3.0, 0.0));
+        let pose = slam.pose();
+        assert_eq!(slam.keyframes().len(), 8);
+        assert!(
+            pose.position().distance(truth.position()) < 0.05,
+            "{pose:?}"
+        );
+        assert!(pose.theta.abs() < 0.01);
+        // The correction maps odometry onto the map.
+        let corrected = slam
+            .correction()
+            .compose(&Pose2D::new(0.66 * 7.0, 0.0, 0.0));
+        assert!(corrected.position().distance(pose.position()) < 1e-4);
+    }
+
+    #[test]
+    fn returning_to_the_start_closes_the_loop() {
+        let mut slam = GraphSlam::new(SlamConfig {
+            loop_closure_min_separation: 3,
+            ..Default::default()
+        });
+        // Out along the room and back, turning at the far end.
+        let mut path: Vec<Pose2D> = (0..6)
+            .map(|step| Pose2D::new(2.0 + 0.6 * step as f32, 3.0, 0.0))
+            .collect();
+        path.push(Pose2D::new(5.0, 3.0, 1.0));
+        path.push(Pose2D::new(5.0, 3.0, 2.0));
+        path.push(Pose2D::new(5.0, 3.0, 3.1));
+        path.extend((1..6).map(|step| Pose2D::new(5.0 - 0.6 * step as f32, 3.0, 3.1)));
+        let start = path[0];
+
+        let mut closed = None;
+        for truth in &path {
+            // Odometry that slowly loses heading.
+            let relative = start.between(truth);
+            let odometry = Pose2D::new(relative.x, relative.y, relative.theta * 1.02);
+            if let SlamEvent::LoopClosed(summary) = slam.update(&PosedScan {
+                scan: scan(truth),
+                base_pose: odometry,
+            }) {
+                closed.get_or_insert(summary);
+            }
+        }
+
+        let summary = closed.expect("no loop was closed");
+        assert!(summary.final_error <= summary.initial_error);
+        assert_eq!(slam.graph().nodes()[0], Pose2D::identity());
+        let truth = start.between(path.last().unwrap());
+        let pose = slam.pose();
+        assert!(
+            pose.position().distance(truth.position()) < 0.05,
+            "{pose:?}"
+        );
+        assert!(normalize_angle(pose.theta - truth.theta).abs() < 0.02);
+        assert!(slam
+            .graph()
+            .edges()
+            .iter()
+            .any(|edge| edge.kind == EdgeKind::LoopClosure));
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::BTreeMap;
+
+use glam::{DMat2, DMat3, DVec2, DVec3};
+
+use crate::primitives::{normalize_angle, wrap_angle, Pose2D};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    Odometry,
+    ScanMatch,
+    LoopClosure,
+}
+
+/// A relative pose measurement: `measurement` is the pose of node `to` in the
+/// frame of node `from`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoseGraphEdge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+    pub measurement: Pose2D,
+    /// Inverse covariance of the measurement, row-major.
+    pub information: [[f32; 3]; 3],
+}
+
+impl PoseGraphEdge {
+    /// Builds an edge from a measurement covariance, which must be invertible.
+    pub fn from_covariance(
+        from: usize,
+        to: usize,
+        kind: EdgeKind,
+        measurement: Pose2D,
+        covariance: &[[f32; 3]; 3],
+    ) -> Option<Self> {
+        let covariance = DMat3::from_cols_array_2d(&covariance.map(|row| row.map(f64::from)));
+        if covariance.determinant().abs() < 1e-18 {
+            return None;
+        }
+        let information = covariance.inverse().transpose().to_cols_array_2d();
+        Some(Self {
+            from,
+            to,
+            kind,
+            measurement,
+            information: information.map(|row| row.map(|v| v as f32)),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OptimizerConfig {
+    pub max_iterations: usize,
+    /// Levenberg-Marquardt damping to start from; zero gives plain Gauss-Newton.
+    pub initial_damping: f64,
+    /// Stop once the relative decrease in error falls below this.
+    pub tolerance: f64,
+    /// Huber kernel width on the Mahalanobis distance of each edge, limiting
+    /// the pull of bad loop closures. `None` disables it.
+    pub huber_delta: Option<f64>,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        Self {
+            max_iterations: 20,
+            initial_damping: 1e-4,
+            tolerance: 1e-6,
+            huber_delta: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptimizationSummary {
+    pub iterations: usize,
+    pub initial_error: f64,
+    pub final_error: f64,
+}
+
+/// A graph of 2D poses linked by relative measurements. The first node anchors
+/// the graph and is held fixed during optimization.
+#[derive(Clone, Debug, Default)]
+pub struct PoseGraph {
+    nodes: Vec<Pose2D>,
+    edges: Vec<PoseGraphEdge>,
+}
+
+impl PoseGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, pose: Pose2D) -> usize {
+        self.nodes.push(pose);
+        self.nodes.len() - 1
+    }
+
+    /// Panics if either end of the edge is not a node.
+    pub fn add_edge(&mut self, edge: PoseGraphEdge) {
+        assert!(
+            edge.from < self.nodes.len() && edge.to < self.nodes.len(),
+            "edge between missing nodes {} and {}",
+            edge.from,
+            edge.to
+        );
+        self.edges.push(edge);
+    }
+
+    pub fn nodes(&self) -> &[Pose2D] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> Option<&Pose2D> {
+        self.nodes.get(index)
+    }
+
+    pub fn edges(&self) -> &[PoseGraphEdge] {
+        &self.edges
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Total weighted squared error over all edges.
+    pub fn error(&self, huber_delta: Option<f64>) -> f64 {
+        let poses = self.poses();
+        self.edges
+            .iter()
+            .map(|edge| {
+                let linearized = linearize(edge, &poses);
+                robust_cost(linearized.chi_squared, huber_delta)
+            })
+            .sum()
+    }
+
+    fn poses(&self) -> Vec<DVec3> {
+        self.nodes
+            .iter()
+            .map(|pose| DVec3::new(pose.x.into(), pose.y.into(), pose.theta.into()))
+            .collect()
+    }
+
+    /// Levenberg-Marquardt over all poses except the first.
+    pub fn optimize(&mut self, config: &OptimizerConfig) -> OptimizationSummary {
+        let mut poses = self.poses();
+        let initial_error = self.error(config.huber_delta);
+        let mut summary = OptimizationSummary {
+            iterations: 0,
+            initial_error,
+            final_error: initial_error,
+        };
+        if poses.len() < 2 || self.edges.is_empty() {
+            return summary;
+        }
+
+        let mut error = initial_error;
+        let mut damping = config.initial_damping;
+        while summary.iterations < config.max_iterations {
+            summary.iterations += 1;
+            let mut system = BlockSystem::new(poses.len() - 1);
+            for edge in &self.edges {
+                let linearized = linearize(edge, &poses);
+                let weight = robust_weight(linearized.chi_squared, config.huber_delta);
+                let information = linearized.information * weight;
+                let blocks = [
+                    (edge.from, linearized.jacobian_from),
+                    (edge.to, linearized.jacobian_to),
+                ];
+                for (node, jacobian) in blocks {
+                    // Node 0 is fixed and has no variables.
+                    let Some(row) = node.checked_sub(1) else {
+                        continue;
+                    };
+                    system.gradient[row] += jacobian.transpose() * information * linearized.error;
+                    for (other, other_jacobian) in blocks {
+                        let Some(column) = other.checked_sub(1) else {
+                            continue;
+                        };
+                        if column >= row {
+                            system.add(
+                                row,
+                                column,
+                                jacobian.transpose() * information * other_jacobian,
+                            );
+                        }
+                    }
+                }
+            }
+
+            let mut improved = false;
+            // Raise the damping until a step lowers the error.
+            for _ in 0..10 {
+                let Some(step) = system.solve_damped(damping) else {
+                    damping = (damping * 10.0).max(1e-6);
+                    continue;
+                };
+                let candidate: Vec<DVec3> = poses
+                    .iter()
+                    .enumerate()
+                    .map(|(index, pose)| match index.checked_sub(1) {
+                        Some(variable) => {
+                            let updated = *pose - step[variable];
+                            DVec3::new(updated.x, updated.y, wrap_angle(updated.z))
+                        }
+                        None => *pose,
+                    })
+                    .collect();
+                let candidate_error = total_error(&self.edges, &candidate, config.huber_delta);
+                if candidate_error <= error {
+                    poses = candidate;
+                    let decrease = error - candidate_error;
+                    error = candidate_error;
+                    damping *= 0.1;
+                    improved = decrease > config.tolerance * error.max(f64::EPSILON);
+                    break;
+                }
+                damping = (damping * 10.0).max(1e-6);
+            }
+            if !improved {
+                break;
+            }
+        }
+
+        for (node, pose) in self.nodes.iter_mut().zip(&poses) {
+            *node = Pose2D::new(pose.x as f32, pose.y as f32, normalize_angle(pose.z as f32));
+        }
+        summary.final_error = error;
+        summary
+    }
+}
+
+fn total_error(edges: &[PoseGraphEdge], poses: &[DVec3], huber_delta: Option<f64>) -> f64 {
+    edges
+        .iter()
+        .map(|edge| robust_cost(linearize(edge, poses).chi_squared, huber_delta))
+        .sum()
+}
+
+fn robust_cost(chi_squared: f64, huber_delta: Option<f64>) -> f64 {
+    match huber_delta {
+        Some(delta) if chi_squared > delta * delta => {
+            2.0 * delta * chi_squared.sqrt() - delta * delta
+        }
+        _ => chi_squared,
+    }
+}
+
+fn robust_weight(chi_squared: f64, huber_delta: Option<f64>) -> f64 {
+    match huber_delta {
+        Some(delta) if chi_squared > delta * delta => delta / chi_squared.sqrt(),
+        _ => 1.0,
+    }
+}
+
+struct LinearizedEdge {
+    error: DVec3,
+    jacobian_from: DMat3,
+    jacobian_to: DMat3,
+    information: DMat3,
+    chi_squared: f64,
+}
+
+// Error and Jacobians of an edge (Grisetti et al., "A Tutorial on Graph-Based
+// SLAM", section IV-A).
+fn linearize(edge: &PoseGraphEdge, poses: &[DVec3]) -> LinearizedEdge {
+    let (from, to) = (poses[edge.from], poses[edge.to]);
+    let measurement = DVec3::new(
+        edge.measurement.x.into(),
+        edge.measurement.y.into(),
+        edge.measurement.theta.into(),
+    );
+    let rotation_from = DMat2::from_angle(from.z);
+    let rotation_measurement = DMat2::from_angle(measurement.z);
+    let rotation_from_derivative = DMat2::from_cols(
+        DVec2::new(-from.z.sin(), from.z.cos()),
+        DVec2::new(-from.z.cos(), -from.z.sin()),
+    );
+    let offset = to.truncate() - from.truncate();
+
+    let error_translation = rotation_measurement.transpose()
+        * (rotation_from.transpose() * offset - measurement.truncate());
+    let error = error_translation.extend(wrap_angle(to.z - from.z - measurement.z));
+
+    let translation_jacobian = rotation_measurement.transpose() * rotation_from.transpose();
+    let heading_jacobian =
+        rotation_measurement.transpose() * rotation_from_derivative.transpose() * offset;
+    let jacobian_from = DMat3::from_cols(
+        (-translation_jacobian.x_axis).extend(0.0),
+        (-translation_jacobian.y_axis).extend(0.0),
+        heading_jacobian.extend(-1.0),
+    );
+    let jacobian_to = DMat3::from_cols(
+        translation_jacobian.x_axis.extend(0.0),
+        translation_jacobian.y_axis.extend(0.0),
+        DVec3::Z,
+    );
+    let information =
+        DMat3::from_cols_array_2d(&edge.information.map(|row| row.map(f64::from))).transpose();
+    LinearizedEdge {
+        error,
+        jacobian_from,
+        jacobian_to,
+        information,
+        chi_squared: error.dot(information * error),
+    }
+}
+
+/// Block-sparse symmetric normal equations `H Δ = g` with 3x3 blocks, storing
+/// the upper triangle row by row.
+struct BlockSystem {
+    rows: Vec<BTreeMap<usize, DMat3>>,
+    gradient: Vec<DVec3>,
+}
+
+impl BlockSystem {
+    fn new(size: usize) -> Self {
+        Self {
+            rows: vec![BTreeMap::new(); size],
+            gradient: vec![DVec3::ZERO; size],
+        }
+    }
+
+    fn add(&mut self, row: usize, column: usize, block: DMat3) {
+        *self.rows[row].entry(column).or_insert(DMat3::ZERO) += block;
+    }
+
+    /// Solves `(H + λ diag(H)) Δ = g` by block LDLᵀ factorization, keeping
+    /// the sparsity of chains and adding fill-in only where loops close.
+    fn solve_damped(&self, damping: f64) -> Option<Vec<DVec3>> {
+        let size = self.rows.len();
+        let mut rows = self.rows.clone();
+        for (index, row) in rows.iter_mut().enumerate() {
+            let diagonal = row.entry(index).or_insert(DMat3::ZERO);
+            let scale = DVec3::new(diagonal.x_axis.x, diagonal.y_axis.y, diagonal.z_axis.z);
+            *diagonal += DMat3::from_diagonal(scale * damping + DVec3::splat(1e-9));
+        }
+
+        // Factor into U, unit block upper triangular, and block diagonal D.
+        let mut diagonal_inverses = Vec::with_capacity(size);
+        for index in 0..size {
+            let diagonal = rows[index][&index];
+            if diagonal.determinant().abs() < 1e-18 {
+                return None;
+            }
+            let diagonal_inverse = diagonal.inverse();
+            let above: Vec<(usize, DMat3)> = rows[index]
+                .range(index + 1..)
+                .map(|(&column, &block)| (column, block))
+                .collect();
+            for (position, &(row, row_block)) in above.iter().enumerate() {
+                let left = row_block.transpose() * diagonal_inverse;
+                for &(column, column_block) in &above[position..] {
+                    *rows[row].entry(column).or_insert(DMat3::ZERO) -= left * column_block;
+                }
+            }
+            for (_, block) in rows[index].range_mut(index + 1..) {
+                *block = diagonal_inverse * *block;
+            }
+            diagonal_inverses.push(diagonal_inverse);
+        }
+
+        // Uᵀ z = g, then D w = z, then U Δ = w.
+        let mut solution = self.gradient.clone();
+        for index in 0..size {
+            let value = solution[index];
+            for (&column, block) in rows[index].range(index + 1..) {
+                solution[column] -= block.transpose() * value;
+            }
+        }
+        for (value, diagonal_inverse) in solution.iter_mut().zip(&diagonal_inverses) {
+            *value = *diagonal_inverse * *value;
+        }
+        for index in (0..size).rev() {
+            let mut value = solution[index];
+            for (&column, block) in rows[index].range(index + 1..) {
+                value -= *block * solution[column];
+            }
+            solution[index] = value;
+        }
+        Some(solution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn edge(from: usize, to: usize, kind: EdgeKind, measurement: Pose2D) -> PoseGraphEdge {
+        let covariance = [[0.01, 0.0, 0.0], [0.0, 0.01, 0.0], [0.0, 0.0, 0.001]];
+        PoseGraphEdge::from_covariance(from, to, kind, measurement, &covariance).unwrap()
+    }
+
+    // A 2 m square driven anticlockwise, with drifting initial guesses and a
+    // loop closure back to the start.
+    fn square() -> (PoseGraph, Vec<Pose2D>) {
+        let truth = vec![
+            Pose2D::new(0.0, 0.0, 0.0),
+            Pose2D::new(2.0, 0.0, FRAC_PI_2),
+            Pose2D::new(2.0, 2.0, 2.0 * FRAC_PI_2),
+            Pose2D::new(0.0, 2.0, -FRAC_PI_2),
+        ];
+        let mut graph = PoseGraph::new();
+        for (index, pose) in truth.iter().enumerate() {
+            let drift = 0.15 * index as f32;
+            graph.add_node(Pose2D::new(
+                pose.x + drift,
+                pose.y - drift,
+                pose.theta + drift,
+            ));
+        }
+        for index in 0..truth.len() {
+            let next = (index + 1) % truth.len();
+            let kind = if next == 0 {
+                EdgeKind::LoopClosure
+            } else {
+                EdgeKind::Odometry
+            };
+            graph.add_edge(edge(index, next, kind, truth[index].between(&truth[next])));
+        }
+        (graph, truth)
+    }
+
+    #[test]
+    fn a_closed_square_is_pulled_back_into_shape() {
+        let (mut graph, truth) = square();
+        let anchor = graph.nodes()[0];
+
+        let summary = graph.optimize(&OptimizerConfig::default());
+
+        assert!(summary.initial_error > 1.0, "{summary:?}");
+        assert!(summary.final_error < 1e-6, "{summary:?}");
+        assert_eq!(graph.nodes()[0], anchor);
+        for (node, truth) in graph.nodes().iter().zip(&truth) {
+            assert!(
+                node.position().distance(truth.position()) < 1e-3,
+                "{node:?}"
+            );
+            assert!(normalize_angle(node.theta - truth.theta).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn huber_kernel_limits_a_bad_loop_closure() {
+        let (mut graph, truth) = square();
+        // A wrong closure claiming the start is a metre off.
+        graph.add_edge(edge(
+            3,
+            0,
+            EdgeKind::LoopClosure,
+            truth[3].between(&Pose2D::new(1.0, 0.0, 0.0)),
+        ));
+        let mut robust = graph.clone();
+
+        graph.optimize(&OptimizerConfig::default());
+        robust.optimize(&OptimizerConfig {
+            huber_delta: Some(1.0),
+            ..Default::default()
+        });
+
+        let error = |graph: &PoseGraph| {
+            graph
+                .nodes()
+                .iter()
+                .zip(&truth)
+                .map(|(node, truth)| node.position().distance(truth.position()))
+                .sum::<f32>()
+        };
+        assert!(error(&robust) < error(&graph));
+    }
+
+    #[test]
+    fn edges_need_an_invertible_covariance() {
+        let singular = [[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+        let edge =
+            PoseGraphEdge::from_covariance(0, 1, EdgeKind::Odometry, Pose2D::identity(), &singular);
+        assert_eq!(edge, None);
+    }
+}
@@ -0,0 +1,494 @@
+mod profile;
+mod topp;
+
+use crate::joints::JointLimits;
+use crate::primitives::{JointCommand, Vec3};
+use profile::UnitProfile;
+
+/// Joint state at a time from the start of a trajectory.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrajectoryPoint {
+    /// Seconds.
+    pub time: f32,
+    pub positions: Vec<f32>,
+    pub velocities: Vec<f32>,
+    pub accelerations: Vec<f32>,
+}
+
+impl TrajectoryPoint {
+    /// Setpoints for every joint. The effort is the feedforward needed to
+    /// accelerate the inertia, or mass, given for each joint; joints without
+    /// one get none.
+    pub fn commands(&self, inertias: &[f32]) -> Vec<JointCommand> {
+        self.positions
+            .iter()
+            .zip(&self.velocities)
+            .zip(&self.accelerations)
+            .enumerate()
+            .map(
+                |(index, ((position, velocity), acceleration))| JointCommand {
+                    position: Some(*position),
+                    velocity: Some(*velocity),
+                    effort: Some(inertias.get(index).copied().unwrap_or(0.0) * acceleration),
+                },
+            )
+            .collect()
+    }
+}
+
+/// Densely sampled joint motion, with points in time order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JointTrajectory {
+    pub joint_names: Vec<String>,
+    pub points: Vec<TrajectoryPoint>,
+}
+
+impl JointTrajectory {
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Seconds from the first point to the last.
+    pub fn duration(&self) -> f32 {
+        match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    /// State at `time`, interpolating positions by cubic Hermite splines
+    /// through the point velocities and the rest linearly. The end points
+    /// are held outside the trajectory.
+    pub fn sample(&self, time: f32) -> Option<TrajectoryPoint> {
+        let after = self.points.partition_point(|point| point.time <= time);
+        let (before, after) = match (after.checked_sub(1), self.points.get(after)) {
+            (Some(before), Some(after)) => (&self.points[before], after),
+            (Some(before), None) => return Some(self.points[before].clone()),
+            (None, _) => return self.points.first().cloned(),
+        };
+        let span = after.time - before.time;
+        let fraction = if span > 0.0 {
+            (time - before.time) / span
+        } else {
+            0.0
+        };
+        let lerp = |a: &[f32], b: &[f32]| -> Vec<f32> {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| a + (b - a) * fraction)
+                .collect()
+        };
+        let (squared, cubed) = (fraction * fraction, fraction * fraction * fraction);
+        let positions = (0..before.positions.len())
+            .map(|joint| {
+                (2.0 * cubed - 3.0 * squared + 1.0) * before.positions[joint]
+                    + (cubed - 2.0 * squared + fraction) * span * before.velocities[joint]
+                    + (3.0 * squared - 2.0 * cubed) * after.positions[joint]
+                    + (cubed - squared) * span * after.velocities[joint]
+            })
+            .collect();
+        Some(TrajectoryPoint {
+            time,
+            positions,
+            velocities: lerp(&before.velocities, &after.velocities),
+            accelerations: lerp(&before.accelerations, &after.accelerations),
+        })
+    }
+
+    /// Setpoints at `time`; see [`TrajectoryPoint::commands`].
+    pub fn commands(&self, time: f32, inertias: &[f32]) -> Option<Vec<JointCommand>> {
+        self.sample(time).map(|point| point.commands(inertias))
+    }
+}
+
+/// How a trajectory is timed along its path.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Stops at every waypoint, with all joints following a shared
+    /// trapezoidal velocity profile along each segment.
+    #[default]
+    Trapezoidal,
+    /// Like [`Profile::Trapezoidal`] but with acceleration ramped within the
+    /// jerk limits.
+    SCurve,
+    /// Moves through the waypoints without stopping, as fast as the velocity
+    /// and acceleration limits allow.
+    TimeOptimal,
+}
+
+/// Times waypoint paths within per-joint limits. Paths may equally be
+/// Cartesian, with one set of limits per axis.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectoryGenerator {
+    pub profile: Profile,
+    /// Seconds between trajectory points.
+    pub period: f32,
+    /// Longest path step, by Euclidean norm, of the time-optimal profile;
+    /// must be positive.
+    pub resolution: f32,
+    /// How far the time-optimal profile may cut corners, by Euclidean norm.
+    /// Without any it stops at every corner.
+    pub max_deviation: f32,
+}
+
+impl Default for TrajectoryGenerator {
+    fn default() -> Self {
+        Self::new(Profile::default(), 0.01)
+    }
+}
+
+impl TrajectoryGenerator {
+    pub fn new(profile: Profile, period: f32) -> Self {
+        Self {
+            profile,
+            period,
+            resolution: 0.01,
+            max_deviation: 0.05,
+        }
+    }
+
+    pub fn with_resolution(mut self, resolution: f32) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    pub fn with_max_deviation(mut self, max_deviation: f32) -> Self {
+        self.max_deviation = max_deviation;
+        self
+    }
+
+    /// A trajectory through `waypoints`, one limit per joint, starting and
+    /// ending at rest. Only the time-optimal profile leaves the straight
+    /// segments between waypoints, within its maximum deviation.
+    ///
+    /// Panics if the time-optimal profile has a non-positive resolution.
+    pub fn generate(
+        &self,
+        joint_names: Vec<String>,
+        waypoints: &[Vec<f32>],
+        limits: &[JointLimits],
+    ) -> JointTrajectory {
+        match self.profile {
+            Profile::Trapezoidal => segmented(joint_names, waypoints, limits, self.period, false),
+            Profile::SCurve => segmented(joint_names, waypoints, limits, self.period, true),
+            Profile::TimeOptimal => topp::time_optimal(
+                joint_names,
+                waypoints,
+                limits,
+                self.resolution,
+                self.max_deviation,
+                self.period,
+            ),
+        }
+    }
+
+    /// A trajectory through Cartesian points, with joints named `x`, `y` and
+    /// `z` and the same limits on each axis.
+    pub fn generate_cartesian(&self, waypoints: &[Vec3], limits: &JointLimits) -> JointTrajectory {
+        let waypoints: Vec<Vec<f32>> = waypoints
+            .iter()
+            .map(|point| point.to_array().to_vec())
+            .collect();
+        self.generate(
+            ["x", "y", "z"].map(String::from).to_vec(),
+            &waypoints,
+            &[*limits; 3],
+        )
+    }
+}
+
+// Moves all joints together along each straight segment, stopping at every
+// waypoint. Sampled every `period` seconds plus each waypoint.
+fn segmented(
+    joint_names: Vec<String>,
+    waypoints: &[Vec<f32>],
+    limits: &[JointLimits],
+    period: f32,
+    jerk_limited: bool,
+) -> JointTrajectory {
+    let mut points = Vec::new();
+    let mut start_time = 0.0;
+    for segment in waypoints.windows(2) {
+        let (from, to) = (&segment[0], &segment[1]);
+        let deltas: Vec<f32> = from.iter().zip(to).map(|(a, b)| b - a).collect();
+        let profile = UnitProfile::synchronized(&deltas, limits, jerk_limited);
+        let steps = ((profile.duration / period).ceil() as usize).max(1);
+        let first = if points.is_empty() { 0 } else { 1 };
+        for step in first..=steps {
+            let time = profile.duration * step as f32 / steps as f32;
+            let (s, s_dot, s_ddot) = profile.evaluate(time);
+            points.push(TrajectoryPoint {
+                time: start_time + time,
+                positions: from.iter().zip(&deltas).map(|(a, d)| a + d * s).collect(),
+                velocities: deltas.iter().map(|d| d * s_dot).collect(),
+                accelerations: deltas.iter().map(|d| d * s_ddot).collect(),
+            });
+        }
+        start_time += profile.duration;
+    }
+    if points.is_empty() {
+        return stationary(joint_names, waypoints.first());
+    }
+    JointTrajectory {
+        joint_names,
+        points,
+    }
+}
+
+// Holds `position`, or nothing without one.
+fn stationary(joint_names: Vec<String>, position: Option<&Vec<f32>>) -> JointTrajectory {
+    JointTrajectory {
+        joint_names,
+        points: position
+            .map(|position| TrajectoryPoint {
+                time: 0.0,
+                positions: position.clone(),
+                velocities: vec![0.0; position.len()],
+                accelerations: vec![0.0; position.len()],
+            })
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(velocity: f32, acceleration: f32, jerk: f32) -> JointLimits {
+        JointLimits {
+            velocity,
+            acceleration,
+            jerk,
+            ..Default::default()
+        }
+    }
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|joint| format!("joint{joint}")).collect()
+    }
+
+    // Checks every point, and every millisecond in between, against the
+    // limits with `slack` relative tolerance; jerk from acceleration
+    // differences if `jerk` is set.
+    fn assert_within_limits(
+        trajectory: &JointTrajectory,
+        limits: &[JointLimits],
+        slack: f32,
+        jerk: bool,
+    ) {
+        let step = 0.001;
+        let samples = (trajectory.duration() / step).ceil() as usize;
+        let mut previous: Option<TrajectoryPoint> = None;
+        for index in 0..=samples {
+            let point = trajectory.sample(index as f32 * step).unwrap();
+            for (joint, limits) in limits.iter().enumerate() {
+                let velocity = point.velocities[joint].abs();
+                let acceleration = point.accelerations[joint].abs();
+                assert!(velocity <= limits.velocity * (1.0 + slack), "{point:?}");
+                assert!(
+                    acceleration <= limits.acceleration * (1.0 + slack),
+                    "{point:?}"
+                );
+                if let (true, Some(previous)) = (jerk, &previous) {
+                    let change = point.accelerations[joint] - previous.accelerations[joint];
+                    assert!(
+                        change.abs() / step <= limits.jerk * (1.0 + slack),
+                        "{point:?}"
+                    );
+                }
+            }
+            previous = Some(point);
+        }
+    }
+
+    // Positions must follow from the velocities.
+    fn assert_consistent(trajectory: &JointTrajectory, tolerance: f32) {
+        for pair in trajectory.points.windows(2) {
+            let dt = pair[1].time - pair[0].time;
+            for joint in 0..pair[0].positions.len() {
+                let travelled = pair[1].positions[joint] - pair[0].positions[joint];
+                let mean = 0.5 * (pair[0].velocities[joint] + pair[1].velocities[joint]);
+                assert!((travelled - mean * dt).abs() <= tolerance, "{pair:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn trapezoidal_profiles_stay_within_limits() {
+        let limits = [
+            limits(1.0, 2.0, f32::INFINITY),
+            limits(0.5, 4.0, f32::INFINITY),
+        ];
+        let waypoints = [vec![0.0, 0.0], vec![2.0, -0.3], vec![2.1, 0.5]];
+        let trajectory = TrajectoryGenerator::new(Profile::Trapezoidal, 0.002).generate(
+            names(2),
+            &waypoints,
+            &limits,
+        );
+
+        assert_within_limits(&trajectory, &limits, 1e-3, false);
+        assert_consistent(&trajectory, 1e-5);
+        // The long first segment cruises at the first joint's velocity limit.
+        let fastest = trajectory
+            .points
+            .iter()
+            .map(|point| point.velocities[0])
+            .fold(0.0, f32::max);
+        assert!((fastest - 1.0).abs() < 1e-3, "{fastest}");
+        // 2 m at 1 m/s with 0.5 s ramps, then the short segments.
+        assert!(trajectory.duration() > 2.5);
+    }
+
+    #[test]
+    fn s_curve_profiles_also_stay_within_the_jerk_limits() {
+        let limits = [limits(1.0, 2.0, 10.0), limits(2.0, 1.0, 5.0)];
+        for waypoints in [
+            [vec![0.0, 0.0], vec![1.5, 0.3]],
+            [vec![0.0, 0.0], vec![0.05, -0.02]],
+        ] {
+            let trajectory = TrajectoryGenerator::new(Profile::SCurve, 0.001).generate(
+                names(2),
+                &waypoints,
+                &limits,
+            );
+            assert_within_limits(&trajectory, &limits, 1e-3, true);
+            assert_consistent(&trajectory, 1e-5);
+            let last = trajectory.points.last().unwrap();
+            assert!((last.positions[0] - waypoints[1][0]).abs() < 1e-5);
+            assert_eq!(last.accelerations, [0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn time_optimal_profiles_stay_within_limits() {
+        let limits = [
+            limits(1.0, 2.0, f32::INFINITY),
+            limits(0.8, 3.0, f32::INFINITY),
+        ];
+        let waypoints = [
+            vec![0.0, 0.0],
+            vec![1.0, 0.2],
+            vec![1.2, 1.2],
+            vec![0.0, 1.5],
+        ];
+        let trajectory = TrajectoryGenerator::new(Profile::TimeOptimal, 0.001)
+            .with_max_deviation(0.1)
+            .generate(names(2), &waypoints, &limits);
+
+        assert_within_limits(&trajectory, &limits, 1e-3, false);
+        assert_consistent(&trajectory, 1e-4);
+        // Blending corners is quicker than stopping at each.
+        let stopping = TrajectoryGenerator::new(Profile::Trapezoidal, 0.001).generate(
+            names(2),
+            &waypoints,
+            &limits,
+        );
+        assert!(trajectory.duration() < stopping.duration());
+    }
+
+    #[test]
+    fn synchronized_joints_finish_together() {
+        let limits = [
+            limits(1.0, 2.0, f32::INFINITY),
+            limits(0.2, 0.5, f32::INFINITY),
+        ];
+        let (from, to) = (vec![0.5, 1.0], vec![-1.5, 1.3]);
+        let trajectory = TrajectoryGenerator::new(Profile::Trapezoidal, 0.01).generate(
+            names(2),
+            &[from.clone(), to.clone()],
+            &limits,
+        );
+
+        // Both joints cover the same fraction of their move at every point.
+        for point in &trajectory.points {
+            let first = (point.positions[0] - from[0]) / (to[0] - from[0]);
+            let second = (point.positions[1] - from[1]) / (to[1] - from[1]);
+            assert!((first - second).abs() < 1e-4, "{point:?}");
+        }
+        let last = trajectory.points.last().unwrap();
+        assert!((last.positions[0] - to[0]).abs() < 1e-5);
+        assert!((last.positions[1] - to[1]).abs() < 1e-5);
+        let before = &trajectory.points[trajectory.len() - 2];
+        assert!(before
+            .velocities
+            .iter()
+            .all(|velocity| velocity.abs() > 0.0));
+        assert_within_limits(&trajectory, &limits, 1e-3, false);
+    }
+
+    #[test]
+    fn sampling_interpolates_and_holds_the_ends() {
+        let trajectory = TrajectoryGenerator::new(Profile::Trapezoidal, 0.05).generate(
+            names(1),
+            &[vec![0.0], vec![1.0]],
+            &[JointLimits::default()],
+        );
+
+        assert_eq!(trajectory.sample(-1.0), trajectory.points.first().cloned());
+        assert_eq!(
+            trajectory.sample(trajectory.duration() + 1.0),
+            trajectory.points.last().cloned()
+        );
+        let (a, b) = (&trajectory.points[4], &trajectory.points[5]);
+        let middle = trajectory.sample(0.5 * (a.time + b.time)).unwrap();
+        assert!(middle.positions[0] > a.positions[0] && middle.positions[0] < b.positions[0]);
+        assert!((middle.velocities[0] - 0.5 * (a.velocities[0] + b.velocities[0])).abs() < 1e-6);
+        assert_eq!(JointTrajectory::default().sample(0.0), None);
+    }
+
+    #[test]
+    fn commands_carry_feedforward_effort() {
+        let trajectory = TrajectoryGenerator::new(Profile::Trapezoidal, 0.01).generate(
+            names(2),
+            &[vec![0.0, 0.0], vec![1.0, -1.0]],
+            &[JointLimits::default(); 2],
+        );
+
+        let commands = trajectory.commands(0.1, &[2.0]).unwrap();
+        let point = trajectory.sample(0.1).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].position, Some(point.positions[0]));
+        assert_eq!(commands[0].velocity, Some(point.velocities[0]));
+        assert_eq!(commands[0].effort, Some(2.0 * point.accelerations[0]));
+        assert!(point.accelerations[0] > 0.0);
+        // No inertia given for the second joint.
+        assert_eq!(commands[1].effort, Some(0.0));
+    }
+
+    #[test]
+    fn time_optimal_trajectories_rest_on_the_end_waypoints() {
+        let waypoints = [vec![0.0, 0.0], vec![1.0, 0.5], vec![1.5, -0.5]];
+        let trajectory = TrajectoryGenerator::new(Profile::TimeOptimal, 0.01).generate(
+            vec!["a".to_string(), "b".to_string()],
+            &waypoints,
+            &[JointLimits::default(); 2],
+        );
+
+        let (first, last) = (&trajectory.points[0], trajectory.points.last().unwrap());
+        assert_eq!(first.time, 0.0);
+        let close = |a: &[f32], b: &[f32], tolerance: f32| {
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < tolerance)
+        };
+        assert!(close(&first.positions, &waypoints[0], 1e-4));
+        assert!(close(&last.positions, &waypoints[2], 1e-4));
+        assert!(close(&first.velocities, &[0.0; 2], 1e-3));
+        assert!(close(&last.velocities, &[0.0; 2], 1e-3));
+        assert!(trajectory
+            .points
+            .windows(2)
+            .all(|pair| pair[1].time > pair[0].time));
+    }
+}
@@ -0,0 +1,218 @@
+use crate::joints::JointLimits;
+
+/// Rest-to-rest progress from 0 to 1 with a symmetric velocity profile:
+/// acceleration ramps up, holds and ramps down, the robot cruises, then the
+/// mirror image. Without jerk phases this is a trapezoid.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct UnitProfile {
+    /// Seconds.
+    pub duration: f32,
+    /// Seconds spent accelerating, and again decelerating.
+    pub ramp: f32,
+    /// Seconds at each end of a ramp spent changing the acceleration.
+    pub jerk_time: f32,
+}
+
+impl UnitProfile {
+    /// The fastest profile moving one joint by `distance`, limited in jerk
+    /// only if `jerk_limited`.
+    pub fn alone(distance: f32, limits: &JointLimits, jerk_limited: bool) -> Self {
+        let (velocity, acceleration, jerk) = (limits.velocity, limits.acceleration, limits.jerk);
+        if distance <= 0.0 {
+            return Self::default();
+        }
+        if !jerk_limited || !jerk.is_finite() {
+            let ramp = if distance * acceleration >= velocity * velocity {
+                velocity / acceleration
+            } else {
+                (distance / acceleration).sqrt()
+            };
+            return Self {
+                duration: distance / (acceleration * ramp) + ramp,
+                ramp,
+                jerk_time: 0.0,
+            };
+        }
+        // Biagiotti and Melchiorri, "Trajectory Planning for Automatic
+        // Machines and Robots", section 3.4, with both ends at rest.
+        let (jerk_time, ramp) = if velocity * jerk >= acceleration * acceleration {
+            let jerk_time = acceleration / jerk;
+            (jerk_time, jerk_time + velocity / acceleration)
+        } else {
+            let jerk_time = (velocity / jerk).sqrt();
+            (jerk_time, 2.0 * jerk_time)
+        };
+        let cruise = distance / velocity - ramp;
+        if cruise >= 0.0 {
+            return Self {
+                duration: 2.0 * ramp + cruise,
+                ramp,
+                jerk_time,
+            };
+        }
+        // Too short to reach the velocity limit.
+        let (jerk_time, ramp) = if distance >= 2.0 * acceleration.powi(3) / (jerk * jerk) {
+            let jerk_time = acceleration / jerk;
+            (
+                jerk_time,
+                0.5 * jerk_time + (0.25 * jerk_time * jerk_time + distance / acceleration).sqrt(),
+            )
+        } else {
+            let jerk_time = (distance / (2.0 * jerk)).cbrt();
+            (jerk_time, 2.0 * jerk_time)
+        };
+        Self {
+            duration: 2.0 * ramp,
+            ramp,
+            jerk_time,
+        }
+    }
+
+    /// A profile shared by all joints, moving each by its delta, slow enough
+    /// for every joint's limits. Its shape is the one the slowest joint needs
+    /// on its own.
+    pub fn synchronized(deltas: &[f32], limits: &[JointLimits], jerk_limited: bool) -> Self {
+        let Some(slowest) = deltas
+            .iter()
+            .zip(limits)
+            .map(|(delta, limits)| Self::alone(delta.abs(), limits, jerk_limited))
+            .max_by(|a, b| a.duration.total_cmp(&b.duration))
+            .filter(|slowest| slowest.duration > 0.0)
+        else {
+            return Self::default();
+        };
+        let ramp = slowest.ramp / slowest.duration;
+        let jerk_time = slowest.jerk_time / slowest.duration;
+        // Peak rates of the unit move lasting one second; a move lasting `T`
+        // has them divided by `T`, `T²` and `T³`.
+        let velocity = 1.0 / (1.0 - ramp);
+        let acceleration = velocity / (ramp - jerk_time);
+        let jerk = if jerk_time > 0.0 {
+            acceleration / jerk_time
+        } else {
+            0.0
+        };
+        let duration = deltas
+            .iter()
+            .zip(limits)
+            .map(|(delta, limits)| {
+                let delta = delta.abs();
+                let duration = (delta * velocity / limits.velocity)
+                    .max((delta * acceleration / limits.acceleration).sqrt());
+                if jerk > 0.0 {
+                    duration.max((delta * jerk / limits.jerk).cbrt())
+                } else {
+                    duration
+                }
+            })
+            .fold(slowest.duration, f32::max);
+        Self {
+            duration,
+            ramp: ramp * duration,
+            jerk_time: jerk_time * duration,
+        }
+    }
+
+    /// Progress at `time`: `(s, ds/dt, d²s/dt²)`.
+    pub fn evaluate(&self, time: f32) -> (f32, f32, f32) {
+        if self.duration <= 0.0 {
+            return (1.0, 0.0, 0.0);
+        }
+        let time = time.clamp(0.0, self.duration);
+        let peak = 1.0 / (self.duration - self.ramp);
+        if time <= self.ramp {
+            self.accelerating(time, peak)
+        } else if time < self.duration - self.ramp {
+            (peak * (time - 0.5 * self.ramp), peak, 0.0)
+        } else {
+            let (s, s_dot, s_ddot) = self.accelerating(self.duration - time, peak);
+            (1.0 - s, s_dot, -s_ddot)
+        }
+    }
+
+    // Progress `time` seconds into the ramp up to `peak` velocity.
+    fn accelerating(&self, time: f32, peak: f32) -> (f32, f32, f32) {
+        let (ramp, jerk_time) = (self.ramp, self.jerk_time);
+        let acceleration = peak / (ramp - jerk_time);
+        if time < jerk_time {
+            let jerk = acceleration / jerk_time;
+            (
+                jerk * time.powi(3) / 6.0,
+                0.5 * jerk * time * time,
+                jerk * time,
+            )
+        } else if time <= ramp - jerk_time {
+            let held = time - jerk_time;
+            (
+                acceleration
+                    * (jerk_time * jerk_time / 6.0 + 0.5 * jerk_time * held + 0.5 * held * held),
+                acceleration * (0.5 * jerk_time + held),
+                acceleration,
+            )
+        } else {
+            let left = ramp - time;
+            let jerk = acceleration / jerk_time;
+            (
+                0.5 * peak * ramp - peak * left + jerk * left.powi(3) / 6.0,
+                peak - 0.5 * jerk * left * left,
+                jerk * left,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(velocity: f32, acceleration: f32, jerk: f32) -> JointLimits {
+        JointLimits {
+            velocity,
+            acceleration,
+            jerk,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trapezoids_and_triangles_take_the_textbook_time() {
+        let limits = limits(1.0, 2.0, f32::INFINITY);
+        // Cruising: 0.5 s ramps and 1 s at full speed.
+        let cruising = UnitProfile::alone(1.5, &limits, false);
+        assert!((cruising.ramp - 0.5).abs() < 1e-6);
+        assert!((cruising.duration - 2.0).abs() < 1e-6);
+        // Too short to reach full speed: 0.25 s up and down.
+        let triangle = UnitProfile::alone(0.125, &limits, false);
+        assert!((triangle.duration - 0.5).abs() < 1e-6);
+        assert_eq!(UnitProfile::alone(0.0, &limits, false).duration, 0.0);
+    }
+
+    #[test]
+    fn s_curves_end_where_they_should() {
+        for distance in [0.001, 0.05, 0.5, 3.0] {
+            let profile = UnitProfile::alone(distance, &limits(1.0, 2.0, 10.0), true);
+            let (s, s_dot, s_ddot) = profile.evaluate(profile.duration);
+            assert!((s - 1.0).abs() < 1e-5, "{distance}: {profile:?}");
+            assert!(s_dot.abs() < 1e-5 && s_ddot.abs() < 1e-3);
+            // Halfway through, half way there.
+            let (half, _, _) = profile.evaluate(0.5 * profile.duration);
+            assert!((half - 0.5).abs() < 1e-4, "{distance}: {half}");
+        }
+    }
+
+    #[test]
+    fn synchronized_profiles_take_the_slowest_joint_time() {
+        let limits = [
+            limits(1.0, 2.0, f32::INFINITY),
+            limits(0.25, 2.0, f32::INFINITY),
+        ];
+        let profile = UnitProfile::synchronized(&[1.5, -0.5], &limits, false);
+        let slowest = UnitProfile::alone(0.5, &limits[1], false);
+        assert!(profile.duration >= slowest.duration - 1e-6);
+        assert!(profile.duration >= 2.0 - 1e-6);
+        assert_eq!(
+            UnitProfile::synchronized(&[0.0, 0.0], &limits, false),
+            UnitProfile::default()
+        );
+    }
+}
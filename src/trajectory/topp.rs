@@ -0,0 +1,416 @@
+use super::{JointTrajectory, TrajectoryPoint};
+use crate::joints::JointLimits;
+
+// Joint rates below this along the path are treated as zero.
+const EPSILON: f32 = 1e-6;
+
+// Directions closer than this, in radians, are treated as one straight line.
+const COLLINEAR: f32 = 1e-4;
+
+// A circular arc replacing a corner, tangent to both segments.
+struct Blend {
+    start: Vec<f32>,
+    center: Vec<f32>,
+    // Unit vectors: from the center to the start, and along the arc there.
+    radial: Vec<f32>,
+    tangent: Vec<f32>,
+    radius: f32,
+    angle: f32,
+}
+
+impl Blend {
+    // The widest blend at `corner` between unit directions `incoming` and
+    // `outgoing` that leaves the path by at most `deviation` and uses at most
+    // `reach` of either segment. Kunz and Stilman, "Time-Optimal Trajectory
+    // Generation for Path Following with Bounded Acceleration and Velocity",
+    // 2012.
+    fn new(corner: &[f32], incoming: &[f32], outgoing: &[f32], reach: f32, deviation: f32) -> Self {
+        let angle = dot(incoming, outgoing).clamp(-1.0, 1.0).acos();
+        let half = 0.5 * angle;
+        let cut = reach.min(deviation * half.sin() / (1.0 - half.cos()));
+        let radius = cut / half.tan();
+        let bisector: Vec<f32> = outgoing.iter().zip(incoming).map(|(b, a)| b - a).collect();
+        let bisector_length = norm(&bisector);
+        let start: Vec<f32> = corner
+            .iter()
+            .zip(incoming)
+            .map(|(corner, direction)| corner - cut * direction)
+            .collect();
+        let center: Vec<f32> = corner
+            .iter()
+            .zip(&bisector)
+            .map(|(corner, bisector)| corner + bisector / bisector_length * radius / half.cos())
+            .collect();
+        let radial = start
+            .iter()
+            .zip(&center)
+            .map(|(start, center)| (start - center) / radius)
+            .collect();
+        Self {
+            start,
+            center,
+            radial,
+            tangent: incoming.to_vec(),
+            radius,
+            angle,
+        }
+    }
+
+    fn point(&self, angle: f32) -> Vec<f32> {
+        let (sin, cos) = angle.sin_cos();
+        (0..self.center.len())
+            .map(|joint| {
+                self.center[joint]
+                    + self.radius * (self.radial[joint] * cos + self.tangent[joint] * sin)
+            })
+            .collect()
+    }
+
+    fn length(&self) -> f32 {
+        self.radius * self.angle
+    }
+}
+
+// A waypoint path subdivided into short steps, with corners replaced by
+// blends.
+struct Grid {
+    positions: Vec<Vec<f32>>,
+    along: Vec<f32>,
+    // Sharp corners, and the ends, where the path speed must be zero.
+    stops: Vec<bool>,
+    // Rate of each joint by path length at each point.
+    tangents: Vec<Vec<f32>>,
+}
+
+impl Grid {
+    fn new(waypoints: &[Vec<f32>], resolution: f32, deviation: f32) -> Self {
+        let mut corners: Vec<&Vec<f32>> = Vec::new();
+        for waypoint in waypoints {
+            if corners
+                .last()
+                .is_none_or(|last| super::distance(last, waypoint) > EPSILON)
+            {
+                corners.push(waypoint);
+            }
+        }
+        let mut grid = Self {
+            positions: Vec::new(),
+            along: Vec::new(),
+            stops: Vec::new(),
+            tangents: Vec::new(),
+        };
+        let Some(&first) = corners.first() else {
+            return grid;
+        };
+        grid.push(first.clone(), 0.0, true);
+        for (index, &corner) in corners.iter().enumerate().skip(1) {
+            let previous = corners[index - 1];
+            let incoming = unit(previous, corner);
+            let Some(&next) = corners.get(index + 1) else {
+                grid.straight(corner, resolution);
+                break;
+            };
+            let outgoing = unit(corner, next);
+            let angle = dot(&incoming, &outgoing).clamp(-1.0, 1.0).acos();
+            if angle < COLLINEAR {
+                grid.straight(corner, resolution);
+                continue;
+            }
+            if deviation <= 0.0 || angle > std::f32::consts::PI - COLLINEAR {
+                grid.straight(corner, resolution);
+                if let Some(stop) = grid.stops.last_mut() {
+                    *stop = true;
+                }
+                continue;
+            }
+            let reach = 0.5 * super::distance(previous, corner).min(super::distance(corner, next));
+            let blend = Blend::new(corner, &incoming, &outgoing, reach, deviation);
+            grid.straight(&blend.start, resolution);
+            let steps = ((blend.length() / resolution).ceil() as usize).max(2);
+            let start = grid.along.last().copied().unwrap_or(0.0);
+            for step in 1..=steps {
+                let fraction = step as f32 / steps as f32;
+                grid.push(
+                    blend.point(blend.angle * fraction),
+                    start + blend.length() * fraction,
+                    false,
+                );
+            }
+        }
+        if let Some(stop) = grid.stops.last_mut() {
+            *stop = true;
+        }
+        grid.differentiate();
+        grid
+    }
+
+    fn push(&mut self, position: Vec<f32>, along: f32, stop: bool) {
+        self.positions.push(position);
+        self.along.push(along);
+        self.stops.push(stop);
+    }
+
+    // Steps in a straight line from the last point to `to`.
+    fn straight(&mut self, to: &[f32], resolution: f32) {
+        let Some(from) = self.positions.last().cloned() else {
+            return;
+        };
+        let span = super::distance(&from, to);
+        if span <= EPSILON {
+            return;
+        }
+        let start = self.along.last().copied().unwrap_or(0.0);
+        let steps = ((span / resolution).ceil() as usize).max(2);
+        for step in 1..=steps {
+            let fraction = step as f32 / steps as f32;
+            self.push(
+                from.iter()
+                    .zip(to)
+                    .map(|(a, b)| a + (b - a) * fraction)
+                    .collect(),
+                start + span * fraction,
+                false,
+            );
+        }
+    }
+
+    // Tangents by finite differences over the neighbouring points,
+    // one-sided next to a stop so that they follow the step the robot is on.
+    fn differentiate(&mut self) {
+        let positions = &self.positions;
+        let last = positions.len().saturating_sub(1);
+        for index in 0..positions.len() {
+            let (before, after) = if index < last && self.stops[index] {
+                (index, index + 1)
+            } else if index > 0 && index < last && self.stops[index + 1] {
+                (index - 1, index)
+            } else {
+                (index.saturating_sub(1), (index + 1).min(last))
+            };
+            let span = self.along[after] - self.along[before];
+            self.tangents.push(
+                positions[before]
+                    .iter()
+                    .zip(&positions[after])
+                    .map(|(a, b)| if span > 0.0 { (b - a) / span } else { 0.0 })
+                    .collect(),
+            );
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn step(&self, index: usize) -> f32 {
+        self.along[index + 1] - self.along[index]
+    }
+
+    // Rate of each joint by path length at either end of the step after
+    // `index`, varying linearly in between. A step into a sharp corner keeps
+    // its direction throughout.
+    fn rates(&self, index: usize) -> (&[f32], &[f32]) {
+        let start = &self.tangents[index];
+        if self.stops[index + 1] {
+            (start, start)
+        } else {
+            (start, &self.tangents[index + 1])
+        }
+    }
+
+    // Change of the joint rates by path length over the step after `index`.
+    fn curvature(&self, index: usize) -> Vec<f32> {
+        let (start, end) = self.rates(index);
+        let step = self.step(index);
+        start.iter().zip(end).map(|(a, b)| (b - a) / step).collect()
+    }
+
+    // Bounds on the path acceleration `u` over the step after `index`, as
+    // `(lower, upper, slope)`: `u` must lie within `lower + slope * x` and
+    // `upper + slope * x` for squared path speed `x` at the start of the
+    // step. Joint accelerations are checked at both ends; at the end the
+    // squared speed is `x + 2 * step * u`. Constraints on `x` alone are
+    // returned as a speed limit.
+    fn acceleration_bounds(
+        &self,
+        index: usize,
+        limits: &[JointLimits],
+    ) -> (Vec<(f32, f32, f32)>, f32) {
+        let (start, end) = self.rates(index);
+        let curvature = self.curvature(index);
+        let step = self.step(index);
+        let mut bounds = Vec::new();
+        let mut speed_limit = f32::INFINITY;
+        for joint in 0..limits.len() {
+            let acceleration = limits[joint].acceleration;
+            let bending = curvature[joint];
+            for rate in [start[joint], end[joint] + 2.0 * step * bending] {
+                if rate.abs() > EPSILON {
+                    let reach = acceleration / rate.abs();
+                    bounds.push((-reach, reach, -bending / rate));
+                } else if bending.abs() > EPSILON {
+                    speed_limit = speed_limit.min(acceleration / bending.abs());
+                }
+            }
+        }
+        (bounds, speed_limit)
+    }
+
+    // Largest squared path speed at `index` within the velocity limits and
+    // leaving some path acceleration over the next step that keeps every
+    // joint within its limit.
+    fn speed_limit(&self, index: usize, limits: &[JointLimits]) -> f32 {
+        if self.stops[index] {
+            return 0.0;
+        }
+        let mut limit = f32::INFINITY;
+        let rates = [
+            index.checked_sub(1).map(|before| self.rates(before).1),
+            (index + 1 < self.len()).then(|| self.rates(index).0),
+        ];
+        for rates in rates.into_iter().flatten() {
+            for (rate, limits) in rates.iter().zip(limits) {
+                if rate.abs() > EPSILON {
+                    limit = limit.min((limits.velocity / rate).powi(2));
+                }
+            }
+        }
+        if index + 1 < self.len() {
+            let (bounds, speed_limit) = self.acceleration_bounds(index, limits);
+            limit = limit.min(speed_limit);
+            for (lower, _, lower_slope) in &bounds {
+                for (_, upper, upper_slope) in &bounds {
+                    if lower_slope > upper_slope {
+                        limit = limit.min((upper - lower) / (lower_slope - upper_slope));
+                    }
+                }
+            }
+        }
+        limit
+    }
+}
+
+/// Times a path through `waypoints` as fast as the velocity and
+/// acceleration limits allow, starting and ending at rest. Jerk is not
+/// limited.
+///
+/// Corners are replaced by circular blends leaving the path by at most
+/// `deviation`, or stopped at without one. The path is then split into steps
+/// of at most `resolution`, and the squared path speed at each step is found
+/// by a backward pass that ensures the robot can still brake, then a forward
+/// pass that accelerates as hard as the limits allow (Kunz and Stilman's
+/// numerical integration, over a fixed grid).
+///
+/// Panics if `resolution` is not positive.
+pub(crate) fn time_optimal(
+    joint_names: Vec<String>,
+    waypoints: &[Vec<f32>],
+    limits: &[JointLimits],
+    resolution: f32,
+    deviation: f32,
+    period: f32,
+) -> JointTrajectory {
+    assert!(
+        resolution > 0.0,
+        "time-optimal path resolution must be positive, got {resolution}"
+    );
+    let grid = Grid::new(waypoints, resolution, deviation);
+    if grid.len() < 2 {
+        return super::stationary(joint_names, waypoints.first());
+    }
+    let last = grid.len() - 1;
+    let mut squared_speeds: Vec<f32> = (0..grid.len())
+        .map(|index| grid.speed_limit(index, limits).max(0.0))
+        .collect();
+
+    for index in (0..last).rev() {
+        let step = grid.step(index);
+        for (lower, _, slope) in grid.acceleration_bounds(index, limits).0 {
+            let scale = 1.0 + 2.0 * step * slope;
+            if scale > 0.0 {
+                squared_speeds[index] = squared_speeds[index]
+                    .min((squared_speeds[index + 1] - 2.0 * step * lower) / scale);
+            }
+        }
+    }
+    let mut accelerations = vec![0.0; last];
+    for index in 0..last {
+        let step = grid.step(index);
+        let squared = squared_speeds[index];
+        let most = grid
+            .acceleration_bounds(index, limits)
+            .0
+            .iter()
+            .map(|(_, upper, slope)| upper + slope * squared)
+            .fold(f32::INFINITY, f32::min);
+        squared_speeds[index + 1] = squared_speeds[index + 1]
+            .min(squared + 2.0 * step * most)
+            .max(0.0);
+        accelerations[index] = (squared_speeds[index + 1] - squared) / (2.0 * step);
+    }
+
+    // With constant path acceleration over each step the mean speed is the
+    // mean of the end speeds.
+    let mut times = vec![0.0];
+    for index in 0..last {
+        let mean = 0.5 * (squared_speeds[index].sqrt() + squared_speeds[index + 1].sqrt());
+        times.push(times[index] + grid.step(index) / mean.max(EPSILON));
+    }
+
+    let duration = times[last];
+    let samples = ((duration / period).ceil() as usize).max(1);
+    let points = (0..=samples)
+        .map(|sample| {
+            let time = duration * sample as f32 / samples as f32;
+            let index = times
+                .partition_point(|start| *start <= time)
+                .saturating_sub(1)
+                .min(last - 1);
+            let elapsed = time - times[index];
+            let step = grid.step(index);
+            let acceleration = accelerations[index];
+            let initial = squared_speeds[index].sqrt();
+            let speed = (initial + acceleration * elapsed).max(0.0);
+            let travelled =
+                (initial * elapsed + 0.5 * acceleration * elapsed * elapsed).clamp(0.0, step);
+            let fraction = travelled / step;
+            let (start, end) = grid.rates(index);
+            let rates = lerp(start, end, fraction);
+            TrajectoryPoint {
+                time,
+                positions: lerp(&grid.positions[index], &grid.positions[index + 1], fraction),
+                velocities: rates.iter().map(|rate| rate * speed).collect(),
+                accelerations: rates
+                    .iter()
+                    .zip(grid.curvature(index))
+                    .map(|(rate, bending)| rate * acceleration + bending * speed * speed)
+                    .collect(),
+            }
+        })
+        .collect();
+    JointTrajectory {
+        joint_names,
+        points,
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn lerp(a: &[f32], b: &[f32], fraction: f32) -> Vec<f32> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a + (b - a) * fraction)
+        .collect()
+}
+
+fn norm(a: &[f32]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+// Unit direction from `from` to `to`, which must differ.
+fn unit(from: &[f32], to: &[f32]) -> Vec<f32> {
+    let length = super::distance(from, to);
+    from.iter().zip(to).map(|(a, b)| (b - a) / length).collect()
+}